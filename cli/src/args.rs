@@ -8,8 +8,10 @@ use futures::Future;
 use tokio_cassandra::tokio::error::Error as TokioCassandraError;
 use tokio_cassandra::tokio::client::{self, ClientHandle, CqlProto, Client};
 use tokio_cassandra::tokio::codec::CqlCodecDebuggingOptions;
+use tokio_cassandra::tokio::recording::Recorder;
 use tokio_cassandra::tokio::ssl;
 use tokio_cassandra::codec::authentication::Credentials;
+use tokio_cassandra::codec::compression::Compression;
 use tokio_cassandra::codec::header::ProtocolVersion;
 use tokio_core::reactor::Core;
 use dns_lookup::lookup_host;
@@ -20,6 +22,7 @@ arg_enum!{
     #[allow(non_camel_case_types)]
     #[derive(Debug)]
     pub enum CliProtoVersion {
+        v4,
         v3
     }
 }
@@ -37,6 +40,7 @@ impl From<ProtocolVersion> for CliProtoVersion {
     fn from(v: ProtocolVersion) -> Self {
         match v {
             ProtocolVersion::Version3 => CliProtoVersion::v3,
+            ProtocolVersion::Version4 => CliProtoVersion::v4,
         }
     }
 }
@@ -45,6 +49,7 @@ impl From<CliProtoVersion> for ProtocolVersion {
     fn from(v: CliProtoVersion) -> Self {
         match v {
             CliProtoVersion::v3 => ProtocolVersion::Version3,
+            CliProtoVersion::v4 => ProtocolVersion::Version4,
         }
     }
 }
@@ -96,7 +101,7 @@ impl FromStr for Pk12WithOptionalPassword {
 
 impl ConnectionOptions {
     pub fn try_from(args: &clap::ArgMatches) -> Result<ConnectionOptions> {
-        let host = args.value_of("host").expect("clap to work");
+        let host = args.value_of("host").ok_or("The --host argument is required to connect")?;
         let port = args.value_of("port").expect("clap to work");
         let port: u16 = port.parse()
             .chain_err(|| format!("Port '{}' could not be parsed as number", port))?;
@@ -111,16 +116,22 @@ impl ConnectionOptions {
                         .expect("clap to work")
                         .into(),
                     debug: match (args.value_of("debug-dump-encoded-frames-into-directory"),
-                                  args.value_of("debug-dump-decoded-frames-into-directory")) {
-                        (None, None) => None,
-                        (encode_path, decode_path) => {
+                                  args.value_of("debug-dump-decoded-frames-into-directory"),
+                                  args.value_of("record-session-into")) {
+                        (None, None, None) => None,
+                        (encode_path, decode_path, record_path) => {
                             Some(CqlCodecDebuggingOptions {
                                 dump_encoded_frames_into: encode_path.map(Into::into),
                                 dump_decoded_frames_into: decode_path.map(Into::into),
+                                record_into: match record_path {
+                                    Some(path) => Some(Recorder::create(path)?),
+                                    None => None,
+                                },
                                 ..Default::default()
                             })
                         }
                     },
+                    ..Default::default()
                 },
             },
             addr: {
@@ -196,6 +207,7 @@ impl ConnectionOptions {
                             })?)
                     }
                 },
+                compression: args.value_of("compression").and_then(Compression::from_name),
                 ..Default::default()
             },
         })
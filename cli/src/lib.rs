@@ -15,6 +15,7 @@ extern crate tokio_service;
 extern crate futures;
 extern crate dns_lookup;
 extern crate semver;
+extern crate rustyline;
 
 pub mod errors {
     use std::num::ParseIntError;
@@ -40,13 +40,26 @@ pub fn run() -> Result<()> {
             .takes_value(true)
             .help("A directory into which to dump all frames in order they arrive, \
                    differentiating them by their op-code."))
+        .arg(Arg::with_name("record-session-into")
+            .required(false)
+            .long("record-session-into")
+            .takes_value(true)
+            .help("A file into which to record all frames sent and received, along with their timing, \
+                   to replay the session later."))
         .arg(Arg::with_name("protocol-version")
             .required(false)
             .takes_value(true)
             .long("protocol-version")
             .default_value(&CliProtoVersion::variants()[0])
             .possible_values(&CliProtoVersion::variants())
-            .help("The protocol version to use. If not specified, the highest-supported version is used."))
+            .help("The protocol version to try first. If not specified, the highest-supported version is used. \
+                   Lower versions are tried as long as the server rejects it."))
+        .arg(Arg::with_name("compression")
+            .required(false)
+            .takes_value(true)
+            .long("compression")
+            .possible_values(&["lz4", "snappy"])
+            .help("The algorithm to compress frames with, which is only used if the server supports it."))
         .arg(Arg::with_name("cql-version")
             .required(false)
             .takes_value(true)
@@ -54,11 +67,12 @@ pub fn run() -> Result<()> {
             .help("The semantic CQL version that you require the server to support, like '3.2.1'. It defaults to \
                    the highest supported version offered by the server."))
         .arg(Arg::with_name("host")
-            .required(true)
+            .required(false)
             .takes_value(true)
             .long("host")
             .short("h")
-            .help("The name or IP address of the host to connect to."))
+            .help("The name or IP address of the host to connect to. It is required by all sub-commands which \
+                   connect."))
         .arg(Arg::with_name("port")
             .required(false)
             .long("port")
@@ -134,19 +148,70 @@ pub fn run() -> Result<()> {
                 .short("o")
                 .possible_values(&OutputFormat::variants())
                 .default_value(&default_output_format)
-                .help("Defines the serialization format of the query-result."))
+                .help("Defines the serialization format of the query-result. The table format is meant to be \
+                       read by humans, csv and tsv are meant to be read by other tools. They only print rows."))
+            .arg(Arg::with_name("max-column-width")
+                .required(false)
+                .takes_value(true)
+                .long("max-column-width")
+                .short("w")
+                .help("Truncates cells of the table output format to the given amount of characters."))
             .arg(Arg::with_name("dry-run")
                 .required(false)
                 .long("dry-run")
                 .short("n")
                 .help("Don't execute the generated query, but display it on standard output. Output formats are \
-                       just ignored if set.")));
+                       just ignored if set.")))
+        .subcommand(SubCommand::with_name("shell")
+            .about("An interactive shell to execute CQL statements, similar to cqlsh.")
+            .arg(Arg::with_name("keyspace")
+                .required(false)
+                .takes_value(true)
+                .long("keyspace")
+                .short("k")
+                .help("Uses the given keyspace right after connecting."))
+            .arg(Arg::with_name("history-file")
+                .required(false)
+                .takes_value(true)
+                .long("history-file")
+                .help("The file to load the history of entered lines from, and to save it to when the shell \
+                       is left. Defaults to ~/.tcc_history."))
+            .arg(Arg::with_name("max-column-width")
+                .required(false)
+                .takes_value(true)
+                .long("max-column-width")
+                .short("w")
+                .help("Truncates cells of result tables to the given amount of characters.")))
+        .subcommand(SubCommand::with_name("decode")
+            .about("Decodes frames as sent on the wire, and prints their header and body.")
+            .arg(Arg::with_name("file")
+                .required(true)
+                .multiple(true)
+                .help("A file with one or more frames, such as the ones dumped by \
+                       --debug-dump-*-frames-into-directory, or a recording made with --record-session-into. \
+                       If the path is '-', frames are read from standard input."))
+            .arg(Arg::with_name("compression")
+                .required(false)
+                .takes_value(true)
+                .long("compression")
+                .possible_values(&["lz4", "snappy"])
+                .help("The algorithm compressed frames use. If unset, it is taken from the STARTUP frames \
+                       decoded before."))
+            .arg(Arg::with_name("output-format")
+                .required(false)
+                .takes_value(true)
+                .long("output-format")
+                .short("o")
+                .possible_values(&["json", "yaml"])
+                .default_value(&default_output_format)
+                .help("Defines the serialization format of the decoded frames.")));
     let args: clap::ArgMatches = app.get_matches();
-    let opts = ConnectionOptions::try_from(&args)?;
 
     match args.subcommand() {
-        ("test-connection", Some(args)) => tcc::test_connection(opts, args),
-        ("query", Some(args)) => tcc::query(opts, args),
+        ("test-connection", Some(sargs)) => tcc::test_connection(ConnectionOptions::try_from(&args)?, sargs),
+        ("query", Some(sargs)) => tcc::query(ConnectionOptions::try_from(&args)?, sargs),
+        ("shell", Some(sargs)) => tcc::shell(ConnectionOptions::try_from(&args)?, sargs),
+        ("decode", Some(sargs)) => tcc::decode(sargs),
         _ => {
             println!("{}", args.usage());
             ::std::process::exit(2);
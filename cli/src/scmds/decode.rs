@@ -0,0 +1,488 @@
+use clap;
+use super::super::errors::*;
+use super::OutputFormat;
+use super::value::{self, hex};
+use tokio_cassandra::codec::header::{Header, Direction, ProtocolVersion, FLAG_COMPRESSION, FLAG_TRACING,
+                                     FLAG_CUSTOM_PAYLOAD, FLAG_WARNING, FLAG_USE_BETA};
+use tokio_cassandra::codec::{request, response};
+use tokio_cassandra::codec::compression::Compression;
+use tokio_cassandra::codec::primitives::{decode, CqlBytes, CqlString};
+use tokio_cassandra::tokio::recording::{Recording, RECORDING_HEADER};
+use tokio_core::io::EasyBuf;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+
+/// Decodes frames one after another, remembering the compression negotiated by STARTUP messages.
+struct Decoder {
+    compression: Option<Compression>,
+    /// If true, the compression was given on the command-line and is never changed.
+    fixed: bool,
+}
+
+impl Decoder {
+    /// Decodes the frame at the beginning of `bytes`, and returns the amount of bytes it occupies,
+    /// or None if the end of the frame is unknown.
+    fn frame(&mut self, bytes: &[u8]) -> (Map<String, Value>, Option<usize>) {
+        let mut entry = Map::new();
+        let h = match Header::try_from(bytes) {
+            Ok(h) => h,
+            Err(e) => {
+                entry.insert("error".into(), format!("Could not decode the header: {}", e).into());
+                entry.insert("hex".into(), hex_dump(bytes));
+                return (entry, None);
+            }
+        };
+        entry.insert("header".into(), header(&h));
+        let end = Header::encoded_len() + h.length as usize;
+        if bytes.len() < end {
+            entry.insert("error".into(),
+                         format!("The body is truncated: expected {} bytes, got {}",
+                                 h.length,
+                                 bytes.len() - Header::encoded_len())
+                             .into());
+            entry.insert("hex".into(), hex_dump(&bytes[Header::encoded_len()..]));
+            return (entry, None);
+        }
+        let body = &bytes[Header::encoded_len()..end];
+        match self.body(&h, body) {
+            Ok(v) => {
+                entry.insert("body".into(), v);
+            }
+            Err(e) => {
+                entry.insert("error".into(), e.into());
+                entry.insert("hex".into(), hex_dump(body));
+            }
+        }
+        (entry, Some(end))
+    }
+
+    fn body(&mut self, h: &Header, body: &[u8]) -> ::std::result::Result<Value, String> {
+        let body = if h.is_compressed() {
+            match self.compression {
+                Some(c) => EasyBuf::from(c.decompress(body).map_err(|e| e.to_string())?),
+                None => {
+                    return Err("The body is compressed, but no compression was negotiated. Please specify it \
+                                with --compression."
+                        .into())
+                }
+            }
+        } else {
+            EasyBuf::from(body.to_vec())
+        };
+        let version = h.version.version;
+        Ok(match h.version.direction {
+            Direction::Request => {
+                let (body, payload) = if h.has_custom_payload() {
+                    let (body, payload) = decode::bytes_map(body).map_err(|e| e.to_string())?;
+                    (body, Some(payload))
+                } else {
+                    (body, None)
+                };
+                let msg = request::Message::decode(version, h.op_code.clone(), body).map_err(|e| e.to_string())?;
+                if let request::Message::Startup(ref m) = msg {
+                    if !self.fixed {
+                        self.compression = m.compression.as_ref().and_then(|c| Compression::from_name(c.as_ref()));
+                    }
+                }
+                let mut v = object();
+                v.insert("message".into(), request_message(&msg));
+                if let Some(ref payload) = payload {
+                    v.insert("custom_payload".into(), bytes_map(payload));
+                }
+                Value::Object(v)
+            }
+            Direction::Response => {
+                let (body, prelude) = response::ResponsePrelude::decode(h, body).map_err(|e| e.to_string())?;
+                let msg = response::Message::decode(version, h.op_code.clone(), body).map_err(|e| e.to_string())?;
+                let mut v = object();
+                v.insert("message".into(), response_message(&msg));
+                if let Some(ref id) = prelude.tracing_id {
+                    v.insert("tracing_id".into(), value::uuid(id).into());
+                }
+                if let Some(ref warnings) = prelude.warnings {
+                    v.insert("warnings".into(),
+                             Value::Array(warnings.iter().map(|w| w.as_ref().into()).collect()));
+                }
+                if let Some(ref payload) = prelude.custom_payload {
+                    v.insert("custom_payload".into(), bytes_map(payload));
+                }
+                Value::Object(v)
+            }
+        })
+    }
+}
+
+fn object() -> Map<String, Value> {
+    Map::new()
+}
+
+/// The bytes in the format of `xxd`, with one string per line of 16 bytes.
+fn hex_dump(bytes: &[u8]) -> Value {
+    Value::Array(bytes.chunks(16)
+        .enumerate()
+        .map(|(n, line)| {
+            let hex: Vec<String> = line.chunks(2)
+                .map(|pair| pair.iter().map(|b| format!("{:02x}", b)).collect())
+                .collect();
+            let ascii: String = line.iter()
+                .map(|&b| if b >= 0x20 && b < 0x7f { b as char } else { '.' })
+                .collect();
+            format!("{:08x}: {:<39}  {}", n * 16, hex.join(" "), ascii).into()
+        })
+        .collect())
+}
+
+fn bytes<T: AsRef<[u8]>>(b: &CqlBytes<T>) -> Value {
+    match b.as_bytes() {
+        Some(b) => hex(b).into(),
+        None => Value::Null,
+    }
+}
+
+fn bytes_map<K: AsRef<str>, T: AsRef<[u8]>>(m: &HashMap<K, CqlBytes<T>>) -> Value {
+    let mut o = object();
+    for (k, v) in m {
+        o.insert(k.as_ref().into(), bytes(v));
+    }
+    Value::Object(o)
+}
+
+fn header(h: &Header) -> Value {
+    let mut o = object();
+    o.insert("version".into(),
+             match h.version.version {
+                     ProtocolVersion::Version3 => 3,
+                     ProtocolVersion::Version4 => 4,
+                 }
+                 .into());
+    o.insert("direction".into(),
+             match h.version.direction {
+                     Direction::Request => "request",
+                     Direction::Response => "response",
+                 }
+                 .into());
+    o.insert("flags".into(),
+             Value::Array([(FLAG_COMPRESSION, "compression"),
+                           (FLAG_TRACING, "tracing"),
+                           (FLAG_CUSTOM_PAYLOAD, "custom_payload"),
+                           (FLAG_WARNING, "warning"),
+                           (FLAG_USE_BETA, "use_beta")]
+                 .iter()
+                 .filter(|&&(flag, _)| h.flags & flag == flag)
+                 .map(|&(_, name)| name.into())
+                 .collect()));
+    o.insert("stream_id".into(), h.stream_id.into());
+    o.insert("opcode".into(), format!("{:?}", h.op_code).into());
+    o.insert("length".into(), h.length.into());
+    Value::Object(o)
+}
+
+fn parameters(p: &request::QueryParameters) -> Value {
+    let mut o = object();
+    o.insert("consistency".into(), format!("{:?}", p.consistency).into());
+    o.insert("skip_metadata".into(), p.skip_metadata.into());
+    match p.values {
+        Some(request::QueryValues::Positional(ref values)) => {
+            o.insert("values".into(), Value::Array(values.iter().map(bytes).collect()));
+        }
+        Some(request::QueryValues::Named(ref values)) => {
+            o.insert("values".into(), bytes_map(values));
+        }
+        None => {}
+    }
+    if let Some(size) = p.page_size {
+        o.insert("page_size".into(), size.into());
+    }
+    if let Some(ref state) = p.paging_state {
+        o.insert("paging_state".into(), bytes(state));
+    }
+    if let Some(ref c) = p.serial_consistency {
+        o.insert("serial_consistency".into(), format!("{:?}", c).into());
+    }
+    if let Some(t) = p.timestamp {
+        o.insert("timestamp".into(), t.into());
+    }
+    Value::Object(o)
+}
+
+fn request_message(msg: &request::Message) -> Value {
+    use tokio_cassandra::codec::request::Message::*;
+    let mut o = object();
+    match *msg {
+        Options => return Value::Null,
+        Startup(ref m) => {
+            o.insert("cql_version".into(), m.cql_version.as_ref().into());
+            if let Some(ref c) = m.compression {
+                o.insert("compression".into(), c.as_ref().into());
+            }
+        }
+        AuthResponse(ref m) => {
+            o.insert("auth_data".into(), bytes(&m.auth_data));
+        }
+        Query(ref m) => {
+            o.insert("query".into(), m.query.as_ref().into());
+            o.insert("parameters".into(), parameters(&m.parameters));
+        }
+        Prepare(ref m) => {
+            o.insert("query".into(), m.query.as_ref().into());
+        }
+        Execute(ref m) => {
+            o.insert("id".into(), bytes(&m.id));
+            o.insert("parameters".into(), parameters(&m.parameters));
+        }
+        Batch(ref m) => {
+            o.insert("batch_type".into(), format!("{:?}", m.batch_type).into());
+            o.insert("queries".into(),
+                     Value::Array(m.queries
+                         .iter()
+                         .map(|q| {
+                             let mut o = object();
+                             match q.statement {
+                                 request::BatchStatement::Query(ref query) => {
+                                     o.insert("query".into(), query.as_ref().into())
+                                 }
+                                 request::BatchStatement::Prepared(ref id) => o.insert("id".into(), bytes(id)),
+                             };
+                             o.insert("values".into(), Value::Array(q.values.iter().map(bytes).collect()));
+                             Value::Object(o)
+                         })
+                         .collect()));
+            o.insert("consistency".into(), format!("{:?}", m.consistency).into());
+            if let Some(ref c) = m.serial_consistency {
+                o.insert("serial_consistency".into(), format!("{:?}", c).into());
+            }
+            if let Some(t) = m.timestamp {
+                o.insert("timestamp".into(), t.into());
+            }
+        }
+        Register(ref m) => {
+            o.insert("events".into(),
+                     Value::Array(m.events.iter().map(|e| format!("{:?}", e).into()).collect()));
+        }
+    }
+    Value::Object(o)
+}
+
+fn table_spec(keyspace: &CqlString<EasyBuf>, table: &CqlString<EasyBuf>, o: &mut Map<String, Value>) {
+    o.insert("keyspace".into(), keyspace.as_ref().into());
+    o.insert("table".into(), table.as_ref().into());
+}
+
+fn columns(specs: &[response::ColumnSpec], global: Option<&response::TableSpec>) -> Value {
+    Value::Array(specs.iter()
+        .map(|c| {
+            let mut o = object();
+            if let Some(spec) = c.table_spec.as_ref().or(global) {
+                table_spec(&spec.keyspace, &spec.table, &mut o);
+            }
+            o.insert("name".into(), c.name.as_ref().into());
+            o.insert("type".into(), format!("{:?}", c.column_type).into());
+            Value::Object(o)
+        })
+        .collect())
+}
+
+fn rows_metadata(m: &response::RowsMetadata) -> Value {
+    let mut o = object();
+    o.insert("columns_count".into(), m.columns_count.into());
+    if !m.no_metadata {
+        o.insert("columns".into(),
+                 columns(&m.column_specs, m.global_tables_spec.as_ref()));
+    }
+    if let Some(ref state) = m.paging_state {
+        o.insert("paging_state".into(), bytes(state));
+    }
+    Value::Object(o)
+}
+
+fn result_message(msg: &response::ResultMessage) -> Value {
+    use tokio_cassandra::codec::response::ResultMessage::*;
+    let mut o = object();
+    match *msg {
+        Void => {
+            o.insert("kind".into(), "void".into());
+        }
+        Rows(ref rows) => {
+            o.insert("kind".into(), "rows".into());
+            o.insert("metadata".into(), rows_metadata(&rows.metadata));
+            o.insert("rows".into(),
+                     Value::Array(rows.rows
+                         .iter()
+                         .map(|row| match rows.decode_row(row) {
+                             Ok(values) => Value::Array(values.iter().map(value::option_to_json).collect()),
+                             Err(e) => {
+                                 let mut o = object();
+                                 o.insert("error".into(), e.to_string().into());
+                                 o.insert("cells".into(), Value::Array(row.cells.iter().map(bytes).collect()));
+                                 Value::Object(o)
+                             }
+                         })
+                         .collect()));
+        }
+        SetKeyspace(ref ks) => {
+            o.insert("kind".into(), "set_keyspace".into());
+            o.insert("keyspace".into(), ks.as_ref().into());
+        }
+        Prepared(ref p) => {
+            o.insert("kind".into(), "prepared".into());
+            o.insert("id".into(), bytes(&p.id));
+            o.insert("pk_indices".into(),
+                     Value::Array(p.metadata.pk_indices.iter().map(|&i| i.into()).collect()));
+            o.insert("bind_columns".into(),
+                     columns(&p.metadata.column_specs, p.metadata.global_tables_spec.as_ref()));
+            o.insert("result_metadata".into(), rows_metadata(&p.result_metadata));
+        }
+        SchemaChange(ref c) => {
+            o.insert("kind".into(), "schema_change".into());
+            o.insert("change".into(), format!("{:?}", c).into());
+        }
+    }
+    Value::Object(o)
+}
+
+fn response_message(msg: &response::Message) -> Value {
+    use tokio_cassandra::codec::response::Message::*;
+    let mut o = object();
+    match *msg {
+        Ready => return Value::Null,
+        Supported(ref m) => {
+            for (k, values) in m.0.iter() {
+                o.insert(k.as_ref().into(),
+                         Value::Array(values.iter().map(|v| v.as_ref().into()).collect()));
+            }
+        }
+        Authenticate(ref m) => {
+            o.insert("authenticator".into(), m.authenticator.as_ref().into());
+        }
+        AuthChallenge(ref m) => {
+            o.insert("token".into(), bytes(&m.token));
+        }
+        AuthSuccess(ref m) => {
+            o.insert("payload".into(), bytes(&m.payload));
+        }
+        Error(ref e) => {
+            o.insert("code".into(), e.code.into());
+            o.insert("kind".into(), format!("{:?}", e.kind).into());
+            o.insert("message".into(), e.text.as_ref().into());
+        }
+        Result(ref r) => return result_message(r),
+        Event(ref e) => {
+            o.insert("event".into(), format!("{:?}", e).into());
+        }
+    }
+    Value::Object(o)
+}
+
+/// Decodes all frames of a file, which are either concatenated as on the wire, or stored as
+/// recording.
+fn decode_file(path: &str, decoder: &mut Decoder) -> Result<Vec<Value>> {
+    let mut content = Vec::new();
+    let s = io::stdin();
+    let mut f: Box<Read> = match path {
+        "-" => Box::new(s.lock()),
+        _ => Box::new(File::open(path).chain_err(|| format!("Failed to open '{}' for reading", path))?),
+    };
+    f.read_to_end(&mut content)?;
+
+    let mut entries = Vec::new();
+    if content.starts_with(RECORDING_HEADER.as_bytes()) {
+        let recording = Recording::read_from(Cursor::new(content))
+            .chain_err(|| format!("Failed to read the recording at '{}'", path))?;
+        for frame in &recording.frames {
+            let (mut entry, _) = decoder.frame(&frame.bytes);
+            entry.insert("file".into(), path.into());
+            entry.insert("connection".into(), frame.connection.into());
+            entry.insert("elapsed".into(),
+                         format!("{}.{:06}", frame.elapsed.as_secs(), frame.elapsed.subsec_nanos() / 1000)
+                             .into());
+            entries.push(Value::Object(entry));
+        }
+        return Ok(entries);
+    }
+
+    let mut offset = 0;
+    while offset < content.len() {
+        let (mut entry, len) = decoder.frame(&content[offset..]);
+        entry.insert("file".into(), path.into());
+        entry.insert("offset".into(), offset.into());
+        entries.push(Value::Object(entry));
+        match len {
+            Some(len) => offset += len,
+            None => break,
+        }
+    }
+    Ok(entries)
+}
+
+pub fn decode(args: &clap::ArgMatches) -> Result<()> {
+    let compression = args.value_of("compression").map(|c| Compression::from_name(c).expect("clap to work"));
+    let mut decoder = Decoder {
+        compression: compression,
+        fixed: compression.is_some(),
+    };
+    let mut entries = Vec::new();
+    for path in args.values_of("file").expect("clap to work") {
+        entries.extend(decode_file(path, &mut decoder)?);
+    }
+
+    let entries = Value::Array(entries);
+    let s = io::stdout();
+    let mut lio = s.lock();
+    match args.value_of("output-format").expect("clap to work").parse().expect("clap to work") {
+        OutputFormat::json => ::serde_json::ser::to_writer_pretty(&mut lio, &entries)?,
+        OutputFormat::yaml => ::serde_yaml::to_writer(&mut lio, &entries)?,
+        format => bail!("Frames cannot be decoded as {}, only as json or yaml", format),
+    }
+    println!();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Decoder;
+
+    /// A v3 RESULT frame with the given body.
+    fn result_frame(body: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x83, 0, 0, 0, 0x08];
+        frame.extend(&[(body.len() >> 24) as u8, (body.len() >> 16) as u8, (body.len() >> 8) as u8, body.len() as u8]);
+        frame.extend(body);
+        frame
+    }
+
+    fn decoder() -> Decoder {
+        Decoder {
+            compression: None,
+            fixed: false,
+        }
+    }
+
+    fn assert_hex_dump(body: &[u8]) {
+        let frame = result_frame(body);
+        let (entry, len) = decoder().frame(&frame);
+        assert_eq!(len, Some(frame.len()));
+        assert!(entry.contains_key("error"), "expected an error in {:?}", entry);
+        assert!(entry["hex"].as_array().map_or(false, |lines| !lines.is_empty()));
+        assert!(!entry.contains_key("body"));
+    }
+
+    #[test]
+    fn oversized_counts_fall_back_to_a_hex_dump() {
+        // rows: no metadata, one column, 0x7fffffff rows
+        assert_hex_dump(&[0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0, 1, 0x7f, 0xff, 0xff, 0xff]);
+        // rows: global table spec, 0x7fffffff columns
+        assert_hex_dump(&[0, 0, 0, 2, 0, 0, 0, 1, 0x7f, 0xff, 0xff, 0xff, 0, 2, b'k', b's', 0, 1, b't']);
+    }
+
+    #[test]
+    fn oversized_element_counts_keep_the_raw_cells() {
+        // rows: global table spec, one list<int> column, one row with 0x7fffffff elements
+        let body = [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, b'k', b's', 0, 1, b't', 0, 1, b'l', 0, 0x20, 0,
+                    0x09, 0, 0, 0, 1, 0, 0, 0, 4, 0x7f, 0xff, 0xff, 0xff];
+        let (entry, _) = decoder().frame(&result_frame(&body));
+        let row = &entry["body"]["message"]["rows"][0];
+        assert!(row["error"].is_string(), "expected an error in {:?}", row);
+        assert_eq!(row["cells"][0], "0x7fffffff");
+    }
+}
@@ -0,0 +1,262 @@
+//! Writes rows as aligned tables, or as values separated by a delimiter.
+use super::super::errors::*;
+use super::value;
+use tokio_cassandra::codec::primitives::{CqlValue, ColumnType};
+use tokio_cassandra::codec::response::RowsMessage;
+use std::io::Write;
+
+/// The name and type of each column.
+type Columns<'a> = Vec<(&'a str, &'a ColumnType)>;
+
+/// Decodes all rows, along with the names and types of the columns.
+fn decode(rows: &RowsMessage) -> Result<(Columns, Vec<Vec<Option<CqlValue>>>)> {
+    let columns = rows.metadata
+        .column_specs
+        .iter()
+        .map(|c| (c.name.as_ref(), &c.column_type))
+        .collect();
+    let mut values = Vec::with_capacity(rows.rows.len());
+    for row in &rows.rows {
+        values.push(rows.decode_row(row)?);
+    }
+    Ok((columns, values))
+}
+
+/// Control characters are escaped to keep the table aligned, and cells longer than `max_width`
+/// characters are truncated, ending with an ellipsis if there is room for it.
+fn table_cell(s: &str, max_width: Option<usize>) -> String {
+    let s = s.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t");
+    match max_width {
+        Some(0) => String::new(),
+        Some(w) if s.chars().count() > w => {
+            let mut s: String = s.chars().take(w - 1).collect();
+            s.push('…');
+            s
+        }
+        _ => s,
+    }
+}
+
+fn pad(s: &str, width: usize, right_aligned: bool) -> String {
+    let fill = " ".repeat(width - s.chars().count());
+    if right_aligned {
+        format!(" {}{} ", fill, s)
+    } else {
+        format!(" {}{} ", s, fill)
+    }
+}
+
+/// Writes the cells as table in the style of cqlsh, preceded by an empty line. Cells of the given
+/// columns are right-aligned.
+pub fn grid<W: Write>(out: &mut W, header: &[String], right_aligned: &[bool], cells: &[Vec<String>]) -> Result<()> {
+    let widths: Vec<usize> = header.iter()
+        .enumerate()
+        .map(|(n, h)| {
+            cells.iter()
+                .map(|row| row[n].chars().count())
+                .chain(Some(h.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |row: &[String]| -> String {
+        row.iter()
+            .zip(widths.iter().zip(right_aligned.iter()))
+            .map(|(cell, (&w, &right))| pad(cell, w, right))
+            .collect::<Vec<_>>()
+            .join("|")
+    };
+
+    writeln!(out)?;
+    writeln!(out, "{}", line(header).trim_right())?;
+    writeln!(out,
+             "{}",
+             widths.iter().map(|w| "-".repeat(w + 2)).collect::<Vec<_>>().join("+"))?;
+    for row in cells {
+        writeln!(out, "{}", line(row).trim_right())?;
+    }
+    Ok(())
+}
+
+fn cells(columns: &Columns, values: &[Vec<Option<CqlValue>>], max_width: Option<usize>) -> Vec<Vec<String>> {
+    values.iter()
+        .map(|row| {
+            row.iter()
+                .zip(columns.iter())
+                .map(|(v, &(_, t))| match *v {
+                    Some(ref v) => table_cell(&value::display(v, t), max_width),
+                    None => "null".into(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Writes the rows as table in the style of cqlsh, preceded by an empty line.
+/// Null cells are rendered as `null`, and numbers are right-aligned.
+pub fn table<W: Write>(out: &mut W, rows: &RowsMessage, max_width: Option<usize>) -> Result<()> {
+    let (columns, values) = decode(rows)?;
+    let header: Vec<String> = columns.iter().map(|&(name, _)| table_cell(name, max_width)).collect();
+    let right_aligned: Vec<bool> = columns.iter().map(|&(_, t)| value::is_numeric(t)).collect();
+    grid(out, &header, &right_aligned, &cells(&columns, &values, max_width))
+}
+
+/// Writes each row as a table of its column names and values, as done by cqlsh in expanded mode.
+/// Rows are numbered starting at `first_row`.
+pub fn expanded<W: Write>(out: &mut W, rows: &RowsMessage, max_width: Option<usize>, first_row: usize) -> Result<()> {
+    let (columns, values) = decode(rows)?;
+    let names: Vec<String> = columns.iter().map(|&(name, _)| table_cell(name, max_width)).collect();
+    let name_width = names.iter().map(|n| n.chars().count()).max().unwrap_or(0);
+    for (n, row) in cells(&columns, &values, max_width).iter().enumerate() {
+        let value_width = row.iter().map(|c| c.chars().count()).max().unwrap_or(0);
+        writeln!(out)?;
+        writeln!(out, "@ Row {}", first_row + n)?;
+        writeln!(out, "{}+{}", "-".repeat(name_width + 2), "-".repeat(value_width + 2))?;
+        for (name, cell) in names.iter().zip(row.iter()) {
+            writeln!(out, "{}|{}", pad(name, name_width, false), pad(cell, value_width, false).trim_right())?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the amount of rows shown by the preceding tables.
+pub fn row_count<W: Write>(out: &mut W, count: usize) -> Result<()> {
+    writeln!(out)?;
+    writeln!(out, "({} rows)", count)?;
+    Ok(())
+}
+
+/// Quotes the field if it contains the delimiter, quotes or line breaks, or if it is empty
+/// to distinguish it from null.
+fn field(s: &str, delimiter: char) -> String {
+    if s.is_empty() || s.contains(&[delimiter, '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.into()
+    }
+}
+
+/// Writes a header line with the column names, followed by one line per row, whose fields
+/// are separated by `delimiter` and quoted as described in RFC 4180. Null cells are empty.
+pub fn delimited<W: Write>(out: &mut W, rows: &RowsMessage, delimiter: char) -> Result<()> {
+    let (columns, values) = decode(rows)?;
+    let separator = delimiter.to_string();
+    writeln!(out,
+             "{}",
+             columns.iter().map(|&(name, _)| field(name, delimiter)).collect::<Vec<_>>().join(&separator))?;
+    for row in &values {
+        writeln!(out,
+                 "{}",
+                 row.iter()
+                     .zip(columns.iter())
+                     .map(|(v, &(_, t))| match *v {
+                         Some(ref v) => field(&value::display(v, t), delimiter),
+                         None => String::new(),
+                     })
+                     .collect::<Vec<_>>()
+                     .join(&separator))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_cassandra::codec::response::ResultMessage;
+    use tokio_cassandra::testing::Reply;
+
+    fn rows(columns: &[(&str, ColumnType)], rows: Vec<Vec<Option<CqlValue>>>) -> RowsMessage {
+        match Reply::rows(columns, rows).unwrap() {
+            Reply::Result(ResultMessage::Rows(rows)) => rows,
+            _ => unreachable!(),
+        }
+    }
+
+    fn text(s: &str) -> Option<CqlValue> {
+        Some(CqlValue::Varchar(s.into()))
+    }
+
+    fn written<F: FnOnce(&mut Vec<u8>) -> Result<()>>(f: F) -> String {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn fields_are_quoted_if_needed() {
+        assert_eq!(field("plain", ','), "plain");
+        assert_eq!(field("a,b", ','), "\"a,b\"");
+        assert_eq!(field("a,b", '\t'), "a,b");
+        assert_eq!(field("a\tb", '\t'), "\"a\tb\"");
+        assert_eq!(field("say \"hi\"", ','), "\"say \"\"hi\"\"\"");
+        assert_eq!(field("two\nlines", ','), "\"two\nlines\"");
+        assert_eq!(field("cr\r", ','), "\"cr\r\"");
+        assert_eq!(field("", ','), "\"\"");
+    }
+
+    #[test]
+    fn delimited_rows() {
+        let rows = rows(&[("name", ColumnType::Varchar), ("note, quoted", ColumnType::Varchar)],
+                        vec![vec![text("alice"), text("likes \"tea\", and cake")],
+                             vec![text("bob"), text("")],
+                             vec![text("carol"), None],
+                             vec![text("dave"), text("line\nbreak")]]);
+        assert_eq!(written(|out| delimited(out, &rows, ',')),
+                   "name,\"note, quoted\"\n\
+                    alice,\"likes \"\"tea\"\", and cake\"\n\
+                    bob,\"\"\n\
+                    carol,\n\
+                    dave,\"line\nbreak\"\n");
+        assert_eq!(written(|out| delimited(out, &rows, '\t')),
+                   "name\tnote, quoted\n\
+                    alice\t\"likes \"\"tea\"\", and cake\"\n\
+                    bob\t\"\"\n\
+                    carol\t\n\
+                    dave\t\"line\nbreak\"\n");
+    }
+
+    #[test]
+    fn table_cells_are_escaped() {
+        assert_eq!(table_cell("a\nb\tc\rd\\e", None), "a\\nb\\tc\\rd\\\\e");
+    }
+
+    #[test]
+    fn table_cells_are_truncated() {
+        assert_eq!(table_cell("abcdef", None), "abcdef");
+        assert_eq!(table_cell("abcdef", Some(6)), "abcdef");
+        assert_eq!(table_cell("abcdef", Some(4)), "abc…");
+        assert_eq!(table_cell("äöüßéè", Some(3)), "äö…");
+        assert_eq!(table_cell("abcdef", Some(1)), "…");
+        assert_eq!(table_cell("a", Some(1)), "a");
+        assert_eq!(table_cell("abcdef", Some(0)), "");
+        assert_eq!(table_cell("", Some(0)), "");
+        // Escapes count towards the width.
+        assert_eq!(table_cell("a\nb", Some(3)), "a\\…");
+    }
+
+    #[test]
+    fn grid_aligns_by_characters() {
+        let header = vec!["name".to_string(), "n".to_string()];
+        let cells = vec![vec!["Jürgen".to_string(), "1".to_string()],
+                         vec!["€".to_string(), "1000".to_string()]];
+        assert_eq!(written(|out| grid(out, &header, &[false, true], &cells)),
+                   "\n name   |    n\n\
+                    --------+------\n \
+                    Jürgen |    1\n \
+                    €      | 1000\n");
+    }
+
+    #[test]
+    fn tables() {
+        let rows = rows(&[("name", ColumnType::Varchar), ("age", ColumnType::Int)],
+                        vec![vec![text("alice"), Some(CqlValue::Int(42))], vec![text("bob\nby"), None]]);
+        assert_eq!(written(|out| table(out, &rows, Some(5))),
+                   "\n name  |  age\n\
+                    -------+------\n \
+                    alice |   42\n \
+                    bob\\… | null\n");
+        assert_eq!(written(|out| expanded(out, &rows, None, 1)),
+                   "\n@ Row 1\n------+-------\n name | alice\n age  | 42\n\
+                    \n@ Row 2\n------+---------\n name | bob\\nby\n age  | null\n");
+    }
+}
@@ -1,5 +1,11 @@
 mod query;
+mod shell;
 mod testcon;
+mod decode;
+mod format;
+mod value;
 
 pub use self::testcon::*;
 pub use self::query::*;
+pub use self::decode::*;
+pub use self::shell::*;
@@ -1,17 +1,32 @@
 use clap;
 use super::super::args::ConnectionOptions;
 use super::super::errors::*;
-use tokio_cassandra::codec::primitives::{CqlFrom, CqlLongString};
-use tokio_cassandra::codec::header::Header;
+use super::value::{self, hex};
+use super::format;
+use tokio_cassandra::codec::primitives::{CqlFrom, CqlBytes, CqlLongString};
+use tokio_cassandra::codec::request::{self, QueryMessage, QueryParameters};
+use tokio_cassandra::codec::response::{ResultMessage, RowsMessage, PreparedMessage, SchemaChange,
+                                       SchemaChangeTarget, SchemaChangeType, TracingId};
+use tokio_cassandra::tokio::client::ClientHandle;
+use tokio_cassandra::tokio::error::Error as TokioCassandraError;
+use tokio_cassandra::tokio::messages::{Request, StreamingMessage};
+use tokio_core::reactor::Core;
+use serde_json::{Map, Value};
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
+
+/// The amount of rows to fetch per page. All pages of a result are fetched before it is printed.
+const PAGE_SIZE: i32 = 5000;
 
 arg_enum! {
     #[allow(non_camel_case_types)]
     #[derive(Debug)]
     pub enum OutputFormat {
         yaml,
-        json
+        json,
+        table,
+        csv,
+        tsv
     }
 }
 
@@ -88,6 +103,236 @@ impl Options {
     }
 }
 
+fn push_trimmed<'a>(statements: &mut Vec<&'a str>, s: &'a str) {
+    let s = s.trim();
+    if !s.is_empty() {
+        statements.push(s);
+    }
+}
+
+/// Splits a script into the statements terminated by semicolons which are neither quoted nor part
+/// of a comment, and the remainder after the last of them. Statements are trimmed, and empty ones
+/// are skipped.
+pub fn split_statements(script: &str) -> (Vec<&str>, &str) {
+    let bytes = script.as_bytes();
+    let mut statements = Vec::new();
+    let (mut start, mut i) = (0, 0);
+    while i < bytes.len() {
+        let rest = &script[i..];
+        let skip_to = |end: &str, from: usize| rest[from..].find(end).map(|p| p + from + end.len());
+        let skipped = match bytes[i] {
+            b';' => {
+                push_trimmed(&mut statements, &script[start..i]);
+                start = i + 1;
+                Some(1)
+            }
+            b'\'' | b'"' => {
+                let quote = bytes[i] as char;
+                // Quotes are escaped by doubling them, which is the same as two adjacent literals.
+                skip_to(&quote.to_string(), 1)
+            }
+            _ if rest.starts_with("$$") => skip_to("$$", 2),
+            _ if rest.starts_with("--") || rest.starts_with("//") => skip_to("\n", 2),
+            _ if rest.starts_with("/*") => skip_to("*/", 2),
+            _ => Some(rest.chars().next().map_or(1, char::len_utf8)),
+        };
+        match skipped {
+            Some(n) => i += n,
+            None => break,
+        }
+    }
+    (statements, &script[start..])
+}
+
+/// Splits a script into its statements, where the last one doesn't need to be terminated.
+pub fn statements(script: &str) -> Vec<&str> {
+    let (mut statements, rest) = split_statements(script);
+    push_trimmed(&mut statements, rest);
+    statements
+}
+
+/// Sends the statement as a single QUERY, and returns its result along with the id of the trace
+/// session if the request was traced.
+pub fn send(core: &mut Core,
+            client: &ClientHandle,
+            query: &CqlLongString<Vec<u8>>,
+            parameters: QueryParameters,
+            traced: bool)
+            -> Result<(ResultMessage, Option<TracingId>)> {
+    let mut req = Request::from(request::Message::Query(QueryMessage {
+        query: query.clone(),
+        parameters: parameters,
+    }));
+    if traced {
+        req = req.traced();
+    }
+    let res = core.run(client.send(req))?;
+    match res.message {
+        StreamingMessage::Result(msg) => Ok((msg, res.prelude.tracing_id)),
+        StreamingMessage::Error(msg) => bail!(TokioCassandraError::from(msg).to_string()),
+        msg => bail!("Unexpected response to QUERY: {:?}", msg),
+    }
+}
+
+/// The paging state to pass to the next QUERY to fetch the page after the given one, if there is one.
+pub fn next_page(rows: &RowsMessage) -> Result<Option<CqlBytes<Vec<u8>>>> {
+    Ok(match rows.metadata.paging_state {
+        Some(ref state) => Some(CqlBytes::try_from(state.as_bytes().unwrap_or(&[]).to_vec())?),
+        None => None,
+    })
+}
+
+/// Sends the statement as QUERY and fetches all pages of its result, each with up to `page_size` rows.
+fn execute(core: &mut Core, client: &ClientHandle, statement: &str, page_size: i32) -> Result<ResultMessage> {
+    // FIXME: provide a consuming version stat consumes a string directly into the vec
+    // and thus prevents an entirely unnecessary copy
+    let query = CqlLongString::<Vec<u8>>::try_from(statement)?;
+    let mut result: Option<RowsMessage> = None;
+    let mut paging_state = None;
+    loop {
+        let parameters = QueryParameters {
+            page_size: Some(page_size),
+            paging_state: paging_state.take(),
+            ..Default::default()
+        };
+        let page = match send(core, client, &query, parameters, false)?.0 {
+            ResultMessage::Rows(rows) => rows,
+            res => return Ok(res),
+        };
+        paging_state = next_page(&page)?;
+        result = Some(match result {
+            None => page,
+            Some(mut rows) => {
+                rows.rows.extend(page.rows);
+                rows.metadata.paging_state = page.metadata.paging_state;
+                rows
+            }
+        });
+        if paging_state.is_none() {
+            return Ok(ResultMessage::Rows(result.expect("at least one page")));
+        }
+    }
+}
+
+fn void_to_json(o: &mut Map<String, Value>) {
+    o.insert("kind".into(), "void".into());
+}
+
+/// Rows become objects from column name to value.
+fn rows_to_json(rows: &RowsMessage, o: &mut Map<String, Value>) -> Result<()> {
+    o.insert("kind".into(), "rows".into());
+    let mut values = Vec::with_capacity(rows.rows.len());
+    for row in &rows.rows {
+        let mut r = Map::new();
+        for (spec, cell) in rows.metadata.column_specs.iter().zip(rows.decode_row(row)?) {
+            r.insert(spec.name.as_ref().into(), value::option_to_json(&cell));
+        }
+        values.push(Value::Object(r));
+    }
+    o.insert("rows".into(), Value::Array(values));
+    Ok(())
+}
+
+fn set_keyspace_to_json(keyspace: &str, o: &mut Map<String, Value>) {
+    o.insert("kind".into(), "set_keyspace".into());
+    o.insert("keyspace".into(), keyspace.into());
+}
+
+fn prepared_to_json(p: &PreparedMessage, o: &mut Map<String, Value>) {
+    o.insert("kind".into(), "prepared".into());
+    o.insert("id".into(), p.id.as_bytes().map_or(Value::Null, |id| hex(id).into()));
+}
+
+fn schema_change_to_json(c: &SchemaChange, o: &mut Map<String, Value>) {
+    o.insert("kind".into(), "schema_change".into());
+    o.insert("change".into(),
+             match c.change_type {
+                     SchemaChangeType::Created => "created",
+                     SchemaChangeType::Updated => "updated",
+                     SchemaChangeType::Dropped => "dropped",
+                 }
+                 .into());
+    let (target, keyspace, name, arg_types) = match c.target {
+        SchemaChangeTarget::Keyspace(ref ks) => ("keyspace", ks, None, None),
+        SchemaChangeTarget::Table { ref keyspace, ref name } => ("table", keyspace, Some(name), None),
+        SchemaChangeTarget::Type { ref keyspace, ref name } => ("type", keyspace, Some(name), None),
+        SchemaChangeTarget::Function { ref keyspace, ref name, ref arg_types } => {
+            ("function", keyspace, Some(name), Some(arg_types))
+        }
+        SchemaChangeTarget::Aggregate { ref keyspace, ref name, ref arg_types } => {
+            ("aggregate", keyspace, Some(name), Some(arg_types))
+        }
+    };
+    o.insert("target".into(), target.into());
+    o.insert("keyspace".into(), keyspace.as_ref().into());
+    if let Some(name) = name {
+        o.insert("name".into(), name.as_ref().into());
+    }
+    if let Some(arg_types) = arg_types {
+        o.insert("arg_types".into(),
+                 Value::Array(arg_types.iter().map(|t| t.as_ref().into()).collect()));
+    }
+}
+
+/// The result of the given statement as it is serialized by the json and yaml output formats.
+fn result_to_json(statement: &str, res: &ResultMessage) -> Result<Value> {
+    let mut o = Map::new();
+    o.insert("statement".into(), statement.into());
+    match *res {
+        ResultMessage::Void => void_to_json(&mut o),
+        ResultMessage::Rows(ref rows) => rows_to_json(rows, &mut o)?,
+        ResultMessage::SetKeyspace(ref ks) => set_keyspace_to_json(ks.as_ref(), &mut o),
+        ResultMessage::Prepared(ref p) => prepared_to_json(p, &mut o),
+        ResultMessage::SchemaChange(ref c) => schema_change_to_json(c, &mut o),
+    }
+    Ok(Value::Object(o))
+}
+
+/// Writes the results of the statements in the given format. Tables and delimited values only
+/// contain rows, just like cqlsh prints them.
+fn write_results<W: Write>(out: &mut W,
+                           format: &OutputFormat,
+                           results: &[(&str, ResultMessage)],
+                           max_width: Option<usize>)
+                           -> Result<()> {
+    match *format {
+        OutputFormat::json | OutputFormat::yaml => {
+            let mut values = Vec::with_capacity(results.len());
+            for &(statement, ref res) in results {
+                values.push(result_to_json(statement, res)?);
+            }
+            let values = Value::Array(values);
+            match *format {
+                OutputFormat::json => ::serde_json::ser::to_writer_pretty(&mut *out, &values)?,
+                _ => ::serde_yaml::to_writer(&mut *out, &values)?,
+            }
+            writeln!(out)?;
+        }
+        OutputFormat::table | OutputFormat::csv | OutputFormat::tsv => {
+            // Sets of delimited rows are separated by an empty line.
+            let mut first = true;
+            for &(_, ref res) in results {
+                if let ResultMessage::Rows(ref rows) = *res {
+                    match *format {
+                        OutputFormat::table => {
+                            format::table(out, rows, max_width)?;
+                            format::row_count(out, rows.rows.len())?;
+                        }
+                        _ => {
+                            if !first {
+                                writeln!(out)?;
+                            }
+                            format::delimited(out, rows, if let OutputFormat::csv = *format { ',' } else { '\t' })?
+                        }
+                    }
+                    first = false;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn query(opts: ConnectionOptions, args: &clap::ArgMatches) -> Result<()> {
     let addr = format!("{}:{}", opts.host, opts.port);
     let query = Options::try_from(args)?.try_into_query_string()?;
@@ -98,32 +343,185 @@ pub fn query(opts: ConnectionOptions, args: &clap::ArgMatches) -> Result<()> {
     }
 
     let (mut core, client) = opts.connect();
-    core.run(client)
-        .chain_err(|| format!("Failed to connect to {}", addr))
-        .and_then(|_client| {
-            // FIXME: provide a consuming version stat consumes a string directly into the vec
-            // and thus prevents an entirely unnecessary copy
-            let _query = CqlLongString::<Vec<u8>>::try_from(&query)?;
-
-            #[derive(Deserialize, Serialize)]
-            struct Demo {
-                result_example: Header,
-                description: String,
-            }
-            let demo = Demo {
-                result_example: Header::try_from(b"\x03\x02\x00\x00\x05\x00\x00\x00\x00").unwrap(),
-                description: "I believe we need to implement the serde-traits manually on our response types to \
-                              implement it in a controlled fashion without extra copies."
-                    .into(),
-            };
-            let s = io::stdout();
-            let mut lio = s.lock();
-            match args.value_of("output-format").expect("clap to work").parse().expect("clap to work") {
-                OutputFormat::json => ::serde_json::ser::to_writer_pretty(&mut lio, &demo)?,
-                OutputFormat::yaml => ::serde_yaml::to_writer(&mut lio, &demo)?,
-            }
-            println!();
-            Ok(())
-        })
-        .map_err(|e| e.into())
+    let client = core.run(client).chain_err(|| format!("Failed to connect to {}", addr))?;
+
+    let mut results = Vec::new();
+    for statement in statements(&query) {
+        let res = execute(&mut core, &client, statement, PAGE_SIZE)
+            .chain_err(|| format!("Failed to execute '{}'", statement))?;
+        results.push((statement, res));
+    }
+
+    let max_width = match args.value_of("max-column-width") {
+        Some(w) => Some(w.parse().chain_err(|| format!("Invalid column width: '{}'", w))?),
+        None => None,
+    };
+    let format = args.value_of("output-format").expect("clap to work").parse().expect("clap to work");
+    let s = io::stdout();
+    let mut lio = s.lock();
+    write_results(&mut lio, &format, &results, max_width)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_cassandra::codec::request::PrepareMessage;
+    use tokio_cassandra::testing::{self, MockServer, MockOptions};
+    use tokio_cassandra::tokio::client::{Client, ConnectOptions, CqlProto};
+    use tokio_service::Service;
+
+    fn connect(server: &MockServer) -> (Core, ClientHandle) {
+        let mut core = Core::new().unwrap();
+        let client = Client { protocol: CqlProto::default() };
+        let client = core.run(client.connect(&server.addr(), &core.handle(), ConnectOptions::default())).unwrap();
+        (core, client)
+    }
+
+    /// A server with the keyspace `ks` and the table `ks.users`, which holds five users.
+    fn server_with_users() -> (MockServer, Core, ClientHandle) {
+        let server = MockServer::start(MockOptions { engine: true, ..Default::default() }).unwrap();
+        let (mut core, client) = connect(&server);
+        for statement in statements("CREATE KEYSPACE ks WITH replication = {'class': 'SimpleStrategy', \
+                                     'replication_factor': 1};
+                                     CREATE TABLE ks.users (name text PRIMARY KEY, age int);
+                                     INSERT INTO ks.users (name, age) VALUES ('alice', 42);
+                                     INSERT INTO ks.users (name, age) VALUES ('bob', 23);
+                                     INSERT INTO ks.users (name, age) VALUES ('carol', 31);
+                                     INSERT INTO ks.users (name) VALUES ('dave');
+                                     INSERT INTO ks.users (name, age) VALUES ('eve', 7)") {
+            execute(&mut core, &client, statement, PAGE_SIZE).unwrap();
+        }
+        server.take_requests();
+        (server, core, client)
+    }
+
+    fn json(statement: &str, res: &ResultMessage) -> Value {
+        result_to_json(statement, res).unwrap()
+    }
+
+    fn output(format: OutputFormat, results: &[(&str, ResultMessage)], max_width: Option<usize>) -> String {
+        let mut out = Vec::new();
+        write_results(&mut out, &format, results, max_width).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn void() {
+        let mut o = Map::new();
+        void_to_json(&mut o);
+        assert_eq!(Value::Object(o)["kind"], "void");
+
+        let (_server, mut core, client) = server_with_users();
+        let res = execute(&mut core, &client, "INSERT INTO ks.users (name) VALUES ('frank')", PAGE_SIZE).unwrap();
+        let v = json("INSERT", &res);
+        assert_eq!(v["kind"], "void");
+        assert_eq!(v["statement"], "INSERT");
+        assert_eq!(output(OutputFormat::table, &[("INSERT", res)], None), "");
+    }
+
+    #[test]
+    fn rows() {
+        let (_server, mut core, client) = server_with_users();
+        let res = execute(&mut core, &client, "SELECT name, age FROM ks.users WHERE name = 'dave'", PAGE_SIZE)
+            .unwrap();
+        let v = json("SELECT", &res);
+        assert_eq!(v["kind"], "rows");
+        assert_eq!(v["rows"][0]["name"], "dave");
+        assert_eq!(v["rows"][0]["age"], Value::Null);
+
+        let results = [("SELECT", res)];
+        assert_eq!(output(OutputFormat::table, &results, None),
+                   "\n name |  age\n------+------\n dave | null\n\n(1 rows)\n");
+        assert_eq!(output(OutputFormat::csv, &results, None), "name,age\ndave,\n");
+        assert_eq!(output(OutputFormat::tsv, &results, None), "name\tage\ndave\t\n");
+        let json: Value = ::serde_json::from_str(&output(OutputFormat::json, &results, None)).unwrap();
+        assert_eq!(json[0]["rows"][0]["name"], "dave");
+    }
+
+    #[test]
+    fn delimited_results_are_separated_by_an_empty_line() {
+        let (_server, mut core, client) = server_with_users();
+        let select = |core: &mut Core, name: &str| {
+            execute(core,
+                    &client,
+                    &format!("SELECT name FROM ks.users WHERE name = '{}'", name),
+                    PAGE_SIZE)
+                .unwrap()
+        };
+        let results = [("a", select(&mut core, "alice")),
+                       ("u", ResultMessage::Void),
+                       ("b", select(&mut core, "bob"))];
+        assert_eq!(output(OutputFormat::csv, &results, None), "name\nalice\n\nname\nbob\n");
+    }
+
+    #[test]
+    fn set_keyspace() {
+        let (_server, mut core, client) = server_with_users();
+        let res = execute(&mut core, &client, "USE ks", PAGE_SIZE).unwrap();
+        let v = json("USE ks", &res);
+        assert_eq!(v["kind"], "set_keyspace");
+        assert_eq!(v["keyspace"], "ks");
+    }
+
+    #[test]
+    fn prepared() {
+        let (_server, mut core, client) = server_with_users();
+        let prepare = request::Message::Prepare(PrepareMessage {
+            query: CqlLongString::try_from("SELECT name FROM ks.users").unwrap(),
+        });
+        let res = match core.run(client.call(prepare)).unwrap() {
+            StreamingMessage::Result(res) => res,
+            res => panic!("Unexpected response {:?}", res),
+        };
+        let v = json("PREPARE", &res);
+        assert_eq!(v["kind"], "prepared");
+        assert_eq!(v["id"], hex(&testing::prepared_id("SELECT name FROM ks.users")));
+    }
+
+    #[test]
+    fn schema_change() {
+        let server = MockServer::start(MockOptions { engine: true, ..Default::default() }).unwrap();
+        let (mut core, client) = connect(&server);
+        let res = execute(&mut core,
+                          &client,
+                          "CREATE KEYSPACE ks WITH replication = {'class': 'SimpleStrategy', \
+                           'replication_factor': 1}",
+                          PAGE_SIZE)
+            .unwrap();
+        let v = json("CREATE KEYSPACE", &res);
+        assert_eq!(v["kind"], "schema_change");
+        assert_eq!(v["change"], "created");
+        assert_eq!(v["target"], "keyspace");
+        assert_eq!(v["keyspace"], "ks");
+        assert_eq!(v.get("name"), None);
+
+        let res = execute(&mut core, &client, "CREATE TABLE ks.t (k int PRIMARY KEY)", PAGE_SIZE).unwrap();
+        let v = json("CREATE TABLE", &res);
+        assert_eq!(v["target"], "table");
+        assert_eq!(v["name"], "t");
+    }
+
+    #[test]
+    fn execute_fetches_all_pages() {
+        let (server, mut core, client) = server_with_users();
+        let rows = match execute(&mut core, &client, "SELECT name FROM ks.users", 2).unwrap() {
+            ResultMessage::Rows(rows) => rows,
+            res => panic!("Expected rows, got {:?}", res),
+        };
+        assert_eq!(rows.rows.len(), 5);
+        assert_eq!(rows.metadata.paging_state, None);
+        let mut names: Vec<_> = rows.rows
+            .iter()
+            .map(|r| value::option_to_json(&rows.decode_row(r).unwrap()[0]))
+            .collect();
+        names.sort_by_key(|n| n.to_string());
+        assert_eq!(names, vec!["alice", "bob", "carol", "dave", "eve"]);
+        // Three pages of at most two rows each.
+        assert_eq!(server.queries(), vec!["SELECT name FROM ks.users"; 3]);
+
+        match execute(&mut core, &client, "SELECT name FROM ks.missing", 2) {
+            Err(e) => assert!(e.to_string().contains("missing"), "{}", e),
+            Ok(res) => panic!("Expected an error, got {:?}", res),
+        }
+    }
 }
@@ -0,0 +1,471 @@
+//! An interactive shell in the style of cqlsh.
+use clap;
+use super::super::args::{ConnectionOptions, CliProtoVersion};
+use super::super::errors::*;
+use super::{format, query, value};
+use tokio_cassandra::codec::primitives::{CqlConsistency, CqlFrom, CqlLongString};
+use tokio_cassandra::codec::request::QueryParameters;
+use tokio_cassandra::codec::response::{ResultMessage, TracingId};
+use tokio_cassandra::tokio::client::ClientHandle;
+use tokio_core::reactor::Core;
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
+use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// The page size used by `PAGING ON`, which is the default of cqlsh.
+const DEFAULT_PAGE_SIZE: i32 = 100;
+/// How often to ask for the trace session until it is complete, waiting a moment in between.
+const TRACE_ATTEMPTS: usize = 10;
+const TRACE_RETRY_MILLIS: u64 = 200;
+
+const CONSISTENCIES: &[(&str, CqlConsistency)] = &[("ANY", CqlConsistency::Any),
+                                                     ("ONE", CqlConsistency::One),
+                                                     ("TWO", CqlConsistency::Two),
+                                                     ("THREE", CqlConsistency::Three),
+                                                     ("QUORUM", CqlConsistency::Quorum),
+                                                     ("ALL", CqlConsistency::All),
+                                                     ("LOCAL_QUORUM", CqlConsistency::LocalQuorum),
+                                                     ("EACH_QUORUM", CqlConsistency::EachQuorum),
+                                                     ("SERIAL", CqlConsistency::Serial),
+                                                     ("LOCAL_SERIAL", CqlConsistency::LocalSerial),
+                                                     ("LOCAL_ONE", CqlConsistency::LocalOne)];
+
+const HELP: &str = "CQL statements end with a semicolon, and may span multiple lines.
+Shell commands don't need a semicolon:
+
+  CONSISTENCY [<level>]    Shows or sets the consistency level of all statements
+  PAGING [ON|OFF|<size>]   Shows results page by page, or all at once
+  TRACING [ON|OFF]         Traces all statements and shows their trace sessions
+  EXPAND [ON|OFF]          Shows each row as list of columns and values
+  HELP                     Shows this help
+  EXIT, QUIT               Leaves the shell, just like Ctrl-D
+
+Ctrl-C discards the statement typed so far.";
+
+fn consistency_name(c: CqlConsistency) -> &'static str {
+    CONSISTENCIES.iter().find(|&&(_, v)| v == c).map(|&(name, _)| name).expect("all levels to be named")
+}
+
+fn on_off(v: bool) -> &'static str {
+    if v { "ON" } else { "OFF" }
+}
+
+/// Parses the argument of a command which switches something on or off.
+fn switch(command: &str, arg: &str) -> Result<bool> {
+    match arg.to_uppercase().as_str() {
+        "ON" => Ok(true),
+        "OFF" => Ok(false),
+        _ => bail!("{} must be followed by ON or OFF, got '{}'", command, arg),
+    }
+}
+
+fn default_history_file() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".tcc_history"))
+}
+
+fn print_error(e: &Error) {
+    let stderr = io::stderr();
+    let mut err = stderr.lock();
+    writeln!(err, "Error: {}", e).ok();
+    for cause in e.iter().skip(1) {
+        writeln!(err, "caused by: {}", cause).ok();
+    }
+}
+
+/// The settings changed by shell commands, which apply to all statements of the session.
+struct Settings {
+    consistency: CqlConsistency,
+    /// If set, results are fetched and shown page by page, asking before fetching the next one.
+    page_size: Option<i32>,
+    tracing: bool,
+    expand: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            consistency: CqlConsistency::One,
+            page_size: Some(DEFAULT_PAGE_SIZE),
+            tracing: false,
+            expand: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Executes the line if it is a shell command, and returns the message to show in that case.
+    fn command(&mut self, line: &str) -> Result<Option<String>> {
+        let words: Vec<&str> = line.trim().trim_right_matches(';').split_whitespace().collect();
+        let command = match words.first() {
+            Some(w) => w.to_uppercase(),
+            None => return Ok(None),
+        };
+        let arg = match words.len() {
+            1 => None,
+            2 => Some(words[1]),
+            _ if ["CONSISTENCY", "PAGING", "TRACING", "EXPAND"].contains(&command.as_str()) => {
+                bail!("{} takes at most one argument", command)
+            }
+            _ => return Ok(None),
+        };
+        let message = match (command.as_str(), arg) {
+            ("CONSISTENCY", None) => {
+                format!("Current consistency level is {}.", consistency_name(self.consistency))
+            }
+            ("CONSISTENCY", Some(level)) => {
+                let level = level.to_uppercase();
+                self.consistency = match CONSISTENCIES.iter().find(|&&(name, _)| name == level) {
+                    Some(&(_, c)) => c,
+                    None => {
+                        bail!("Unknown consistency level '{}', choose one of {}",
+                              level,
+                              CONSISTENCIES.iter().map(|&(name, _)| name).collect::<Vec<_>>().join(", "))
+                    }
+                };
+                format!("Consistency level set to {}.", level)
+            }
+            ("PAGING", None) => {
+                match self.page_size {
+                    Some(size) => format!("Query paging is currently enabled with a page size of {}.", size),
+                    None => "Query paging is currently disabled.".into(),
+                }
+            }
+            ("PAGING", Some(arg)) => {
+                self.page_size = match arg.parse::<i32>() {
+                    Ok(size) if size > 0 => Some(size),
+                    Ok(_) => bail!("The page size must be positive"),
+                    Err(_) => if switch("PAGING", arg)? { Some(DEFAULT_PAGE_SIZE) } else { None },
+                };
+                return self.command("PAGING");
+            }
+            ("TRACING", None) => format!("Tracing is {}.", on_off(self.tracing)),
+            ("TRACING", Some(arg)) => {
+                self.tracing = switch("TRACING", arg)?;
+                format!("Tracing is now {}.", on_off(self.tracing))
+            }
+            ("EXPAND", None) => format!("Expanded output is {}.", on_off(self.expand)),
+            ("EXPAND", Some(arg)) => {
+                self.expand = switch("EXPAND", arg)?;
+                format!("Expanded output is now {}.", on_off(self.expand))
+            }
+            ("HELP", None) => HELP.into(),
+            _ => return Ok(None),
+        };
+        Ok(Some(message))
+    }
+}
+
+/// Appends the line to the statement typed so far, and returns all statements it completes.
+/// The buffer keeps what follows the last semicolon, unless that is just whitespace.
+fn push_line(buffer: &mut String, line: &str) -> Vec<String> {
+    buffer.push_str(line);
+    buffer.push('\n');
+    let (statements, rest) = {
+        let (statements, rest) = query::split_statements(buffer);
+        let rest = if rest.trim().is_empty() { String::new() } else { rest.to_string() };
+        (statements.into_iter().map(String::from).collect(), rest)
+    };
+    *buffer = rest;
+    statements
+}
+
+/// The connection along with the settings changed by shell commands.
+struct Session {
+    core: Core,
+    client: ClientHandle,
+    /// The keyspace of the last USE statement, shown in the prompt.
+    keyspace: Option<String>,
+    settings: Settings,
+    max_width: Option<usize>,
+}
+
+impl Session {
+    fn prompt(&self) -> String {
+        match self.keyspace {
+            Some(ref ks) => format!("tcc:{}> ", ks),
+            None => "tcc> ".into(),
+        }
+    }
+
+    /// Executes the statement and shows its result. If paging is enabled, the user is asked before
+    /// each page is fetched, and may stop at any time.
+    fn execute(&mut self, editor: &mut Editor<()>, statement: &str) -> Result<()> {
+        let query = CqlLongString::<Vec<u8>>::try_from(statement)?;
+        let mut paging_state = None;
+        let mut shown = None;
+        let mut trace = None;
+        let s = io::stdout();
+        loop {
+            let parameters = QueryParameters {
+                consistency: self.settings.consistency,
+                page_size: self.settings.page_size,
+                paging_state: paging_state.take(),
+                ..Default::default()
+            };
+            let (res, tracing_id) = query::send(&mut self.core, &self.client, &query, parameters, self.settings.tracing)?;
+            trace = trace.or(tracing_id);
+            let rows = match res {
+                ResultMessage::Rows(rows) => rows,
+                ResultMessage::SetKeyspace(ks) => {
+                    self.keyspace = Some(ks.as_ref().into());
+                    break;
+                }
+                _ => break,
+            };
+            let count = shown.unwrap_or(0);
+            {
+                let mut lio = s.lock();
+                if self.settings.expand {
+                    format::expanded(&mut lio, &rows, self.max_width, count + 1)?;
+                } else {
+                    format::table(&mut lio, &rows, self.max_width)?;
+                }
+            }
+            shown = Some(count + rows.rows.len());
+            paging_state = query::next_page(&rows)?;
+            if paging_state.is_none() {
+                break;
+            }
+            match editor.readline("---MORE--- (Enter for the next page, q to stop) ") {
+                Ok(ref line) if line.trim().is_empty() => {}
+                _ => break,
+            }
+        }
+        if let Some(count) = shown {
+            format::row_count(&mut s.lock(), count)?;
+        }
+        if let Some(id) = trace {
+            self.show_trace(id)?;
+        }
+        Ok(())
+    }
+
+    /// Shows the events of the trace session. As the coordinator writes them after it responded,
+    /// the session is fetched again until it is complete, up to a few times.
+    fn show_trace(&mut self, id: TracingId) -> Result<()> {
+        let mut session = None;
+        for attempt in 0..TRACE_ATTEMPTS {
+            if attempt > 0 {
+                thread::sleep(Duration::from_millis(TRACE_RETRY_MILLIS));
+            }
+            session = self.core
+                .run(self.client.trace_session(id))
+                .chain_err(|| "Failed to fetch the trace session")?;
+            if session.as_ref().map_or(false, |s| s.duration.is_some()) {
+                break;
+            }
+        }
+
+        println!();
+        println!("Tracing session: {}", value::uuid(&id));
+        let session = match session {
+            Some(session) => session,
+            None => {
+                println!();
+                println!("The trace session was not written by the coordinator yet.");
+                return Ok(());
+            }
+        };
+        let header: Vec<String> = ["activity", "source", "source_elapsed", "thread"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        let cells: Vec<Vec<String>> = session.events
+            .iter()
+            .map(|e| {
+                vec![e.activity.clone().unwrap_or_default(),
+                     e.source.map(|s| s.to_string()).unwrap_or_default(),
+                     e.source_elapsed.map(|s| s.to_string()).unwrap_or_default(),
+                     e.thread.clone().unwrap_or_default()]
+            })
+            .collect();
+        format::grid(&mut io::stdout(), &header, &[false, false, true, false], &cells)?;
+        println!();
+        match session.duration {
+            Some(micros) => println!("Request complete after {} microseconds.", micros),
+            None => println!("The request was still in progress when the session was fetched."),
+        }
+        Ok(())
+    }
+}
+
+pub fn shell(opts: ConnectionOptions, args: &clap::ArgMatches) -> Result<()> {
+    let addr = format!("{}:{}", opts.host, opts.port);
+    let (mut core, client) = opts.connect();
+    let client = core.run(client).chain_err(|| format!("Failed to connect to {}", addr))?;
+    println!("Connected to {}, using protocol {}.",
+             addr,
+             CliProtoVersion::from(client.protocol_version()));
+    println!("Use HELP for help.");
+
+    let mut session = Session {
+        core: core,
+        client: client,
+        keyspace: None,
+        settings: Settings::default(),
+        max_width: match args.value_of("max-column-width") {
+            Some(w) => Some(w.parse().chain_err(|| format!("Invalid column width: '{}'", w))?),
+            None => None,
+        },
+    };
+    let history = args.value_of("history-file").map(PathBuf::from).or_else(default_history_file);
+    let mut editor = Editor::<()>::new();
+    if let Some(ref history) = history {
+        // There is no history before the first session ends.
+        editor.load_history(history).ok();
+    }
+    if let Some(ks) = args.value_of("keyspace") {
+        session.execute(&mut editor, &format!("USE {}", ks))?;
+    }
+
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() {
+            session.prompt()
+        } else {
+            " ".repeat(session.prompt().len() - 4) + "... "
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e).chain_err(|| "Failed to read from the terminal"),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str());
+
+        if buffer.is_empty() {
+            match line.trim().trim_right_matches(';').to_uppercase().as_str() {
+                "EXIT" | "QUIT" => break,
+                _ => {}
+            }
+            match session.settings.command(&line) {
+                Ok(Some(message)) => {
+                    println!("{}", message);
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    print_error(&e);
+                    continue;
+                }
+            }
+        }
+
+        for statement in push_line(&mut buffer, &line) {
+            if let Err(e) = session.execute(&mut editor, &statement) {
+                print_error(&e);
+            }
+        }
+    }
+
+    if let Some(ref history) = history {
+        editor.save_history(history)
+            .chain_err(|| format!("Failed to save the history to '{}'", history.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(settings: &mut Settings, line: &str) -> String {
+        settings.command(line).unwrap().expect("a shell command")
+    }
+
+    #[test]
+    fn consistency() {
+        let mut s = Settings::default();
+        assert_eq!(message(&mut s, "CONSISTENCY"), "Current consistency level is ONE.");
+        assert_eq!(message(&mut s, "consistency local_quorum;"), "Consistency level set to LOCAL_QUORUM.");
+        assert_eq!(s.consistency, CqlConsistency::LocalQuorum);
+        assert_eq!(message(&mut s, "CONSISTENCY"), "Current consistency level is LOCAL_QUORUM.");
+
+        let err = s.command("CONSISTENCY SOME").unwrap_err().to_string();
+        assert!(err.starts_with("Unknown consistency level 'SOME', choose one of ANY, ONE,"), "{}", err);
+        assert_eq!(s.consistency, CqlConsistency::LocalQuorum);
+        assert!(s.command("CONSISTENCY ONE TWO").is_err());
+    }
+
+    #[test]
+    fn paging() {
+        let mut s = Settings::default();
+        assert_eq!(message(&mut s, "PAGING"),
+                   "Query paging is currently enabled with a page size of 100.");
+        assert_eq!(message(&mut s, "PAGING OFF"), "Query paging is currently disabled.");
+        assert_eq!(s.page_size, None);
+        assert_eq!(message(&mut s, "paging on"),
+                   "Query paging is currently enabled with a page size of 100.");
+        assert_eq!(message(&mut s, "PAGING 20;"),
+                   "Query paging is currently enabled with a page size of 20.");
+        assert_eq!(s.page_size, Some(20));
+
+        assert!(s.command("PAGING 0").is_err());
+        assert!(s.command("PAGING -5").is_err());
+        assert!(s.command("PAGING MAYBE").is_err());
+        assert_eq!(s.page_size, Some(20));
+    }
+
+    #[test]
+    fn tracing_and_expand() {
+        let mut s = Settings::default();
+        assert_eq!(message(&mut s, "TRACING"), "Tracing is OFF.");
+        assert_eq!(message(&mut s, "TRACING ON"), "Tracing is now ON.");
+        assert!(s.tracing);
+        assert_eq!(message(&mut s, "tracing off;"), "Tracing is now OFF.");
+        assert!(!s.tracing);
+        assert!(s.command("TRACING YES").is_err());
+
+        assert_eq!(message(&mut s, "EXPAND"), "Expanded output is OFF.");
+        assert_eq!(message(&mut s, "Expand On"), "Expanded output is now ON.");
+        assert!(s.expand);
+        assert!(s.command("EXPAND 1").is_err());
+        assert!(s.expand);
+    }
+
+    #[test]
+    fn help_and_statements() {
+        let mut s = Settings::default();
+        assert_eq!(message(&mut s, "HELP"), HELP);
+        assert_eq!(s.command("").unwrap(), None);
+        assert_eq!(s.command("SELECT * FROM ks.users;").unwrap(), None);
+        // Statements may start with words which are commands otherwise.
+        assert_eq!(s.command("HELP me please").unwrap(), None);
+    }
+
+    #[test]
+    fn statements_span_lines_until_a_semicolon() {
+        let mut buffer = String::new();
+        assert!(push_line(&mut buffer, "SELECT *").is_empty());
+        assert!(push_line(&mut buffer, "  FROM ks.users").is_empty());
+        assert_eq!(push_line(&mut buffer, "  WHERE id = 1;"),
+                   vec!["SELECT *\n  FROM ks.users\n  WHERE id = 1"]);
+        assert_eq!(buffer, "");
+
+        assert_eq!(push_line(&mut buffer, "USE ks; SELECT a"), vec!["USE ks"]);
+        assert_eq!(buffer, " SELECT a\n");
+        assert_eq!(push_line(&mut buffer, "FROM t;  "), vec!["SELECT a\nFROM t"]);
+        assert_eq!(buffer, "");
+    }
+
+    #[test]
+    fn semicolons_in_literals_do_not_end_statements() {
+        let mut buffer = String::new();
+        assert!(push_line(&mut buffer, "INSERT INTO t (a) VALUES ('x;").is_empty());
+        assert_eq!(push_line(&mut buffer, "y');"), vec!["INSERT INTO t (a) VALUES ('x;\ny')"]);
+        assert_eq!(buffer, "");
+
+        assert_eq!(push_line(&mut buffer, "INSERT INTO t (a) VALUES ('it''s; fine');"),
+                   vec!["INSERT INTO t (a) VALUES ('it''s; fine')"]);
+    }
+}
@@ -1,5 +1,5 @@
 use clap;
-use super::super::args::ConnectionOptions;
+use super::super::args::{ConnectionOptions, CliProtoVersion};
 use super::super::errors::*;
 
 pub fn test_connection(opts: ConnectionOptions, _args: &clap::ArgMatches) -> Result<()> {
@@ -7,8 +7,10 @@ pub fn test_connection(opts: ConnectionOptions, _args: &clap::ArgMatches) -> Res
     let (mut core, client) = opts.connect();
     core.run(client)
         .chain_err(|| format!("Failed to connect to {}", addr))
-        .map(|_response| {
-            println!("Connection to {} successful", addr);
+        .map(|handle| {
+            println!("Connection to {} successful, using protocol {}",
+                     addr,
+                     CliProtoVersion::from(handle.protocol_version()));
             ()
         })
         .map_err(|e| e.into())
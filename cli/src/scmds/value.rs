@@ -0,0 +1,396 @@
+//! Renders the values of CQL columns for the output formats.
+use tokio_cassandra::codec::primitives::{CqlValue, ColumnType};
+use serde_json::{self, Value};
+
+pub fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(2 + bytes.len() * 2);
+    s.push_str("0x");
+    for b in bytes {
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
+
+pub fn uuid(u: &[u8; 16]) -> String {
+    let h = hex(&u[..]);
+    format!("{}-{}-{}-{}-{}", &h[2..10], &h[10..14], &h[14..18], &h[18..22], &h[22..])
+}
+
+/// The decimal representation of a big-endian two's complement integer of arbitrary length.
+pub fn varint(bytes: &[u8]) -> String {
+    let negative = bytes.first().map(|b| b & 0x80 == 0x80) == Some(true);
+    let mut magnitude = bytes.to_vec();
+    if negative {
+        for b in magnitude.iter_mut() {
+            *b = !*b;
+        }
+        for b in magnitude.iter_mut().rev() {
+            let (sum, overflow) = b.overflowing_add(1);
+            *b = sum;
+            if !overflow {
+                break;
+            }
+        }
+    }
+    let mut digits = Vec::new();
+    while magnitude.iter().any(|b| *b != 0) {
+        let mut remainder = 0u32;
+        for b in magnitude.iter_mut() {
+            let current = remainder << 8 | u32::from(*b);
+            *b = (current / 10) as u8;
+            remainder = current % 10;
+        }
+        digits.push(b'0' + remainder as u8);
+    }
+    if digits.is_empty() {
+        digits.push(b'0');
+    }
+    if negative {
+        digits.push(b'-');
+    }
+    digits.reverse();
+    String::from_utf8(digits).expect("ascii digits")
+}
+
+/// How many zeros more than it has digits a decimal may be padded with, before it is rendered in
+/// scientific notation instead. The scale is read from the wire, and may be as large as 2^31.
+const MAX_DECIMAL_PADDING: usize = 20;
+
+pub fn decimal(scale: i32, unscaled: &[u8]) -> String {
+    let unscaled = varint(unscaled);
+    let (sign, digits) = unscaled.split_at(if unscaled.starts_with('-') { 1 } else { 0 });
+    if digits == "0" && scale <= 0 {
+        return unscaled;
+    }
+    let exponent = -(scale as i64);
+    if exponent.abs() as usize > digits.len() + MAX_DECIMAL_PADDING {
+        return format!("{}E{}", unscaled, exponent);
+    }
+    if scale <= 0 {
+        return format!("{}{}{}", sign, digits, "0".repeat(exponent as usize));
+    }
+    let scale = scale as usize;
+    let digits = if digits.len() <= scale {
+        format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits)
+    } else {
+        digits.to_string()
+    };
+    let point = digits.len() - scale;
+    format!("{}{}.{}", sign, &digits[..point], &digits[point..])
+}
+
+/// The year, month and day of the given amount of days since the unix epoch.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+fn floor_div(a: i64, b: i64) -> (i64, i64) {
+    let (d, r) = (a / b, a % b);
+    if r < 0 { (d - 1, r + b) } else { (d, r) }
+}
+
+/// Formats days since `-5877641-06-23` as done by cqlsh, like `2017-03-21`.
+pub fn date(days: u32) -> String {
+    let (year, month, day) = civil_from_days(i64::from(days) - (1 << 31));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Formats nanoseconds since midnight, like `13:30:54.234000000`.
+pub fn time(nanos: i64) -> String {
+    let secs = nanos / 1_000_000_000;
+    format!("{:02}:{:02}:{:02}.{:09}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            nanos % 1_000_000_000)
+}
+
+/// Formats milliseconds since the unix epoch as done by cqlsh, like `2017-03-21 13:30:54.234000+0000`.
+pub fn timestamp(millis: i64) -> String {
+    let (days, millis) = floor_div(millis, 86_400_000);
+    let (year, month, day) = civil_from_days(days);
+    let secs = millis / 1000;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}+0000",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            millis % 1000 * 1000)
+}
+
+/// Converts a value to JSON. Numbers which JSON cannot represent exactly, as well as dates and
+/// times, become strings. Maps with keys other than strings become lists of key-value pairs.
+pub fn to_json(v: &CqlValue) -> Value {
+    use self::CqlValue::*;
+    match *v {
+        Custom(ref b) | Blob(ref b) => Value::from(hex(b)),
+        Ascii(ref s) | Varchar(ref s) => Value::from(s.as_str()),
+        Bigint(n) | Counter(n) => Value::from(n),
+        Boolean(b) => Value::from(b),
+        Decimal { scale, ref unscaled } => Value::from(decimal(scale, unscaled)),
+        Double(f) => Value::from(f),
+        Float(f) => Value::from(f),
+        Int(n) => Value::from(n),
+        Timestamp(ms) => Value::from(timestamp(ms)),
+        Uuid(ref u) | Timeuuid(ref u) => Value::from(uuid(u)),
+        Varint(ref b) => Value::from(varint(b)),
+        Inet(ref ip) => Value::from(ip.to_string()),
+        Date(d) => Value::from(date(d)),
+        Time(t) => Value::from(time(t)),
+        Smallint(n) => Value::from(n),
+        Tinyint(n) => Value::from(n),
+        List(ref l) | Set(ref l) => Value::Array(l.iter().map(to_json).collect()),
+        Map(ref m) => {
+            if m.iter().all(|&(ref k, _)| match *k {
+                Ascii(_) | Varchar(_) => true,
+                _ => false,
+            }) {
+                let mut o = serde_json::Map::new();
+                for &(ref k, ref v) in m {
+                    if let Ascii(ref k) = *k {
+                        o.insert(k.clone(), to_json(v));
+                    } else if let Varchar(ref k) = *k {
+                        o.insert(k.clone(), to_json(v));
+                    }
+                }
+                Value::Object(o)
+            } else {
+                Value::Array(m.iter().map(|&(ref k, ref v)| Value::Array(vec![to_json(k), to_json(v)])).collect())
+            }
+        }
+        Udt(ref fields) => {
+            let mut o = serde_json::Map::new();
+            for &(ref name, ref v) in fields {
+                o.insert(name.clone(), option_to_json(v));
+            }
+            Value::Object(o)
+        }
+        Tuple(ref values) => Value::Array(values.iter().map(option_to_json).collect()),
+    }
+}
+
+/// Converts a cell to JSON, where null cells become `null`.
+pub fn option_to_json(v: &Option<CqlValue>) -> Value {
+    match *v {
+        Some(ref v) => to_json(v),
+        None => Value::Null,
+    }
+}
+
+fn quoted(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Renders a value in CQL literal syntax, as used within collections: strings, dates and
+/// addresses are quoted, blobs are hexadecimal.
+pub fn literal(v: &CqlValue, t: &ColumnType) -> String {
+    use self::CqlValue::*;
+    match *v {
+        Ascii(ref s) | Varchar(ref s) => quoted(s),
+        Timestamp(_) | Date(_) | Time(_) | Inet(_) => quoted(&display(v, t)),
+        _ => display(v, t),
+    }
+}
+
+fn literal_or_null(v: &Option<CqlValue>, t: &ColumnType) -> String {
+    match *v {
+        Some(ref v) => literal(v, t),
+        None => "null".into(),
+    }
+}
+
+/// The type of the n-th element of a collection, tuple or user defined type.
+fn element(t: &ColumnType, n: usize) -> &ColumnType {
+    match *t {
+        ColumnType::List(ref t) | ColumnType::Set(ref t) => t,
+        ColumnType::Map(ref k, _) if n == 0 => k,
+        ColumnType::Map(_, ref v) => v,
+        ColumnType::Tuple(ref types) => types.get(n).unwrap_or(&ColumnType::Blob),
+        ColumnType::Udt(ref udt) => udt.fields.get(n).map_or(&ColumnType::Blob, |f| &f.1),
+        ref t => t,
+    }
+}
+
+fn join<I: Iterator<Item = String>>(open: &str, items: I, close: &str) -> String {
+    format!("{}{}{}", open, items.collect::<Vec<_>>().join(", "), close)
+}
+
+/// Renders a value as displayed in a cell of a table, which is its CQL literal without quotes
+/// at the top level. The column type determines how the elements of collections are rendered.
+pub fn display(v: &CqlValue, t: &ColumnType) -> String {
+    use self::CqlValue::*;
+    match *v {
+        Custom(ref b) | Blob(ref b) => hex(b),
+        Ascii(ref s) | Varchar(ref s) => s.clone(),
+        Bigint(n) | Counter(n) => n.to_string(),
+        Boolean(b) => b.to_string(),
+        Decimal { scale, ref unscaled } => decimal(scale, unscaled),
+        Double(f) => f.to_string(),
+        Float(f) => f.to_string(),
+        Int(n) => n.to_string(),
+        Timestamp(ms) => timestamp(ms),
+        Uuid(ref u) | Timeuuid(ref u) => uuid(u),
+        Varint(ref b) => varint(b),
+        Inet(ref ip) => ip.to_string(),
+        Date(d) => date(d),
+        Time(nanos) => time(nanos),
+        Smallint(n) => n.to_string(),
+        Tinyint(n) => n.to_string(),
+        List(ref l) => {
+            let t = element(t, 0);
+            join("[", l.iter().map(|v| literal(v, t)), "]")
+        }
+        Set(ref s) => {
+            let t = element(t, 0);
+            join("{", s.iter().map(|v| literal(v, t)), "}")
+        }
+        Map(ref m) => {
+            let (kt, vt) = (element(t, 0), element(t, 1));
+            join("{",
+                 m.iter().map(|&(ref k, ref v)| format!("{}: {}", literal(k, kt), literal(v, vt))),
+                 "}")
+        }
+        Udt(ref fields) => {
+            join("{",
+                 fields.iter()
+                     .enumerate()
+                     .map(|(n, &(ref name, ref v))| format!("{}: {}", name, literal_or_null(v, element(t, n)))),
+                 "}")
+        }
+        Tuple(ref values) => {
+            join("(",
+                 values.iter().enumerate().map(|(n, v)| literal_or_null(v, element(t, n))),
+                 ")")
+        }
+    }
+}
+
+/// True for types whose values are right-aligned in tables.
+pub fn is_numeric(t: &ColumnType) -> bool {
+    match *t {
+        ColumnType::Bigint | ColumnType::Counter | ColumnType::Decimal | ColumnType::Double |
+        ColumnType::Float | ColumnType::Int | ColumnType::Varint | ColumnType::Smallint |
+        ColumnType::Tinyint => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    const UUID: [u8; 16] = [0x55, 0x0e, 0x84, 0x00, 0xe2, 0x9b, 0x41, 0xd4, 0xa7, 0x16, 0x44, 0x66, 0x55, 0x44, 0x00,
+                            0x00];
+
+    fn text(s: &str) -> CqlValue {
+        CqlValue::Varchar(s.into())
+    }
+
+    fn list_of(t: ColumnType) -> ColumnType {
+        ColumnType::List(Box::new(t))
+    }
+
+    #[test]
+    fn nulls() {
+        assert_eq!(option_to_json(&None), Value::Null);
+        assert_eq!(option_to_json(&Some(CqlValue::Int(1))), Value::from(1));
+        let t = ColumnType::Tuple(vec![ColumnType::Int, ColumnType::Varchar]);
+        assert_eq!(display(&CqlValue::Tuple(vec![None, Some(text("a"))]), &t), "(null, 'a')");
+        assert_eq!(to_json(&CqlValue::Tuple(vec![None, Some(text("a"))])),
+                   Value::Array(vec![Value::Null, Value::from("a")]));
+    }
+
+    #[test]
+    fn blobs() {
+        assert_eq!(hex(&[]), "0x");
+        assert_eq!(display(&CqlValue::Blob(vec![0x00, 0xab, 0xff]), &ColumnType::Blob), "0x00abff");
+        assert_eq!(to_json(&CqlValue::Blob(vec![0x0f])), Value::from("0x0f"));
+        assert_eq!(display(&CqlValue::List(vec![CqlValue::Blob(vec![1])]), &list_of(ColumnType::Blob)),
+                   "[0x01]");
+    }
+
+    #[test]
+    fn uuids() {
+        assert_eq!(uuid(&UUID), "550e8400-e29b-41d4-a716-446655440000");
+        assert_eq!(display(&CqlValue::Timeuuid(UUID), &ColumnType::Timeuuid),
+                   "550e8400-e29b-41d4-a716-446655440000");
+        assert_eq!(to_json(&CqlValue::Uuid(UUID)), Value::from("550e8400-e29b-41d4-a716-446655440000"));
+        // Unlike strings, uuids are not quoted within collections.
+        assert_eq!(display(&CqlValue::Set(vec![CqlValue::Uuid(UUID)]),
+                           &ColumnType::Set(Box::new(ColumnType::Uuid))),
+                   "{550e8400-e29b-41d4-a716-446655440000}");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(varint(&[]), "0");
+        assert_eq!(varint(&[0x7f]), "127");
+        assert_eq!(varint(&[0x80]), "-128");
+        assert_eq!(varint(&[0xff, 0xff]), "-1");
+        assert_eq!(varint(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]), "18446744073709551616");
+        assert_eq!(decimal(2, &[0x04, 0xd2]), "12.34");
+        assert_eq!(decimal(3, &[0x05]), "0.005");
+        assert_eq!(decimal(2, &[0xfb]), "-0.05");
+        assert_eq!(decimal(-2, &[0x05]), "500");
+        assert_eq!(decimal(0, &[]), "0");
+        assert_eq!(decimal(-5, &[]), "0");
+    }
+
+    #[test]
+    fn decimals_with_extreme_scales() {
+        assert_eq!(decimal(21, &[0x05]), "0.000000000000000000005");
+        assert_eq!(decimal(22, &[0x05]), "5E-22");
+        assert_eq!(decimal(-21, &[0x05]), "5000000000000000000000");
+        assert_eq!(decimal(-22, &[0x05]), "5E22");
+        assert_eq!(decimal(i32::max_value(), &[0xfb]), "-5E-2147483647");
+        assert_eq!(decimal(i32::min_value(), &[0x04, 0xd2]), "1234E2147483648");
+        assert_eq!(decimal(i32::min_value(), &[]), "0");
+        assert_eq!(decimal(i32::max_value(), &[]), "0E-2147483647");
+    }
+
+    #[test]
+    fn dates_and_times() {
+        assert_eq!(timestamp(0), "1970-01-01 00:00:00.000000+0000");
+        assert_eq!(timestamp(1490103054234), "2017-03-21 13:30:54.234000+0000");
+        assert_eq!(timestamp(-1), "1969-12-31 23:59:59.999000+0000");
+        assert_eq!(date(1 << 31), "1970-01-01");
+        assert_eq!(date((1 << 31) + 17246), "2017-03-21");
+        assert_eq!(time(48654234000000), "13:30:54.234000000");
+    }
+
+    #[test]
+    fn collections() {
+        let strings = CqlValue::List(vec![text("it's"), text("b")]);
+        assert_eq!(display(&strings, &list_of(ColumnType::Varchar)), "['it''s', 'b']");
+        assert_eq!(display(&CqlValue::List(Vec::new()), &list_of(ColumnType::Int)), "[]");
+        assert_eq!(to_json(&strings), Value::Array(vec![Value::from("it's"), Value::from("b")]));
+
+        let t = ColumnType::Map(Box::new(ColumnType::Varchar), Box::new(list_of(ColumnType::Int)));
+        let map = CqlValue::Map(vec![(text("a"), CqlValue::List(vec![CqlValue::Int(1), CqlValue::Int(2)]))]);
+        assert_eq!(display(&map, &t), "{'a': [1, 2]}");
+        let mut o = serde_json::Map::new();
+        o.insert("a".into(), Value::Array(vec![Value::from(1), Value::from(2)]));
+        assert_eq!(to_json(&map), Value::Object(o));
+
+        let t = ColumnType::Map(Box::new(ColumnType::Inet), Box::new(ColumnType::Timestamp));
+        let ip = CqlValue::Inet(IpAddr::from_str("10.0.0.1").unwrap());
+        let map = CqlValue::Map(vec![(ip, CqlValue::Timestamp(0))]);
+        assert_eq!(display(&map, &t), "{'10.0.0.1': '1970-01-01 00:00:00.000000+0000'}");
+        assert_eq!(to_json(&map),
+                   Value::Array(vec![Value::Array(vec![Value::from("10.0.0.1"),
+                                                       Value::from("1970-01-01 00:00:00.000000+0000")])]));
+    }
+}
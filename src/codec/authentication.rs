@@ -1,49 +1,119 @@
-
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
 error_chain! {
     errors {
         UnknownAuthenticator(auth: String)
+        UnexpectedChallenge(auth: String) {
+            description("The authenticator does not support challenges")
+            display("Authenticator {} does not support challenges", auth)
+        }
+        UnsupportedCredentials(auth: String) {
+            description("The credentials cannot be used with the authenticator")
+            display("The given credentials cannot be used with authenticator {}", auth)
+        }
     }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Credentials {
     Login { username: String, password: String },
+    /// A token, as used by token based authenticators.
+    Token(String),
+    /// Arbitrary values, for use by custom authenticators.
+    Custom(BTreeMap<String, String>),
 }
 
+/// The client side of a SASL exchange with the authenticator configured on the server.
+///
+/// The initial response answers AUTHENTICATE, and each AUTH_CHALLENGE is answered by evaluating
+/// it, until the server sends AUTH_SUCCESS.
+pub trait Authenticator {
+    /// Encodes the token to send in answer to AUTHENTICATE.
+    fn initial_response(&mut self, buf: &mut Vec<u8>) -> Result<()>;
+
+    /// Encodes the token to send in answer to the given challenge token.
+    fn evaluate_challenge(&mut self, challenge: Option<&[u8]>, buf: &mut Vec<u8>) -> Result<()>;
 
-pub enum Authenticator {
-    PlainTextAuthenticator { username: String, password: String },
+    /// Called with the final token sent by the server once authentication succeeded.
+    fn on_success(&mut self, _token: Option<&[u8]>) -> Result<()> {
+        Ok(())
+    }
 }
 
-impl Authenticator {
-    pub fn from_name(name: &str, credentials: Credentials) -> Result<Authenticator> {
-        use self::Credentials::*;
-        match name {
-            "org.apache.cassandra.auth.PasswordAuthenticator" => {
-                match credentials {
-                    Login { username: user, password: pwd } => {
-                        Ok(Authenticator::PlainTextAuthenticator {
-                            username: user,
-                            password: pwd,
-                        })
-                    }
-                }
-            }
-            _ => Err(ErrorKind::UnknownAuthenticator(name.to_string()).into()),
+/// Creates an authenticator from the credentials provided by the user.
+pub type AuthenticatorFactory = Arc<Fn(Credentials) -> Result<Box<Authenticator>> + Send + Sync>;
+
+/// Maps the class names of server side authenticators to factories for their client side.
+/// Its default contains all authenticators provided by this crate.
+#[derive(Clone)]
+pub struct AuthenticatorRegistry {
+    factories: HashMap<String, AuthenticatorFactory>,
+}
+
+impl AuthenticatorRegistry {
+    /// A registry without any authenticator.
+    pub fn empty() -> AuthenticatorRegistry {
+        AuthenticatorRegistry { factories: HashMap::new() }
+    }
+
+    /// Registers the factory for the server side authenticator with the given class name,
+    /// replacing the one registered previously.
+    pub fn register<F>(&mut self, name: &str, factory: F) -> &mut Self
+        where F: Fn(Credentials) -> Result<Box<Authenticator>> + Send + Sync + 'static
+    {
+        self.factories.insert(name.to_string(), Arc::new(factory));
+        self
+    }
+
+    pub fn from_name(&self, name: &str, credentials: Credentials) -> Result<Box<Authenticator>> {
+        match self.factories.get(name) {
+            Some(factory) => factory(credentials),
+            None => Err(ErrorKind::UnknownAuthenticator(name.to_string()).into()),
         }
     }
+}
+
+impl Default for AuthenticatorRegistry {
+    fn default() -> Self {
+        let mut r = AuthenticatorRegistry::empty();
+        r.register("org.apache.cassandra.auth.PasswordAuthenticator",
+                   PlainTextAuthenticator::from_credentials);
+        r
+    }
+}
+
+/// Authenticates with username and password, as required by the `PasswordAuthenticator`.
+pub struct PlainTextAuthenticator {
+    pub username: String,
+    pub password: String,
+}
 
-    pub fn encode_auth_response<'a>(&self, v: &mut Vec<u8>) -> () {
-        match self {
-            &Authenticator::PlainTextAuthenticator { username: ref user, password: ref pwd } => {
-                v.push(0x00);
-                v.extend(user.as_bytes());
-                v.push(0x00);
-                v.extend(pwd.as_bytes());
+impl PlainTextAuthenticator {
+    pub fn from_credentials(credentials: Credentials) -> Result<Box<Authenticator>> {
+        match credentials {
+            Credentials::Login { username: user, password: pwd } => {
+                Ok(Box::new(PlainTextAuthenticator {
+                    username: user,
+                    password: pwd,
+                }))
             }
+            _ => Err(ErrorKind::UnsupportedCredentials("PlainTextAuthenticator".into()).into()),
         }
+    }
+}
+
+impl Authenticator for PlainTextAuthenticator {
+    fn initial_response(&mut self, v: &mut Vec<u8>) -> Result<()> {
+        v.push(0x00);
+        v.extend(self.username.as_bytes());
+        v.push(0x00);
+        v.extend(self.password.as_bytes());
+        Ok(())
+    }
 
+    fn evaluate_challenge(&mut self, _challenge: Option<&[u8]>, _v: &mut Vec<u8>) -> Result<()> {
+        Err(ErrorKind::UnexpectedChallenge("PlainTextAuthenticator".into()).into())
     }
 }
 
@@ -52,48 +122,99 @@ impl Authenticator {
 mod test {
     use super::*;
 
+    fn login() -> Credentials {
+        Credentials::Login {
+            username: String::new(),
+            password: String::new(),
+        }
+    }
 
     #[test]
     fn plain_text_auth() {
         let srv_auth = "org.apache.cassandra.auth.PasswordAuthenticator";
-        let auth = Authenticator::from_name(srv_auth,
-                                            Credentials::Login {
-                                                username: String::new(),
-                                                password: String::new(),
-                                            });
+        let auth = AuthenticatorRegistry::default().from_name(srv_auth, login());
 
-        use super::Authenticator::*;
-
-        match auth.unwrap() {
-            PlainTextAuthenticator { .. } => (),
-        }
+        assert!(auth.is_ok());
+        assert!(AuthenticatorRegistry::default().from_name(srv_auth, Credentials::Token("t".into())).is_err());
     }
 
     #[test]
     fn unknown_auth() {
         let srv_auth = "unknown";
-        let auth = Authenticator::from_name(srv_auth,
-                                            Credentials::Login {
-                                                username: String::new(),
-                                                password: String::new(),
-                                            });
+        let auth = AuthenticatorRegistry::default().from_name(srv_auth, login());
 
         assert!(auth.is_err());
     }
 
     #[test]
     fn plain_text_encode() {
-        let auth = Authenticator::PlainTextAuthenticator {
+        let mut auth = PlainTextAuthenticator {
             username: String::from("abcuser"),
             password: String::from("abcpass"),
         };
 
         let mut encoded = Vec::new();
-        auth.encode_auth_response(&mut encoded);
+        auth.initial_response(&mut encoded).unwrap();
 
         let expected = &[0u8, 97, 98, 99, 117, 115, 101, 114, 0, 97, 98, 99, 112, 97, 115, 115];
 
         assert_eq!(&encoded[..], &expected[..]);
     }
 
+    #[test]
+    fn plain_text_rejects_challenges() {
+        let mut auth = PlainTextAuthenticator {
+            username: String::from("abcuser"),
+            password: String::from("abcpass"),
+        };
+
+        assert!(auth.evaluate_challenge(Some(&[1]), &mut Vec::new()).is_err());
+    }
+
+    struct TokenAuthenticator {
+        token: String,
+        rounds: usize,
+    }
+
+    impl Authenticator for TokenAuthenticator {
+        fn initial_response(&mut self, v: &mut Vec<u8>) -> Result<()> {
+            v.extend(b"TOKEN");
+            Ok(())
+        }
+
+        fn evaluate_challenge(&mut self, challenge: Option<&[u8]>, v: &mut Vec<u8>) -> Result<()> {
+            self.rounds += 1;
+            assert_eq!(challenge, Some(&b"TOKEN-START"[..]));
+            v.extend(self.token.as_bytes());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn custom_authenticator() {
+        let srv_auth = "com.example.TokenAuthenticator";
+        let mut registry = AuthenticatorRegistry::default();
+        registry.register(srv_auth, move |creds| match creds {
+            Credentials::Token(token) => {
+                Ok(Box::new(TokenAuthenticator {
+                    token: token,
+                    rounds: 0,
+                }))
+            }
+            _ => Err(ErrorKind::UnsupportedCredentials(srv_auth.into()).into()),
+        });
+
+        let mut auth = registry.from_name(srv_auth, Credentials::Token("abc".into())).unwrap();
+        let mut encoded = Vec::new();
+        auth.initial_response(&mut encoded).unwrap();
+        assert_eq!(&encoded[..], b"TOKEN");
+
+        let mut encoded = Vec::new();
+        auth.evaluate_challenge(Some(b"TOKEN-START"), &mut encoded).unwrap();
+        assert_eq!(&encoded[..], b"abc");
+        auth.on_success(None).unwrap();
+
+        assert!(registry.from_name("org.apache.cassandra.auth.PasswordAuthenticator", login()).is_ok());
+        assert!(AuthenticatorRegistry::empty().from_name(srv_auth, Credentials::Token("abc".into())).is_err());
+    }
 }
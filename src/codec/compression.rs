@@ -0,0 +1,157 @@
+//! Frame body compression, which is negotiated in the STARTUP message and applies to all
+//! frames exchanged afterwards that have the compression flag set.
+use byteorder::{BigEndian, ByteOrder};
+use lz4_flex::block;
+use snap::raw;
+
+error_chain! {
+    errors {
+        CompressionFailed(algorithm: &'static str, msg: String) {
+            description("A frame body could not be compressed")
+            display("Failed to compress frame body with {}: {}", algorithm, msg)
+        }
+        DecompressionFailed(algorithm: &'static str, msg: String) {
+            description("A frame body could not be decompressed")
+            display("Failed to decompress frame body with {}: {}", algorithm, msg)
+        }
+    }
+}
+
+/// The maximum length of a frame body, which also limits the length of decompressed bodies.
+pub const MAX_BODY_LEN: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// The body is prefixed with its uncompressed length as 4 byte integer,
+    /// followed by a LZ4 block.
+    Lz4,
+    /// The body is compressed in the raw snappy format.
+    Snappy,
+}
+
+impl Compression {
+    /// The name used for the algorithm in the SUPPORTED and STARTUP messages.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Compression::Lz4 => "lz4",
+            Compression::Snappy => "snappy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Compression> {
+        match name {
+            "lz4" => Some(Compression::Lz4),
+            "snappy" => Some(Compression::Snappy),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, body: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            Compression::Lz4 => {
+                let mut buf = vec![0; 4];
+                BigEndian::write_u32(&mut buf, body.len() as u32);
+                buf.extend(block::compress(body));
+                Ok(buf)
+            }
+            Compression::Snappy => {
+                raw::Encoder::new()
+                    .compress_vec(body)
+                    .map_err(|e| ErrorKind::CompressionFailed(self.as_str(), e.to_string()).into())
+            }
+        }
+    }
+
+    pub fn decompress(&self, body: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            Compression::Lz4 => {
+                if body.len() < 4 {
+                    return Err(ErrorKind::DecompressionFailed(self.as_str(),
+                                                              format!("Expected at least 4 bytes, got {}",
+                                                                      body.len()))
+                        .into());
+                }
+                let len = BigEndian::read_u32(&body[..4]) as usize;
+                self.check_len(len)?;
+                block::decompress(&body[4..], len)
+                    .map_err(|e| ErrorKind::DecompressionFailed(self.as_str(), e.to_string()).into())
+            }
+            Compression::Snappy => {
+                let len = raw::decompress_len(body)
+                    .map_err(|e| ErrorKind::DecompressionFailed(self.as_str(), e.to_string()))?;
+                self.check_len(len)?;
+                raw::Decoder::new()
+                    .decompress_vec(body)
+                    .map_err(|e| ErrorKind::DecompressionFailed(self.as_str(), e.to_string()).into())
+            }
+        }
+    }
+
+    /// Rejects uncompressed lengths which exceed the frame size limit before allocating for them.
+    fn check_len(&self, len: usize) -> Result<()> {
+        if len > MAX_BODY_LEN {
+            return Err(ErrorKind::DecompressionFailed(self.as_str(),
+                                                      format!("The uncompressed length of {} bytes exceeds the \
+                                                               maximum of {} bytes",
+                                                              len,
+                                                              MAX_BODY_LEN))
+                .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roundtrip(c: Compression) {
+        let body: Vec<u8> = (0..1024).map(|i| (i % 7) as u8).collect();
+        let compressed = c.compress(&body).unwrap();
+        assert!(compressed.len() < body.len());
+        assert_eq!(c.decompress(&compressed).unwrap(), body);
+    }
+
+    #[test]
+    fn lz4() {
+        roundtrip(Compression::Lz4);
+        let compressed = Compression::Lz4.compress(b"abc").unwrap();
+        assert_eq!(&compressed[..4], &[0, 0, 0, 3]);
+        assert!(Compression::Lz4.decompress(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn lz4_rejects_oversized_lengths() {
+        let mut compressed = Compression::Lz4.compress(b"abc").unwrap();
+        BigEndian::write_u32(&mut compressed[..4], MAX_BODY_LEN as u32 + 1);
+        match Compression::Lz4.decompress(&compressed) {
+            Err(Error(ErrorKind::DecompressionFailed(_, msg), _)) => assert!(msg.contains("exceeds"), "{}", msg),
+            res => panic!("Expected the length to be rejected, got {:?}", res),
+        }
+        BigEndian::write_u32(&mut compressed[..4], u32::max_value());
+        assert!(Compression::Lz4.decompress(&compressed).is_err());
+    }
+
+    #[test]
+    fn snappy_rejects_oversized_lengths() {
+        // The uncompressed length is a varint preceding the data, here 2^28 + 1.
+        match Compression::Snappy.decompress(&[0x81, 0x80, 0x80, 0x80, 0x01, 0x00]) {
+            Err(Error(ErrorKind::DecompressionFailed(_, msg), _)) => assert!(msg.contains("exceeds"), "{}", msg),
+            res => panic!("Expected the length to be rejected, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn snappy() {
+        roundtrip(Compression::Snappy);
+        assert!(Compression::Snappy.decompress(&[0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn names() {
+        for c in &[Compression::Lz4, Compression::Snappy] {
+            assert_eq!(Compression::from_name(c.as_str()), Some(*c));
+        }
+        assert_eq!(Compression::from_name("deflate"), None);
+    }
+}
@@ -36,6 +36,17 @@
 use byteorder::{BigEndian, ReadBytesExt, ByteOrder};
 const HEADER_LENGTH: usize = 9;
 
+/// The frame body is compressed.
+pub const FLAG_COMPRESSION: u8 = 0x01;
+/// The request is to be traced, or the response contains a tracing id.
+pub const FLAG_TRACING: u8 = 0x02;
+/// The frame body starts with a custom payload (since v4).
+pub const FLAG_CUSTOM_PAYLOAD: u8 = 0x04;
+/// The response contains warnings (since v4).
+pub const FLAG_WARNING: u8 = 0x08;
+/// The request uses a protocol version which is still in beta (since v5).
+pub const FLAG_USE_BETA: u8 = 0x10;
+
 error_chain! {
     errors {
         UnsupportedVersion(v: u8) {
@@ -151,6 +162,13 @@ pub struct Header {
     /// a tracing ID. The tracing ID is a [uuid] and is the first thing in
     /// the frame body. The rest of the body will then be the usual body
     /// corresponding to the response opcode.
+    /// 0x04: Custom payload flag (since v4). For a request or response frame, this
+    /// indicates that a generic key-value custom payload, a [bytes map], is present
+    /// in the frame body.
+    /// 0x08: Warning flag (since v4). The response contains warnings which were
+    /// generated by the server. The warnings are a [string list] and will be the
+    /// first value in the frame body if the tracing flag is not set, or directly
+    /// after the tracing ID if it is.
     /// The rest of the flags is currently unused and ignored.
     pub flags: u8,
     /// A frame has a stream id (a [short] value). When sending request messages, this
@@ -198,11 +216,19 @@ impl Header {
     }
 
     pub fn is_compressed(&self) -> bool {
-        self.flags & 0x01 == 0x01
+        self.flags & FLAG_COMPRESSION == FLAG_COMPRESSION
     }
 
     pub fn is_traced(&self) -> bool {
-        self.flags & 0x02 == 0x02
+        self.flags & FLAG_TRACING == FLAG_TRACING
+    }
+
+    pub fn has_custom_payload(&self) -> bool {
+        self.flags & FLAG_CUSTOM_PAYLOAD == FLAG_CUSTOM_PAYLOAD
+    }
+
+    pub fn has_warnings(&self) -> bool {
+        self.flags & FLAG_WARNING == FLAG_WARNING
     }
 
     pub fn encode(&self) -> Result<[u8; 9]> {
@@ -244,10 +270,28 @@ impl Header {
 /// connection.
 /// This document describe the version 3 of the protocol. For the changes made since
 /// version 2, see Section 10.
+///
+/// Version 4 uses 0x04 and 0x84 respectively.
 #[cfg_attr(feature = "with-serde", derive(Deserialize, Serialize))]
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, PartialOrd, Debug, Clone, Copy)]
 pub enum ProtocolVersion {
     Version3,
+    Version4,
+}
+
+impl ProtocolVersion {
+    /// The highest protocol version supported by this library.
+    pub fn latest() -> ProtocolVersion {
+        ProtocolVersion::Version4
+    }
+
+    /// The next lower protocol version, if it is supported by this library.
+    pub fn lower(&self) -> Option<ProtocolVersion> {
+        match *self {
+            ProtocolVersion::Version4 => Some(ProtocolVersion::Version3),
+            ProtocolVersion::Version3 => None,
+        }
+    }
 }
 
 #[cfg_attr(feature = "with-serde", derive(Deserialize, Serialize))]
@@ -262,6 +306,8 @@ impl Version {
         Ok(match b {
             0x03 => Version::v3_request(),
             0x83 => Version::v3_response(),
+            0x04 => Version::v4_request(),
+            0x84 => Version::v4_response(),
             _ => return Err(ErrorKind::UnsupportedVersion(b).into()),
         })
     }
@@ -273,10 +319,19 @@ impl Version {
         }
     }
 
+    pub fn response(version: ProtocolVersion) -> Version {
+        Version {
+            version: version,
+            direction: Direction::Response,
+        }
+    }
+
     pub fn encode(&self) -> u8 {
         match (&self.version, &self.direction) {
             (&ProtocolVersion::Version3, &Direction::Request) => 0x03,
             (&ProtocolVersion::Version3, &Direction::Response) => 0x83,
+            (&ProtocolVersion::Version4, &Direction::Request) => 0x04,
+            (&ProtocolVersion::Version4, &Direction::Response) => 0x84,
         }
     }
     pub fn v3_response() -> Version {
@@ -291,6 +346,18 @@ impl Version {
             direction: Direction::Request,
         }
     }
+    pub fn v4_response() -> Version {
+        Version {
+            version: ProtocolVersion::Version4,
+            direction: Direction::Response,
+        }
+    }
+    pub fn v4_request() -> Version {
+        Version {
+            version: ProtocolVersion::Version4,
+            direction: Direction::Request,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -312,6 +379,21 @@ mod test {
         assert!(Version::try_from(b'\x88').is_err());
     }
 
+    #[test]
+    fn version4() {
+        assert_eq!(Version::v4_request().encode(), b'\x04');
+        assert_eq!(Version::v4_response().encode(), b'\x84');
+        assert_eq!(Version::try_from(b'\x04').unwrap(), Version::v4_request());
+        assert_eq!(Version::try_from(b'\x84').unwrap(), Version::v4_response());
+    }
+
+    #[test]
+    fn downgrade() {
+        assert_eq!(ProtocolVersion::latest(), ProtocolVersion::Version4);
+        assert_eq!(ProtocolVersion::Version4.lower(), Some(ProtocolVersion::Version3));
+        assert_eq!(ProtocolVersion::Version3.lower(), None);
+    }
+
     #[test]
     fn complete_decode() {
         let bytes = b"\x03\x00\x01\x01\x05\x00\x00\x01\x05";
@@ -381,12 +463,22 @@ mod test {
         assert_eq!(h.is_traced(), true);
     }
 
+    #[test]
+    fn flags_custom_payload_and_warnings() {
+        let bytes = b"\x84\x0c\x00\x00\x08\x00\x00\x00\x00";
+        let h = Header::try_from(&bytes[..]).unwrap();
+
+        assert_eq!(h.is_traced(), false);
+        assert_eq!(h.has_custom_payload(), true);
+        assert_eq!(h.has_warnings(), true);
+    }
+
     #[test]
     fn unsupported_version() {
-        let bytes = b"\x04\x02\x00\x00\x05\x00\x00\x00\x00";
+        let bytes = b"\x05\x02\x00\x00\x05\x00\x00\x00\x00";
         let res = Header::try_from(&bytes[..]);
 
-        assert!(err_is(res, ErrorKind::UnsupportedVersion(0x04)));
+        assert!(err_is(res, ErrorKind::UnsupportedVersion(0x05)));
     }
 
 
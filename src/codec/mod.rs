@@ -6,3 +6,6 @@ pub mod response;
 pub mod primitives;
 
 pub mod authentication;
+
+pub mod compression;
+pub mod stream_id;
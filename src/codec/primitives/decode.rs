@@ -1,6 +1,7 @@
 use codec::primitives::types::{CqlStringList, CqlString, CqlLongString, CqlStringMap, CqlStringMultiMap, CqlBytes,
-                               CqlConsistency};
+                               CqlConsistency, ColumnType, UdtType};
 use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use tokio_core::io::EasyBuf;
 use byteorder::{ByteOrder, BigEndian};
 use codec::primitives::CqlFrom;
@@ -34,6 +35,14 @@ use self::Needed::*;
 
 pub type ParseResult<T> = Result<(EasyBuf, T), Error>;
 
+pub fn byte(mut i: EasyBuf) -> ParseResult<u8> {
+    if i.len() < 1 {
+        return Err(Incomplete(Size(1)));
+    }
+    let b = i.drain_to(1).as_slice()[0];
+    Ok((i, b))
+}
+
 pub fn short(mut i: EasyBuf) -> ParseResult<u16> {
     if i.len() < 2 {
         return Err(Incomplete(Size(2)));
@@ -83,6 +92,8 @@ pub fn bytes(buf: EasyBuf) -> ParseResult<CqlBytes<EasyBuf>> {
     let (mut buf, len) = int(buf)?;
     if (buf.len() as isize) < len as isize {
         return Err(Incomplete(Size(len as usize)));
+    } else if len == -2 {
+        return Ok((buf, CqlBytes::unset_value()));
     } else if len < 0 {
         return Ok((buf, CqlBytes::null_value()));
     }
@@ -90,6 +101,66 @@ pub fn bytes(buf: EasyBuf) -> ParseResult<CqlBytes<EasyBuf>> {
     Ok((buf, b))
 }
 
+pub fn short_bytes(buf: EasyBuf) -> ParseResult<CqlBytes<EasyBuf>> {
+    let (mut buf, len) = short(buf)?;
+    if buf.len() < len as usize {
+        return Err(Incomplete(Size(len as usize)));
+    }
+    let b = CqlBytes::from(buf.drain_to(len as usize));
+    Ok((buf, b))
+}
+
+/// Decodes a [bytes map], which is a [short] n followed by n pairs of [string] and [bytes].
+pub fn bytes_map(i: EasyBuf) -> ParseResult<HashMap<CqlString<EasyBuf>, CqlBytes<EasyBuf>>> {
+    let (mut buf, len) = short(i)?;
+    let mut map = HashMap::new();
+
+    for _ in 0..len {
+        let (nb, key) = string(buf)?;
+        buf = nb;
+        let (nb, value) = bytes(buf)?;
+        buf = nb;
+        map.insert(key, value);
+    }
+
+    Ok((buf, map))
+}
+
+pub fn uuid(mut i: EasyBuf) -> ParseResult<[u8; 16]> {
+    if i.len() < 16 {
+        return Err(Incomplete(Size(16)));
+    }
+    let mut id = [0u8; 16];
+    id.copy_from_slice(i.drain_to(16).as_slice());
+    Ok((i, id))
+}
+
+/// Decodes an [inet], which is a [byte] n followed by n address bytes and an [int] port.
+pub fn inet(mut i: EasyBuf) -> ParseResult<SocketAddr> {
+    if i.len() < 1 {
+        return Err(Incomplete(Size(1)));
+    }
+    let len = i.drain_to(1).as_slice()[0] as usize;
+    if i.len() < len {
+        return Err(Incomplete(Size(len)));
+    }
+    let ip = {
+        let addr = i.drain_to(len);
+        let b = addr.as_slice();
+        match len {
+            4 => IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3])),
+            16 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(b);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err(ParseError(format!("Expected an address of 4 or 16 bytes, got {}", len))),
+        }
+    };
+    let (buf, port) = int(i)?;
+    Ok((buf, SocketAddr::new(ip, port as u16)))
+}
+
 pub fn string_list(i: EasyBuf) -> ParseResult<CqlStringList<EasyBuf>> {
     let (mut buf, len) = short(i)?;
     let mut v = Vec::new();
@@ -142,6 +213,73 @@ pub fn consistency(mut i: EasyBuf) -> ParseResult<CqlConsistency> {
     Ok((i, c))
 }
 
+/// How deeply collections, tuples and user defined types may be nested in a column type, which is
+/// plenty for any real schema, but keeps a malicious peer from overflowing the stack.
+pub const MAX_TYPE_NESTING: usize = 32;
+
+pub fn option(i: EasyBuf) -> ParseResult<ColumnType> {
+    nested_option(i, 0)
+}
+
+/// Decodes a column type which is nested into `depth` others.
+fn nested_option(i: EasyBuf, depth: usize) -> ParseResult<ColumnType> {
+    if depth > MAX_TYPE_NESTING {
+        return Err(ParseError(format!("Column types may be nested at most {} levels deep", MAX_TYPE_NESTING)));
+    }
+    let (buf, id) = short(i)?;
+    if let Some(t) = ColumnType::native_from_id(id) {
+        return Ok((buf, t));
+    }
+    Ok(match id {
+        0x0000 => {
+            let (buf, class) = string(buf)?;
+            (buf, ColumnType::Custom(class))
+        }
+        0x0020 => {
+            let (buf, t) = nested_option(buf, depth + 1)?;
+            (buf, ColumnType::List(Box::new(t)))
+        }
+        0x0021 => {
+            let (buf, k) = nested_option(buf, depth + 1)?;
+            let (buf, v) = nested_option(buf, depth + 1)?;
+            (buf, ColumnType::Map(Box::new(k), Box::new(v)))
+        }
+        0x0022 => {
+            let (buf, t) = nested_option(buf, depth + 1)?;
+            (buf, ColumnType::Set(Box::new(t)))
+        }
+        0x0030 => {
+            let (buf, keyspace) = string(buf)?;
+            let (buf, name) = string(buf)?;
+            let (mut buf, len) = short(buf)?;
+            let mut fields = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                let (nb, field) = string(buf)?;
+                let (nb, t) = nested_option(nb, depth + 1)?;
+                buf = nb;
+                fields.push((field, t));
+            }
+            (buf,
+             ColumnType::Udt(UdtType {
+                keyspace: keyspace,
+                name: name,
+                fields: fields,
+            }))
+        }
+        0x0031 => {
+            let (mut buf, len) = short(buf)?;
+            let mut types = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                let (nb, t) = nested_option(buf, depth + 1)?;
+                buf = nb;
+                types.push(t);
+            }
+            (buf, ColumnType::Tuple(types))
+        }
+        _ => return Err(ParseError(format!("Unknown option id {:#06x}", id))),
+    })
+}
+
 mod test {
     // TODO: figure out why it doesn't get it!
     #[allow(unused_imports)]
@@ -149,6 +287,14 @@ mod test {
     #[allow(unused_imports)]
     use super::super::encode;
 
+    #[test]
+    fn byte_incomplete_and_complete() {
+        assert_eq!(byte(vec![].into()).unwrap_err(), Incomplete(Size(1)));
+        let (nb, res) = byte(vec![7u8, 8].into()).unwrap();
+        assert_eq!(res, 7);
+        assert_eq!(nb.as_slice(), &[8]);
+    }
+
     #[test]
     fn short_incomplete() {
         assert_eq!(short(vec![0].into()).unwrap_err(), Incomplete(Size(2)));
@@ -225,6 +371,43 @@ mod test {
                    Incomplete(Size(5)));
     }
 
+    #[test]
+    fn bytes_map_complete() {
+        let mut m = HashMap::new();
+        m.insert(CqlString::try_from("k").unwrap(), CqlBytes::try_from(vec![1u8, 2]).unwrap());
+        m.insert(CqlString::try_from("n").unwrap(), CqlBytes::null_value());
+        let mut b = Vec::new();
+        encode::bytes_map(&m, &mut b);
+
+        let (e, res) = bytes_map(b.into()).unwrap();
+        assert_eq!(e.len(), 0);
+        assert_eq!(res, m);
+    }
+
+    #[test]
+    fn uuid_incomplete_and_complete() {
+        assert_eq!(uuid(vec![0; 15].into()).unwrap_err(), Incomplete(Size(16)));
+        let (e, res) = uuid((0u8..17).collect::<Vec<_>>().into()).unwrap();
+        assert_eq!(e.len(), 1);
+        assert_eq!(res[15], 15);
+    }
+
+    #[test]
+    fn inet_complete() {
+        use std::str::FromStr;
+        for addr in &["127.0.0.1:9042", "[::1]:9042"] {
+            let addr = SocketAddr::from_str(addr).unwrap();
+            let mut b = Vec::new();
+            encode::inet(&addr, &mut b);
+            assert_eq!(inet(Vec::from(&b[..b.len() - 1]).into()).unwrap_err(),
+                       Incomplete(Size(4)));
+            let (e, res) = inet(b.into()).unwrap();
+            assert_eq!(e.len(), 0);
+            assert_eq!(res, addr);
+        }
+        assert!(inet(vec![3, 1, 2, 3, 0, 0, 0, 0].into()).is_err());
+    }
+
     // TODO: move tests from types here, cause it seems very similar
     //
     //    #[test]
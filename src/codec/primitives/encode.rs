@@ -1,5 +1,8 @@
 use byteorder::{ByteOrder, BigEndian};
-use super::{CqlStringList, CqlLongString, CqlString, CqlBytes, CqlStringMap, CqlStringMultiMap, CqlConsistency};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use super::{CqlStringList, CqlLongString, CqlString, CqlBytes, CqlStringMap, CqlStringMultiMap, CqlConsistency,
+            ColumnType, Result, ErrorKind};
 
 pub fn short(v: u16) -> [u8; 2] {
     let mut bytes = [0u8; 2];
@@ -42,6 +45,40 @@ pub fn bytes<T>(b: &CqlBytes<T>, buf: &mut Vec<u8>)
     }
 }
 
+/// Fails if there are more bytes than the length prefix of 2 bytes can announce.
+pub fn short_bytes(b: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+    if b.len() > u16::max_value() as usize {
+        return Err(ErrorKind::MaximumLengthExceeded(b.len()).into());
+    }
+    buf.extend(&short(b.len() as u16)[..]);
+    buf.extend(b);
+    Ok(())
+}
+
+pub fn bytes_map<T>(m: &HashMap<CqlString<T>, CqlBytes<T>>, buf: &mut Vec<u8>)
+    where T: AsRef<[u8]> + PartialEq + Eq
+{
+    buf.extend(&short(m.len() as u16)[..]);
+    for (k, v) in m.iter() {
+        string(k, buf);
+        bytes(v, buf);
+    }
+}
+
+pub fn inet(addr: &SocketAddr, buf: &mut Vec<u8>) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(4);
+            buf.extend(&ip.octets()[..]);
+        }
+        IpAddr::V6(ip) => {
+            buf.push(16);
+            buf.extend(&ip.octets()[..]);
+        }
+    }
+    buf.extend(&int(addr.port() as i32)[..]);
+}
+
 pub fn string_list<T>(l: &CqlStringList<T>, buf: &mut Vec<u8>)
     where T: AsRef<[u8]> + PartialEq + Eq
 {
@@ -76,3 +113,32 @@ pub fn consistency(v: &CqlConsistency) -> [u8; 2] {
     BigEndian::write_u16(&mut bytes[..], v.as_short());
     bytes
 }
+
+pub fn option(t: &ColumnType, buf: &mut Vec<u8>) {
+    use super::ColumnType::*;
+    buf.extend(&short(t.id())[..]);
+    match *t {
+        Custom(ref class) => string(class, buf),
+        List(ref t) | Set(ref t) => option(t, buf),
+        Map(ref k, ref v) => {
+            option(k, buf);
+            option(v, buf);
+        }
+        Udt(ref udt) => {
+            string(&udt.keyspace, buf);
+            string(&udt.name, buf);
+            buf.extend(&short(udt.fields.len() as u16)[..]);
+            for &(ref name, ref t) in &udt.fields {
+                string(name, buf);
+                option(t, buf);
+            }
+        }
+        Tuple(ref types) => {
+            buf.extend(&short(types.len() as u16)[..]);
+            for t in types {
+                option(t, buf);
+            }
+        }
+        _ => {}
+    }
+}
@@ -110,6 +110,10 @@ impl<T> CqlStringMap<T>
     pub fn iter(&self) -> ::std::collections::hash_map::Iter<CqlString<T>, CqlString<T>> {
         self.container.iter()
     }
+
+    pub fn get(&self, k: &CqlString<T>) -> Option<&CqlString<T>> {
+        self.container.get(k)
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -9,25 +9,36 @@ pub struct CqlBytes<T>
     where T: AsRef<[u8]>
 {
     buf: Option<T>,
+    /// Only meaningful without buffer, distinguishing `not set` from `null` (since v4).
+    unset: bool,
 }
 
 impl<T> Debug for CqlBytes<T>
     where T: AsRef<[u8]> + Debug
 {
     fn fmt(&self, f: &mut Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        if self.unset {
+            return f.write_str("Unset");
+        }
         self.buf.fmt(f)
     }
 }
 
 impl CqlBytes<::tokio_core::io::EasyBuf> {
     pub fn from(buf: ::tokio_core::io::EasyBuf) -> CqlBytes<::tokio_core::io::EasyBuf> {
-        CqlBytes { buf: Some(buf) }
+        CqlBytes {
+            buf: Some(buf),
+            unset: false,
+        }
     }
 }
 
 impl<'a> CqlFrom<CqlBytes<EasyBuf>, Vec<u8>> for CqlBytes<EasyBuf> {
     unsafe fn unchecked_from(vec: Vec<u8>) -> CqlBytes<EasyBuf> {
-        CqlBytes { buf: Some(vec.into()) }
+        CqlBytes {
+            buf: Some(vec.into()),
+            unset: false,
+        }
     }
 
     fn max_len() -> usize {
@@ -37,7 +48,10 @@ impl<'a> CqlFrom<CqlBytes<EasyBuf>, Vec<u8>> for CqlBytes<EasyBuf> {
 
 impl<'a> CqlFrom<CqlBytes<Vec<u8>>, Vec<u8>> for CqlBytes<Vec<u8>> {
     unsafe fn unchecked_from(vec: Vec<u8>) -> CqlBytes<Vec<u8>> {
-        CqlBytes { buf: Some(vec) }
+        CqlBytes {
+            buf: Some(vec),
+            unset: false,
+        }
     }
 
     fn max_len() -> usize {
@@ -51,6 +65,7 @@ impl<T> CqlBytes<T>
     pub fn len(&self) -> i32 {
         match &self.buf {
             &Some(ref buf) => buf.as_ref().len() as i32,
+            &None if self.unset => -2,
             &None => -1,
         }
     }
@@ -63,7 +78,31 @@ impl<T> CqlBytes<T>
     }
 
     pub fn null_value() -> CqlBytes<T> {
-        CqlBytes { buf: None }
+        CqlBytes {
+            buf: None,
+            unset: false,
+        }
+    }
+
+    /// A bind value which leaves the existing value untouched. It requires protocol v4.
+    pub fn unset_value() -> CqlBytes<T> {
+        CqlBytes {
+            buf: None,
+            unset: true,
+        }
+    }
+
+    pub fn is_unset(&self) -> bool {
+        self.unset
+    }
+}
+
+impl From<CqlBytes<EasyBuf>> for CqlBytes<Vec<u8>> {
+    fn from(bytes: CqlBytes<EasyBuf>) -> CqlBytes<Vec<u8>> {
+        CqlBytes {
+            buf: bytes.buf.map(Into::into),
+            unset: bytes.unset,
+        }
     }
 }
 
@@ -93,4 +132,17 @@ mod test {
         let res = decode::bytes(buf);
         assert_eq!(res.unwrap().1, s);
     }
+
+    #[test]
+    fn unset_value() {
+        let s = CqlBytes::unset_value();
+        let mut buf = Vec::new();
+        encode::bytes(&s, &mut buf);
+        assert_eq!(buf, vec![0xff, 0xff, 0xff, 0xfe]);
+
+        let res = decode::bytes(buf.into()).unwrap().1;
+        assert!(res.is_unset());
+        assert_eq!(res.as_bytes(), None);
+        assert_eq!(res, s);
+    }
 }
@@ -0,0 +1,165 @@
+use super::CqlString;
+use tokio_core::io::EasyBuf;
+
+/// The type of a column as described by an [option] in the metadata of a result.
+/// Native types carry no value, whereas collections, UDTs and tuples describe their
+/// components recursively.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ColumnType {
+    /// The value is the fully qualified class name of the type represented.
+    Custom(CqlString<EasyBuf>),
+    Ascii,
+    Bigint,
+    Blob,
+    Boolean,
+    Counter,
+    Decimal,
+    Double,
+    Float,
+    Int,
+    Timestamp,
+    Uuid,
+    Varchar,
+    Varint,
+    Timeuuid,
+    Inet,
+    /// Since v4.
+    Date,
+    /// Since v4.
+    Time,
+    /// Since v4.
+    Smallint,
+    /// Since v4.
+    Tinyint,
+    List(Box<ColumnType>),
+    Map(Box<ColumnType>, Box<ColumnType>),
+    Set(Box<ColumnType>),
+    Udt(UdtType),
+    Tuple(Vec<ColumnType>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UdtType {
+    pub keyspace: CqlString<EasyBuf>,
+    pub name: CqlString<EasyBuf>,
+    pub fields: Vec<(CqlString<EasyBuf>, ColumnType)>,
+}
+
+impl ColumnType {
+    pub fn id(&self) -> u16 {
+        use self::ColumnType::*;
+        match *self {
+            Custom(_) => 0x0000,
+            Ascii => 0x0001,
+            Bigint => 0x0002,
+            Blob => 0x0003,
+            Boolean => 0x0004,
+            Counter => 0x0005,
+            Decimal => 0x0006,
+            Double => 0x0007,
+            Float => 0x0008,
+            Int => 0x0009,
+            Timestamp => 0x000B,
+            Uuid => 0x000C,
+            Varchar => 0x000D,
+            Varint => 0x000E,
+            Timeuuid => 0x000F,
+            Inet => 0x0010,
+            Date => 0x0011,
+            Time => 0x0012,
+            Smallint => 0x0013,
+            Tinyint => 0x0014,
+            List(_) => 0x0020,
+            Map(_, _) => 0x0021,
+            Set(_) => 0x0022,
+            Udt(_) => 0x0030,
+            Tuple(_) => 0x0031,
+        }
+    }
+
+    /// Returns the type for the given option id, if it is a native type without value.
+    pub fn native_from_id(id: u16) -> Option<ColumnType> {
+        use self::ColumnType::*;
+        Some(match id {
+            0x0001 => Ascii,
+            0x0002 => Bigint,
+            0x0003 => Blob,
+            0x0004 => Boolean,
+            0x0005 => Counter,
+            0x0006 => Decimal,
+            0x0007 => Double,
+            0x0008 => Float,
+            0x0009 => Int,
+            0x000B => Timestamp,
+            0x000C => Uuid,
+            0x000D => Varchar,
+            0x000E => Varint,
+            0x000F => Timeuuid,
+            0x0010 => Inet,
+            0x0011 => Date,
+            0x0012 => Time,
+            0x0013 => Smallint,
+            0x0014 => Tinyint,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::CqlFrom;
+    use super::super::super::{encode, decode};
+
+    fn roundtrip(t: ColumnType) {
+        let mut buf = Vec::new();
+        encode::option(&t, &mut buf);
+        let (rest, res) = decode::option(buf.into()).unwrap();
+        assert_eq!(rest.len(), 0);
+        assert_eq!(res, t);
+    }
+
+    #[test]
+    fn native() {
+        roundtrip(ColumnType::Varchar);
+        roundtrip(ColumnType::Inet);
+        roundtrip(ColumnType::Date);
+        roundtrip(ColumnType::Tinyint);
+        roundtrip(ColumnType::Custom(cql_string!("org.apache.cassandra.db.marshal.DateType")));
+    }
+
+    #[test]
+    fn nested() {
+        roundtrip(ColumnType::Map(Box::new(ColumnType::Varchar),
+                                  Box::new(ColumnType::List(Box::new(ColumnType::Int)))));
+        roundtrip(ColumnType::Tuple(vec![ColumnType::Int, ColumnType::Set(Box::new(ColumnType::Uuid))]));
+        roundtrip(ColumnType::Udt(UdtType {
+            keyspace: cql_string!("ks"),
+            name: cql_string!("address"),
+            fields: vec![(cql_string!("street"), ColumnType::Varchar), (cql_string!("zip"), ColumnType::Int)],
+        }));
+    }
+
+    #[test]
+    fn unknown_id() {
+        assert!(decode::option(vec![0x00, 0x0A].into()).is_err());
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let list_of = |depth: usize| {
+            let mut buf = Vec::new();
+            for _ in 0..depth {
+                buf.extend(&[0x00, 0x20]);
+            }
+            buf.extend(&[0x00, 0x09]);
+            buf
+        };
+        assert!(decode::option(list_of(decode::MAX_TYPE_NESTING).into()).is_ok());
+        assert_eq!(decode::option(list_of(decode::MAX_TYPE_NESTING + 1).into()).unwrap_err(),
+                   decode::Error::ParseError(format!("Column types may be nested at most {} levels deep",
+                                                     decode::MAX_TYPE_NESTING)));
+        // Deep enough to overflow the stack if the nesting was not limited.
+        assert!(decode::option(list_of(2_000_000).into()).is_err());
+    }
+}
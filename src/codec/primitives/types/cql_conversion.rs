@@ -0,0 +1,242 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::net::IpAddr;
+use super::{CqlValue, CqlBytes, Result, ErrorKind};
+
+/// Converts a rust value into a CQL value, which is `None` for null. Fails for values that
+/// cannot be represented, like collections containing null.
+pub trait ToCql {
+    fn to_cql(&self) -> Result<Option<CqlValue>>;
+
+    /// Returns the serialized value, suitable to be used as bind parameter of a query.
+    fn to_cql_bytes(&self) -> Result<CqlBytes<Vec<u8>>> {
+        match self.to_cql()? {
+            Some(v) => v.to_cql_bytes(),
+            None => Ok(CqlBytes::null_value()),
+        }
+    }
+}
+
+/// Converts a CQL value, which is `None` for null, of the given column into a rust value.
+pub trait FromCql: Sized {
+    fn from_cql(column: &str, value: Option<CqlValue>) -> Result<Self>;
+}
+
+fn mismatch<T>(column: &str, expected: &str, value: Option<CqlValue>) -> Result<T> {
+    Err(ErrorKind::TypeMismatch(column.into(), expected.into(), format!("{:?}", value)).into())
+}
+
+macro_rules! impl_cql_conversion {
+    ($t:ty, $expected:expr, $to:path, $($from:path),+) => {
+        impl ToCql for $t {
+            fn to_cql(&self) -> Result<Option<CqlValue>> {
+                Ok(Some($to(self.clone())))
+            }
+        }
+
+        impl FromCql for $t {
+            fn from_cql(column: &str, value: Option<CqlValue>) -> Result<Self> {
+                match value {
+                    $(Some($from(v)))|+ => Ok(v),
+                    v => mismatch(column, $expected, v),
+                }
+            }
+        }
+    };
+}
+
+impl_cql_conversion!(i8, "tinyint", CqlValue::Tinyint, CqlValue::Tinyint);
+impl_cql_conversion!(i16, "smallint", CqlValue::Smallint, CqlValue::Smallint);
+impl_cql_conversion!(i32, "int", CqlValue::Int, CqlValue::Int);
+impl_cql_conversion!(i64,
+                     "bigint",
+                     CqlValue::Bigint,
+                     CqlValue::Bigint,
+                     CqlValue::Counter,
+                     CqlValue::Timestamp,
+                     CqlValue::Time);
+impl_cql_conversion!(f32, "float", CqlValue::Float, CqlValue::Float);
+impl_cql_conversion!(f64, "double", CqlValue::Double, CqlValue::Double);
+impl_cql_conversion!(bool, "boolean", CqlValue::Boolean, CqlValue::Boolean);
+impl_cql_conversion!(String, "varchar", CqlValue::Varchar, CqlValue::Varchar, CqlValue::Ascii);
+impl_cql_conversion!(Vec<u8>, "blob", CqlValue::Blob, CqlValue::Blob);
+impl_cql_conversion!(IpAddr, "inet", CqlValue::Inet, CqlValue::Inet);
+impl_cql_conversion!([u8; 16], "uuid", CqlValue::Uuid, CqlValue::Uuid, CqlValue::Timeuuid);
+
+impl<'a> ToCql for &'a str {
+    fn to_cql(&self) -> Result<Option<CqlValue>> {
+        Ok(Some(CqlValue::Varchar(String::from(*self))))
+    }
+}
+
+impl<T> ToCql for Option<T>
+    where T: ToCql
+{
+    fn to_cql(&self) -> Result<Option<CqlValue>> {
+        match *self {
+            Some(ref v) => v.to_cql(),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<T> FromCql for Option<T>
+    where T: FromCql
+{
+    fn from_cql(column: &str, value: Option<CqlValue>) -> Result<Self> {
+        match value {
+            None => Ok(None),
+            v => T::from_cql(column, v).map(Some),
+        }
+    }
+}
+
+/// Converts a collection element, which must not be null as the protocol cannot represent it.
+fn element_to_cql<T: ToCql>(kind: &str, element: &T) -> Result<CqlValue> {
+    match element.to_cql()? {
+        Some(v) => Ok(v),
+        None => Err(ErrorKind::InvalidValue(kind.into(), "null elements cannot be represented".into()).into()),
+    }
+}
+
+fn elements_to_cql<'a, I, T>(kind: &str, elements: I) -> Result<Vec<CqlValue>>
+    where I: Iterator<Item = &'a T>,
+          T: ToCql + 'a
+{
+    elements.map(|e| element_to_cql(kind, e)).collect()
+}
+
+impl<T> ToCql for Vec<T>
+    where T: ToCql
+{
+    fn to_cql(&self) -> Result<Option<CqlValue>> {
+        Ok(Some(CqlValue::List(elements_to_cql("list", self.iter())?)))
+    }
+}
+
+impl<T> FromCql for Vec<T>
+    where T: FromCql
+{
+    fn from_cql(column: &str, value: Option<CqlValue>) -> Result<Self> {
+        match value {
+            Some(CqlValue::List(elements)) |
+            Some(CqlValue::Set(elements)) => {
+                elements.into_iter().map(|e| T::from_cql(column, Some(e))).collect()
+            }
+            v => mismatch(column, "list", v),
+        }
+    }
+}
+
+impl<T> ToCql for HashSet<T>
+    where T: ToCql + Eq + Hash
+{
+    fn to_cql(&self) -> Result<Option<CqlValue>> {
+        Ok(Some(CqlValue::Set(elements_to_cql("set", self.iter())?)))
+    }
+}
+
+impl<T> FromCql for HashSet<T>
+    where T: FromCql + Eq + Hash
+{
+    fn from_cql(column: &str, value: Option<CqlValue>) -> Result<Self> {
+        match value {
+            Some(CqlValue::Set(elements)) |
+            Some(CqlValue::List(elements)) => {
+                elements.into_iter().map(|e| T::from_cql(column, Some(e))).collect()
+            }
+            v => mismatch(column, "set", v),
+        }
+    }
+}
+
+impl<K, V> ToCql for HashMap<K, V>
+    where K: ToCql + Eq + Hash,
+          V: ToCql
+{
+    fn to_cql(&self) -> Result<Option<CqlValue>> {
+        Ok(Some(CqlValue::Map(self.iter()
+            .map(|(k, v)| Ok((element_to_cql("map", k)?, element_to_cql("map", v)?)))
+            .collect::<Result<_>>()?)))
+    }
+}
+
+impl<K, V> FromCql for HashMap<K, V>
+    where K: FromCql + Eq + Hash,
+          V: FromCql
+{
+    fn from_cql(column: &str, value: Option<CqlValue>) -> Result<Self> {
+        match value {
+            Some(CqlValue::Map(entries)) => {
+                entries.into_iter()
+                    .map(|(k, v)| Ok((K::from_cql(column, Some(k))?, V::from_cql(column, Some(v))?)))
+                    .collect()
+            }
+            v => mismatch(column, "map", v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+    use std::fmt::Debug;
+
+    fn roundtrip<T>(v: T)
+        where T: ToCql + FromCql + PartialEq + Debug
+    {
+        assert_eq!(T::from_cql("c", v.to_cql().unwrap()).unwrap(), v);
+    }
+
+    #[test]
+    fn natives() {
+        roundtrip(-5i8);
+        roundtrip(5i16);
+        roundtrip(5i32);
+        roundtrip(-5i64);
+        roundtrip(1.5f32);
+        roundtrip(2.5f64);
+        roundtrip(true);
+        roundtrip(String::from("abc"));
+        roundtrip(vec![0u8, 1, 2]);
+        roundtrip(IpAddr::from_str("10.0.0.1").unwrap());
+        roundtrip([3u8; 16]);
+        assert_eq!(<[u8; 16]>::from_cql("c", Some(CqlValue::Timeuuid([1; 16]))).unwrap(), [1; 16]);
+        assert_eq!("abc".to_cql().unwrap(), Some(CqlValue::Varchar("abc".into())));
+    }
+
+    #[test]
+    fn optional() {
+        roundtrip(Some(5i32));
+        roundtrip(None::<i32>);
+        assert_eq!(None::<i32>.to_cql_bytes().unwrap(), CqlBytes::null_value());
+        assert!(i32::from_cql("c", None).is_err());
+    }
+
+    #[test]
+    fn collections() {
+        roundtrip(vec![String::from("a"), String::from("b")]);
+        roundtrip(vec![1i32, 2]);
+        roundtrip([1i64, 2].iter().cloned().collect::<HashSet<_>>());
+        roundtrip([(String::from("a"), 1i32)].iter().cloned().collect::<HashMap<_, _>>());
+    }
+
+    #[test]
+    fn null_elements_are_rejected() {
+        assert!(vec![Some(1i32), None].to_cql().is_err());
+        assert!(vec![Some(1i32), None].to_cql_bytes().is_err());
+        assert!([Some(1i32), None].iter().cloned().collect::<HashSet<_>>().to_cql().is_err());
+        assert!([(String::from("a"), None::<i32>)].iter().cloned().collect::<HashMap<_, _>>().to_cql().is_err());
+        assert!([(None::<i32>, 1i32)].iter().cloned().collect::<HashMap<_, _>>().to_cql().is_err());
+        assert_eq!(vec![Some(1i32)].to_cql().unwrap(), Some(CqlValue::List(vec![CqlValue::Int(1)])));
+    }
+
+    #[test]
+    fn mismatch_names_column() {
+        let err = i32::from_cql("age", Some(CqlValue::Varchar("x".into()))).unwrap_err();
+        assert!(format!("{}", err).contains("age"));
+        let err = Vec::<i32>::from_cql("ages", Some(CqlValue::List(vec![CqlValue::Bigint(1)]))).unwrap_err();
+        assert!(format!("{}", err).contains("ages"));
+    }
+}
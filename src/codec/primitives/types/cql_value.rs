@@ -0,0 +1,430 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use byteorder::{ByteOrder, BigEndian};
+use super::{ColumnType, CqlBytes, CqlFrom, Result, ErrorKind};
+
+/// A typed CQL value, as serialized within a [bytes] cell.
+/// Null values are represented by `None` wherever they are allowed, which is why
+/// UDT fields and tuple components are optional.
+#[derive(Debug, PartialEq, Clone)]
+pub enum CqlValue {
+    /// The raw bytes of a value of a custom type.
+    Custom(Vec<u8>),
+    Ascii(String),
+    Bigint(i64),
+    Blob(Vec<u8>),
+    Boolean(bool),
+    Counter(i64),
+    /// Represents `unscaled * 10 ^ (-1 * scale)`, where unscaled is a varint.
+    Decimal { scale: i32, unscaled: Vec<u8> },
+    Double(f64),
+    Float(f32),
+    Int(i32),
+    /// Milliseconds since the unix epoch.
+    Timestamp(i64),
+    Uuid([u8; 16]),
+    Varchar(String),
+    /// A two's complement, big-endian encoded integer of arbitrary length.
+    Varint(Vec<u8>),
+    Timeuuid([u8; 16]),
+    Inet(IpAddr),
+    /// Days since `-5877641-06-23`, which makes 2^31 the unix epoch.
+    Date(u32),
+    /// Nanoseconds since midnight.
+    Time(i64),
+    Smallint(i16),
+    Tinyint(i8),
+    List(Vec<CqlValue>),
+    Map(Vec<(CqlValue, CqlValue)>),
+    Set(Vec<CqlValue>),
+    Udt(Vec<(String, Option<CqlValue>)>),
+    Tuple(Vec<Option<CqlValue>>),
+}
+
+fn invalid<T>(t: &ColumnType, msg: String) -> Result<T> {
+    Err(ErrorKind::InvalidValue(format!("{:?}", t), msg).into())
+}
+
+fn exact<'a>(t: &ColumnType, b: &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if b.len() != len {
+        return invalid(t, format!("Expected {} bytes, got {}", len, b.len()));
+    }
+    Ok(b)
+}
+
+fn uuid(t: &ColumnType, b: &[u8]) -> Result<[u8; 16]> {
+    let mut id = [0u8; 16];
+    id.copy_from_slice(exact(t, b, 16)?);
+    Ok(id)
+}
+
+/// Reads an [int] length followed by that many bytes, or a null value if the length is negative.
+fn read_bytes<'a>(t: &ColumnType, b: &mut &'a [u8]) -> Result<Option<&'a [u8]>> {
+    let len = read_int(t, b)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    let len = len as usize;
+    if b.len() < len {
+        return invalid(t, format!("Expected {} bytes, got {}", len, b.len()));
+    }
+    let (value, rest) = b.split_at(len);
+    *b = rest;
+    Ok(Some(value))
+}
+
+fn read_int(t: &ColumnType, b: &mut &[u8]) -> Result<i32> {
+    if b.len() < 4 {
+        return invalid(t, format!("Expected 4 bytes, got {}", b.len()));
+    }
+    let n = BigEndian::read_i32(b);
+    *b = &b[4..];
+    Ok(n)
+}
+
+/// Reads the amount of elements of a collection, which must fit into the remaining bytes as each
+/// element takes at least `min_size` bytes. This prevents huge allocations for corrupt counts.
+fn read_count(t: &ColumnType, b: &mut &[u8], min_size: usize) -> Result<usize> {
+    let n = read_int(t, b)?;
+    if n < 0 || n as usize > b.len() / min_size {
+        return invalid(t,
+                       format!("{} elements cannot fit into the remaining {} bytes", n, b.len()));
+    }
+    Ok(n as usize)
+}
+
+fn read_element(t: &ColumnType, b: &mut &[u8]) -> Result<CqlValue> {
+    match read_bytes(t, b)? {
+        Some(v) => CqlValue::decode(t, v),
+        None => invalid(t, "Collections must not contain null values".into()),
+    }
+}
+
+fn string(t: &ColumnType, b: &[u8]) -> Result<String> {
+    String::from_utf8(b.to_vec()).or_else(|e| invalid(t, format!("{}", e)))
+}
+
+impl CqlValue {
+    /// Decodes the content of a non-null [bytes] cell as value of the given type.
+    pub fn decode(t: &ColumnType, b: &[u8]) -> Result<CqlValue> {
+        use self::CqlValue::*;
+        Ok(match *t {
+            ColumnType::Custom(_) => Custom(b.to_vec()),
+            ColumnType::Ascii => {
+                if let Some(c) = b.iter().find(|&&c| c > 127) {
+                    return invalid(t, format!("Byte {:#04x} is not in the ASCII range", c));
+                }
+                Ascii(string(t, b)?)
+            }
+            ColumnType::Bigint => Bigint(BigEndian::read_i64(exact(t, b, 8)?)),
+            ColumnType::Counter => Counter(BigEndian::read_i64(exact(t, b, 8)?)),
+            ColumnType::Timestamp => Timestamp(BigEndian::read_i64(exact(t, b, 8)?)),
+            ColumnType::Blob => Blob(b.to_vec()),
+            ColumnType::Boolean => Boolean(exact(t, b, 1)?[0] != 0),
+            ColumnType::Decimal => {
+                if b.len() < 4 {
+                    return invalid(t, format!("Expected at least 4 bytes, got {}", b.len()));
+                }
+                Decimal {
+                    scale: BigEndian::read_i32(b),
+                    unscaled: b[4..].to_vec(),
+                }
+            }
+            ColumnType::Double => Double(BigEndian::read_f64(exact(t, b, 8)?)),
+            ColumnType::Float => Float(BigEndian::read_f32(exact(t, b, 4)?)),
+            ColumnType::Int => Int(BigEndian::read_i32(exact(t, b, 4)?)),
+            ColumnType::Date => Date(BigEndian::read_u32(exact(t, b, 4)?)),
+            ColumnType::Time => {
+                let v = BigEndian::read_i64(exact(t, b, 8)?);
+                if v < 0 || v >= 86_400_000_000_000 {
+                    return invalid(t, format!("{} nanoseconds are not within a day", v));
+                }
+                Time(v)
+            }
+            ColumnType::Smallint => Smallint(BigEndian::read_i16(exact(t, b, 2)?)),
+            ColumnType::Tinyint => Tinyint(exact(t, b, 1)?[0] as i8),
+            ColumnType::Uuid => Uuid(uuid(t, b)?),
+            ColumnType::Timeuuid => Timeuuid(uuid(t, b)?),
+            ColumnType::Varchar => Varchar(string(t, b)?),
+            ColumnType::Varint => Varint(b.to_vec()),
+            ColumnType::Inet => {
+                match b.len() {
+                    4 => Inet(IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3]))),
+                    16 => {
+                        let mut s = [0u16; 8];
+                        for (i, s) in s.iter_mut().enumerate() {
+                            *s = BigEndian::read_u16(&b[i * 2..]);
+                        }
+                        Inet(IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7])))
+                    }
+                    l => return invalid(t, format!("Expected 4 or 16 bytes, got {}", l)),
+                }
+            }
+            ColumnType::List(ref et) |
+            ColumnType::Set(ref et) => {
+                let mut b = b;
+                let n = read_count(t, &mut b, 4)?;
+                let mut elements = Vec::with_capacity(n);
+                for _ in 0..n {
+                    elements.push(read_element(et, &mut b)?);
+                }
+                match *t {
+                    ColumnType::List(_) => List(elements),
+                    _ => Set(elements),
+                }
+            }
+            ColumnType::Map(ref kt, ref vt) => {
+                let mut b = b;
+                let n = read_count(t, &mut b, 8)?;
+                let mut entries = Vec::with_capacity(n);
+                for _ in 0..n {
+                    let k = read_element(kt, &mut b)?;
+                    let v = read_element(vt, &mut b)?;
+                    entries.push((k, v));
+                }
+                Map(entries)
+            }
+            ColumnType::Udt(ref udt) => {
+                let mut b = b;
+                let mut fields = Vec::with_capacity(udt.fields.len());
+                for &(ref name, ref ft) in &udt.fields {
+                    if b.is_empty() {
+                        break;
+                    }
+                    let value = match read_bytes(ft, &mut b)? {
+                        Some(v) => Some(CqlValue::decode(ft, v)?),
+                        None => None,
+                    };
+                    fields.push((String::from(name.as_ref()), value));
+                }
+                Udt(fields)
+            }
+            ColumnType::Tuple(ref types) => {
+                let mut b = b;
+                let mut values = Vec::with_capacity(types.len());
+                for ct in types {
+                    values.push(match read_bytes(ct, &mut b)? {
+                        Some(v) => Some(CqlValue::decode(ct, v)?),
+                        None => None,
+                    });
+                }
+                Tuple(values)
+            }
+        })
+    }
+
+    /// Decodes the given cell, which is `None` if it is null.
+    pub fn decode_cell<T>(t: &ColumnType, cell: &CqlBytes<T>) -> Result<Option<CqlValue>>
+        where T: AsRef<[u8]>
+    {
+        match cell.as_bytes() {
+            Some(b) => CqlValue::decode(t, b).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Encodes this value without any length prefix, as it is the content of a [bytes] cell.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        use self::CqlValue::*;
+        match *self {
+            Custom(ref b) | Blob(ref b) | Varint(ref b) => buf.extend(b),
+            Ascii(ref s) | Varchar(ref s) => buf.extend(s.as_bytes()),
+            Bigint(v) | Counter(v) | Timestamp(v) | Time(v) => write_long(v, buf),
+            Boolean(v) => buf.push(if v { 1 } else { 0 }),
+            Decimal { scale, ref unscaled } => {
+                write_int(scale, buf);
+                buf.extend(unscaled);
+            }
+            Double(v) => {
+                let mut b = [0u8; 8];
+                BigEndian::write_f64(&mut b, v);
+                buf.extend(&b[..]);
+            }
+            Float(v) => {
+                let mut b = [0u8; 4];
+                BigEndian::write_f32(&mut b, v);
+                buf.extend(&b[..]);
+            }
+            Int(v) => write_int(v, buf),
+            Date(v) => {
+                let mut b = [0u8; 4];
+                BigEndian::write_u32(&mut b, v);
+                buf.extend(&b[..]);
+            }
+            Smallint(v) => {
+                let mut b = [0u8; 2];
+                BigEndian::write_i16(&mut b, v);
+                buf.extend(&b[..]);
+            }
+            Tinyint(v) => buf.push(v as u8),
+            Uuid(ref id) | Timeuuid(ref id) => buf.extend(&id[..]),
+            Inet(IpAddr::V4(ref ip)) => buf.extend(&ip.octets()[..]),
+            Inet(IpAddr::V6(ref ip)) => buf.extend(&ip.octets()[..]),
+            List(ref elements) | Set(ref elements) => {
+                write_int(elements.len() as i32, buf);
+                for e in elements {
+                    write_value(Some(e), buf);
+                }
+            }
+            Map(ref entries) => {
+                write_int(entries.len() as i32, buf);
+                for &(ref k, ref v) in entries {
+                    write_value(Some(k), buf);
+                    write_value(Some(v), buf);
+                }
+            }
+            Udt(ref fields) => {
+                for &(_, ref v) in fields {
+                    write_value(v.as_ref(), buf);
+                }
+            }
+            Tuple(ref values) => {
+                for v in values {
+                    write_value(v.as_ref(), buf);
+                }
+            }
+        }
+    }
+
+    /// Returns this value as cell, suitable to be used as value of a query.
+    pub fn to_cql_bytes(&self) -> Result<CqlBytes<Vec<u8>>> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        CqlBytes::try_from(buf)
+    }
+}
+
+fn write_int(v: i32, buf: &mut Vec<u8>) {
+    let mut b = [0u8; 4];
+    BigEndian::write_i32(&mut b, v);
+    buf.extend(&b[..]);
+}
+
+fn write_long(v: i64, buf: &mut Vec<u8>) {
+    let mut b = [0u8; 8];
+    BigEndian::write_i64(&mut b, v);
+    buf.extend(&b[..]);
+}
+
+/// Writes the value with [int] length prefix, or a null value if there is none.
+fn write_value(v: Option<&CqlValue>, buf: &mut Vec<u8>) {
+    match v {
+        Some(v) => {
+            let at = buf.len();
+            write_int(0, buf);
+            v.encode(buf);
+            let len = (buf.len() - at - 4) as i32;
+            BigEndian::write_i32(&mut buf[at..at + 4], len);
+        }
+        None => write_int(-1, buf),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::{CqlString, UdtType};
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    fn roundtrip(t: ColumnType, v: CqlValue) {
+        let mut buf = Vec::new();
+        v.encode(&mut buf);
+        assert_eq!(CqlValue::decode(&t, &buf).unwrap(), v);
+    }
+
+    #[test]
+    fn native() {
+        roundtrip(ColumnType::Ascii, CqlValue::Ascii("abc".into()));
+        roundtrip(ColumnType::Varchar, CqlValue::Varchar("Hello üß".into()));
+        roundtrip(ColumnType::Bigint, CqlValue::Bigint(-5));
+        roundtrip(ColumnType::Counter, CqlValue::Counter(5));
+        roundtrip(ColumnType::Timestamp, CqlValue::Timestamp(1486294317376));
+        roundtrip(ColumnType::Blob, CqlValue::Blob(vec![0, 1, 2]));
+        roundtrip(ColumnType::Boolean, CqlValue::Boolean(true));
+        roundtrip(ColumnType::Double, CqlValue::Double(1.5));
+        roundtrip(ColumnType::Float, CqlValue::Float(-1.5));
+        roundtrip(ColumnType::Int, CqlValue::Int(-342));
+        roundtrip(ColumnType::Uuid, CqlValue::Uuid([7; 16]));
+        roundtrip(ColumnType::Timeuuid, CqlValue::Timeuuid([1; 16]));
+        roundtrip(ColumnType::Varint, CqlValue::Varint(vec![0xff, 0x7f]));
+        roundtrip(ColumnType::Decimal,
+                  CqlValue::Decimal {
+                      scale: 2,
+                      unscaled: vec![0x00, 0x80],
+                  });
+        roundtrip(ColumnType::Inet,
+                  CqlValue::Inet(IpAddr::from_str("172.17.0.2").unwrap()));
+        roundtrip(ColumnType::Inet, CqlValue::Inet(IpAddr::from_str("::1").unwrap()));
+        roundtrip(ColumnType::Custom(CqlString::try_from("a.B").unwrap()),
+                  CqlValue::Custom(vec![1]));
+    }
+
+    #[test]
+    fn native_v4() {
+        roundtrip(ColumnType::Date, CqlValue::Date(1 << 31));
+        roundtrip(ColumnType::Time, CqlValue::Time(86_399_999_999_999));
+        roundtrip(ColumnType::Smallint, CqlValue::Smallint(-2));
+        roundtrip(ColumnType::Tinyint, CqlValue::Tinyint(-128));
+        assert!(CqlValue::decode(&ColumnType::Time, &[0xff; 8]).is_err());
+        assert!(CqlValue::decode(&ColumnType::Smallint, &[0]).is_err());
+    }
+
+    #[test]
+    fn collections() {
+        roundtrip(ColumnType::List(Box::new(ColumnType::Int)),
+                  CqlValue::List(vec![CqlValue::Int(1), CqlValue::Int(2)]));
+        roundtrip(ColumnType::Set(Box::new(ColumnType::Varchar)),
+                  CqlValue::Set(vec![CqlValue::Varchar("a".into())]));
+        roundtrip(ColumnType::Map(Box::new(ColumnType::Varchar), Box::new(ColumnType::Bigint)),
+                  CqlValue::Map(vec![(CqlValue::Varchar("a".into()), CqlValue::Bigint(1))]));
+    }
+
+    #[test]
+    fn composites() {
+        let udt = ColumnType::Udt(UdtType {
+            keyspace: CqlString::try_from("ks").unwrap(),
+            name: CqlString::try_from("address").unwrap(),
+            fields: vec![(CqlString::try_from("street").unwrap(), ColumnType::Varchar),
+                         (CqlString::try_from("zip").unwrap(), ColumnType::Int)],
+        });
+        roundtrip(udt.clone(),
+                  CqlValue::Udt(vec![("street".into(), Some(CqlValue::Varchar("main".into()))),
+                                     ("zip".into(), None)]));
+        // values may have less fields than the type
+        roundtrip(udt,
+                  CqlValue::Udt(vec![("street".into(), Some(CqlValue::Varchar("main".into())))]));
+        roundtrip(ColumnType::Tuple(vec![ColumnType::Int, ColumnType::Blob]),
+                  CqlValue::Tuple(vec![None, Some(CqlValue::Blob(vec![1]))]));
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(CqlValue::decode(&ColumnType::Int, &[0, 1]).is_err());
+        assert!(CqlValue::decode(&ColumnType::Ascii, &[200]).is_err());
+        assert!(CqlValue::decode(&ColumnType::Varchar, &[0xff, 0xfe]).is_err());
+        assert!(CqlValue::decode(&ColumnType::Inet, &[1, 2, 3]).is_err());
+        assert!(CqlValue::decode(&ColumnType::List(Box::new(ColumnType::Int)),
+                                 &[0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff])
+            .is_err());
+    }
+
+    #[test]
+    fn impossible_element_counts() {
+        let list = ColumnType::List(Box::new(ColumnType::Int));
+        assert!(CqlValue::decode(&list, &[0x7f, 0xff, 0xff, 0xff]).is_err());
+        assert!(CqlValue::decode(&list, &[0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(CqlValue::decode(&list, &[0, 0, 0, 2, 0, 0, 0, 0]).is_err());
+        let map = ColumnType::Map(Box::new(ColumnType::Int), Box::new(ColumnType::Int));
+        assert!(CqlValue::decode(&map, &[0, 0, 0, 1, 0, 0, 0, 0]).is_err());
+        assert_eq!(CqlValue::decode(&list, &[0, 0, 0, 0]).unwrap(), CqlValue::List(vec![]));
+    }
+
+    #[test]
+    fn null_cell() {
+        assert_eq!(CqlValue::decode_cell(&ColumnType::Int, &CqlBytes::<Vec<u8>>::null_value()).unwrap(),
+                   None);
+        let cell = CqlValue::Int(3).to_cql_bytes().unwrap();
+        assert_eq!(CqlValue::decode_cell(&ColumnType::Int, &cell).unwrap(),
+                   Some(CqlValue::Int(3)));
+    }
+}
@@ -18,13 +18,30 @@ pub use self::cql_consistency::*;
 mod collections;
 pub use self::collections::*;
 
+mod cql_column_type;
+pub use self::cql_column_type::*;
+
+mod cql_value;
+pub use self::cql_value::*;
+
+mod cql_conversion;
+pub use self::cql_conversion::*;
+
 
 
 error_chain! {
     errors {
         MaximumLengthExceeded(l: usize) {
           description("Too many elements container")
-          display("Expected not more than {} elements, got {}.", u16::max_value(), l)
+          display("Got {} elements, which is more than the protocol allows.", l)
+        }
+        InvalidValue(t: String, msg: String) {
+          description("A value could not be decoded as its column type")
+          display("Invalid value of type {}: {}", t, msg)
+        }
+        TypeMismatch(column: String, expected: String, got: String) {
+          description("A value could not be converted into the requested type")
+          display("Column '{}' was expected to be of type {}, got {}", column, expected, got)
         }
     }
 }
@@ -33,7 +50,7 @@ pub trait CqlFrom<C, V>
     where V: HasLength
 {
     fn try_from(s: V) -> Result<C> {
-        match s.length() > u16::max_value() as usize {
+        match s.length() > Self::max_len() {
             true => Err(ErrorKind::MaximumLengthExceeded(s.length()).into()),
             false => {
                 Ok({
@@ -1,14 +1,18 @@
-use codec::header::{ProtocolVersion, OpCode, Header, Version};
+use codec::header::{ProtocolVersion, OpCode, Header, Version, FLAG_COMPRESSION, FLAG_CUSTOM_PAYLOAD};
 use std::collections::HashMap;
 
-use codec::primitives::{BVec, CqlConsistency, CqlFrom, CqlStringMap, CqlString, CqlBytes, CqlLongString};
-use codec::primitives::encode;
+use codec::primitives::{BVec, CqlConsistency, CqlFrom, CqlStringMap, CqlStringList, CqlString, CqlBytes, CqlLongString,
+                        ToCql};
+use codec::primitives::{encode, decode};
+use codec::compression::Compression;
 
 error_chain! {
     foreign_links {
         Io(::std::io::Error);
         HeaderError(::codec::header::Error);
         PrimitiveError(::codec::primitives::Error);
+        CompressionError(::codec::compression::Error);
+        DecodeError(::codec::primitives::decode::Error);
     }
     errors {
         BodyLengthExceeded(len: usize) {
@@ -17,6 +21,18 @@ error_chain! {
             display("The current body length {} exceeded the \
             maximum allowed length for a body", len)
         }
+        UnsetValueNotSupported(v: ProtocolVersion) {
+            description("Unset values are not supported by the protocol version")
+            display("Unset values require protocol version 4 or higher, got {:?}", v)
+        }
+        UnexpectedOpCode(code: OpCode) {
+            description("The op-code does not belong to a request")
+            display("{:?} is not the op-code of a request", code)
+        }
+        CustomPayloadNotSupported(v: ProtocolVersion) {
+            description("Custom payloads are not supported by the protocol version")
+            display("Custom payloads require protocol version 4 or higher, got {:?}", v)
+        }
     }
 }
 
@@ -24,12 +40,29 @@ pub trait CqlEncode {
     fn encode(&self, v: ProtocolVersion, f: &mut Vec<u8>) -> Result<usize>;
 }
 
+/// Decodes a request, as done by servers.
+pub trait CqlDecode<T> {
+    fn decode(v: ProtocolVersion, buf: EasyBuf) -> Result<T>;
+}
+
+fn long_string_from(s: CqlLongString<EasyBuf>) -> CqlLongString<BVec> {
+    unsafe { CqlLongString::unchecked_from(s.as_ref()) }
+}
+
+fn string_from(s: CqlString<EasyBuf>) -> CqlString<BVec> {
+    unsafe { CqlString::unchecked_from(s.as_ref()) }
+}
+
 #[derive(Debug)]
 pub enum Message {
     Options,
     Startup(StartupMessage),
     AuthResponse(AuthResponseMessage),
     Query(QueryMessage),
+    Prepare(PrepareMessage),
+    Execute(ExecuteMessage),
+    Batch(BatchMessage),
+    Register(RegisterMessage),
 }
 
 use tokio_core::io::EasyBuf;
@@ -59,6 +92,18 @@ impl CqlEncode for StartupMessage {
     }
 }
 
+impl CqlDecode<StartupMessage> for StartupMessage {
+    fn decode(_v: ProtocolVersion, buf: EasyBuf) -> Result<StartupMessage> {
+        let (_, options) = decode::string_map(buf)?;
+        let option = |name| options.get(&unsafe { CqlString::unchecked_from(name) }).cloned();
+        Ok(StartupMessage {
+            cql_version: option("CQL_VERSION")
+                .ok_or_else(|| decode::Error::ParseError("STARTUP lacks the CQL_VERSION option".into()))?,
+            compression: option("COMPRESSION"),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct AuthResponseMessage {
     pub auth_data: CqlBytes<BVec>,
@@ -78,24 +123,39 @@ pub enum QueryValues {
     Named(HashMap<CqlString<BVec>, CqlBytes<BVec>>),
 }
 
+impl QueryValues {
+    /// Creates positional values from the given rust values, in order of the bind markers.
+    pub fn positional(values: &[&ToCql]) -> Result<QueryValues> {
+        Ok(QueryValues::Positional(values.iter().map(|v| v.to_cql_bytes()).collect::<::std::result::Result<_, _>>()?))
+    }
+
+    /// Creates named values from the given pairs of bind marker names and rust values.
+    pub fn named(values: &[(&str, &ToCql)]) -> Result<QueryValues> {
+        let mut m = HashMap::with_capacity(values.len());
+        for &(name, value) in values {
+            m.insert(CqlString::try_from(name)?, value.to_cql_bytes()?);
+        }
+        Ok(QueryValues::Named(m))
+    }
+}
+
 impl CqlEncode for QueryValues {
-    fn encode(&self, _v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+    fn encode(&self, v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
         use self::QueryValues::*;
         let len = buf.len();
 
         match self {
             &Positional(ref values) => {
-                // TODO: possible overflow return ERR then
-                buf.extend(&encode::short(values.len() as u16)[..]);
+                buf.extend(&encode::short(checked_short_len(values.len())?)[..]);
                 for value in values {
-                    encode::bytes(value, buf);
+                    encode_value(v, value, buf)?;
                 }
             }
             &Named(ref values) => {
-                buf.extend(&encode::short(values.len() as u16)[..]);
+                buf.extend(&encode::short(checked_short_len(values.len())?)[..]);
                 for (key, value) in values {
                     encode::string(key, buf);
-                    encode::bytes(value, buf);
+                    encode_value(v, value, buf)?;
                 }
             }
         }
@@ -104,9 +164,46 @@ impl CqlEncode for QueryValues {
     }
 }
 
+impl CqlDecode<AuthResponseMessage> for AuthResponseMessage {
+    fn decode(_v: ProtocolVersion, buf: EasyBuf) -> Result<AuthResponseMessage> {
+        let (_, auth_data) = decode::bytes(buf)?;
+        Ok(AuthResponseMessage { auth_data: auth_data.into() })
+    }
+}
+
+impl QueryValues {
+    /// Decodes `<n><value_1>...<value_n>`, where each value is preceded by its name if `named` is set.
+    fn decode(buf: EasyBuf, named: bool) -> decode::ParseResult<QueryValues> {
+        let (mut buf, n) = decode::short(buf)?;
+        if named {
+            let mut values = HashMap::with_capacity(n as usize);
+            for _ in 0..n {
+                let (nb, name) = decode::string(buf)?;
+                let (nb, value) = decode::bytes(nb)?;
+                buf = nb;
+                values.insert(string_from(name), value.into());
+            }
+            Ok((buf, QueryValues::Named(values)))
+        } else {
+            let (buf, values) = decode_values(buf, n)?;
+            Ok((buf, QueryValues::Positional(values)))
+        }
+    }
+}
+
+fn decode_values(mut buf: EasyBuf, n: u16) -> decode::ParseResult<Vec<CqlBytes<BVec>>> {
+    let mut values = Vec::with_capacity(n as usize);
+    for _ in 0..n {
+        let (nb, value) = decode::bytes(buf)?;
+        buf = nb;
+        values.push(value.into());
+    }
+    Ok((buf, values))
+}
+
+/// The parameters shared by QUERY and EXECUTE messages, encoded as `<query_parameters>`.
 #[derive(Debug)]
-pub struct QueryMessage {
-    pub query: CqlLongString<BVec>,
+pub struct QueryParameters {
     pub values: Option<QueryValues>,
     pub consistency: CqlConsistency,
     pub skip_metadata: bool,
@@ -116,15 +213,16 @@ pub struct QueryMessage {
     pub timestamp: Option<i64>,
 }
 
-impl CqlEncode for QueryMessage {
+impl CqlEncode for QueryParameters {
     fn encode(&self, version: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
         let l = buf.len();
-        encode::long_string(&self.query, buf);
         buf.extend(&encode::consistency(&self.consistency)[..]);
 
         buf.push(self.compute_flags());
 
-        self.values.as_ref().map(|v| v.encode(version, buf));
+        if let Some(ref values) = self.values {
+            values.encode(version, buf)?;
+        }
         self.page_size.map(|v| buf.extend(&encode::int(v)[..]));
         self.paging_state.as_ref().map(|v| encode::bytes(v, buf));
         self.serial_consistency.as_ref().map(|v| buf.extend(&encode::consistency(&v)[..]));
@@ -134,7 +232,55 @@ impl CqlEncode for QueryMessage {
     }
 }
 
-impl QueryMessage {
+impl QueryParameters {
+    /// Decodes `<consistency><flags>[<n>[name_1]<value_1>...][<result_page_size>][<paging_state>]
+    /// [<serial_consistency>][<timestamp>]`.
+    pub fn decode(buf: EasyBuf) -> decode::ParseResult<QueryParameters> {
+        let (buf, consistency) = decode::consistency(buf)?;
+        let (buf, flags) = decode::byte(buf)?;
+        let mut params = QueryParameters {
+            consistency: consistency,
+            skip_metadata: flags & 0x02 == 0x02,
+            ..Default::default()
+        };
+        let buf = if flags & 0x01 == 0x01 {
+            let (buf, values) = QueryValues::decode(buf, flags & 0x40 == 0x40)?;
+            params.values = Some(values);
+            buf
+        } else {
+            buf
+        };
+        let buf = if flags & 0x04 == 0x04 {
+            let (buf, page_size) = decode::int(buf)?;
+            params.page_size = Some(page_size);
+            buf
+        } else {
+            buf
+        };
+        let buf = if flags & 0x08 == 0x08 {
+            let (buf, paging_state) = decode::bytes(buf)?;
+            params.paging_state = Some(paging_state.into());
+            buf
+        } else {
+            buf
+        };
+        let buf = if flags & 0x10 == 0x10 {
+            let (buf, serial_consistency) = decode::consistency(buf)?;
+            params.serial_consistency = Some(serial_consistency);
+            buf
+        } else {
+            buf
+        };
+        let buf = if flags & 0x20 == 0x20 {
+            let (buf, timestamp) = decode::long(buf)?;
+            params.timestamp = Some(timestamp);
+            buf
+        } else {
+            buf
+        };
+        Ok((buf, params))
+    }
+
     pub fn compute_flags(&self) -> u8 {
         let mut flags = 0x00;
 
@@ -147,7 +293,6 @@ impl QueryMessage {
         self.page_size.as_ref().map(|_| flags |= 0x04);
         self.paging_state.as_ref().map(|_| flags |= 0x08);
         self.serial_consistency.as_ref().map(|_| flags |= 0x10);
-        self.page_size.as_ref().map(|_| flags |= 0x04);
         self.timestamp.as_ref().map(|_| flags |= 0x20);
 
         if let Some(QueryValues::Named(_)) = self.values {
@@ -158,10 +303,9 @@ impl QueryMessage {
     }
 }
 
-impl Default for QueryMessage {
+impl Default for QueryParameters {
     fn default() -> Self {
-        QueryMessage {
-            query: CqlLongString::try_from("").unwrap(),
+        QueryParameters {
             values: None,
             consistency: CqlConsistency::One,
             skip_metadata: false,
@@ -173,14 +317,394 @@ impl Default for QueryMessage {
     }
 }
 
+#[derive(Debug)]
+pub struct QueryMessage {
+    pub query: CqlLongString<BVec>,
+    pub parameters: QueryParameters,
+}
+
+impl CqlEncode for QueryMessage {
+    fn encode(&self, version: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        encode::long_string(&self.query, buf);
+        self.parameters.encode(version, buf)?;
+        Ok(buf.len() - l)
+    }
+}
+
+impl CqlDecode<QueryMessage> for QueryMessage {
+    fn decode(_v: ProtocolVersion, buf: EasyBuf) -> Result<QueryMessage> {
+        let (buf, query) = decode::long_string(buf)?;
+        let (_, parameters) = QueryParameters::decode(buf)?;
+        Ok(QueryMessage {
+            query: long_string_from(query),
+            parameters: parameters,
+        })
+    }
+}
+
+impl Default for QueryMessage {
+    fn default() -> Self {
+        QueryMessage {
+            query: CqlLongString::try_from("").unwrap(),
+            parameters: QueryParameters::default(),
+        }
+    }
+}
+
+/// Prepares a query for later execution through EXECUTE.
+#[derive(Debug)]
+pub struct PrepareMessage {
+    pub query: CqlLongString<BVec>,
+}
+
+impl CqlEncode for PrepareMessage {
+    fn encode(&self, _v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        encode::long_string(&self.query, buf);
+        Ok(buf.len() - l)
+    }
+}
+
+impl CqlDecode<PrepareMessage> for PrepareMessage {
+    fn decode(_v: ProtocolVersion, buf: EasyBuf) -> Result<PrepareMessage> {
+        let (_, query) = decode::long_string(buf)?;
+        Ok(PrepareMessage { query: long_string_from(query) })
+    }
+}
+
+/// Executes a statement previously prepared through PREPARE.
+#[derive(Debug)]
+pub struct ExecuteMessage {
+    /// The id of the prepared statement, as returned in the Prepared result.
+    pub id: CqlBytes<BVec>,
+    pub parameters: QueryParameters,
+}
+
+impl CqlEncode for ExecuteMessage {
+    fn encode(&self, version: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        encode::short_bytes(self.id.as_bytes().unwrap_or(&[]), buf)?;
+        self.parameters.encode(version, buf)?;
+        Ok(buf.len() - l)
+    }
+}
+
+impl CqlDecode<ExecuteMessage> for ExecuteMessage {
+    fn decode(_v: ProtocolVersion, buf: EasyBuf) -> Result<ExecuteMessage> {
+        let (buf, id) = decode::short_bytes(buf)?;
+        let (_, parameters) = QueryParameters::decode(buf)?;
+        Ok(ExecuteMessage {
+            id: id.into(),
+            parameters: parameters,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BatchType {
+    /// Equivalent to a normal CQL3 batch statement.
+    Logged,
+    Unlogged,
+    /// Only counter statements are accepted in such a batch.
+    Counter,
+}
+
+impl BatchType {
+    pub fn as_u8(&self) -> u8 {
+        match *self {
+            BatchType::Logged => 0x00,
+            BatchType::Unlogged => 0x01,
+            BatchType::Counter => 0x02,
+        }
+    }
+
+    pub fn from_u8(b: u8) -> Option<BatchType> {
+        match b {
+            0x00 => Some(BatchType::Logged),
+            0x01 => Some(BatchType::Unlogged),
+            0x02 => Some(BatchType::Counter),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BatchStatement {
+    /// A query string, which may contain bind markers.
+    Query(CqlLongString<BVec>),
+    /// The id of a prepared statement.
+    Prepared(CqlBytes<BVec>),
+}
+
+/// A single statement of a batch, along with the values for its bind markers.
+#[derive(Debug)]
+pub struct BatchQuery {
+    pub statement: BatchStatement,
+    pub values: Vec<CqlBytes<BVec>>,
+}
+
+impl CqlEncode for BatchQuery {
+    fn encode(&self, v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        match self.statement {
+            BatchStatement::Query(ref query) => {
+                buf.push(0x00);
+                encode::long_string(query, buf);
+            }
+            BatchStatement::Prepared(ref id) => {
+                buf.push(0x01);
+                encode::short_bytes(id.as_bytes().unwrap_or(&[]), buf)?;
+            }
+        }
+        buf.extend(&encode::short(checked_short_len(self.values.len())?)[..]);
+        for value in &self.values {
+            encode_value(v, value, buf)?;
+        }
+        Ok(buf.len() - l)
+    }
+}
+
+impl BatchQuery {
+    /// Decodes `<kind><string_or_id><n>[<name_1>]<value_1>...[<name_n>]<value_n>`.
+    fn decode(buf: EasyBuf) -> decode::ParseResult<BatchQuery> {
+        let (buf, kind) = decode::byte(buf)?;
+        let (buf, statement) = match kind {
+            0x00 => {
+                let (buf, query) = decode::long_string(buf)?;
+                (buf, BatchStatement::Query(long_string_from(query)))
+            }
+            0x01 => {
+                let (buf, id) = decode::short_bytes(buf)?;
+                (buf, BatchStatement::Prepared(id.into()))
+            }
+            _ => return Err(decode::Error::ParseError(format!("Unknown batch query kind {}", kind))),
+        };
+        let (buf, n) = decode::short(buf)?;
+        let (buf, values) = decode_values(buf, n)?;
+        Ok((buf,
+            BatchQuery {
+                statement: statement,
+                values: values,
+            }))
+    }
+}
+
+/// Executes multiple DML statements, plain or prepared ones, as a single batch.
+///
+/// Named values are not supported, as the protocol does not allow servers to implement them.
+#[derive(Debug)]
+pub struct BatchMessage {
+    pub batch_type: BatchType,
+    pub queries: Vec<BatchQuery>,
+    pub consistency: CqlConsistency,
+    pub serial_consistency: Option<CqlConsistency>,
+    pub timestamp: Option<i64>,
+}
+
+impl BatchMessage {
+    pub fn new(batch_type: BatchType) -> BatchMessage {
+        BatchMessage { batch_type: batch_type, ..Default::default() }
+    }
+
+    /// Adds the given query string, which is executed with the given values.
+    pub fn add_query<S>(&mut self, query: S, values: Vec<CqlBytes<BVec>>) -> Result<&mut Self>
+        where S: AsRef<str>
+    {
+        self.queries.push(BatchQuery {
+            statement: BatchStatement::Query(CqlLongString::try_from(query.as_ref())?),
+            values: values,
+        });
+        Ok(self)
+    }
+
+    /// Adds the statement with the given prepared id, which is executed with the given values.
+    pub fn add_prepared(&mut self, id: CqlBytes<BVec>, values: Vec<CqlBytes<BVec>>) -> &mut Self {
+        self.queries.push(BatchQuery {
+            statement: BatchStatement::Prepared(id),
+            values: values,
+        });
+        self
+    }
+
+    pub fn compute_flags(&self) -> u8 {
+        let mut flags = 0x00;
+
+        self.serial_consistency.as_ref().map(|_| flags |= 0x10);
+        self.timestamp.as_ref().map(|_| flags |= 0x20);
+
+        flags
+    }
+}
+
+impl Default for BatchMessage {
+    fn default() -> Self {
+        BatchMessage {
+            batch_type: BatchType::Logged,
+            queries: Vec::new(),
+            consistency: CqlConsistency::One,
+            serial_consistency: None,
+            timestamp: None,
+        }
+    }
+}
+
+impl CqlDecode<BatchMessage> for BatchMessage {
+    fn decode(_v: ProtocolVersion, buf: EasyBuf) -> Result<BatchMessage> {
+        let (buf, batch_type) = decode::byte(buf)?;
+        let batch_type = BatchType::from_u8(batch_type)
+            .ok_or_else(|| decode::Error::ParseError(format!("Unknown batch type {}", batch_type)))?;
+        let (mut buf, n) = decode::short(buf)?;
+        let mut queries = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+            let (nb, query) = BatchQuery::decode(buf)?;
+            buf = nb;
+            queries.push(query);
+        }
+        let (buf, consistency) = decode::consistency(buf)?;
+        let (buf, flags) = decode::byte(buf)?;
+        if flags & 0x40 == 0x40 {
+            return Err(decode::Error::ParseError("Named values are not supported in batches".into()).into());
+        }
+        let (buf, serial_consistency) = if flags & 0x10 == 0x10 {
+            let (buf, c) = decode::consistency(buf)?;
+            (buf, Some(c))
+        } else {
+            (buf, None)
+        };
+        let timestamp = if flags & 0x20 == 0x20 {
+            Some(decode::long(buf)?.1)
+        } else {
+            None
+        };
+        Ok(BatchMessage {
+            batch_type: batch_type,
+            queries: queries,
+            consistency: consistency,
+            serial_consistency: serial_consistency,
+            timestamp: timestamp,
+        })
+    }
+}
+
+impl CqlEncode for BatchMessage {
+    fn encode(&self, version: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        buf.push(self.batch_type.as_u8());
+        buf.extend(&encode::short(checked_short_len(self.queries.len())?)[..]);
+        for query in &self.queries {
+            query.encode(version, buf)?;
+        }
+        buf.extend(&encode::consistency(&self.consistency)[..]);
+        buf.push(self.compute_flags());
+        self.serial_consistency.as_ref().map(|v| buf.extend(&encode::consistency(&v)[..]));
+        self.timestamp.map(|v| buf.extend(&encode::long(v)[..]));
+        Ok(buf.len() - l)
+    }
+}
+
+/// Encodes a bind value as [value], which may only be `not set` since v4.
+fn encode_value(v: ProtocolVersion, value: &CqlBytes<BVec>, buf: &mut Vec<u8>) -> Result<()> {
+    if value.is_unset() && v == ProtocolVersion::Version3 {
+        return Err(ErrorKind::UnsetValueNotSupported(v).into());
+    }
+    encode::bytes(value, buf);
+    Ok(())
+}
+
+/// The types of events a connection can register for.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EventType {
+    TopologyChange,
+    StatusChange,
+    SchemaChange,
+}
+
+impl EventType {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            EventType::TopologyChange => "TOPOLOGY_CHANGE",
+            EventType::StatusChange => "STATUS_CHANGE",
+            EventType::SchemaChange => "SCHEMA_CHANGE",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<EventType> {
+        match s {
+            "TOPOLOGY_CHANGE" => Some(EventType::TopologyChange),
+            "STATUS_CHANGE" => Some(EventType::StatusChange),
+            "SCHEMA_CHANGE" => Some(EventType::SchemaChange),
+            _ => None,
+        }
+    }
+}
+
+/// Registers the connection to receive the given types of events, which the server
+/// answers with READY.
+#[derive(Debug)]
+pub struct RegisterMessage {
+    pub events: Vec<EventType>,
+}
+
+impl CqlEncode for RegisterMessage {
+    fn encode(&self, _v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        let events = CqlStringList::try_from_iter(self.events.iter().map(|e| e.as_str()))?;
+        encode::string_list(&events, buf);
+        Ok(buf.len() - l)
+    }
+}
+
+impl CqlDecode<RegisterMessage> for RegisterMessage {
+    fn decode(_v: ProtocolVersion, buf: EasyBuf) -> Result<RegisterMessage> {
+        let (_, events) = decode::string_list(buf)?;
+        Ok(RegisterMessage {
+            events: events.iter()
+                .map(|e| {
+                    EventType::from_name(e.as_ref())
+                        .ok_or_else(|| decode::Error::ParseError(format!("Unknown event type '{}'", e.as_ref())).into())
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+fn checked_short_len(len: usize) -> Result<u16> {
+    if len > u16::max_value() as usize {
+        use codec::primitives::{Error as PrimitiveError, ErrorKind as PrimitiveErrorKind};
+        return Err(PrimitiveError::from_kind(PrimitiveErrorKind::MaximumLengthExceeded(len)).into());
+    }
+    Ok(len as u16)
+}
+
 impl Message {
-    fn opcode(&self) -> OpCode {
+    /// Decodes the body of a request frame with the given op-code, which must not contain a custom
+    /// payload anymore.
+    pub fn decode(v: ProtocolVersion, code: OpCode, buf: EasyBuf) -> Result<Message> {
+        Ok(match code {
+            OpCode::Options => Message::Options,
+            OpCode::Startup => Message::Startup(StartupMessage::decode(v, buf)?),
+            OpCode::AuthResponse => Message::AuthResponse(AuthResponseMessage::decode(v, buf)?),
+            OpCode::Query => Message::Query(QueryMessage::decode(v, buf)?),
+            OpCode::Prepare => Message::Prepare(PrepareMessage::decode(v, buf)?),
+            OpCode::Execute => Message::Execute(ExecuteMessage::decode(v, buf)?),
+            OpCode::Batch => Message::Batch(BatchMessage::decode(v, buf)?),
+            OpCode::Register => Message::Register(RegisterMessage::decode(v, buf)?),
+            code => return Err(ErrorKind::UnexpectedOpCode(code).into()),
+        })
+    }
+
+    pub fn opcode(&self) -> OpCode {
         use self::Message::*;
         match self {
             &Options => OpCode::Options,
             &Startup(_) => OpCode::Startup,
             &AuthResponse(_) => OpCode::AuthResponse,
             &Query(_) => OpCode::Query,
+            &Prepare(_) => OpCode::Prepare,
+            &Execute(_) => OpCode::Execute,
+            &Batch(_) => OpCode::Batch,
+            &Register(_) => OpCode::Register,
         }
     }
 }
@@ -192,6 +716,10 @@ impl CqlEncode for Message {
             Message::Startup(ref msg) => msg.encode(v, buf),
             Message::AuthResponse(ref msg) => msg.encode(v, buf),
             Message::Query(ref msg) => msg.encode(v, buf),
+            Message::Prepare(ref msg) => msg.encode(v, buf),
+            Message::Execute(ref msg) => msg.encode(v, buf),
+            Message::Batch(ref msg) => msg.encode(v, buf),
+            Message::Register(ref msg) => msg.encode(v, buf),
         }
     }
 }
@@ -202,9 +730,43 @@ pub fn cql_encode(version: ProtocolVersion,
                   to_encode: Message,
                   sink: &mut Vec<u8>)
                   -> Result<()> {
+    cql_encode_frame(version, flags, stream_id, None, None, to_encode, sink)
+}
+
+/// A generic key-value payload for custom query handlers on the server (since v4).
+pub type CustomPayload = HashMap<CqlString<BVec>, CqlBytes<BVec>>;
+
+/// Encodes the message like `cql_encode`, but precedes it with the given custom payload and
+/// compresses the body with the given compression, which must have been negotiated with a STARTUP
+/// message before. The custom payload and compression flags are set accordingly.
+pub fn cql_encode_frame(version: ProtocolVersion,
+                        flags: u8,
+                        stream_id: u16,
+                        custom_payload: Option<&CustomPayload>,
+                        compression: Option<Compression>,
+                        to_encode: Message,
+                        sink: &mut Vec<u8>)
+                        -> Result<()> {
+    let mut flags = flags & !(FLAG_CUSTOM_PAYLOAD | FLAG_COMPRESSION);
     sink.resize(Header::encoded_len(), 0);
 
-    let len = to_encode.encode(version, sink)?;
+    if let Some(payload) = custom_payload {
+        if version == ProtocolVersion::Version3 {
+            return Err(ErrorKind::CustomPayloadNotSupported(version).into());
+        }
+        encode::bytes_map(payload, sink);
+        flags |= FLAG_CUSTOM_PAYLOAD;
+    }
+    to_encode.encode(version, sink)?;
+
+    if let Some(compression) = compression {
+        let body = compression.compress(&sink[Header::encoded_len()..])?;
+        sink.truncate(Header::encoded_len());
+        sink.extend(body);
+        flags |= FLAG_COMPRESSION;
+    }
+
+    let len = sink.len() - Header::encoded_len();
     if len > u32::max_value() as usize {
         return Err(ErrorKind::BodyLengthExceeded(len).into());
     }
@@ -230,7 +792,8 @@ mod test {
     use super::*;
     use codec::header::ProtocolVersion::*;
     use codec::primitives::{CqlConsistency, CqlFrom, CqlBytes};
-    use codec::authentication::Authenticator;
+    use codec::authentication::{Authenticator, PlainTextAuthenticator};
+    use codec::header::Header;
     use std::collections::HashMap;
 
     #[test]
@@ -264,15 +827,56 @@ mod test {
         assert_eq!(&buf[..], &expected_bytes[..]);
     }
 
+    #[test]
+    fn compressed_req() {
+        let prepare = || {
+            Message::Prepare(PrepareMessage {
+                query: CqlLongString::try_from("SELECT * FROM system.local WHERE key = 'local'").unwrap(),
+            })
+        };
+        let mut expected = Vec::new();
+        cql_encode(Version4, 0, 3, prepare(), &mut expected).unwrap();
+
+        for c in &[Compression::Lz4, Compression::Snappy] {
+            let mut buf = Vec::new();
+            cql_encode_frame(Version4, 0x02, 3, None, Some(*c), prepare(), &mut buf).unwrap();
+
+            let h = Header::try_from(&buf[..]).unwrap();
+            assert!(h.is_compressed());
+            assert!(h.is_traced());
+            assert_eq!(h.length as usize, buf.len() - Header::encoded_len());
+            assert_eq!(&c.decompress(&buf[Header::encoded_len()..]).unwrap()[..],
+                       &expected[Header::encoded_len()..]);
+        }
+    }
+
+    #[test]
+    fn custom_payload_req() {
+        let mut payload = HashMap::new();
+        payload.insert(cql_string!("k"), cql_bytes!(1, 2));
+
+        let mut buf = Vec::new();
+        cql_encode_frame(Version4, 0x01, 1, Some(&payload), None, Message::Options, &mut buf).unwrap();
+        let h = Header::try_from(&buf[..]).unwrap();
+        assert!(h.has_custom_payload());
+        assert!(!h.is_compressed());
+
+        let mut expected = Vec::new();
+        encode::bytes_map(&payload, &mut expected);
+        assert_eq!(&buf[Header::encoded_len()..], &expected[..]);
+
+        assert!(cql_encode_frame(Version3, 0, 1, Some(&payload), None, Message::Options, &mut Vec::new()).is_err());
+    }
+
     #[test]
     fn from_auth_response_req() {
-        let a = Authenticator::PlainTextAuthenticator {
+        let mut a = PlainTextAuthenticator {
             username: String::from("abcdef12"),
             password: String::from("123456789asdfghjklqwertyuiopzx"),
         };
 
         let mut v = Vec::new();
-        a.encode_auth_response(&mut v);
+        a.initial_response(&mut v).unwrap();
 
         println!("v.len() = {:?}", v.len());
 
@@ -297,13 +901,15 @@ mod test {
 
         let o = Message::Query(QueryMessage {
             query: CqlLongString::try_from("select * from system.local where key = 'local'").unwrap(),
-            values: None,
-            consistency: CqlConsistency::One,
-            skip_metadata: false,
-            page_size: Some(5000),
-            paging_state: None,
-            serial_consistency: None,
-            timestamp: Some(1486294317376770),
+            parameters: QueryParameters {
+                values: None,
+                consistency: CqlConsistency::One,
+                skip_metadata: false,
+                page_size: Some(5000),
+                paging_state: None,
+                serial_consistency: None,
+                timestamp: Some(1486294317376770),
+            },
         });
 
         cql_encode(Version3, flags, stream_id, o, &mut buf).unwrap();
@@ -312,9 +918,148 @@ mod test {
         assert_eq!(&buf[..], &expected_bytes[..]);
     }
 
+    #[test]
+    fn from_prepare_req() {
+        let mut buf = Vec::new();
+        let o = Message::Prepare(PrepareMessage { query: CqlLongString::try_from("select * from ks.t where id = ?").unwrap() });
+
+        cql_encode(Version3, 0, 3, o, &mut buf).unwrap();
+
+        let mut expected_bytes = Vec::from(&b"\x03\x00\x00\x03\x09\x00\x00\x00\x23\x00\x00\x00\x1f"[..]);
+        expected_bytes.extend(b"select * from ks.t where id = ?");
+        assert_eq!(buf, expected_bytes);
+    }
+
+    #[test]
+    fn from_execute_req() {
+        let mut buf = Vec::new();
+        let o = Message::Execute(ExecuteMessage {
+            id: cql_bytes!(0xab, 0xcd),
+            parameters: QueryParameters {
+                values: Some(QueryValues::Positional(vec![cql_bytes!(0x01)])),
+                page_size: Some(100),
+                ..Default::default()
+            },
+        });
+
+        cql_encode(Version3, 0, 4, o, &mut buf).unwrap();
+
+        let expected_bytes = b"\x03\x00\x00\x04\x0a\x00\x00\x00\x12\
+                              \x00\x02\xab\xcd\
+                              \x00\x01\x05\
+                              \x00\x01\x00\x00\x00\x01\x01\
+                              \x00\x00\x00\x64";
+        assert_eq!(&buf[..], &expected_bytes[..]);
+    }
+
+    #[test]
+    fn oversized_prepared_ids_fail_to_encode() {
+        let id = || CqlBytes::try_from(vec![0u8; u16::max_value() as usize + 1]).unwrap();
+        let execute = Message::Execute(ExecuteMessage {
+            id: id(),
+            parameters: Default::default(),
+        });
+        assert!(cql_encode(Version3, 0, 4, execute, &mut Vec::new()).is_err());
+
+        let mut batch = BatchMessage::new(BatchType::Logged);
+        batch.add_prepared(id(), Vec::new());
+        assert!(cql_encode(Version3, 0, 4, Message::Batch(batch), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn too_many_values_fail_to_encode() {
+        let count = u16::max_value() as usize + 1;
+        let positional = QueryValues::Positional((0..count).map(|_| CqlBytes::null_value()).collect());
+        let named = QueryValues::Named((0..count)
+            .map(|n| (CqlString::try_from(format!("v{}", n).as_str()).unwrap(), CqlBytes::null_value()))
+            .collect());
+        for values in vec![positional, named] {
+            let execute = Message::Execute(ExecuteMessage {
+                id: cql_bytes!(1),
+                parameters: QueryParameters { values: Some(values), ..Default::default() },
+            });
+            assert!(cql_encode(Version3, 0, 4, execute, &mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn query_and_execute_share_parameters() {
+        let parameters = || {
+            QueryParameters {
+                values: Some(QueryValues::Positional(vec![cql_bytes!(7)])),
+                consistency: CqlConsistency::Quorum,
+                serial_consistency: Some(CqlConsistency::LocalSerial),
+                timestamp: Some(42),
+                ..Default::default()
+            }
+        };
+        let mut expected = Vec::new();
+        parameters().encode(Version3, &mut expected).unwrap();
+
+        let mut query = Vec::new();
+        QueryMessage {
+                query: CqlLongString::try_from("q").unwrap(),
+                parameters: parameters(),
+            }
+            .encode(Version3, &mut query)
+            .unwrap();
+        assert_eq!(&query[5..], &expected[..]);
+
+        let mut execute = Vec::new();
+        ExecuteMessage {
+                id: cql_bytes!(1),
+                parameters: parameters(),
+            }
+            .encode(Version3, &mut execute)
+            .unwrap();
+        assert_eq!(&execute[3..], &expected[..]);
+    }
+
+    #[test]
+    fn from_batch_req() {
+        let mut batch = BatchMessage::new(BatchType::Unlogged);
+        batch.add_query("a", vec![cql_bytes!(1)]).unwrap().add_prepared(cql_bytes!(0xab), Vec::new());
+        batch.consistency = CqlConsistency::Quorum;
+        batch.timestamp = Some(5);
+
+        let mut buf = Vec::new();
+        cql_encode(Version3, 0, 5, Message::Batch(batch), &mut buf).unwrap();
+
+        let expected_bytes = b"\x03\x00\x00\x05\x0d\x00\x00\x00\x21\
+                              \x01\x00\x02\
+                              \x00\x00\x00\x00\x01a\x00\x01\x00\x00\x00\x01\x01\
+                              \x01\x00\x01\xab\x00\x00\
+                              \x00\x04\x20\
+                              \x00\x00\x00\x00\x00\x00\x00\x05";
+        assert_eq!(&buf[..], &expected_bytes[..]);
+    }
+
+    #[test]
+    fn from_register_req() {
+        let o = Message::Register(RegisterMessage { events: vec![EventType::StatusChange, EventType::SchemaChange] });
+        let mut buf = Vec::new();
+        cql_encode(Version4, 0, 6, o, &mut buf).unwrap();
+
+        let expected_bytes = b"\x04\x00\x00\x06\x0b\x00\x00\x00\x20\
+                              \x00\x02\x00\x0dSTATUS_CHANGE\x00\x0dSCHEMA_CHANGE";
+        assert_eq!(&buf[..], &expected_bytes[..]);
+    }
+
+    #[test]
+    fn batch_flags() {
+        let mut b = BatchMessage::default();
+        assert_eq!(b.compute_flags(), 0x00u8);
+
+        b.serial_consistency = Some(CqlConsistency::Serial);
+        assert_eq!(b.compute_flags(), 0x10u8);
+
+        b.timestamp = Some(1);
+        assert_eq!(b.compute_flags(), 0x30u8);
+    }
+
     #[test]
     fn query_flags() {
-        let mut o = QueryMessage::default();
+        let mut o = QueryParameters::default();
         assert_eq!(o.compute_flags(), 0x00u8);
 
         o.values = Some(QueryValues::Positional(Vec::new()));
@@ -352,6 +1097,35 @@ mod test {
         assert_eq!(expected, buf);
     }
 
+    #[test]
+    fn encode_unset_query_values() {
+        let values = QueryValues::Positional(vec![CqlBytes::unset_value()]);
+
+        let mut buf = Vec::new();
+        values.encode(Version4, &mut buf).unwrap();
+        assert_eq!(buf, vec![0x00, 0x01, 0xff, 0xff, 0xff, 0xfe]);
+
+        let mut buf = Vec::new();
+        assert!(values.encode(Version3, &mut buf).is_err());
+    }
+
+    #[test]
+    fn query_values_from_rust_values() {
+        let values = QueryValues::positional(&[&1i32, &"a", &None::<i64>]).unwrap();
+        match values {
+            QueryValues::Positional(v) => {
+                assert_eq!(v, vec![cql_bytes!(0, 0, 0, 1), cql_bytes!(97), CqlBytes::null_value()])
+            }
+            _ => unreachable!(),
+        }
+
+        let values = QueryValues::named(&[("a", &true)]).unwrap();
+        match values {
+            QueryValues::Named(v) => assert_eq!(v.get(&cql_string!("a")), Some(&cql_bytes!(1))),
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     fn encode_query_values_named() {
         let values = {
@@ -368,4 +1142,89 @@ mod test {
         let expected = vec![0x00, 0x01, 0x00, 0x01, 97, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01];
         assert_eq!(expected, buf);
     }
+
+    fn reencoded(frame: &[u8]) -> Vec<u8> {
+        let h = Header::try_from(&frame[..Header::encoded_len()]).unwrap();
+        let body = Vec::from(&frame[Header::encoded_len()..]).into();
+        let msg = Message::decode(h.version.version, h.op_code, body).unwrap();
+
+        let mut buf = Vec::new();
+        cql_encode(h.version.version, h.flags, h.stream_id, msg, &mut buf).unwrap();
+        buf
+    }
+
+    fn assert_roundtrip(v: ProtocolVersion, msg: Message) {
+        let mut buf = Vec::new();
+        cql_encode(v, 0, 7, msg, &mut buf).unwrap();
+        assert_eq!(reencoded(&buf), buf);
+    }
+
+    #[test]
+    fn decode_request_fixtures() {
+        for frame in &[&include_bytes!("../../tests/fixtures/v3/requests/auth_response.msg")[..],
+                       &include_bytes!("../../tests/fixtures/v3/requests/cli_query.msg")[..]] {
+            assert_eq!(&reencoded(frame)[..], *frame);
+        }
+    }
+
+    #[test]
+    fn decode_requests() {
+        assert_roundtrip(Version3, Message::Options);
+        let mut buf = Vec::new();
+        StartupMessage {
+                cql_version: CqlString::try_from("3.2.1").unwrap(),
+                compression: Some(CqlString::try_from("lz4").unwrap()),
+            }
+            .encode(Version4, &mut buf)
+            .unwrap();
+        let startup = StartupMessage::decode(Version4, buf.into()).unwrap();
+        assert_eq!(startup.cql_version, CqlString::try_from("3.2.1").unwrap());
+        assert_eq!(startup.compression, Some(CqlString::try_from("lz4").unwrap()));
+        assert_roundtrip(Version3,
+                         Message::Prepare(PrepareMessage { query: CqlLongString::try_from("select 1").unwrap() }));
+        assert_roundtrip(Version4,
+                         Message::Query(QueryMessage {
+                             query: CqlLongString::try_from("select * from t where a = :a").unwrap(),
+                             parameters: QueryParameters {
+                                 values: Some(QueryValues::Named({
+                                     let mut m = HashMap::new();
+                                     m.insert(cql_string!("a"), cql_bytes!(0, 1));
+                                     m
+                                 })),
+                                 consistency: CqlConsistency::LocalQuorum,
+                                 skip_metadata: true,
+                                 page_size: Some(10),
+                                 paging_state: Some(cql_bytes!(9, 9)),
+                                 serial_consistency: Some(CqlConsistency::Serial),
+                                 timestamp: Some(42),
+                             },
+                         }));
+        assert_roundtrip(Version3,
+                         Message::Execute(ExecuteMessage {
+                             id: cql_bytes!(0xab, 0xcd),
+                             parameters: QueryParameters {
+                                 values: Some(QueryValues::Positional(vec![cql_bytes!(1), CqlBytes::null_value()])),
+                                 ..Default::default()
+                             },
+                         }));
+
+        let mut batch = BatchMessage::new(BatchType::Counter);
+        batch.add_query("a", vec![cql_bytes!(1)]).unwrap().add_prepared(cql_bytes!(0xab), Vec::new());
+        batch.serial_consistency = Some(CqlConsistency::LocalSerial);
+        batch.timestamp = Some(5);
+        assert_roundtrip(Version4, Message::Batch(batch));
+        assert_roundtrip(Version4,
+                         Message::Register(RegisterMessage {
+                             events: vec![EventType::TopologyChange, EventType::StatusChange],
+                         }));
+    }
+
+    #[test]
+    fn decode_rejects_response_op_code() {
+        match Message::decode(Version3, OpCode::Ready, Vec::new().into()) {
+            Err(Error(ErrorKind::UnexpectedOpCode(OpCode::Ready), _)) => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+        assert!(Message::decode(Version3, OpCode::Register, b"\x00\x01\x00\x03FOO".to_vec().into()).is_err());
+    }
 }
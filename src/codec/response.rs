@@ -1,14 +1,21 @@
-use codec::primitives::{CqlFrom, CqlString, CqlBytes, CqlStringList, CqlStringMultiMap};
-use codec::header::ProtocolVersion;
-use codec::primitives::decode;
+use codec::primitives::{self, CqlFrom, CqlString, CqlBytes, CqlStringList, CqlStringMultiMap, CqlConsistency,
+                        ColumnType, CqlValue, FromCql};
+use codec::header::{self, Header, ProtocolVersion, OpCode, FLAG_COMPRESSION, FLAG_TRACING, FLAG_CUSTOM_PAYLOAD,
+                    FLAG_WARNING};
+use codec::primitives::{decode, encode, BVec};
+use codec::compression::{Compression, MAX_BODY_LEN};
 use tokio_core::io::EasyBuf;
 use semver::Version;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 
 error_chain! {
     foreign_links {
         Io(::std::io::Error);
         HeaderError(::codec::header::Error);
         DecodeError(::codec::primitives::decode::Error);
+        PrimitiveError(::codec::primitives::Error);
+        CompressionError(::codec::compression::Error);
     }
 
     errors {
@@ -20,6 +27,22 @@ error_chain! {
             description("Error during parsing")
             display("{}", err)
         }
+        BodyLengthExceeded(len: usize) {
+            description("The length of the body exceeded the maximum length specified by the protocol")
+            display("The current body length {} exceeded the maximum allowed length for a body", len)
+        }
+        MissingTableSpec(column: String) {
+            description("A column spec lacks its table spec, and there is no global one")
+            display("Column '{}' needs a table spec, as there is no global table spec", column)
+        }
+        UnexpectedOpCode(code: OpCode) {
+            description("The op-code is not the one of a response")
+            display("Cannot decode a response with op-code {:?}", code)
+        }
+        PreludeNotSupported(v: ProtocolVersion) {
+            description("Warnings and custom payloads are not supported by the protocol version")
+            display("Warnings and custom payloads require protocol version 4 or higher, got {:?}", v)
+        }
     }
 }
 
@@ -28,15 +51,22 @@ pub enum Message {
     Supported(SupportedMessage),
     Ready,
     Authenticate(AuthenticateMessage),
+    AuthChallenge(AuthChallengeMessage),
     AuthSuccess(AuthSuccessMessage),
     Error(ErrorMessage),
-    Result,
+    Result(ResultMessage),
+    Event(Event),
 }
 
 pub trait CqlDecode<T> {
     fn decode(v: ProtocolVersion, buf: ::tokio_core::io::EasyBuf) -> Result<T>;
 }
 
+/// Encodes a response, as done by servers.
+pub trait CqlEncode {
+    fn encode(&self, v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize>;
+}
+
 #[derive(Debug)]
 pub struct SupportedMessage(pub CqlStringMultiMap<EasyBuf>);
 
@@ -87,6 +117,20 @@ impl CqlDecode<AuthenticateMessage> for AuthenticateMessage {
     }
 }
 
+/// A SASL challenge sent by the server during authentication, to be answered by AUTH_RESPONSE.
+#[derive(Debug)]
+pub struct AuthChallengeMessage {
+    pub token: CqlBytes<EasyBuf>,
+}
+
+impl CqlDecode<AuthChallengeMessage> for AuthChallengeMessage {
+    fn decode(_v: ProtocolVersion, buf: ::tokio_core::io::EasyBuf) -> Result<AuthChallengeMessage> {
+        decode::bytes(buf)
+            .map(|d| AuthChallengeMessage { token: d.1 })
+            .map_err(|err| ErrorKind::ParserError(format!("{}", err)).into())
+    }
+}
+
 #[derive(Debug)]
 pub struct AuthSuccessMessage {
     pub payload: CqlBytes<EasyBuf>,
@@ -100,44 +144,447 @@ impl CqlDecode<AuthSuccessMessage> for AuthSuccessMessage {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ErrorMessage {
     pub code: i32,
     pub text: CqlString<EasyBuf>,
+    /// The kind of error, along with the additional information specific to it.
+    pub kind: CqlErrorKind,
+}
+
+/// The kinds of errors an ERROR message may carry, as identified by its code.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CqlErrorKind {
+    ServerError,
+    ProtocolError,
+    AuthenticationError,
+    /// Not enough replicas were alive to achieve the requested consistency.
+    Unavailable {
+        consistency: CqlConsistency,
+        required: i32,
+        alive: i32,
+    },
+    Overloaded,
+    IsBootstrapping,
+    TruncateError,
+    WriteTimeout {
+        consistency: CqlConsistency,
+        received: i32,
+        block_for: i32,
+        write_type: WriteType,
+    },
+    ReadTimeout {
+        consistency: CqlConsistency,
+        received: i32,
+        block_for: i32,
+        /// Whether the replica that was asked for data responded.
+        data_present: bool,
+    },
+    /// Since v4.
+    ReadFailure {
+        consistency: CqlConsistency,
+        received: i32,
+        block_for: i32,
+        num_failures: i32,
+        data_present: bool,
+    },
+    /// Since v4.
+    FunctionFailure {
+        keyspace: CqlString<EasyBuf>,
+        function: CqlString<EasyBuf>,
+        arg_types: CqlStringList<EasyBuf>,
+    },
+    /// Since v4.
+    WriteFailure {
+        consistency: CqlConsistency,
+        received: i32,
+        block_for: i32,
+        num_failures: i32,
+        write_type: WriteType,
+    },
+    SyntaxError,
+    Unauthorized,
+    Invalid,
+    ConfigError,
+    /// The table is empty if it was the keyspace that already existed.
+    AlreadyExists {
+        keyspace: CqlString<EasyBuf>,
+        table: CqlString<EasyBuf>,
+    },
+    /// The statement with the given id has to be prepared again.
+    Unprepared { id: CqlBytes<EasyBuf> },
+    /// An error code unknown to us.
+    Unknown(i32),
+}
+
+impl CqlErrorKind {
+    pub fn code(&self) -> i32 {
+        use self::CqlErrorKind::*;
+        match *self {
+            ServerError => 0x0000,
+            ProtocolError => 0x000A,
+            AuthenticationError => 0x0100,
+            Unavailable { .. } => 0x1000,
+            Overloaded => 0x1001,
+            IsBootstrapping => 0x1002,
+            TruncateError => 0x1003,
+            WriteTimeout { .. } => 0x1100,
+            ReadTimeout { .. } => 0x1200,
+            ReadFailure { .. } => 0x1300,
+            FunctionFailure { .. } => 0x1400,
+            WriteFailure { .. } => 0x1500,
+            SyntaxError => 0x2000,
+            Unauthorized => 0x2100,
+            Invalid => 0x2200,
+            ConfigError => 0x2300,
+            AlreadyExists { .. } => 0x2400,
+            Unprepared { .. } => 0x2500,
+            Unknown(code) => code,
+        }
+    }
+
+    /// Decodes the kind of error with the given code from the remainder of an ERROR message.
+    pub fn decode(code: i32, buf: EasyBuf) -> decode::ParseResult<CqlErrorKind> {
+        use self::CqlErrorKind::*;
+        Ok(match code {
+            0x0000 => (buf, ServerError),
+            0x000A => (buf, ProtocolError),
+            0x0100 => (buf, AuthenticationError),
+            0x1000 => {
+                let (buf, consistency) = decode::consistency(buf)?;
+                let (buf, required) = decode::int(buf)?;
+                let (buf, alive) = decode::int(buf)?;
+                (buf,
+                 Unavailable {
+                     consistency: consistency,
+                     required: required,
+                     alive: alive,
+                 })
+            }
+            0x1001 => (buf, Overloaded),
+            0x1002 => (buf, IsBootstrapping),
+            0x1003 => (buf, TruncateError),
+            0x1100 => {
+                let (buf, consistency) = decode::consistency(buf)?;
+                let (buf, received) = decode::int(buf)?;
+                let (buf, block_for) = decode::int(buf)?;
+                let (buf, write_type) = decode::string(buf)?;
+                (buf,
+                 WriteTimeout {
+                     consistency: consistency,
+                     received: received,
+                     block_for: block_for,
+                     write_type: WriteType::from(write_type),
+                 })
+            }
+            0x1200 => {
+                let (buf, consistency) = decode::consistency(buf)?;
+                let (buf, received) = decode::int(buf)?;
+                let (buf, block_for) = decode::int(buf)?;
+                let (buf, data_present) = decode::byte(buf)?;
+                (buf,
+                 ReadTimeout {
+                     consistency: consistency,
+                     received: received,
+                     block_for: block_for,
+                     data_present: data_present != 0,
+                 })
+            }
+            0x1300 => {
+                let (buf, consistency) = decode::consistency(buf)?;
+                let (buf, received) = decode::int(buf)?;
+                let (buf, block_for) = decode::int(buf)?;
+                let (buf, num_failures) = decode::int(buf)?;
+                let (buf, data_present) = decode::byte(buf)?;
+                (buf,
+                 ReadFailure {
+                     consistency: consistency,
+                     received: received,
+                     block_for: block_for,
+                     num_failures: num_failures,
+                     data_present: data_present != 0,
+                 })
+            }
+            0x1400 => {
+                let (buf, keyspace) = decode::string(buf)?;
+                let (buf, function) = decode::string(buf)?;
+                let (buf, arg_types) = decode::string_list(buf)?;
+                (buf,
+                 FunctionFailure {
+                     keyspace: keyspace,
+                     function: function,
+                     arg_types: arg_types,
+                 })
+            }
+            0x1500 => {
+                let (buf, consistency) = decode::consistency(buf)?;
+                let (buf, received) = decode::int(buf)?;
+                let (buf, block_for) = decode::int(buf)?;
+                let (buf, num_failures) = decode::int(buf)?;
+                let (buf, write_type) = decode::string(buf)?;
+                (buf,
+                 WriteFailure {
+                     consistency: consistency,
+                     received: received,
+                     block_for: block_for,
+                     num_failures: num_failures,
+                     write_type: WriteType::from(write_type),
+                 })
+            }
+            0x2000 => (buf, SyntaxError),
+            0x2100 => (buf, Unauthorized),
+            0x2200 => (buf, Invalid),
+            0x2300 => (buf, ConfigError),
+            0x2400 => {
+                let (buf, keyspace) = decode::string(buf)?;
+                let (buf, table) = decode::string(buf)?;
+                (buf,
+                 AlreadyExists {
+                     keyspace: keyspace,
+                     table: table,
+                 })
+            }
+            0x2500 => {
+                let (buf, id) = decode::short_bytes(buf)?;
+                (buf, Unprepared { id: id })
+            }
+            code => (buf, Unknown(code)),
+        })
+    }
+}
+
+/// The type of write that timed out or failed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum WriteType {
+    /// A non-batched, non-counter write.
+    Simple,
+    /// A logged batch write, whose batch log was written successfully.
+    Batch,
+    UnloggedBatch,
+    /// A counter write, batched or not.
+    Counter,
+    /// The write to the batch log of a logged batch.
+    BatchLog,
+    /// The paxos phase of a conditional update.
+    Cas,
+    /// A write type unknown to us.
+    Other(CqlString<EasyBuf>),
+}
+
+impl WriteType {
+    pub fn as_str(&self) -> &str {
+        match *self {
+            WriteType::Simple => "SIMPLE",
+            WriteType::Batch => "BATCH",
+            WriteType::UnloggedBatch => "UNLOGGED_BATCH",
+            WriteType::Counter => "COUNTER",
+            WriteType::BatchLog => "BATCH_LOG",
+            WriteType::Cas => "CAS",
+            WriteType::Other(ref s) => s.as_ref(),
+        }
+    }
+}
+
+impl From<CqlString<EasyBuf>> for WriteType {
+    fn from(s: CqlString<EasyBuf>) -> WriteType {
+        match s.as_ref() {
+            "SIMPLE" => WriteType::Simple,
+            "BATCH" => WriteType::Batch,
+            "UNLOGGED_BATCH" => WriteType::UnloggedBatch,
+            "COUNTER" => WriteType::Counter,
+            "BATCH_LOG" => WriteType::BatchLog,
+            "CAS" => WriteType::Cas,
+            _ => WriteType::Other(s.clone()),
+        }
+    }
+}
+
+/// Identifies a trace session in the `system_traces` keyspace.
+pub type TracingId = [u8; 16];
+
+/// Values which precede the message in a response body, as announced by the flags of its header.
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct ResponsePrelude {
+    /// The id of the trace session, if tracing was requested.
+    pub tracing_id: Option<TracingId>,
+    /// Warnings generated by the server while processing the request (since v4).
+    pub warnings: Option<CqlStringList<EasyBuf>>,
+    /// A payload for custom query handlers, which is ignored by the default one (since v4).
+    pub custom_payload: Option<HashMap<CqlString<EasyBuf>, CqlBytes<EasyBuf>>>,
+}
+
+impl ResponsePrelude {
+    /// Decodes the prelude of a complete response body, returning it along with the remaining bytes,
+    /// which hold the actual message.
+    pub fn decode(header: &Header, buf: EasyBuf) -> decode::ParseResult<ResponsePrelude> {
+        let mut prelude = ResponsePrelude::default();
+        let buf = if header.is_traced() {
+            let (buf, id) = decode::uuid(buf)?;
+            prelude.tracing_id = Some(id);
+            buf
+        } else {
+            buf
+        };
+        let buf = if header.has_warnings() {
+            let (buf, warnings) = decode::string_list(buf)?;
+            prelude.warnings = Some(warnings);
+            buf
+        } else {
+            buf
+        };
+        let buf = if header.has_custom_payload() {
+            let (buf, payload) = decode::bytes_map(buf)?;
+            prelude.custom_payload = Some(payload);
+            buf
+        } else {
+            buf
+        };
+        Ok((buf, prelude))
+    }
 }
 
 impl CqlDecode<ErrorMessage> for ErrorMessage {
     fn decode(_v: ProtocolVersion, buf: ::tokio_core::io::EasyBuf) -> Result<ErrorMessage> {
         let (buf, code) = decode::int(buf)?;
-        let (_, text) = decode::string(buf)?;
+        let (buf, text) = decode::string(buf)?;
+        let (_, kind) = CqlErrorKind::decode(code, buf)?;
         Ok(ErrorMessage {
             code: code,
             text: text,
+            kind: kind,
         })
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum ResultHeader {
+/// A completely decoded RESULT message.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ResultMessage {
     Void,
+    Rows(RowsMessage),
     SetKeyspace(CqlString<EasyBuf>),
-    SchemaChange(SchemaChangePayload),
-    Rows(RowsMetadata),
+    Prepared(PreparedMessage),
+    SchemaChange(SchemaChange),
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct SchemaChangePayload {
-    change_type: CqlString<EasyBuf>,
-    target: CqlString<EasyBuf>,
-    options: CqlString<EasyBuf>,
+impl CqlDecode<ResultMessage> for ResultMessage {
+    fn decode(v: ProtocolVersion, buf: ::tokio_core::io::EasyBuf) -> Result<ResultMessage> {
+        let (buf, header) = ResultHeader::decode_with_rest(v, buf)?;
+        Ok(match header {
+            ResultHeader::Void => ResultMessage::Void,
+            ResultHeader::SetKeyspace(ks) => ResultMessage::SetKeyspace(ks),
+            ResultHeader::Prepared(p) => ResultMessage::Prepared(p),
+            ResultHeader::SchemaChange(c) => ResultMessage::SchemaChange(c),
+            ResultHeader::Rows(metadata) => {
+                let (_, rows) = RowsMessage::decode_rows(buf, metadata.columns_count)?;
+                ResultMessage::Rows(RowsMessage {
+                    metadata: metadata,
+                    rows: rows,
+                })
+            }
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RowsMessage {
+    pub metadata: RowsMetadata,
+    pub rows: Vec<Row>,
+}
+
+/// A single row, with one cell per column in the order of the column specs.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Row {
+    pub cells: Vec<CqlBytes<EasyBuf>>,
+}
+
+/// Checks that `count` items, each taking at least `min_size` bytes, fit into a frame, and are
+/// received completely. Counts are read from the wire, and must not be trusted before allocating
+/// for them.
+fn check_count(buf: &EasyBuf, count: i32, min_size: usize, what: &str) -> ::std::result::Result<usize, decode::Error> {
+    let min_size = min_size.max(1);
+    if count < 0 || count as usize > MAX_BODY_LEN / min_size {
+        return Err(decode::Error::ParseError(format!("{} {} cannot fit into a frame", count, what)));
+    }
+    let count = count as usize;
+    if count * min_size > buf.len() {
+        return Err(decode::Error::Incomplete(decode::Needed::Unknown));
+    }
+    Ok(count)
+}
+
+impl RowsMessage {
+    /// Decodes `<rows_count><rows_content>`, where each row has exactly `columns_count` cells.
+    pub fn decode_rows(buf: EasyBuf, columns_count: i32) -> decode::ParseResult<Vec<Row>> {
+        if columns_count < 0 {
+            return Err(decode::Error::ParseError(format!("Invalid columns count {}", columns_count)));
+        }
+        let columns_count = columns_count as usize;
+        let (mut buf, rows_count) = decode::int(buf)?;
+        // Every cell takes at least the four bytes of its length.
+        let rows_count = check_count(&buf, rows_count, columns_count.saturating_mul(4), "rows")?;
+        let mut rows = Vec::with_capacity(rows_count);
+        for _ in 0..rows_count {
+            let mut cells = Vec::with_capacity(columns_count);
+            for _ in 0..columns_count {
+                let (nb, cell) = decode::bytes(buf)?;
+                buf = nb;
+                cells.push(cell);
+            }
+            rows.push(Row { cells: cells });
+        }
+        Ok((buf, rows))
+    }
+
+    /// Decodes all cells of the given row into typed values according to the column specs.
+    /// Null cells are returned as `None`.
+    pub fn decode_row(&self, row: &Row) -> primitives::Result<Vec<Option<CqlValue>>> {
+        if self.metadata.no_metadata {
+            return Err("Cannot decode rows without metadata, as column types are unknown".into());
+        }
+        row.cells
+            .iter()
+            .zip(self.metadata.column_specs.iter())
+            .map(|(cell, spec)| CqlValue::decode_cell(&spec.column_type, cell))
+            .collect()
+    }
+
+    /// Converts the cell of the given column in the given row into a rust value.
+    pub fn get<T>(&self, row: &Row, column: &str) -> primitives::Result<T>
+        where T: FromCql
+    {
+        let index = self.column_index(column)
+            .ok_or_else(|| primitives::Error::from(format!("Column '{}' does not exist", column)))?;
+        let cell = row.cells
+            .get(index)
+            .ok_or_else(|| primitives::Error::from(format!("Row has no cell for column '{}'", column)))?;
+        T::from_cql(column,
+                    CqlValue::decode_cell(&self.metadata.column_specs[index].column_type, cell)?)
+    }
+
+    /// Returns the index of the column with the given name, if there is metadata.
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.metadata.column_specs.iter().position(|c| c.name.as_ref() == name)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
+pub enum ResultHeader {
+    Void,
+    SetKeyspace(CqlString<EasyBuf>),
+    SchemaChange(SchemaChange),
+    Rows(RowsMetadata),
+    Prepared(PreparedMessage),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct RowsMetadata {
-    global_tables_spec: Option<TableSpec>,
-    paging_state: Option<CqlBytes<EasyBuf>>,
-    no_metadata: bool,
-    columns_count: i32,
+    pub global_tables_spec: Option<TableSpec>,
+    /// If set, there are more pages to retrieve by passing it to the next QUERY or EXECUTE.
+    pub paging_state: Option<CqlBytes<EasyBuf>>,
+    /// If set, there is neither a global table spec nor any column spec.
+    pub no_metadata: bool,
+    pub columns_count: i32,
+    pub column_specs: Vec<ColumnSpec>,
 }
 
 impl Default for RowsMetadata {
@@ -147,61 +594,110 @@ impl Default for RowsMetadata {
             paging_state: None,
             no_metadata: false,
             columns_count: -1,
+            column_specs: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct TableSpec {
-    keyspace: CqlString<EasyBuf>,
-    table: CqlString<EasyBuf>,
+    pub keyspace: CqlString<EasyBuf>,
+    pub table: CqlString<EasyBuf>,
 }
 
-impl ResultHeader {
-    pub fn decode(_v: ProtocolVersion, buf: ::tokio_core::io::EasyBuf) -> Result<Option<ResultHeader>> {
+/// The specification of a single column of a result, or of a bind variable of a prepared statement.
+/// The table spec is only set if there is no global table spec in the surrounding metadata.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ColumnSpec {
+    pub table_spec: Option<TableSpec>,
+    pub name: CqlString<EasyBuf>,
+    pub column_type: ColumnType,
+}
 
-        if buf.len() < 4 {
-            Ok(None)
-        } else {
-            let (buf, t) = decode::int(buf)?;
-            match t {
-                0x0001 => Ok(Some(ResultHeader::Void)),
-                0x0002 => Self::match_decode(Self::decode_rows_metadata(buf), |d| ResultHeader::Rows(d)),
-                0x0003 => Self::match_decode(decode::string(buf), |s| ResultHeader::SetKeyspace(s)),
-                0x0005 => {
-                    Self::match_decode(Self::decode_schema_change(buf),
-                                       |c| ResultHeader::SchemaChange(c))
-                }
-                // TODO:
-                // 0x0004    Prepared: result to a PREPARE message.
-                _ => Ok(None),
-            }
-        }
-    }
+/// The result to a PREPARE message.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PreparedMessage {
+    /// The id of the prepared statement, to be used by EXECUTE.
+    pub id: CqlBytes<EasyBuf>,
+    /// Describes the variables to be bound when executing the statement.
+    pub metadata: PreparedMetadata,
+    /// Describes the rows that executing the statement will yield. It might be empty.
+    pub result_metadata: RowsMetadata,
+}
 
-    fn match_decode<T, F>(decoded: decode::ParseResult<T>, f: F) -> Result<Option<ResultHeader>>
-        where F: Fn(T) -> ResultHeader
-    {
-        match decoded {
-            Ok((_, s)) => Ok(Some(f(s))),
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PreparedMetadata {
+    pub global_tables_spec: Option<TableSpec>,
+    /// The indices of the bind variables which make up the partition key, in the order of the
+    /// partition key columns. It is only sent since v4, and empty otherwise.
+    pub pk_indices: Vec<u16>,
+    pub column_specs: Vec<ColumnSpec>,
+}
+
+impl ResultHeader {
+    /// Decodes the header of a result, returning `None` if there are not enough bytes to do so.
+    pub fn decode(v: ProtocolVersion, buf: ::tokio_core::io::EasyBuf) -> Result<Option<ResultHeader>> {
+        match Self::decode_with_rest(v, buf) {
+            Ok((_, h)) => Ok(Some(h)),
             Err(decode::Error::Incomplete(_)) => Ok(None),
             Err(a) => Err(a.into()),
         }
     }
 
-    fn decode_schema_change(buf: EasyBuf) -> decode::ParseResult<SchemaChangePayload> {
-        let (buf, change_type) = decode::string(buf)?;
-        let (buf, target) = decode::string(buf)?;
-        let (buf, options) = decode::string(buf)?;
+    /// Decodes the header of a result and returns it along with the remaining bytes, which
+    /// contain the rows content in case of a `Rows` result.
+    pub fn decode_with_rest(v: ProtocolVersion, buf: EasyBuf) -> decode::ParseResult<ResultHeader> {
+        let (buf, t) = decode::int(buf)?;
+        match t {
+            0x0001 => Ok((buf, ResultHeader::Void)),
+            0x0002 => Self::decode_rows_metadata(buf).map(|(b, d)| (b, ResultHeader::Rows(d))),
+            0x0003 => decode::string(buf).map(|(b, s)| (b, ResultHeader::SetKeyspace(s))),
+            0x0004 => Self::decode_prepared(v, buf).map(|(b, p)| (b, ResultHeader::Prepared(p))),
+            0x0005 => SchemaChange::decode(buf).map(|(b, c)| (b, ResultHeader::SchemaChange(c))),
+            _ => Err(decode::Error::ParseError(format!("Unknown result kind {:#06x}", t))),
+        }
+    }
+
+    fn decode_prepared(v: ProtocolVersion, buf: EasyBuf) -> decode::ParseResult<PreparedMessage> {
+        let (buf, id) = decode::short_bytes(buf)?;
+
+        // <flags><columns_count>[<pk_count><pk_index_1>...<pk_index_n>]
+        // [<global_table_spec>?<col_spec_1>...<col_spec_n>]
+        let (buf, flags) = decode::int(buf)?;
+        let (buf, col_count) = decode::int(buf)?;
+        let (buf, pk_indices) = match v {
+            ProtocolVersion::Version3 => (buf, Vec::new()),
+            ProtocolVersion::Version4 => Self::decode_pk_indices(buf)?,
+        };
+        let (buf, global_tables_spec) = Self::decode_global_tables_spec(buf, flags)?;
+        let (buf, column_specs) = Self::decode_column_specs(buf, col_count, global_tables_spec.is_some())?;
+
+        let (buf, result_metadata) = Self::decode_rows_metadata(buf)?;
 
         Ok((buf,
-            SchemaChangePayload {
-                change_type: change_type,
-                target: target,
-                options: options,
+            PreparedMessage {
+                id: id,
+                metadata: PreparedMetadata {
+                    global_tables_spec: global_tables_spec,
+                    pk_indices: pk_indices,
+                    column_specs: column_specs,
+                },
+                result_metadata: result_metadata,
             }))
     }
 
+    fn decode_pk_indices(buf: EasyBuf) -> decode::ParseResult<Vec<u16>> {
+        let (mut buf, pk_count) = decode::int(buf)?;
+        let pk_count = check_count(&buf, pk_count, 2, "partition key indices")?;
+        let mut pk_indices = Vec::with_capacity(pk_count);
+        for _ in 0..pk_count {
+            let (nb, index) = decode::short(buf)?;
+            buf = nb;
+            pk_indices.push(index);
+        }
+        Ok((buf, pk_indices))
+    }
+
     fn decode_rows_metadata(buf: EasyBuf) -> decode::ParseResult<RowsMetadata> {
         let (buf, flags) = decode::int(buf)?;
         let (buf, col_count) = decode::int(buf)?;
@@ -212,36 +708,672 @@ impl ResultHeader {
         let mut rows_metadata = RowsMetadata::default();
 
         rows_metadata.columns_count = col_count;
+        rows_metadata.no_metadata = (flags & 0x0004) == 0x0004;
 
-        if (flags & 0x0002) == 0x0002 {
-            rows_metadata.paging_state = Some(cql_bytes!(1, 2, 3));
+        let buf = if (flags & 0x0002) == 0x0002 {
+            let (buf, paging_state) = decode::bytes(buf)?;
+            rows_metadata.paging_state = Some(paging_state);
+            buf
+        } else {
+            buf
+        };
+
+        if rows_metadata.no_metadata {
+            return Ok((buf, rows_metadata));
+        }
+
+        let (buf, global_tables_spec) = Self::decode_global_tables_spec(buf, flags)?;
+        let (buf, column_specs) = Self::decode_column_specs(buf, col_count, global_tables_spec.is_some())?;
+        rows_metadata.global_tables_spec = global_tables_spec;
+        rows_metadata.column_specs = column_specs;
+
+        Ok((buf, rows_metadata))
+    }
+
+    fn decode_global_tables_spec(buf: EasyBuf, flags: i32) -> decode::ParseResult<Option<TableSpec>> {
+        if (flags & 0x0001) == 0x0001 {
+            let (buf, spec) = Self::decode_table_spec(buf)?;
+            Ok((buf, Some(spec)))
+        } else {
+            Ok((buf, None))
         }
+    }
 
-        let buf = if (flags & 0x0001) == 0x0001 {
-            let (buf, keyspace) = decode::string(buf)?;
-            let (buf, table) = decode::string(buf)?;
-            rows_metadata.global_tables_spec = Some(TableSpec {
+    fn decode_table_spec(buf: EasyBuf) -> decode::ParseResult<TableSpec> {
+        let (buf, keyspace) = decode::string(buf)?;
+        let (buf, table) = decode::string(buf)?;
+        Ok((buf,
+            TableSpec {
                 keyspace: keyspace,
                 table: table,
+            }))
+    }
+
+    fn decode_column_specs(mut buf: EasyBuf,
+                           col_count: i32,
+                           has_global_tables_spec: bool)
+                           -> decode::ParseResult<Vec<ColumnSpec>> {
+        // Each spec has at least a name and a type, which take two bytes each.
+        let col_count = check_count(&buf, col_count, 4, "column specs")?;
+        let mut specs = Vec::with_capacity(col_count);
+        for _ in 0..col_count {
+            let (nb, table_spec) = if has_global_tables_spec {
+                (buf, None)
+            } else {
+                let (nb, spec) = Self::decode_table_spec(buf)?;
+                (nb, Some(spec))
+            };
+            let (nb, name) = decode::string(nb)?;
+            let (nb, column_type) = decode::option(nb)?;
+            buf = nb;
+            specs.push(ColumnSpec {
+                table_spec: table_spec,
+                name: name,
+                column_type: column_type,
             });
-            buf
+        }
+        Ok((buf, specs))
+    }
+}
+
+/// An event pushed by the server on stream -1, for the types of events the connection registered for.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Event {
+    /// A node was added to or removed from the cluster, at the given address.
+    TopologyChange(TopologyChange, SocketAddr),
+    /// A node went up or down, at the given address.
+    StatusChange(StatusChange, SocketAddr),
+    SchemaChange(SchemaChange),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TopologyChange {
+    NewNode,
+    RemovedNode,
+    /// Only sent up to v3.
+    MovedNode,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StatusChange {
+    Up,
+    Down,
+}
+
+/// Describes a change to the schema, as sent in SCHEMA_CHANGE events and results.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SchemaChange {
+    pub change_type: SchemaChangeType,
+    pub target: SchemaChangeTarget,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SchemaChangeType {
+    Created,
+    Updated,
+    Dropped,
+}
+
+/// What has been changed, identified by keyspace and name where applicable.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SchemaChangeTarget {
+    Keyspace(CqlString<EasyBuf>),
+    Table {
+        keyspace: CqlString<EasyBuf>,
+        name: CqlString<EasyBuf>,
+    },
+    /// A user defined type.
+    Type {
+        keyspace: CqlString<EasyBuf>,
+        name: CqlString<EasyBuf>,
+    },
+    /// A user defined function, along with its argument types as CQL types (since v4).
+    Function {
+        keyspace: CqlString<EasyBuf>,
+        name: CqlString<EasyBuf>,
+        arg_types: CqlStringList<EasyBuf>,
+    },
+    /// A user defined aggregate, along with its argument types as CQL types (since v4).
+    Aggregate {
+        keyspace: CqlString<EasyBuf>,
+        name: CqlString<EasyBuf>,
+        arg_types: CqlStringList<EasyBuf>,
+    },
+}
+
+fn unknown(what: &str, value: &CqlString<EasyBuf>) -> decode::Error {
+    decode::Error::ParseError(format!("Unknown {} '{}'", what, value.as_ref()))
+}
+
+impl SchemaChange {
+    /// Decodes `<change_type><target><options>`.
+    pub fn decode(buf: EasyBuf) -> decode::ParseResult<SchemaChange> {
+        let (buf, change_type) = decode::string(buf)?;
+        let change_type = match change_type.as_ref() {
+            "CREATED" => SchemaChangeType::Created,
+            "UPDATED" => SchemaChangeType::Updated,
+            "DROPPED" => SchemaChangeType::Dropped,
+            _ => return Err(unknown("schema change type", &change_type)),
+        };
+        let (buf, target) = decode::string(buf)?;
+        let (buf, keyspace) = decode::string(buf)?;
+        let (buf, target) = match target.as_ref() {
+            "KEYSPACE" => (buf, SchemaChangeTarget::Keyspace(keyspace)),
+            "TABLE" | "TYPE" => {
+                let (buf, name) = decode::string(buf)?;
+                (buf,
+                 if target.as_ref() == "TABLE" {
+                     SchemaChangeTarget::Table {
+                         keyspace: keyspace,
+                         name: name,
+                     }
+                 } else {
+                     SchemaChangeTarget::Type {
+                         keyspace: keyspace,
+                         name: name,
+                     }
+                 })
+            }
+            "FUNCTION" | "AGGREGATE" => {
+                let (buf, name) = decode::string(buf)?;
+                let (buf, arg_types) = decode::string_list(buf)?;
+                (buf,
+                 if target.as_ref() == "FUNCTION" {
+                     SchemaChangeTarget::Function {
+                         keyspace: keyspace,
+                         name: name,
+                         arg_types: arg_types,
+                     }
+                 } else {
+                     SchemaChangeTarget::Aggregate {
+                         keyspace: keyspace,
+                         name: name,
+                         arg_types: arg_types,
+                     }
+                 })
+            }
+            _ => return Err(unknown("schema change target", &target)),
+        };
+        Ok((buf,
+            SchemaChange {
+                change_type: change_type,
+                target: target,
+            }))
+    }
+}
+
+impl CqlDecode<Event> for Event {
+    fn decode(_v: ProtocolVersion, buf: ::tokio_core::io::EasyBuf) -> Result<Event> {
+        let (buf, event_type) = decode::string(buf)?;
+        Ok(match event_type.as_ref() {
+            "TOPOLOGY_CHANGE" => {
+                let (buf, change) = decode::string(buf)?;
+                let change = match change.as_ref() {
+                    "NEW_NODE" => TopologyChange::NewNode,
+                    "REMOVED_NODE" => TopologyChange::RemovedNode,
+                    "MOVED_NODE" => TopologyChange::MovedNode,
+                    _ => return Err(unknown("topology change", &change).into()),
+                };
+                let (_, addr) = decode::inet(buf)?;
+                Event::TopologyChange(change, addr)
+            }
+            "STATUS_CHANGE" => {
+                let (buf, change) = decode::string(buf)?;
+                let change = match change.as_ref() {
+                    "UP" => StatusChange::Up,
+                    "DOWN" => StatusChange::Down,
+                    _ => return Err(unknown("status change", &change).into()),
+                };
+                let (_, addr) = decode::inet(buf)?;
+                Event::StatusChange(change, addr)
+            }
+            "SCHEMA_CHANGE" => Event::SchemaChange(SchemaChange::decode(buf)?.1),
+            _ => return Err(unknown("event type", &event_type).into()),
+        })
+    }
+}
+
+/// Encodes a string which is known to fit into a [string].
+fn encode_str(s: &str, buf: &mut Vec<u8>) {
+    encode::string(&unsafe { CqlString::<BVec>::unchecked_from(s) }, buf);
+}
+
+fn encode_byte_flag(v: bool, buf: &mut Vec<u8>) {
+    buf.push(if v { 1 } else { 0 });
+}
+
+impl CqlErrorKind {
+    /// Encodes the additional information of this kind of error, which follows code and message.
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        use self::CqlErrorKind::*;
+        match *self {
+            Unavailable { ref consistency, required, alive } => {
+                buf.extend(&encode::consistency(consistency)[..]);
+                buf.extend(&encode::int(required)[..]);
+                buf.extend(&encode::int(alive)[..]);
+            }
+            WriteTimeout { ref consistency, received, block_for, ref write_type } => {
+                buf.extend(&encode::consistency(consistency)[..]);
+                buf.extend(&encode::int(received)[..]);
+                buf.extend(&encode::int(block_for)[..]);
+                encode_str(write_type.as_str(), buf);
+            }
+            ReadTimeout { ref consistency, received, block_for, data_present } => {
+                buf.extend(&encode::consistency(consistency)[..]);
+                buf.extend(&encode::int(received)[..]);
+                buf.extend(&encode::int(block_for)[..]);
+                encode_byte_flag(data_present, buf);
+            }
+            ReadFailure { ref consistency, received, block_for, num_failures, data_present } => {
+                buf.extend(&encode::consistency(consistency)[..]);
+                buf.extend(&encode::int(received)[..]);
+                buf.extend(&encode::int(block_for)[..]);
+                buf.extend(&encode::int(num_failures)[..]);
+                encode_byte_flag(data_present, buf);
+            }
+            FunctionFailure { ref keyspace, ref function, ref arg_types } => {
+                encode::string(keyspace, buf);
+                encode::string(function, buf);
+                encode::string_list(arg_types, buf);
+            }
+            WriteFailure { ref consistency, received, block_for, num_failures, ref write_type } => {
+                buf.extend(&encode::consistency(consistency)[..]);
+                buf.extend(&encode::int(received)[..]);
+                buf.extend(&encode::int(block_for)[..]);
+                buf.extend(&encode::int(num_failures)[..]);
+                encode_str(write_type.as_str(), buf);
+            }
+            AlreadyExists { ref keyspace, ref table } => {
+                encode::string(keyspace, buf);
+                encode::string(table, buf);
+            }
+            Unprepared { ref id } => encode::short_bytes(id.as_bytes().unwrap_or(&[]), buf)?,
+            ServerError | ProtocolError | AuthenticationError | Overloaded | IsBootstrapping | TruncateError |
+            SyntaxError | Unauthorized | Invalid | ConfigError | Unknown(_) => {}
+        }
+        Ok(())
+    }
+}
+
+impl ErrorMessage {
+    /// Creates an error of the given kind, whose code is derived from it.
+    pub fn new(kind: CqlErrorKind, text: &str) -> Result<ErrorMessage> {
+        Ok(ErrorMessage {
+            code: kind.code(),
+            text: CqlString::try_from(text)?,
+            kind: kind,
+        })
+    }
+}
+
+impl CqlEncode for ErrorMessage {
+    fn encode(&self, _v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        buf.extend(&encode::int(self.code)[..]);
+        encode::string(&self.text, buf);
+        self.kind.encode(buf)?;
+        Ok(buf.len() - l)
+    }
+}
+
+impl CqlEncode for SupportedMessage {
+    fn encode(&self, _v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        encode::string_multimap(&self.0, buf);
+        Ok(buf.len() - l)
+    }
+}
+
+impl CqlEncode for AuthenticateMessage {
+    fn encode(&self, _v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        encode::string(&self.authenticator, buf);
+        Ok(buf.len() - l)
+    }
+}
+
+impl CqlEncode for AuthChallengeMessage {
+    fn encode(&self, _v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        encode::bytes(&self.token, buf);
+        Ok(buf.len() - l)
+    }
+}
+
+impl CqlEncode for AuthSuccessMessage {
+    fn encode(&self, _v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        encode::bytes(&self.payload, buf);
+        Ok(buf.len() - l)
+    }
+}
+
+impl TableSpec {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode::string(&self.keyspace, buf);
+        encode::string(&self.table, buf);
+    }
+}
+
+/// Encodes `[<global_table_spec>?<col_spec_1>...<col_spec_n>]`.
+fn encode_column_specs(global_tables_spec: Option<&TableSpec>,
+                       column_specs: &[ColumnSpec],
+                       buf: &mut Vec<u8>)
+                       -> Result<()> {
+    if let Some(spec) = global_tables_spec {
+        spec.encode(buf);
+    }
+    for column in column_specs {
+        if global_tables_spec.is_none() {
+            column.table_spec
+                .as_ref()
+                .ok_or_else(|| ErrorKind::MissingTableSpec(column.name.as_ref().into()))?
+                .encode(buf);
+        }
+        encode::string(&column.name, buf);
+        encode::option(&column.column_type, buf);
+    }
+    Ok(())
+}
+
+impl RowsMetadata {
+    /// Encodes `<flags><columns_count>[<paging_state>][<global_table_spec>?<col_spec_1>...<col_spec_n>]`.
+    /// Without metadata, `columns_count` is used, and the amount of column specs otherwise.
+    pub fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        let mut flags = 0;
+        if self.global_tables_spec.is_some() && !self.no_metadata {
+            flags |= 0x0001;
+        }
+        if self.paging_state.is_some() {
+            flags |= 0x0002;
+        }
+        if self.no_metadata {
+            flags |= 0x0004;
+        }
+        let columns_count = if self.no_metadata {
+            self.columns_count
         } else {
-            buf
+            self.column_specs.len() as i32
         };
+        buf.extend(&encode::int(flags)[..]);
+        buf.extend(&encode::int(columns_count)[..]);
+        if let Some(ref paging_state) = self.paging_state {
+            encode::bytes(paging_state, buf);
+        }
+        if self.no_metadata {
+            return Ok(());
+        }
+        encode_column_specs(self.global_tables_spec.as_ref(), &self.column_specs, buf)
+    }
+}
 
-        // TODO: parse column spec if present
+impl RowsMessage {
+    /// Encodes `<rows_count><rows_content>`.
+    pub fn encode_rows(&self, buf: &mut Vec<u8>) {
+        buf.extend(&encode::int(self.rows.len() as i32)[..]);
+        for row in &self.rows {
+            for cell in &row.cells {
+                encode::bytes(cell, buf);
+            }
+        }
+    }
+}
 
-        rows_metadata.no_metadata = (flags & 0x0004) == 0x0004;
+impl PreparedMessage {
+    fn encode(&self, v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<()> {
+        encode::short_bytes(self.id.as_bytes().unwrap_or(&[]), buf)?;
+        let flags = if self.metadata.global_tables_spec.is_some() {
+            0x0001
+        } else {
+            0x0000
+        };
+        buf.extend(&encode::int(flags)[..]);
+        buf.extend(&encode::int(self.metadata.column_specs.len() as i32)[..]);
+        if v == ProtocolVersion::Version4 {
+            buf.extend(&encode::int(self.metadata.pk_indices.len() as i32)[..]);
+            for index in &self.metadata.pk_indices {
+                buf.extend(&encode::short(*index)[..]);
+            }
+        }
+        encode_column_specs(self.metadata.global_tables_spec.as_ref(),
+                            &self.metadata.column_specs,
+                            buf)?;
+        self.result_metadata.encode(buf)
+    }
+}
 
-        Ok((buf, rows_metadata))
+impl SchemaChange {
+    /// Encodes `<change_type><target><options>`.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        use self::SchemaChangeTarget::*;
+        encode_str(match self.change_type {
+                       SchemaChangeType::Created => "CREATED",
+                       SchemaChangeType::Updated => "UPDATED",
+                       SchemaChangeType::Dropped => "DROPPED",
+                   },
+                   buf);
+        match self.target {
+            Keyspace(ref keyspace) => {
+                encode_str("KEYSPACE", buf);
+                encode::string(keyspace, buf);
+            }
+            Table { ref keyspace, ref name } |
+            Type { ref keyspace, ref name } => {
+                encode_str(if let Table { .. } = self.target {
+                               "TABLE"
+                           } else {
+                               "TYPE"
+                           },
+                           buf);
+                encode::string(keyspace, buf);
+                encode::string(name, buf);
+            }
+            Function { ref keyspace, ref name, ref arg_types } |
+            Aggregate { ref keyspace, ref name, ref arg_types } => {
+                encode_str(if let Function { .. } = self.target {
+                               "FUNCTION"
+                           } else {
+                               "AGGREGATE"
+                           },
+                           buf);
+                encode::string(keyspace, buf);
+                encode::string(name, buf);
+                encode::string_list(arg_types, buf);
+            }
+        }
     }
 }
 
+impl CqlEncode for ResultMessage {
+    fn encode(&self, v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        match *self {
+            ResultMessage::Void => buf.extend(&encode::int(0x0001)[..]),
+            ResultMessage::Rows(ref rows) => {
+                buf.extend(&encode::int(0x0002)[..]);
+                rows.metadata.encode(buf)?;
+                rows.encode_rows(buf);
+            }
+            ResultMessage::SetKeyspace(ref keyspace) => {
+                buf.extend(&encode::int(0x0003)[..]);
+                encode::string(keyspace, buf);
+            }
+            ResultMessage::Prepared(ref prepared) => {
+                buf.extend(&encode::int(0x0004)[..]);
+                prepared.encode(v, buf)?;
+            }
+            ResultMessage::SchemaChange(ref change) => {
+                buf.extend(&encode::int(0x0005)[..]);
+                change.encode(buf);
+            }
+        }
+        Ok(buf.len() - l)
+    }
+}
+
+impl CqlEncode for Event {
+    fn encode(&self, _v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        let l = buf.len();
+        match *self {
+            Event::TopologyChange(ref change, ref addr) => {
+                encode_str("TOPOLOGY_CHANGE", buf);
+                encode_str(match *change {
+                               TopologyChange::NewNode => "NEW_NODE",
+                               TopologyChange::RemovedNode => "REMOVED_NODE",
+                               TopologyChange::MovedNode => "MOVED_NODE",
+                           },
+                           buf);
+                encode::inet(addr, buf);
+            }
+            Event::StatusChange(ref change, ref addr) => {
+                encode_str("STATUS_CHANGE", buf);
+                encode_str(match *change {
+                               StatusChange::Up => "UP",
+                               StatusChange::Down => "DOWN",
+                           },
+                           buf);
+                encode::inet(addr, buf);
+            }
+            Event::SchemaChange(ref change) => {
+                encode_str("SCHEMA_CHANGE", buf);
+                change.encode(buf);
+            }
+        }
+        Ok(buf.len() - l)
+    }
+}
+
+impl ResponsePrelude {
+    /// Encodes the values which are set, returning the header flags announcing them.
+    pub fn encode(&self, v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<u8> {
+        let mut flags = 0;
+        if let Some(ref id) = self.tracing_id {
+            buf.extend(&id[..]);
+            flags |= FLAG_TRACING;
+        }
+        if (self.warnings.is_some() || self.custom_payload.is_some()) && v == ProtocolVersion::Version3 {
+            return Err(ErrorKind::PreludeNotSupported(v).into());
+        }
+        if let Some(ref warnings) = self.warnings {
+            encode::string_list(warnings, buf);
+            flags |= FLAG_WARNING;
+        }
+        if let Some(ref payload) = self.custom_payload {
+            encode::bytes_map(payload, buf);
+            flags |= FLAG_CUSTOM_PAYLOAD;
+        }
+        Ok(flags)
+    }
+}
+
+impl Message {
+    /// Decodes the body of a response frame with the given op-code, which must not contain a
+    /// prelude anymore.
+    pub fn decode(v: ProtocolVersion, code: OpCode, buf: EasyBuf) -> Result<Message> {
+        Ok(match code {
+            OpCode::Supported => Message::Supported(SupportedMessage::decode(v, buf)?),
+            OpCode::Ready => Message::Ready,
+            OpCode::Authenticate => Message::Authenticate(AuthenticateMessage::decode(v, buf)?),
+            OpCode::AuthChallenge => Message::AuthChallenge(AuthChallengeMessage::decode(v, buf)?),
+            OpCode::AuthSuccess => Message::AuthSuccess(AuthSuccessMessage::decode(v, buf)?),
+            OpCode::Error => Message::Error(ErrorMessage::decode(v, buf)?),
+            OpCode::Result => Message::Result(ResultMessage::decode(v, buf)?),
+            OpCode::Event => Message::Event(Event::decode(v, buf)?),
+            code => return Err(ErrorKind::UnexpectedOpCode(code).into()),
+        })
+    }
+
+    pub fn opcode(&self) -> OpCode {
+        match *self {
+            Message::Supported(_) => OpCode::Supported,
+            Message::Ready => OpCode::Ready,
+            Message::Authenticate(_) => OpCode::Authenticate,
+            Message::AuthChallenge(_) => OpCode::AuthChallenge,
+            Message::AuthSuccess(_) => OpCode::AuthSuccess,
+            Message::Error(_) => OpCode::Error,
+            Message::Result(_) => OpCode::Result,
+            Message::Event(_) => OpCode::Event,
+        }
+    }
+}
+
+impl CqlEncode for Message {
+    fn encode(&self, v: ProtocolVersion, buf: &mut Vec<u8>) -> Result<usize> {
+        match *self {
+            Message::Ready => Ok(0),
+            Message::Supported(ref msg) => msg.encode(v, buf),
+            Message::Authenticate(ref msg) => msg.encode(v, buf),
+            Message::AuthChallenge(ref msg) => msg.encode(v, buf),
+            Message::AuthSuccess(ref msg) => msg.encode(v, buf),
+            Message::Error(ref msg) => msg.encode(v, buf),
+            Message::Result(ref msg) => msg.encode(v, buf),
+            Message::Event(ref msg) => msg.encode(v, buf),
+        }
+    }
+}
+
+pub fn cql_encode(version: ProtocolVersion,
+                  flags: u8,
+                  stream_id: u16,
+                  to_encode: &Message,
+                  sink: &mut Vec<u8>)
+                  -> Result<()> {
+    cql_encode_frame(version,
+                     flags,
+                     stream_id,
+                     &ResponsePrelude::default(),
+                     None,
+                     to_encode,
+                     sink)
+}
+
+/// Encodes the response message into a frame, preceded by the values of the given prelude, and
+/// compresses the body with the given compression, which must have been negotiated with a
+/// STARTUP message before. The flags announcing prelude and compression are set accordingly.
+pub fn cql_encode_frame(version: ProtocolVersion,
+                        flags: u8,
+                        stream_id: u16,
+                        prelude: &ResponsePrelude,
+                        compression: Option<Compression>,
+                        to_encode: &Message,
+                        sink: &mut Vec<u8>)
+                        -> Result<()> {
+    let mut flags = flags & !(FLAG_COMPRESSION | FLAG_TRACING | FLAG_CUSTOM_PAYLOAD | FLAG_WARNING);
+    sink.resize(Header::encoded_len(), 0);
+
+    flags |= prelude.encode(version, sink)?;
+    to_encode.encode(version, sink)?;
+
+    if let Some(compression) = compression {
+        let body = compression.compress(&sink[Header::encoded_len()..])?;
+        sink.truncate(Header::encoded_len());
+        sink.extend(body);
+        flags |= FLAG_COMPRESSION;
+    }
+
+    let len = sink.len() - Header::encoded_len();
+    if len > u32::max_value() as usize {
+        return Err(ErrorKind::BodyLengthExceeded(len).into());
+    }
+
+    let header = Header {
+        version: header::Version::response(version),
+        flags: flags,
+        stream_id: stream_id,
+        op_code: to_encode.opcode(),
+        length: len as u32,
+    };
+    sink[0..Header::encoded_len()].copy_from_slice(&header.encode()?);
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use codec::header::Header;
     use codec::header::ProtocolVersion::*;
-    use codec::primitives::{CqlStringMultiMap, CqlStringList, CqlString};
+    use codec::primitives::{CqlStringMultiMap, CqlStringList, CqlString, ColumnType, CqlValue};
+    use codec::primitives::encode;
     use super::*;
 
     fn skip_header(b: &[u8]) -> &[u8] {
@@ -263,6 +1395,20 @@ mod test {
         assert_eq!(res.compression().unwrap(), &csl2);
     }
 
+    #[test]
+    fn decode_message_by_opcode() {
+        let msg = include_bytes!("../../tests/fixtures/v3/responses/supported.msg");
+        let buf: EasyBuf = Vec::from(skip_header(&msg[..])).into();
+        match Message::decode(Version3, OpCode::Supported, buf.clone()).unwrap() {
+            Message::Supported(_) => {}
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+        match Message::decode(Version3, OpCode::Query, buf) {
+            Err(Error(ErrorKind::UnexpectedOpCode(OpCode::Query), _)) => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+    }
+
     #[test]
     fn supported_message_latest_cql_version() {
         let versions = ["3.2.1", "3.1.2", "4.0.1"];
@@ -294,6 +1440,15 @@ mod test {
         assert_eq!(res.payload.as_bytes(), None);
     }
 
+    #[test]
+    fn decode_auth_challenge_message() {
+        let mut buf = Vec::new();
+        encode::bytes(&CqlBytes::<Vec<u8>>::try_from(vec![1u8, 2, 3]).unwrap(), &mut buf);
+        let res = AuthChallengeMessage::decode(Version4, buf.into()).unwrap();
+
+        assert_eq!(res.token.as_bytes(), Some(&[1u8, 2, 3][..]));
+    }
+
     #[test]
     fn decode_error_message() {
         let msg = include_bytes!("../../tests/fixtures/v3/responses/error_credentials.msg");
@@ -301,10 +1456,143 @@ mod test {
         let res = ErrorMessage::decode(Version3, buf).unwrap();
 
         assert_eq!(res.code, 256);
+        assert_eq!(res.kind, CqlErrorKind::AuthenticationError);
         assert_eq!(res.text,
                    CqlString::try_from("Username and/or password are incorrect").unwrap());
     }
 
+    fn error_body(code: i32, rest: &[u8]) -> EasyBuf {
+        let mut buf = Vec::new();
+        buf.extend(&encode::int(code)[..]);
+        put_string("failed", &mut buf);
+        buf.extend(rest);
+        buf.into()
+    }
+
+    #[test]
+    fn decode_error_kinds() {
+        let mut rest = Vec::new();
+        rest.extend(&encode::consistency(&CqlConsistency::Quorum)[..]);
+        rest.extend(&encode::int(3)[..]);
+        rest.extend(&encode::int(1)[..]);
+        let res = ErrorMessage::decode(Version4, error_body(0x1000, &rest)).unwrap();
+        assert_eq!(res.kind,
+                   CqlErrorKind::Unavailable {
+                       consistency: CqlConsistency::Quorum,
+                       required: 3,
+                       alive: 1,
+                   });
+
+        let mut rest = Vec::new();
+        rest.extend(&encode::consistency(&CqlConsistency::One)[..]);
+        rest.extend(&encode::int(0)[..]);
+        rest.extend(&encode::int(1)[..]);
+        put_string("BATCH_LOG", &mut rest);
+        let res = ErrorMessage::decode(Version4, error_body(0x1100, &rest)).unwrap();
+        assert_eq!(res.kind,
+                   CqlErrorKind::WriteTimeout {
+                       consistency: CqlConsistency::One,
+                       received: 0,
+                       block_for: 1,
+                       write_type: WriteType::BatchLog,
+                   });
+
+        let mut rest = Vec::new();
+        rest.extend(&encode::consistency(&CqlConsistency::All)[..]);
+        rest.extend(&encode::int(2)[..]);
+        rest.extend(&encode::int(3)[..]);
+        rest.push(0x01);
+        let res = ErrorMessage::decode(Version4, error_body(0x1200, &rest)).unwrap();
+        assert_eq!(res.kind,
+                   CqlErrorKind::ReadTimeout {
+                       consistency: CqlConsistency::All,
+                       received: 2,
+                       block_for: 3,
+                       data_present: true,
+                   });
+
+        let mut rest = Vec::new();
+        put_string("ks", &mut rest);
+        put_string("", &mut rest);
+        let res = ErrorMessage::decode(Version4, error_body(0x2400, &rest)).unwrap();
+        assert_eq!(res.kind,
+                   CqlErrorKind::AlreadyExists {
+                       keyspace: cql_string!("ks"),
+                       table: cql_string!(""),
+                   });
+
+        let mut rest = Vec::new();
+        encode::short_bytes(&[1, 2], &mut rest).unwrap();
+        let res = ErrorMessage::decode(Version4, error_body(0x2500, &rest)).unwrap();
+        assert_eq!(res.kind, CqlErrorKind::Unprepared { id: cql_bytes!(1, 2) });
+        assert_eq!(res.kind.code(), 0x2500);
+
+        let res = ErrorMessage::decode(Version4, error_body(0x7777, &[])).unwrap();
+        assert_eq!(res.kind, CqlErrorKind::Unknown(0x7777));
+        assert_eq!(res.kind.code(), 0x7777);
+    }
+
+    #[test]
+    fn decode_v4_failure_kinds() {
+        let mut rest = Vec::new();
+        rest.extend(&encode::consistency(&CqlConsistency::LocalQuorum)[..]);
+        rest.extend(&encode::int(1)[..]);
+        rest.extend(&encode::int(2)[..]);
+        rest.extend(&encode::int(1)[..]);
+        put_string("CAS", &mut rest);
+        let res = ErrorMessage::decode(Version4, error_body(0x1500, &rest)).unwrap();
+        assert_eq!(res.kind,
+                   CqlErrorKind::WriteFailure {
+                       consistency: CqlConsistency::LocalQuorum,
+                       received: 1,
+                       block_for: 2,
+                       num_failures: 1,
+                       write_type: WriteType::Cas,
+                   });
+
+        let mut rest = Vec::new();
+        put_string("ks", &mut rest);
+        put_string("fn", &mut rest);
+        encode::string_list(&CqlStringList::try_from_iter(vec!["int"]).unwrap(), &mut rest);
+        let res = ErrorMessage::decode(Version4, error_body(0x1400, &rest)).unwrap();
+        match res.kind {
+            CqlErrorKind::FunctionFailure { keyspace, function, arg_types } => {
+                assert_eq!(keyspace, cql_string!("ks"));
+                assert_eq!(function, cql_string!("fn"));
+                assert_eq!(arg_types.len(), 1);
+            }
+            kind => panic!("Unexpected kind {:?}", kind),
+        }
+
+        assert!(ErrorMessage::decode(Version4, error_body(0x1300, &[0x00])).is_err());
+    }
+
+    #[test]
+    fn decode_response_prelude() {
+        let header = Header::try_from(&b"\x84\x0e\x00\x01\x08\x00\x00\x00\x00"[..]).unwrap();
+        let mut buf = vec![7; 16];
+        encode::string_list(&CqlStringList::try_from_iter(vec!["careful"]).unwrap(), &mut buf);
+        let mut payload = HashMap::new();
+        payload.insert(cql_string!("k"), cql_bytes!(1));
+        encode::bytes_map(&payload, &mut buf);
+        buf.extend(&encode::int(0x0001)[..]);
+
+        let (rest, res) = ResponsePrelude::decode(&header, buf.into()).unwrap();
+        assert_eq!(res.tracing_id, Some([7; 16]));
+        assert_eq!(res.warnings.unwrap().iter().next().map(|w| w.as_ref().to_string()),
+                   Some("careful".to_string()));
+        assert_eq!(res.custom_payload, Some(payload));
+        assert_eq!(ResultMessage::decode(Version4, rest).unwrap(), ResultMessage::Void);
+
+        let header = Header::try_from(&b"\x83\x02\x00\x01\x08\x00\x00\x00\x00"[..]).unwrap();
+        let mut buf = vec![1; 16];
+        buf.extend(&encode::int(0x0001)[..]);
+        let (rest, res) = ResponsePrelude::decode(&header, buf.into()).unwrap();
+        assert_eq!(res.tracing_id, Some([1; 16]));
+        assert_eq!(res.warnings, None);
+        assert_eq!(ResultMessage::decode(Version3, rest).unwrap(), ResultMessage::Void);
+    }
+
     #[test]
     fn decode_result_header_rows() {
         let msg = include_bytes!("../../tests/fixtures/v3/responses/result_rows.msg");
@@ -314,6 +1602,21 @@ mod test {
         let res = ResultHeader::decode(Version3, Vec::from(&buf[0..5]).into()).unwrap();
         assert_eq!(res, None);
 
+        // The counts of a truncated header are not mistaken for impossible ones.
+        let (rest, _) = ResultHeader::decode_with_rest(Version3, Vec::from(&buf[..]).into()).unwrap();
+        for end in 0..buf.len() - rest.len() {
+            let res = ResultHeader::decode(Version3, Vec::from(&buf[0..end]).into()).unwrap();
+            assert_eq!(res, None, "decoding {} bytes", end);
+        }
+
+        let column = |name: &str, column_type: ColumnType| {
+            ColumnSpec {
+                table_spec: None,
+                name: cql_string!(name),
+                column_type: column_type,
+            }
+        };
+
         let rexpected = RowsMetadata {
             global_tables_spec: Some(TableSpec {
                 keyspace: cql_string!("system"),
@@ -322,12 +1625,124 @@ mod test {
             paging_state: None,
             no_metadata: false,
             columns_count: 18,
+            column_specs: vec![column("key", ColumnType::Varchar),
+                               column("bootstrapped", ColumnType::Varchar),
+                               column("broadcast_address", ColumnType::Inet),
+                               column("cluster_name", ColumnType::Varchar),
+                               column("cql_version", ColumnType::Varchar),
+                               column("data_center", ColumnType::Varchar),
+                               column("gossip_generation", ColumnType::Int),
+                               column("host_id", ColumnType::Uuid),
+                               column("listen_address", ColumnType::Inet),
+                               column("native_protocol_version", ColumnType::Varchar),
+                               column("partitioner", ColumnType::Varchar),
+                               column("rack", ColumnType::Varchar),
+                               column("release_version", ColumnType::Varchar),
+                               column("rpc_address", ColumnType::Inet),
+                               column("schema_version", ColumnType::Uuid),
+                               column("thrift_version", ColumnType::Varchar),
+                               column("tokens", ColumnType::Set(Box::new(ColumnType::Varchar))),
+                               column("truncated_at",
+                                      ColumnType::Map(Box::new(ColumnType::Uuid), Box::new(ColumnType::Blob)))],
         };
 
         let res = ResultHeader::decode(Version3, buf.into()).unwrap();
         assert_eq!(res, Some(ResultHeader::Rows(rexpected)));
+    }
+
+    #[test]
+    fn decode_result_rows() {
+        let msg = include_bytes!("../../tests/fixtures/v3/responses/result_rows.msg");
+        let buf = Vec::from(skip_header(&msg[..]));
 
-        // rest of drained buf should be used for streaming results after that
+        assert!(ResultMessage::decode(Version3, Vec::from(&buf[0..400]).into()).is_err());
+
+        let res = match ResultMessage::decode(Version3, buf.into()).unwrap() {
+            ResultMessage::Rows(rows) => rows,
+            res => panic!("Unexpected result {:?}", res),
+        };
+
+        assert_eq!(res.rows.len(), 1);
+        let row = &res.rows[0];
+        assert_eq!(row.cells.len(), 18);
+        assert_eq!(row.cells[res.column_index("key").unwrap()].as_bytes(),
+                   Some(&b"local"[..]));
+        assert_eq!(row.cells[res.column_index("cluster_name").unwrap()].as_bytes(),
+                   Some(&b"Test Cluster"[..]));
+        assert_eq!(row.cells[res.column_index("broadcast_address").unwrap()].as_bytes(),
+                   Some(&[172u8, 17, 0, 2][..]));
+        assert_eq!(row.cells[res.column_index("gossip_generation").unwrap()].len(), 4);
+        assert_eq!(res.column_index("unknown"), None);
+
+        let values = res.decode_row(row).unwrap();
+        assert_eq!(values[res.column_index("cluster_name").unwrap()],
+                   Some(CqlValue::Varchar("Test Cluster".into())));
+        assert_eq!(values[res.column_index("gossip_generation").unwrap()],
+                   Some(CqlValue::Int(1486214396)));
+        assert_eq!(res.get::<String>(row, "cluster_name").unwrap(), "Test Cluster");
+        assert_eq!(res.get::<Option<String>>(row, "rack").unwrap(),
+                   Some(String::from("rack1")));
+        assert!(res.get::<i64>(row, "gossip_generation").is_err());
+        assert!(res.get::<i32>(row, "unknown").is_err());
+
+        match values[res.column_index("tokens").unwrap()] {
+            Some(CqlValue::Set(ref tokens)) => assert_eq!(tokens.len(), 256),
+            ref v => panic!("Unexpected tokens {:?}", v),
+        }
+    }
+
+    #[test]
+    fn decode_rows_metadata_with_paging_state() {
+        let mut buf = Vec::new();
+        buf.extend(&encode::int(0x0002)[..]);
+        buf.extend(&encode::int(0x0002 | 0x0004)[..]);
+        buf.extend(&encode::int(1)[..]);
+        encode::bytes(&CqlBytes::<Vec<u8>>::try_from(vec![9u8, 8, 7]).unwrap(), &mut buf);
+        buf.extend(&encode::int(2)[..]);
+        encode::bytes(&CqlBytes::<Vec<u8>>::try_from(vec![1u8]).unwrap(), &mut buf);
+        encode::bytes(&CqlBytes::<Vec<u8>>::null_value(), &mut buf);
+
+        let expected = RowsMessage {
+            metadata: RowsMetadata {
+                global_tables_spec: None,
+                paging_state: Some(cql_bytes!(9, 8, 7)),
+                no_metadata: true,
+                columns_count: 1,
+                column_specs: Vec::new(),
+            },
+            rows: vec![Row { cells: vec![cql_bytes!(1)] }, Row { cells: vec![CqlBytes::null_value()] }],
+        };
+
+        let res = ResultMessage::decode(Version3, buf.into()).unwrap();
+        assert_eq!(res, ResultMessage::Rows(expected));
+    }
+
+    #[test]
+    fn decode_rows_with_impossible_counts() {
+        // a rows count which would need gigabytes of rows
+        let mut buf = Vec::new();
+        buf.extend(&encode::int(0x0002)[..]);
+        buf.extend(&encode::int(0x0004)[..]);
+        buf.extend(&encode::int(1)[..]);
+        buf.extend(&encode::int(0x7fffffff)[..]);
+        buf.extend(&encode::int(-1)[..]);
+        assert!(ResultMessage::decode(Version3, buf.into()).is_err());
+
+        // a columns count which would need gigabytes of column specs
+        let mut buf = Vec::new();
+        buf.extend(&encode::int(0x0002)[..]);
+        buf.extend(&encode::int(0x0001)[..]);
+        buf.extend(&encode::int(0x7fffffff)[..]);
+        buf.extend(&[0, 2, b'k', b's', 0, 1, b't', 0, 1, b'a', 0, 9]);
+        assert!(ResultMessage::decode(Version3, buf.into()).is_err());
+
+        assert!(RowsMessage::decode_rows(Vec::from(&encode::int(-1)[..]).into(), 1).is_err());
+        // a rows count which fits into a frame, but was not received completely
+        assert_eq!(RowsMessage::decode_rows(Vec::from(&encode::int(2)[..]).into(), 1).unwrap_err(),
+                   decode::Error::Incomplete(decode::Needed::Unknown));
+        assert!(RowsMessage::decode_rows(Vec::from(&encode::int(1)[..]).into(), -1).is_err());
+        let (_, rows) = RowsMessage::decode_rows(Vec::from(&encode::int(0)[..]).into(), 1000).unwrap();
+        assert!(rows.is_empty());
     }
 
     #[test]
@@ -370,10 +1785,413 @@ mod test {
 
         let res = ResultHeader::decode(Version3, buf.into()).unwrap();
         assert_eq!(res,
-                   Some(ResultHeader::SchemaChange(SchemaChangePayload {
-                       change_type: cql_string!("change_type"),
-                       target: cql_string!("target"),
-                       options: cql_string!("options"),
+                   Some(ResultHeader::SchemaChange(SchemaChange {
+                       change_type: SchemaChangeType::Created,
+                       target: SchemaChangeTarget::Table {
+                           keyspace: cql_string!("ks"),
+                           name: cql_string!("users"),
+                       },
                    })));
     }
+
+    #[test]
+    fn decode_result_schema_change_targets() {
+        let mut buf = Vec::new();
+        buf.extend(&encode::int(0x0005)[..]);
+        put_string("DROPPED", &mut buf);
+        put_string("KEYSPACE", &mut buf);
+        put_string("ks", &mut buf);
+        assert_eq!(ResultMessage::decode(Version4, buf.into()).unwrap(),
+                   ResultMessage::SchemaChange(SchemaChange {
+                       change_type: SchemaChangeType::Dropped,
+                       target: SchemaChangeTarget::Keyspace(cql_string!("ks")),
+                   }));
+
+        let mut buf = Vec::new();
+        buf.extend(&encode::int(0x0005)[..]);
+        put_string("UPDATED", &mut buf);
+        put_string("FUNCTION", &mut buf);
+        put_string("ks", &mut buf);
+        put_string("plus", &mut buf);
+        encode::string_list(&CqlStringList::try_from_iter(vec!["int", "int"]).unwrap(), &mut buf);
+        match ResultMessage::decode(Version4, buf.into()).unwrap() {
+            ResultMessage::SchemaChange(SchemaChange {
+                change_type: SchemaChangeType::Updated,
+                target: SchemaChangeTarget::Function { name, arg_types, .. } }) => {
+                assert_eq!(name, cql_string!("plus"));
+                assert_eq!(arg_types.iter().count(), 2);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    fn put_string(s: &str, buf: &mut Vec<u8>) {
+        encode::string(&CqlString::<EasyBuf>::try_from(s).unwrap(), buf);
+    }
+
+    #[test]
+    fn decode_result_header_prepared() {
+        let mut buf = Vec::new();
+        buf.extend(&encode::int(0x0004)[..]);
+        encode::short_bytes(&[1, 2, 3, 4], &mut buf).unwrap();
+        // bind variables: global table spec and a single column
+        buf.extend(&encode::int(0x0001)[..]);
+        buf.extend(&encode::int(1)[..]);
+        put_string("ks", &mut buf);
+        put_string("users", &mut buf);
+        put_string("id", &mut buf);
+        encode::option(&ColumnType::Uuid, &mut buf);
+        // result metadata: no global table spec
+        buf.extend(&encode::int(0x0000)[..]);
+        buf.extend(&encode::int(1)[..]);
+        put_string("ks", &mut buf);
+        put_string("users", &mut buf);
+        put_string("name", &mut buf);
+        encode::option(&ColumnType::Varchar, &mut buf);
+
+        let res = ResultHeader::decode(Version3, Vec::from(&buf[0..10]).into()).unwrap();
+        assert_eq!(res, None);
+
+        let users = TableSpec {
+            keyspace: cql_string!("ks"),
+            table: cql_string!("users"),
+        };
+        let expected = PreparedMessage {
+            id: cql_bytes!(1, 2, 3, 4),
+            metadata: PreparedMetadata {
+                global_tables_spec: Some(users.clone()),
+                pk_indices: Vec::new(),
+                column_specs: vec![ColumnSpec {
+                                       table_spec: None,
+                                       name: cql_string!("id"),
+                                       column_type: ColumnType::Uuid,
+                                   }],
+            },
+            result_metadata: RowsMetadata {
+                global_tables_spec: None,
+                paging_state: None,
+                no_metadata: false,
+                columns_count: 1,
+                column_specs: vec![ColumnSpec {
+                                       table_spec: Some(users),
+                                       name: cql_string!("name"),
+                                       column_type: ColumnType::Varchar,
+                                   }],
+            },
+        };
+
+        let res = ResultHeader::decode(Version3, buf.into()).unwrap();
+        assert_eq!(res, Some(ResultHeader::Prepared(expected)));
+    }
+
+    #[test]
+    fn decode_result_header_prepared_v4() {
+        let mut buf = Vec::new();
+        buf.extend(&encode::int(0x0004)[..]);
+        encode::short_bytes(&[1], &mut buf).unwrap();
+        // bind variables: two columns, of which the second is the partition key
+        buf.extend(&encode::int(0x0001)[..]);
+        buf.extend(&encode::int(2)[..]);
+        buf.extend(&encode::int(1)[..]);
+        buf.extend(&encode::short(1)[..]);
+        put_string("ks", &mut buf);
+        put_string("users", &mut buf);
+        put_string("name", &mut buf);
+        encode::option(&ColumnType::Varchar, &mut buf);
+        put_string("id", &mut buf);
+        encode::option(&ColumnType::Uuid, &mut buf);
+        // result metadata: none
+        buf.extend(&encode::int(0x0004)[..]);
+        buf.extend(&encode::int(0)[..]);
+
+        let res = match ResultHeader::decode(Version4, buf.into()).unwrap() {
+            Some(ResultHeader::Prepared(p)) => p,
+            res => panic!("Unexpected result {:?}", res),
+        };
+        assert_eq!(res.metadata.pk_indices, vec![1]);
+        assert_eq!(res.metadata.column_specs.len(), 2);
+        assert_eq!(res.metadata.column_specs[1].name, cql_string!("id"));
+        assert!(res.result_metadata.no_metadata);
+    }
+
+    #[test]
+    fn decode_prepared_with_impossible_pk_count() {
+        let mut buf = Vec::new();
+        buf.extend(&encode::int(0x0004)[..]);
+        encode::short_bytes(&[1], &mut buf).unwrap();
+        buf.extend(&encode::int(0)[..]);
+        buf.extend(&encode::int(0)[..]);
+        buf.extend(&encode::int(0x7fffffff)[..]);
+        buf.extend(&encode::short(0)[..]);
+        assert!(ResultHeader::decode(Version4, buf.into()).is_err());
+    }
+
+    #[test]
+    fn decode_node_events() {
+        use std::str::FromStr;
+        let addr = SocketAddr::from_str("10.0.0.1:9042").unwrap();
+        let mut buf = Vec::new();
+        put_string("TOPOLOGY_CHANGE", &mut buf);
+        put_string("NEW_NODE", &mut buf);
+        encode::inet(&addr, &mut buf);
+        assert_eq!(Event::decode(Version4, buf.into()).unwrap(),
+                   Event::TopologyChange(TopologyChange::NewNode, addr));
+
+        let mut buf = Vec::new();
+        put_string("STATUS_CHANGE", &mut buf);
+        put_string("DOWN", &mut buf);
+        encode::inet(&addr, &mut buf);
+        assert_eq!(Event::decode(Version4, buf.into()).unwrap(),
+                   Event::StatusChange(StatusChange::Down, addr));
+
+        let mut buf = Vec::new();
+        put_string("STATUS_CHANGE", &mut buf);
+        put_string("SIDEWAYS", &mut buf);
+        assert!(Event::decode(Version4, buf.into()).is_err());
+    }
+
+    #[test]
+    fn decode_schema_change_events() {
+        let mut buf = Vec::new();
+        put_string("SCHEMA_CHANGE", &mut buf);
+        put_string("CREATED", &mut buf);
+        put_string("TABLE", &mut buf);
+        put_string("ks", &mut buf);
+        put_string("users", &mut buf);
+        assert_eq!(Event::decode(Version4, buf.into()).unwrap(),
+                   Event::SchemaChange(SchemaChange {
+                       change_type: SchemaChangeType::Created,
+                       target: SchemaChangeTarget::Table {
+                           keyspace: cql_string!("ks"),
+                           name: cql_string!("users"),
+                       },
+                   }));
+
+        let mut buf = Vec::new();
+        put_string("SCHEMA_CHANGE", &mut buf);
+        put_string("DROPPED", &mut buf);
+        put_string("KEYSPACE", &mut buf);
+        put_string("ks", &mut buf);
+        assert_eq!(Event::decode(Version4, buf.into()).unwrap(),
+                   Event::SchemaChange(SchemaChange {
+                       change_type: SchemaChangeType::Dropped,
+                       target: SchemaChangeTarget::Keyspace(cql_string!("ks")),
+                   }));
+
+        let mut buf = Vec::new();
+        put_string("SCHEMA_CHANGE", &mut buf);
+        put_string("UPDATED", &mut buf);
+        put_string("AGGREGATE", &mut buf);
+        put_string("ks", &mut buf);
+        put_string("avg", &mut buf);
+        encode::string_list(&CqlStringList::try_from_iter(vec!["int", "text"]).unwrap(), &mut buf);
+        match Event::decode(Version4, buf.into()).unwrap() {
+            Event::SchemaChange(SchemaChange { target: SchemaChangeTarget::Aggregate { name, arg_types, .. }, .. }) => {
+                assert_eq!(name, cql_string!("avg"));
+                assert_eq!(arg_types.len(), 2);
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    fn decode_frame(frame: &[u8]) -> (Header, ResponsePrelude, EasyBuf) {
+        let h = Header::try_from(&frame[..Header::encoded_len()]).unwrap();
+        let body = Vec::from(&frame[Header::encoded_len()..]).into();
+        let (body, prelude) = ResponsePrelude::decode(&h, body).unwrap();
+        (h, prelude, body)
+    }
+
+    fn reencoded<T>(frame: &[u8], wrap: fn(T) -> Message) -> Vec<u8>
+        where T: CqlDecode<T>
+    {
+        let (h, _, body) = decode_frame(frame);
+        let msg = wrap(T::decode(h.version.version, body).unwrap());
+        let mut buf = Vec::new();
+        cql_encode(h.version.version, h.flags, h.stream_id, &msg, &mut buf).unwrap();
+        buf
+    }
+
+    fn assert_roundtrip<T>(v: ProtocolVersion, msg: T)
+        where T: CqlEncode + CqlDecode<T> + PartialEq + ::std::fmt::Debug
+    {
+        let mut buf = Vec::new();
+        msg.encode(v, &mut buf).unwrap();
+        assert_eq!(T::decode(v, buf.into()).unwrap(), msg);
+    }
+
+    #[test]
+    fn encode_response_fixtures() {
+        let frame = &include_bytes!("../../tests/fixtures/v3/responses/error_credentials.msg")[..];
+        assert_eq!(&reencoded(frame, Message::Error)[..], frame);
+        let frame = &include_bytes!("../../tests/fixtures/v3/responses/authenticate.msg")[..];
+        assert_eq!(&reencoded(frame, Message::Authenticate)[..], frame);
+        let frame = &include_bytes!("../../tests/fixtures/v3/responses/auth_success.msg")[..];
+        assert_eq!(&reencoded(frame, Message::AuthSuccess)[..], frame);
+        for frame in &[&include_bytes!("../../tests/fixtures/v3/responses/result_rows.msg")[..],
+                       &include_bytes!("../../tests/fixtures/v3/responses/result_schema_change.msg")[..],
+                       &include_bytes!("../../tests/fixtures/v3/responses/result_set_keyspace.msg")[..],
+                       &include_bytes!("../../tests/fixtures/v3/responses/result_void.msg")[..]] {
+            assert_eq!(&reencoded(frame, Message::Result)[..], *frame);
+        }
+
+        let mut buf = Vec::new();
+        cql_encode(Version3, 0, 1, &Message::Ready, &mut buf).unwrap();
+        assert_eq!(&buf[..],
+                   &include_bytes!("../../tests/fixtures/v3/responses/srv_ready.msg")[..]);
+    }
+
+    #[test]
+    fn encode_error_kinds() {
+        let kinds = vec![CqlErrorKind::Unavailable {
+                             consistency: CqlConsistency::Quorum,
+                             required: 3,
+                             alive: 1,
+                         },
+                         CqlErrorKind::WriteTimeout {
+                             consistency: CqlConsistency::One,
+                             received: 0,
+                             block_for: 1,
+                             write_type: WriteType::BatchLog,
+                         },
+                         CqlErrorKind::ReadTimeout {
+                             consistency: CqlConsistency::Two,
+                             received: 1,
+                             block_for: 2,
+                             data_present: true,
+                         },
+                         CqlErrorKind::ReadFailure {
+                             consistency: CqlConsistency::All,
+                             received: 1,
+                             block_for: 3,
+                             num_failures: 2,
+                             data_present: false,
+                         },
+                         CqlErrorKind::FunctionFailure {
+                             keyspace: cql_string!("ks"),
+                             function: cql_string!("f"),
+                             arg_types: CqlStringList::try_from_iter_easy(vec!["int"]).unwrap(),
+                         },
+                         CqlErrorKind::WriteFailure {
+                             consistency: CqlConsistency::LocalOne,
+                             received: 0,
+                             block_for: 1,
+                             num_failures: 1,
+                             write_type: WriteType::Cas,
+                         },
+                         CqlErrorKind::AlreadyExists {
+                             keyspace: cql_string!("ks"),
+                             table: cql_string!("t"),
+                         },
+                         CqlErrorKind::Unprepared { id: cql_bytes!(0xab) },
+                         CqlErrorKind::Invalid];
+        for kind in kinds {
+            assert_roundtrip(Version4, ErrorMessage::new(kind, "failed").unwrap());
+        }
+    }
+
+    #[test]
+    fn encode_results() {
+        let spec = |name: &str, t| {
+            ColumnSpec {
+                table_spec: None,
+                name: CqlString::try_from(name).unwrap(),
+                column_type: t,
+            }
+        };
+        assert_roundtrip(Version4,
+                         ResultMessage::Rows(RowsMessage {
+                             metadata: RowsMetadata {
+                                 global_tables_spec: Some(TableSpec {
+                                     keyspace: cql_string!("ks"),
+                                     table: cql_string!("t"),
+                                 }),
+                                 paging_state: Some(cql_bytes!(1, 2)),
+                                 no_metadata: false,
+                                 columns_count: 2,
+                                 column_specs: vec![spec("id", ColumnType::Int),
+                                                    spec("tags", ColumnType::Set(Box::new(ColumnType::Varchar)))],
+                             },
+                             rows: vec![Row { cells: vec![cql_bytes!(0, 0, 0, 1), CqlBytes::null_value()] }],
+                         }));
+        assert_roundtrip(Version3,
+                         ResultMessage::Rows(RowsMessage {
+                             metadata: RowsMetadata {
+                                 no_metadata: true,
+                                 columns_count: 1,
+                                 ..Default::default()
+                             },
+                             rows: vec![Row { cells: vec![cql_bytes!(7)] }],
+                         }));
+        for v in &[Version3, Version4] {
+            assert_roundtrip(*v,
+                             ResultMessage::Prepared(PreparedMessage {
+                                 id: cql_bytes!(0xab, 0xcd),
+                                 metadata: PreparedMetadata {
+                                     global_tables_spec: None,
+                                     pk_indices: if *v == Version4 { vec![0] } else { Vec::new() },
+                                     column_specs: vec![ColumnSpec {
+                                                            table_spec: Some(TableSpec {
+                                                                keyspace: cql_string!("ks"),
+                                                                table: cql_string!("t"),
+                                                            }),
+                                                            ..spec("id", ColumnType::Uuid)
+                                                        }],
+                                 },
+                                 result_metadata: RowsMetadata {
+                                     no_metadata: true,
+                                     columns_count: 0,
+                                     ..Default::default()
+                                 },
+                             }));
+        }
+        assert_roundtrip(Version4,
+                         ResultMessage::SchemaChange(SchemaChange {
+                             change_type: SchemaChangeType::Updated,
+                             target: SchemaChangeTarget::Type {
+                                 keyspace: cql_string!("ks"),
+                                 name: cql_string!("address"),
+                             },
+                         }));
+    }
+
+    #[test]
+    fn encode_events() {
+        assert_roundtrip(Version4,
+                         Event::StatusChange(StatusChange::Down, "127.0.0.1:9042".parse().unwrap()));
+        assert_roundtrip(Version4,
+                         Event::TopologyChange(TopologyChange::NewNode, "[::1]:9042".parse().unwrap()));
+        assert_roundtrip(Version4,
+                         Event::SchemaChange(SchemaChange {
+                             change_type: SchemaChangeType::Dropped,
+                             target: SchemaChangeTarget::Keyspace(cql_string!("ks")),
+                         }));
+    }
+
+    #[test]
+    fn encode_frame_with_prelude() {
+        let prelude = ResponsePrelude {
+            tracing_id: Some([1; 16]),
+            warnings: Some(CqlStringList::try_from_iter_easy(vec!["careful"]).unwrap()),
+            custom_payload: None,
+        };
+        let mut buf = Vec::new();
+        cql_encode_frame(Version4,
+                         0,
+                         3,
+                         &prelude,
+                         Some(Compression::Lz4),
+                         &Message::Result(ResultMessage::Void),
+                         &mut buf)
+            .unwrap();
+
+        let h = Header::try_from(&buf[..Header::encoded_len()]).unwrap();
+        assert!(h.is_compressed() && h.is_traced() && h.has_warnings());
+        assert_eq!(h.op_code, OpCode::Result);
+        let body = Compression::Lz4.decompress(&buf[Header::encoded_len()..]).unwrap();
+        let (body, decoded) = ResponsePrelude::decode(&h, body.into()).unwrap();
+        assert_eq!(decoded, prelude);
+        assert_eq!(ResultMessage::decode(Version4, body).unwrap(), ResultMessage::Void);
+
+        assert!(cql_encode_frame(Version3, 0, 3, &prelude, None, &Message::Ready, &mut Vec::new()).is_err());
+    }
 }
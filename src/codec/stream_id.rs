@@ -0,0 +1,153 @@
+//! Allocation of the stream ids which associate responses with their requests.
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::cell::Cell;
+
+/// The amount of stream ids available for requests since v3, as only non-negative ids of the
+/// 16 bit stream id may be used by clients.
+pub const MAX_STREAMS: usize = 32768;
+
+/// Hands out free stream ids for requests and maps them back once their response arrives.
+/// Released ids are reused before new ones are taken.
+#[derive(Debug, Clone)]
+pub struct StreamIds {
+    capacity: usize,
+    /// The next id that was never handed out.
+    next: usize,
+    free: Vec<u16>,
+    requests: HashMap<u16, u64>,
+}
+
+impl Default for StreamIds {
+    fn default() -> Self {
+        StreamIds::with_capacity(MAX_STREAMS)
+    }
+}
+
+impl StreamIds {
+    /// Allocates at most `capacity` stream ids, which is capped at `MAX_STREAMS`.
+    pub fn with_capacity(capacity: usize) -> StreamIds {
+        StreamIds {
+            capacity: capacity.min(MAX_STREAMS),
+            next: 0,
+            free: Vec::new(),
+            requests: HashMap::new(),
+        }
+    }
+
+    /// Returns a stream id for the given request, or `None` if all ids are in use.
+    pub fn allocate(&mut self, request: u64) -> Option<u16> {
+        let id = match self.free.pop() {
+            Some(id) => id,
+            None if self.next < self.capacity => {
+                self.next += 1;
+                (self.next - 1) as u16
+            }
+            None => return None,
+        };
+        self.requests.insert(id, request);
+        Some(id)
+    }
+
+    /// Frees the given stream id, returning the request it was allocated for, if any.
+    pub fn release(&mut self, id: u16) -> Option<u64> {
+        let request = self.requests.remove(&id);
+        if request.is_some() {
+            self.free.push(id);
+        }
+        request
+    }
+
+    /// The amount of stream ids currently in use.
+    pub fn in_flight(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Counts the stream ids reserved by requests which did not yet receive a response. It is shared
+/// by the sender, which reserves an id before handing a request to the connection, and the codec,
+/// which releases it once the response arrived. If the request fails instead, the sender releases
+/// it. This keeps the codec from ever running out of stream ids, even if the sender lost interest
+/// in a response it is still waiting for.
+#[derive(Debug, Clone, Default)]
+pub struct InFlight(Rc<Cell<usize>>);
+
+impl InFlight {
+    /// Reserves a stream id, returning false if all `MAX_STREAMS` ids are reserved.
+    pub fn reserve(&self) -> bool {
+        if self.0.get() >= MAX_STREAMS {
+            return false;
+        }
+        self.0.set(self.0.get() + 1);
+        true
+    }
+
+    /// Releases a previously reserved stream id.
+    pub fn release(&self) {
+        debug_assert!(self.0.get() > 0, "released more stream ids than were reserved");
+        self.0.set(self.0.get().saturating_sub(1));
+    }
+
+    /// The amount of reserved stream ids.
+    pub fn count(&self) -> usize {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn allocate_and_release() {
+        let mut ids = StreamIds::default();
+        assert_eq!(ids.capacity(), MAX_STREAMS);
+        assert_eq!(ids.allocate(100), Some(0));
+        assert_eq!(ids.allocate(101), Some(1));
+        assert_eq!(ids.in_flight(), 2);
+
+        assert_eq!(ids.release(0), Some(100));
+        assert_eq!(ids.release(0), None);
+        assert_eq!(ids.release(5), None);
+        assert_eq!(ids.in_flight(), 1);
+
+        assert_eq!(ids.allocate(102), Some(0));
+        assert_eq!(ids.allocate(103), Some(2));
+    }
+
+    #[test]
+    fn exhaustion() {
+        let mut ids = StreamIds::with_capacity(2);
+        assert_eq!(ids.allocate(0), Some(0));
+        assert_eq!(ids.allocate(1), Some(1));
+        assert_eq!(ids.allocate(2), None);
+        assert_eq!(ids.release(1), Some(1));
+        assert_eq!(ids.allocate(2), Some(1));
+    }
+
+    #[test]
+    fn never_negative() {
+        let mut ids = StreamIds::with_capacity(usize::max_value());
+        for r in 0..MAX_STREAMS as u64 {
+            let id = ids.allocate(r).unwrap();
+            assert!(id as i16 >= 0);
+        }
+        assert_eq!(ids.allocate(MAX_STREAMS as u64), None);
+    }
+
+    #[test]
+    fn reservations() {
+        let in_flight = InFlight::default();
+        for _ in 0..MAX_STREAMS {
+            assert!(in_flight.clone().reserve());
+        }
+        assert!(!in_flight.reserve());
+        in_flight.release();
+        assert_eq!(in_flight.count(), MAX_STREAMS - 1);
+        assert!(in_flight.reserve());
+    }
+}
@@ -3,6 +3,8 @@ extern crate error_chain;
 #[macro_use]
 extern crate quick_error;
 extern crate byteorder;
+extern crate lz4_flex;
+extern crate snap;
 
 #[cfg(feature = "with-serde")]
 extern crate serde;
@@ -31,3 +33,4 @@ mod macros;
 
 pub mod codec;
 pub mod tokio;
+pub mod testing;
@@ -0,0 +1,899 @@
+//! Parses the subset of CQL understood by the in-memory engine.
+use codec::primitives::ColumnType;
+
+error_chain! {
+    errors {
+        Syntax(msg: String) {
+            description("The statement could not be parsed")
+            display("line 1: {}", msg)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    /// An unquoted identifier or keyword, in lower case.
+    Ident(String),
+    /// A double-quoted identifier, which keeps its case.
+    QuotedIdent(String),
+    Str(String),
+    Integer(String),
+    Float(String),
+    Uuid([u8; 16]),
+    Blob(Vec<u8>),
+    Symbol(&'static str),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TableName {
+    pub keyspace: Option<String>,
+    pub name: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Literal {
+    Null,
+    Str(String),
+    Integer(String),
+    Float(String),
+    Boolean(bool),
+    Uuid([u8; 16]),
+    Blob(Vec<u8>),
+    List(Vec<Literal>),
+    /// Also used for empty maps, as `{}` is ambiguous.
+    Set(Vec<Literal>),
+    Map(Vec<(Literal, Literal)>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Term {
+    Literal(Literal),
+    /// A bind marker, identified by its position among all markers of the statement, and by name
+    /// if it is a named one.
+    Bind(usize, Option<String>),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operator {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+}
+
+/// A restriction of the WHERE clause. All operators but `In` have exactly one term.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Relation {
+    pub column: String,
+    pub operator: Operator,
+    pub terms: Vec<Term>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Selection {
+    All,
+    Columns(Vec<String>),
+    Count,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Statement {
+    CreateKeyspace { name: String, if_not_exists: bool },
+    CreateTable {
+        table: TableName,
+        if_not_exists: bool,
+        columns: Vec<(String, ColumnType)>,
+        partition_key: Vec<String>,
+        clustering_key: Vec<String>,
+    },
+    DropKeyspace { name: String, if_exists: bool },
+    DropTable { table: TableName, if_exists: bool },
+    Use(String),
+    Insert {
+        table: TableName,
+        columns: Vec<String>,
+        values: Vec<Term>,
+    },
+    Update {
+        table: TableName,
+        assignments: Vec<(String, Term)>,
+        relations: Vec<Relation>,
+    },
+    /// Deletes the given columns, or whole rows if there are none.
+    Delete {
+        table: TableName,
+        columns: Vec<String>,
+        relations: Vec<Relation>,
+    },
+    Select {
+        table: TableName,
+        selection: Selection,
+        relations: Vec<Relation>,
+        /// Set if the rows are ordered descending by clustering key.
+        descending: bool,
+        limit: Option<Term>,
+    },
+}
+
+fn syntax<T>(msg: String) -> Result<T> {
+    Err(ErrorKind::Syntax(msg).into())
+}
+
+fn parse_uuid(s: &[char]) -> Option<[u8; 16]> {
+    if s.len() < 36 {
+        return None;
+    }
+    let mut id = [0u8; 16];
+    let mut digits = Vec::with_capacity(32);
+    for (i, c) in s[..36].iter().enumerate() {
+        match i {
+            8 | 13 | 18 | 23 => {
+                if *c != '-' {
+                    return None;
+                }
+            }
+            _ => digits.push(c.to_digit(16)? as u8),
+        }
+    }
+    if s.get(36).map(|c| c.is_alphanumeric() || *c == '_') == Some(true) {
+        return None;
+    }
+    for (i, pair) in digits.chunks(2).enumerate() {
+        id[i] = pair[0] << 4 | pair[1];
+    }
+    Some(id)
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        if c.is_whitespace() {
+            i += 1;
+        } else if (c == '-' && next == Some('-')) || (c == '/' && next == Some('/')) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '\'' || c == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return syntax(format!("unterminated quote at '{}'", s)),
+                    Some(&q) if q == c => {
+                        if chars.get(i + 1) == Some(&c) {
+                            s.push(c);
+                            i += 2;
+                        } else {
+                            i += 1;
+                            break;
+                        }
+                    }
+                    Some(&other) => {
+                        s.push(other);
+                        i += 1;
+                    }
+                }
+            }
+            tokens.push(if c == '\'' { Token::Str(s) } else { Token::QuotedIdent(s) });
+        } else if let Some(id) = if c.is_ascii_hexdigit() { parse_uuid(&chars[i..]) } else { None } {
+            tokens.push(Token::Uuid(id));
+            i += 36;
+        } else if c == '0' && (next == Some('x') || next == Some('X')) {
+            i += 2;
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                i += 1;
+            }
+            let digits: Vec<u8> = chars[start..i].iter().map(|c| c.to_digit(16).expect("hex digit") as u8).collect();
+            if digits.len() & 1 == 1 {
+                return syntax("blob literals need an even amount of hex digits".into());
+            }
+            tokens.push(Token::Blob(digits.chunks(2).map(|p| p[0] << 4 | p[1]).collect()));
+        } else if c.is_ascii_digit() || (c == '-' && next.map(|n| n.is_ascii_digit()) == Some(true)) {
+            let start = i;
+            i += 1;
+            let mut float = false;
+            while i < chars.len() {
+                match chars[i] {
+                    d if d.is_ascii_digit() => i += 1,
+                    '.' if !float => {
+                        float = true;
+                        i += 1
+                    }
+                    'e' | 'E' => {
+                        float = true;
+                        i += 1;
+                        if chars.get(i) == Some(&'-') || chars.get(i) == Some(&'+') {
+                            i += 1;
+                        }
+                    }
+                    _ => break,
+                }
+            }
+            let s: String = chars[start..i].iter().collect();
+            tokens.push(if float { Token::Float(s) } else { Token::Integer(s) });
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect::<String>().to_lowercase()));
+        } else {
+            let symbol = match (c, next) {
+                ('<', Some('=')) => "<=",
+                ('>', Some('=')) => ">=",
+                ('(', _) => "(",
+                (')', _) => ")",
+                (',', _) => ",",
+                (';', _) => ";",
+                ('.', _) => ".",
+                ('=', _) => "=",
+                ('<', _) => "<",
+                ('>', _) => ">",
+                ('*', _) => "*",
+                ('?', _) => "?",
+                (':', _) => ":",
+                ('[', _) => "[",
+                (']', _) => "]",
+                ('{', _) => "{",
+                ('}', _) => "}",
+                _ => return syntax(format!("unexpected character '{}'", c)),
+            };
+            i += symbol.len();
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    binds: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(t) => {
+                self.pos += 1;
+                Ok(t)
+            }
+            None => syntax("unexpected end of statement".into()),
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Ident(ref i)) => i == keyword,
+            _ => false,
+        }
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Symbol(s)) => s == symbol,
+            _ => false,
+        }
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        if self.is_symbol(symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T> {
+        match self.peek() {
+            Some(t) => syntax(format!("expected {}, got {:?}", expected, t)),
+            None => syntax(format!("expected {}, got end of statement", expected)),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.accept_keyword(keyword) {
+            Ok(())
+        } else {
+            self.unexpected(&keyword.to_uppercase())
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Result<()> {
+        if self.accept_symbol(symbol) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("'{}'", symbol))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String> {
+        match self.peek().cloned() {
+            Some(Token::Ident(i)) |
+            Some(Token::QuotedIdent(i)) => {
+                self.pos += 1;
+                Ok(i)
+            }
+            _ => self.unexpected("identifier"),
+        }
+    }
+
+    fn table_name(&mut self) -> Result<TableName> {
+        let first = self.identifier()?;
+        if self.accept_symbol(".") {
+            Ok(TableName {
+                keyspace: Some(first),
+                name: self.identifier()?,
+            })
+        } else {
+            Ok(TableName {
+                keyspace: None,
+                name: first,
+            })
+        }
+    }
+
+    fn list<T, F>(&mut self, open: &str, close: &str, mut item: F) -> Result<Vec<T>>
+        where F: FnMut(&mut Parser) -> Result<T>
+    {
+        self.symbol(open)?;
+        let mut items = Vec::new();
+        if self.accept_symbol(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.accept_symbol(close) {
+                return Ok(items);
+            }
+            self.symbol(",")?;
+        }
+    }
+
+    fn column_type(&mut self) -> Result<ColumnType> {
+        use codec::primitives::ColumnType::*;
+        let name = self.identifier()?;
+        Ok(match name.as_str() {
+            "ascii" => Ascii,
+            "bigint" => Bigint,
+            "blob" => Blob,
+            "boolean" => Boolean,
+            "counter" => Counter,
+            "decimal" => Decimal,
+            "double" => Double,
+            "float" => Float,
+            "int" => Int,
+            "timestamp" => Timestamp,
+            "uuid" => Uuid,
+            "text" | "varchar" => Varchar,
+            "varint" => Varint,
+            "timeuuid" => Timeuuid,
+            "inet" => Inet,
+            "date" => Date,
+            "time" => Time,
+            "smallint" => Smallint,
+            "tinyint" => Tinyint,
+            "frozen" => {
+                self.symbol("<")?;
+                let t = self.column_type()?;
+                self.symbol(">")?;
+                t
+            }
+            "list" | "set" => {
+                self.symbol("<")?;
+                let t = Box::new(self.column_type()?);
+                self.symbol(">")?;
+                if name == "list" { List(t) } else { Set(t) }
+            }
+            "map" => {
+                self.symbol("<")?;
+                let k = Box::new(self.column_type()?);
+                self.symbol(",")?;
+                let v = Box::new(self.column_type()?);
+                self.symbol(">")?;
+                Map(k, v)
+            }
+            _ => return syntax(format!("unknown type {}", name)),
+        })
+    }
+
+    fn literal(&mut self) -> Result<Literal> {
+        Ok(match self.next()? {
+            Token::Str(s) => Literal::Str(s),
+            Token::Integer(s) => Literal::Integer(s),
+            Token::Float(s) => Literal::Float(s),
+            Token::Uuid(id) => Literal::Uuid(id),
+            Token::Blob(b) => Literal::Blob(b),
+            Token::Ident(ref i) if i == "null" => Literal::Null,
+            Token::Ident(ref i) if i == "true" => Literal::Boolean(true),
+            Token::Ident(ref i) if i == "false" => Literal::Boolean(false),
+            Token::Symbol("[") => {
+                self.pos -= 1;
+                Literal::List(self.list("[", "]", |p| p.literal())?)
+            }
+            Token::Symbol("{") => {
+                if self.accept_symbol("}") {
+                    return Ok(Literal::Set(Vec::new()));
+                }
+                let first = self.literal()?;
+                if self.accept_symbol(":") {
+                    let mut entries = vec![(first, self.literal()?)];
+                    while self.accept_symbol(",") {
+                        let k = self.literal()?;
+                        self.symbol(":")?;
+                        entries.push((k, self.literal()?));
+                    }
+                    self.symbol("}")?;
+                    Literal::Map(entries)
+                } else {
+                    let mut elements = vec![first];
+                    while self.accept_symbol(",") {
+                        elements.push(self.literal()?);
+                    }
+                    self.symbol("}")?;
+                    Literal::Set(elements)
+                }
+            }
+            t => return syntax(format!("expected a value, got {:?}", t)),
+        })
+    }
+
+    fn term(&mut self) -> Result<Term> {
+        if self.accept_symbol("?") {
+            self.binds += 1;
+            return Ok(Term::Bind(self.binds - 1, None));
+        }
+        if self.accept_symbol(":") {
+            let name = self.identifier()?;
+            self.binds += 1;
+            return Ok(Term::Bind(self.binds - 1, Some(name)));
+        }
+        self.literal().map(Term::Literal)
+    }
+
+    fn relations(&mut self) -> Result<Vec<Relation>> {
+        let mut relations = Vec::new();
+        if !self.accept_keyword("where") {
+            return Ok(relations);
+        }
+        loop {
+            let column = self.identifier()?;
+            let operator = match self.next()? {
+                Token::Symbol("=") => Operator::Eq,
+                Token::Symbol("<") => Operator::Lt,
+                Token::Symbol("<=") => Operator::Le,
+                Token::Symbol(">") => Operator::Gt,
+                Token::Symbol(">=") => Operator::Ge,
+                Token::Ident(ref i) if i == "in" => Operator::In,
+                t => return syntax(format!("expected an operator, got {:?}", t)),
+            };
+            let terms = if operator == Operator::In {
+                self.list("(", ")", |p| p.term())?
+            } else {
+                vec![self.term()?]
+            };
+            relations.push(Relation {
+                column: column,
+                operator: operator,
+                terms: terms,
+            });
+            if !self.accept_keyword("and") {
+                return Ok(relations);
+            }
+        }
+    }
+
+    /// Skips `WITH <options>`, which are of no interest to the engine.
+    fn skip_options(&mut self) {
+        if self.accept_keyword("with") {
+            while self.peek().is_some() && !self.is_symbol(";") {
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn if_exists(&mut self, not: bool) -> Result<bool> {
+        if !self.accept_keyword("if") {
+            return Ok(false);
+        }
+        if not {
+            self.keyword("not")?;
+        }
+        self.keyword("exists")?;
+        Ok(true)
+    }
+
+    fn create(&mut self) -> Result<Statement> {
+        if self.accept_keyword("keyspace") {
+            let if_not_exists = self.if_exists(true)?;
+            let name = self.identifier()?;
+            self.skip_options();
+            return Ok(Statement::CreateKeyspace {
+                name: name,
+                if_not_exists: if_not_exists,
+            });
+        }
+        if !self.accept_keyword("table") {
+            self.keyword("columnfamily")?;
+        }
+        let if_not_exists = self.if_exists(true)?;
+        let table = self.table_name()?;
+        let mut columns = Vec::new();
+        let mut primary_key = None;
+        self.symbol("(")?;
+        loop {
+            if self.accept_keyword("primary") {
+                self.keyword("key")?;
+                self.symbol("(")?;
+                let partition_key = if self.is_symbol("(") {
+                    self.list("(", ")", |p| p.identifier())?
+                } else {
+                    vec![self.identifier()?]
+                };
+                let mut clustering_key = Vec::new();
+                while self.accept_symbol(",") {
+                    clustering_key.push(self.identifier()?);
+                }
+                self.symbol(")")?;
+                primary_key = Some((partition_key, clustering_key));
+            } else {
+                let name = self.identifier()?;
+                let t = self.column_type()?;
+                if self.accept_keyword("primary") {
+                    self.keyword("key")?;
+                    primary_key = Some((vec![name.clone()], Vec::new()));
+                }
+                columns.push((name, t));
+            }
+            if self.accept_symbol(")") {
+                break;
+            }
+            self.symbol(",")?;
+        }
+        let (partition_key, clustering_key) = match primary_key {
+            Some(key) => key,
+            None => return syntax(format!("table {} has no PRIMARY KEY", table.name)),
+        };
+        self.skip_options();
+        Ok(Statement::CreateTable {
+            table: table,
+            if_not_exists: if_not_exists,
+            columns: columns,
+            partition_key: partition_key,
+            clustering_key: clustering_key,
+        })
+    }
+
+    fn drop(&mut self) -> Result<Statement> {
+        if self.accept_keyword("keyspace") {
+            let if_exists = self.if_exists(false)?;
+            return Ok(Statement::DropKeyspace {
+                if_exists: if_exists,
+                name: self.identifier()?,
+            });
+        }
+        if !self.accept_keyword("table") {
+            self.keyword("columnfamily")?;
+        }
+        let if_exists = self.if_exists(false)?;
+        Ok(Statement::DropTable {
+            if_exists: if_exists,
+            table: self.table_name()?,
+        })
+    }
+
+    fn insert(&mut self) -> Result<Statement> {
+        self.keyword("into")?;
+        let table = self.table_name()?;
+        let columns = self.list("(", ")", |p| p.identifier())?;
+        self.keyword("values")?;
+        let values = self.list("(", ")", |p| p.term())?;
+        if columns.len() != values.len() {
+            return syntax(format!("expected {} values, got {}", columns.len(), values.len()));
+        }
+        Ok(Statement::Insert {
+            table: table,
+            columns: columns,
+            values: values,
+        })
+    }
+
+    fn update(&mut self) -> Result<Statement> {
+        let table = self.table_name()?;
+        self.keyword("set")?;
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.identifier()?;
+            self.symbol("=")?;
+            assignments.push((column, self.term()?));
+            if !self.accept_symbol(",") {
+                break;
+            }
+        }
+        Ok(Statement::Update {
+            table: table,
+            assignments: assignments,
+            relations: self.relations()?,
+        })
+    }
+
+    fn delete(&mut self) -> Result<Statement> {
+        let mut columns = Vec::new();
+        if !self.accept_keyword("from") {
+            loop {
+                columns.push(self.identifier()?);
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+            self.keyword("from")?;
+        }
+        Ok(Statement::Delete {
+            table: self.table_name()?,
+            columns: columns,
+            relations: self.relations()?,
+        })
+    }
+
+    fn select(&mut self) -> Result<Statement> {
+        let selection = if self.accept_symbol("*") {
+            Selection::All
+        } else if self.accept_keyword("count") {
+            self.symbol("(")?;
+            if !self.accept_symbol("*") {
+                match self.next()? {
+                    Token::Integer(ref i) if i == "1" => {}
+                    t => return syntax(format!("expected * or 1, got {:?}", t)),
+                }
+            }
+            self.symbol(")")?;
+            Selection::Count
+        } else {
+            let mut columns = Vec::new();
+            loop {
+                columns.push(self.identifier()?);
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+            Selection::Columns(columns)
+        };
+        self.keyword("from")?;
+        let table = self.table_name()?;
+        let relations = self.relations()?;
+        let mut descending = false;
+        if self.accept_keyword("order") {
+            self.keyword("by")?;
+            self.identifier()?;
+            if self.accept_keyword("desc") {
+                descending = true;
+            } else {
+                self.accept_keyword("asc");
+            }
+        }
+        let limit = if self.accept_keyword("limit") {
+            Some(self.term()?)
+        } else {
+            None
+        };
+        if self.accept_keyword("allow") {
+            self.keyword("filtering")?;
+        }
+        Ok(Statement::Select {
+            table: table,
+            selection: selection,
+            relations: relations,
+            descending: descending,
+            limit: limit,
+        })
+    }
+
+    fn statement(&mut self) -> Result<Statement> {
+        let statement = match self.identifier()?.as_str() {
+            "create" => self.create()?,
+            "drop" => self.drop()?,
+            "use" => Statement::Use(self.identifier()?),
+            "insert" => self.insert()?,
+            "update" => self.update()?,
+            "delete" => self.delete()?,
+            "select" => self.select()?,
+            other => return syntax(format!("unsupported statement {}", other.to_uppercase())),
+        };
+        self.accept_symbol(";");
+        match self.peek() {
+            Some(_) => self.unexpected("end of statement"),
+            None => Ok(statement),
+        }
+    }
+}
+
+/// Parses a single statement, returning it along with the amount of bind markers it contains.
+pub fn parse(query: &str) -> Result<(Statement, usize)> {
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        pos: 0,
+        binds: 0,
+    };
+    let statement = parser.statement()?;
+    Ok((statement, parser.binds))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn table(name: &str) -> TableName {
+        TableName {
+            keyspace: None,
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn create_table() {
+        let (s, _) = parse("CREATE TABLE IF NOT EXISTS ks.\"Events\" (\
+                            id uuid, at timestamp, tags set<text>, data map<text, frozen<list<int>>>, \
+                            PRIMARY KEY ((id), at)) WITH CLUSTERING ORDER BY (at DESC);")
+            .unwrap();
+        assert_eq!(s,
+                   Statement::CreateTable {
+                       table: TableName {
+                           keyspace: Some("ks".into()),
+                           name: "Events".into(),
+                       },
+                       if_not_exists: true,
+                       columns: vec![("id".into(), ColumnType::Uuid),
+                                     ("at".into(), ColumnType::Timestamp),
+                                     ("tags".into(), ColumnType::Set(Box::new(ColumnType::Varchar))),
+                                     ("data".into(),
+                                      ColumnType::Map(Box::new(ColumnType::Varchar),
+                                                      Box::new(ColumnType::List(Box::new(ColumnType::Int)))))],
+                       partition_key: vec!["id".into()],
+                       clustering_key: vec!["at".into()],
+                   });
+
+        let (s, _) = parse("create table users (name text PRIMARY KEY, age int)").unwrap();
+        match s {
+            Statement::CreateTable { partition_key, clustering_key, .. } => {
+                assert_eq!(partition_key, vec![String::from("name")]);
+                assert!(clustering_key.is_empty());
+            }
+            s => panic!("Unexpected statement {:?}", s),
+        }
+    }
+
+    #[test]
+    fn create_keyspace() {
+        assert_eq!(parse("CREATE KEYSPACE ks WITH replication = {'class': 'SimpleStrategy', \
+                          'replication_factor': 1}")
+                       .unwrap()
+                       .0,
+                   Statement::CreateKeyspace {
+                       name: "ks".into(),
+                       if_not_exists: false,
+                   });
+    }
+
+    #[test]
+    fn insert_literals() {
+        let (s, binds) = parse("INSERT INTO t (a, b, c, d, e, f, g) VALUES ('it''s', -5, 1.5e3, \
+                                123e4567-e89b-12d3-a456-426655440000, 0xcafe, [1, 2], {'k': null})")
+            .unwrap();
+        assert_eq!(binds, 0);
+        match s {
+            Statement::Insert { values, .. } => {
+                assert_eq!(values,
+                           vec![Term::Literal(Literal::Str("it's".into())),
+                                Term::Literal(Literal::Integer("-5".into())),
+                                Term::Literal(Literal::Float("1.5e3".into())),
+                                Term::Literal(Literal::Uuid([0x12, 0x3e, 0x45, 0x67, 0xe8, 0x9b, 0x12, 0xd3,
+                                                            0xa4, 0x56, 0x42, 0x66, 0x55, 0x44, 0x00, 0x00])),
+                                Term::Literal(Literal::Blob(vec![0xca, 0xfe])),
+                                Term::Literal(Literal::List(vec![Literal::Integer("1".into()),
+                                                                 Literal::Integer("2".into())])),
+                                Term::Literal(Literal::Map(vec![(Literal::Str("k".into()), Literal::Null)]))]);
+            }
+            s => panic!("Unexpected statement {:?}", s),
+        }
+        assert!(parse("INSERT INTO t (a, b) VALUES (1)").is_err());
+    }
+
+    #[test]
+    fn select() {
+        let (s, binds) = parse("SELECT a, \"B\" FROM t WHERE k = ? AND c >= :low AND c < 10 AND d IN (1, ?) \
+                                ORDER BY c DESC LIMIT ?")
+            .unwrap();
+        assert_eq!(binds, 4);
+        assert_eq!(s,
+                   Statement::Select {
+                       table: table("t"),
+                       selection: Selection::Columns(vec!["a".into(), "B".into()]),
+                       relations: vec![Relation {
+                                           column: "k".into(),
+                                           operator: Operator::Eq,
+                                           terms: vec![Term::Bind(0, None)],
+                                       },
+                                       Relation {
+                                           column: "c".into(),
+                                           operator: Operator::Ge,
+                                           terms: vec![Term::Bind(1, Some("low".into()))],
+                                       },
+                                       Relation {
+                                           column: "c".into(),
+                                           operator: Operator::Lt,
+                                           terms: vec![Term::Literal(Literal::Integer("10".into()))],
+                                       },
+                                       Relation {
+                                           column: "d".into(),
+                                           operator: Operator::In,
+                                           terms: vec![Term::Literal(Literal::Integer("1".into())),
+                                                       Term::Bind(2, None)],
+                                       }],
+                       descending: true,
+                       limit: Some(Term::Bind(3, None)),
+                   });
+        assert_eq!(parse("select count(*) from t").unwrap().0,
+                   Statement::Select {
+                       table: table("t"),
+                       selection: Selection::Count,
+                       relations: Vec::new(),
+                       descending: false,
+                       limit: None,
+                   });
+    }
+
+    #[test]
+    fn update_and_delete() {
+        assert_eq!(parse("UPDATE t SET a = 1, b = ? WHERE k = 'x'").unwrap().0,
+                   Statement::Update {
+                       table: table("t"),
+                       assignments: vec![("a".into(), Term::Literal(Literal::Integer("1".into()))),
+                                         ("b".into(), Term::Bind(0, None))],
+                       relations: vec![Relation {
+                                           column: "k".into(),
+                                           operator: Operator::Eq,
+                                           terms: vec![Term::Literal(Literal::Str("x".into()))],
+                                       }],
+                   });
+        match parse("DELETE a, b FROM t WHERE k = 1").unwrap().0 {
+            Statement::Delete { columns, .. } => assert_eq!(columns, vec![String::from("a"), String::from("b")]),
+            s => panic!("Unexpected statement {:?}", s),
+        }
+        match parse("delete from t where k = 1 -- a comment").unwrap().0 {
+            Statement::Delete { columns, .. } => assert!(columns.is_empty()),
+            s => panic!("Unexpected statement {:?}", s),
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for q in &["SELECT", "SELECT * FROM", "TRUNCATE t", "SELECT * FROM t WHERE a ~ 1", "SELECT 'abc",
+                   "SELECT * FROM t; SELECT * FROM u", "CREATE TABLE t (a int)"] {
+            match parse(q) {
+                Err(Error(ErrorKind::Syntax(_), _)) => {}
+                res => panic!("Expected a syntax error for '{}', got {:?}", q, res),
+            }
+        }
+    }
+}
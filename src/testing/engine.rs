@@ -0,0 +1,1054 @@
+//! Executes CQL statements against tables held in memory, to test data-access code without a
+//! Cassandra node.
+//!
+//! Partitions are kept in the order of their keys rather than of their tokens, and there is no
+//! support for ALLOW FILTERING, secondary indices, TTLs or lightweight transactions.
+use super::cql::{self, Literal, Operator, Relation, Selection, Statement, TableName, Term};
+use codec::request::{QueryParameters, QueryValues};
+use codec::response::{self, ColumnSpec, CqlErrorKind, ErrorMessage, PreparedMetadata, ResultMessage, Row,
+                      RowsMessage, RowsMetadata, SchemaChange, SchemaChangeTarget, SchemaChangeType, TableSpec};
+use codec::primitives::{CqlBytes, CqlFrom, CqlString, CqlValue, ColumnType};
+use byteorder::{BigEndian, ByteOrder};
+use tokio_core::io::EasyBuf;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+error_chain! {
+    links {
+        Cql(cql::Error, cql::ErrorKind);
+    }
+    foreign_links {
+        PrimitiveError(::codec::primitives::Error);
+    }
+    errors {
+        Invalid(msg: String) {
+            description("The statement is invalid")
+            display("{}", msg)
+        }
+        AlreadyExists(keyspace: String, table: String) {
+            description("The keyspace or table to create exists already")
+            display("Cannot add already existing {} {}{}",
+                    if table.is_empty() { "keyspace" } else { "table" },
+                    keyspace,
+                    if table.is_empty() { String::new() } else { format!(".{}", table) })
+        }
+    }
+}
+
+impl Error {
+    /// The ERROR message a server would send for this error.
+    pub fn to_message(&self) -> response::Result<ErrorMessage> {
+        let kind = match *self.kind() {
+            ErrorKind::Cql(_) => CqlErrorKind::SyntaxError,
+            ErrorKind::AlreadyExists(ref keyspace, ref table) => {
+                CqlErrorKind::AlreadyExists {
+                    keyspace: CqlString::try_from(keyspace.as_str())?,
+                    table: CqlString::try_from(table.as_str())?,
+                }
+            }
+            _ => CqlErrorKind::Invalid,
+        };
+        ErrorMessage::new(kind, &self.to_string())
+    }
+}
+
+fn invalid<T>(msg: String) -> Result<T> {
+    Err(ErrorKind::Invalid(msg).into())
+}
+
+fn encoded(v: &CqlValue) -> Vec<u8> {
+    let mut buf = Vec::new();
+    v.encode(&mut buf);
+    buf
+}
+
+fn varint_to_i128(b: &[u8]) -> Option<i128> {
+    if b.is_empty() || b.len() > 16 {
+        return None;
+    }
+    let mut v: i128 = if b[0] & 0x80 == 0x80 { -1 } else { 0 };
+    for byte in b {
+        v = v << 8 | i128::from(*byte);
+    }
+    Some(v)
+}
+
+fn i64_to_varint(v: i64) -> Vec<u8> {
+    let mut buf = [0u8; 8];
+    BigEndian::write_i64(&mut buf, v);
+    let mut start = 0;
+    while start < 7 &&
+          ((buf[start] == 0x00 && buf[start + 1] & 0x80 == 0) || (buf[start] == 0xff && buf[start + 1] & 0x80 != 0)) {
+        start += 1;
+    }
+    buf[start..].to_vec()
+}
+
+fn compare_all<'a, I>(a: I, b: I) -> Ordering
+    where I: Iterator<Item = &'a CqlValue>
+{
+    let mut b = b;
+    for x in a {
+        match b.next() {
+            Some(y) => {
+                match compare(x, y) {
+                    Ordering::Equal => continue,
+                    o => return o,
+                }
+            }
+            None => return Ordering::Greater,
+        }
+    }
+    if b.next().is_some() { Ordering::Less } else { Ordering::Equal }
+}
+
+/// Orders values of the same type the way Cassandra orders them, falling back to comparing their
+/// serialized form.
+pub fn compare(a: &CqlValue, b: &CqlValue) -> Ordering {
+    use codec::primitives::CqlValue::*;
+    match (a, b) {
+        (&Int(a), &Int(b)) => a.cmp(&b),
+        (&Bigint(a), &Bigint(b)) |
+        (&Counter(a), &Counter(b)) |
+        (&Timestamp(a), &Timestamp(b)) |
+        (&Time(a), &Time(b)) => a.cmp(&b),
+        (&Smallint(a), &Smallint(b)) => a.cmp(&b),
+        (&Tinyint(a), &Tinyint(b)) => a.cmp(&b),
+        (&Date(a), &Date(b)) => a.cmp(&b),
+        (&Boolean(a), &Boolean(b)) => a.cmp(&b),
+        (&Float(a), &Float(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        (&Double(a), &Double(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        (&Ascii(ref a), &Ascii(ref b)) |
+        (&Varchar(ref a), &Varchar(ref b)) => a.cmp(b),
+        (&Varint(ref a), &Varint(ref b)) => {
+            match (varint_to_i128(a), varint_to_i128(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            }
+        }
+        (&List(ref a), &List(ref b)) |
+        (&Set(ref a), &Set(ref b)) => compare_all(a.iter(), b.iter()),
+        (&Map(ref a), &Map(ref b)) => {
+            compare_all(a.iter().flat_map(|&(ref k, ref v)| vec![k, v]).collect::<Vec<_>>().into_iter(),
+                        b.iter().flat_map(|&(ref k, ref v)| vec![k, v]).collect::<Vec<_>>().into_iter())
+        }
+        _ => encoded(a).cmp(&encoded(b)),
+    }
+}
+
+/// Sorts the elements of sets and the entries of maps, as Cassandra stores them that way.
+fn normalize(v: CqlValue) -> CqlValue {
+    match v {
+        CqlValue::Set(mut elements) => {
+            elements.sort_by(compare);
+            elements.dedup_by(|a, b| compare(a, b) == Ordering::Equal);
+            CqlValue::Set(elements)
+        }
+        CqlValue::Map(mut entries) => {
+            entries.sort_by(|a, b| compare(&a.0, &b.0));
+            entries.dedup_by(|a, b| compare(&a.0, &b.0) == Ordering::Equal);
+            CqlValue::Map(entries)
+        }
+        v => v,
+    }
+}
+
+/// The values of the partition or clustering key of a row.
+#[derive(Debug, Clone)]
+struct Key(Vec<CqlValue>);
+
+impl Ord for Key {
+    fn cmp(&self, other: &Key) -> Ordering {
+        compare_all(self.0.iter(), other.0.iter())
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Key) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+/// The values of all columns of a row, in the order of the table's columns.
+type Values = Vec<Option<CqlValue>>;
+
+#[derive(Debug, Clone)]
+struct Table {
+    columns: Vec<(String, ColumnType)>,
+    partition_key: Vec<usize>,
+    clustering_key: Vec<usize>,
+    partitions: BTreeMap<Key, BTreeMap<Key, Values>>,
+}
+
+impl Table {
+    fn column(&self, name: &str) -> Result<usize> {
+        self.columns
+            .iter()
+            .position(|&(ref c, _)| c == name)
+            .ok_or_else(|| ErrorKind::Invalid(format!("Undefined column name {}", name)).into())
+    }
+
+    fn is_key(&self, column: usize) -> bool {
+        self.partition_key.contains(&column) || self.clustering_key.contains(&column)
+    }
+
+    /// The columns returned for `SELECT *`: the partition key, the clustering key, and all other
+    /// columns ordered by name.
+    fn all_columns(&self) -> Vec<usize> {
+        let mut rest: Vec<usize> = (0..self.columns.len()).filter(|c| !self.is_key(*c)).collect();
+        rest.sort_by(|a, b| self.columns[*a].0.cmp(&self.columns[*b].0));
+        self.partition_key.iter().chain(self.clustering_key.iter()).cloned().chain(rest).collect()
+    }
+}
+
+/// Provides the values of bind markers.
+struct Binds<'a> {
+    values: Option<&'a QueryValues>,
+}
+
+impl<'a> Binds<'a> {
+    fn value(&self, term: &Term, t: &ColumnType, column: &str) -> Result<Option<CqlValue>> {
+        let (index, name) = match *term {
+            Term::Literal(ref l) => return literal(l, t, column),
+            Term::Bind(index, ref name) => (index, name),
+        };
+        let bytes = match (self.values, name) {
+            (Some(&QueryValues::Positional(ref values)), _) => values.get(index),
+            (Some(&QueryValues::Named(ref values)), &Some(ref name)) => {
+                values.iter().find(|&(k, _)| k.as_ref().to_lowercase() == *name).map(|(_, v)| v)
+            }
+            (Some(&QueryValues::Named(_)), &None) => {
+                return invalid("Positional bind markers cannot be bound by name".into())
+            }
+            (None, _) => None,
+        };
+        let bytes = match bytes {
+            Some(b) => b,
+            None => return invalid(format!("There is no value bound for marker {} of column {}", index, column)),
+        };
+        if bytes.is_unset() {
+            return invalid(format!("Unset value for column {} is not supported", column));
+        }
+        Ok(CqlValue::decode_cell(t, bytes)?.map(normalize))
+    }
+
+    fn non_null(&self, term: &Term, t: &ColumnType, column: &str) -> Result<CqlValue> {
+        self.value(term, t, column)?
+            .ok_or_else(|| ErrorKind::Invalid(format!("Invalid null value for column {}", column)).into())
+    }
+}
+
+fn number<T: ::std::str::FromStr>(s: &str, column: &str) -> Result<T> {
+    s.parse().map_err(|_| ErrorKind::Invalid(format!("Invalid number {} for column {}", s, column)).into())
+}
+
+fn elements(literals: &[Literal], t: &ColumnType, column: &str) -> Result<Vec<CqlValue>> {
+    literals.iter()
+        .map(|l| {
+            literal(l, t, column)?
+                .ok_or_else(|| ErrorKind::Invalid(format!("null is not supported inside collections ({})", column)).into())
+        })
+        .collect()
+}
+
+/// Converts a constant into a value of the given type, which is `None` for null.
+fn literal(l: &Literal, t: &ColumnType, column: &str) -> Result<Option<CqlValue>> {
+    use codec::primitives::ColumnType as T;
+    use super::cql::Literal as L;
+    Ok(Some(normalize(match (l, t) {
+        (&L::Null, _) => return Ok(None),
+        (&L::Integer(ref s), &T::Int) => CqlValue::Int(number(s, column)?),
+        (&L::Integer(ref s), &T::Bigint) => CqlValue::Bigint(number(s, column)?),
+        (&L::Integer(ref s), &T::Counter) => CqlValue::Counter(number(s, column)?),
+        (&L::Integer(ref s), &T::Smallint) => CqlValue::Smallint(number(s, column)?),
+        (&L::Integer(ref s), &T::Tinyint) => CqlValue::Tinyint(number(s, column)?),
+        (&L::Integer(ref s), &T::Timestamp) => CqlValue::Timestamp(number(s, column)?),
+        (&L::Integer(ref s), &T::Time) => CqlValue::Time(number(s, column)?),
+        (&L::Integer(ref s), &T::Varint) => CqlValue::Varint(i64_to_varint(number(s, column)?)),
+        (&L::Integer(ref s), &T::Float) |
+        (&L::Float(ref s), &T::Float) => CqlValue::Float(number(s, column)?),
+        (&L::Integer(ref s), &T::Double) |
+        (&L::Float(ref s), &T::Double) => CqlValue::Double(number(s, column)?),
+        (&L::Str(ref s), &T::Varchar) => CqlValue::Varchar(s.clone()),
+        (&L::Str(ref s), &T::Ascii) if s.is_ascii() => CqlValue::Ascii(s.clone()),
+        (&L::Str(ref s), &T::Inet) => {
+            CqlValue::Inet(s.parse::<IpAddr>()
+                .map_err(|_| ErrorKind::Invalid(format!("Invalid address '{}' for column {}", s, column)))?)
+        }
+        (&L::Boolean(b), &T::Boolean) => CqlValue::Boolean(b),
+        (&L::Uuid(id), &T::Uuid) => CqlValue::Uuid(id),
+        (&L::Uuid(id), &T::Timeuuid) => CqlValue::Timeuuid(id),
+        (&L::Blob(ref b), &T::Blob) => CqlValue::Blob(b.clone()),
+        (&L::List(ref l), &T::List(ref e)) => CqlValue::List(elements(l, e, column)?),
+        (&L::Set(ref l), &T::Set(ref e)) => CqlValue::Set(elements(l, e, column)?),
+        (&L::Set(ref l), &T::Map(..)) if l.is_empty() => CqlValue::Map(Vec::new()),
+        (&L::Map(ref m), &T::Map(ref k, ref v)) => {
+            let non_null = |l: &Literal, t: &ColumnType| {
+                literal(l, t, column)?.ok_or_else(|| {
+                    Error::from(ErrorKind::Invalid(format!("null is not supported inside collections ({})", column)))
+                })
+            };
+            CqlValue::Map(m.iter()
+                .map(|&(ref key, ref value)| Ok((non_null(key, k)?, non_null(value, v)?)))
+                .collect::<Result<_>>()?)
+        }
+        _ => return invalid(format!("Invalid constant {:?} for column {} of type {:?}", l, column, t)),
+    })))
+}
+
+/// A relation of the WHERE clause with its values resolved.
+struct Restriction {
+    column: usize,
+    operator: Operator,
+    values: Vec<CqlValue>,
+}
+
+impl Restriction {
+    fn matches(&self, v: &CqlValue) -> bool {
+        // `IN` may have no terms at all, which matches nothing.
+        let o = || compare(v, &self.values[0]);
+        match self.operator {
+            Operator::Eq => o() == Ordering::Equal,
+            Operator::Lt => o() == Ordering::Less,
+            Operator::Le => o() != Ordering::Greater,
+            Operator::Gt => o() == Ordering::Greater,
+            Operator::Ge => o() != Ordering::Less,
+            Operator::In => self.values.iter().any(|e| compare(v, e) == Ordering::Equal),
+        }
+    }
+}
+
+fn restrictions(table: &Table, relations: &[Relation], binds: &Binds) -> Result<Vec<Restriction>> {
+    relations.iter()
+        .map(|r| {
+            let column = table.column(&r.column)?;
+            if !table.is_key(column) {
+                return invalid(format!("Cannot restrict column {} as it is not part of the primary key", r.column));
+            }
+            let t = &table.columns[column].1;
+            Ok(Restriction {
+                column: column,
+                operator: r.operator,
+                values: r.terms.iter().map(|term| binds.non_null(term, t, &r.column)).collect::<Result<_>>()?,
+            })
+        })
+        .collect()
+}
+
+/// The rows selected by the restrictions of a statement: either the given partitions, or all of
+/// them, along with the restrictions of clustering columns each row has to match.
+struct Selected<'a> {
+    partitions: Option<Vec<Key>>,
+    clustering: Vec<&'a Restriction>,
+}
+
+impl<'a> Selected<'a> {
+    fn matches(&self, columns: &[usize], clustering_key: &Key) -> bool {
+        self.clustering.iter().all(|r| {
+            let position = columns.iter().position(|c| *c == r.column).expect("clustering column");
+            r.matches(&clustering_key.0[position])
+        })
+    }
+
+    fn restricts_clustering(&self) -> bool {
+        !self.clustering.is_empty()
+    }
+}
+
+fn select<'a>(table: &Table, restrictions: &'a [Restriction]) -> Result<Selected<'a>> {
+    let name = |c: usize| table.columns[c].0.clone();
+    let partition: Vec<&Restriction> = restrictions.iter()
+        .filter(|r| table.partition_key.contains(&r.column))
+        .collect();
+    let partitions = if partition.is_empty() {
+        None
+    } else {
+        let mut keys = vec![Vec::new()];
+        for column in &table.partition_key {
+            let r = match partition.iter().filter(|r| r.column == *column).collect::<Vec<_>>().as_slice() {
+                [r] if r.operator == Operator::Eq || r.operator == Operator::In => *r,
+                [] => {
+                    return invalid(format!("Partition key parts: {} must be restricted as other parts are",
+                                           name(*column)))
+                }
+                _ => {
+                    return invalid(format!("Only EQ and IN relations are supported on the partition key column {}",
+                                           name(*column)))
+                }
+            };
+            keys = keys.into_iter()
+                .flat_map(|k: Vec<CqlValue>| {
+                    r.values.iter().map(move |v| {
+                        let mut k = k.clone();
+                        k.push(v.clone());
+                        k
+                    })
+                })
+                .collect();
+        }
+        let mut keys: Vec<Key> = keys.into_iter().map(Key).collect();
+        keys.sort();
+        keys.dedup();
+        Some(keys)
+    };
+
+    let clustering: Vec<&Restriction> = restrictions.iter()
+        .filter(|r| table.clustering_key.contains(&r.column))
+        .collect();
+    if !clustering.is_empty() && partitions.is_none() {
+        return invalid("Clustering columns can only be restricted along with the partition key".into());
+    }
+    for r in &clustering {
+        let position = table.clustering_key.iter().position(|c| *c == r.column).expect("clustering column");
+        for preceding in &table.clustering_key[..position] {
+            if !clustering.iter().any(|p| p.column == *preceding && p.operator == Operator::Eq) {
+                return invalid(format!("Clustering column {} cannot be restricted as preceding column {} is not \
+                                        restricted by an EQ relation",
+                                       name(r.column),
+                                       name(*preceding)));
+            }
+        }
+    }
+    Ok(Selected {
+        partitions: partitions,
+        clustering: clustering,
+    })
+}
+
+/// Returns the values of the given key columns, which all have to be restricted by an EQ relation.
+fn full_key(table: &Table, columns: &[usize], restrictions: &[Restriction], what: &str) -> Result<Key> {
+    columns.iter()
+        .map(|c| match restrictions.iter().find(|r| r.column == *c) {
+            Some(r) if r.operator == Operator::Eq => Ok(r.values[0].clone()),
+            Some(_) => invalid(format!("Only EQ relations are supported on {} column {}", what, table.columns[*c].0)),
+            None => invalid(format!("Some {} parts are missing: {}", what, table.columns[*c].0)),
+        })
+        .collect::<Result<_>>()
+        .map(Key)
+}
+
+fn cql_string(s: &str) -> Result<CqlString<EasyBuf>> {
+    Ok(CqlString::try_from(s)?)
+}
+
+/// Holds keyspaces and their tables, which are shared by all connections of a server.
+#[derive(Debug, Default, Clone)]
+pub struct Engine {
+    keyspaces: BTreeMap<String, BTreeMap<String, Table>>,
+}
+
+impl Engine {
+    /// Executes a single statement with the given parameters. `keyspace` is the keyspace of the
+    /// connection, which is changed by USE statements.
+    pub fn execute(&mut self,
+                   keyspace: &mut Option<String>,
+                   query: &str,
+                   parameters: &QueryParameters)
+                   -> Result<ResultMessage> {
+        let (statement, _) = cql::parse(query)?;
+        let binds = Binds { values: parameters.values.as_ref() };
+        match statement {
+            Statement::CreateKeyspace { name, if_not_exists } => {
+                if self.keyspaces.contains_key(&name) {
+                    if if_not_exists {
+                        return Ok(ResultMessage::Void);
+                    }
+                    return Err(ErrorKind::AlreadyExists(name, String::new()).into());
+                }
+                self.keyspaces.insert(name.clone(), BTreeMap::new());
+                Ok(ResultMessage::SchemaChange(SchemaChange {
+                    change_type: SchemaChangeType::Created,
+                    target: SchemaChangeTarget::Keyspace(cql_string(&name)?),
+                }))
+            }
+            Statement::DropKeyspace { name, if_exists } => {
+                if self.keyspaces.remove(&name).is_none() {
+                    if if_exists {
+                        return Ok(ResultMessage::Void);
+                    }
+                    return invalid(format!("Cannot drop non existing keyspace '{}'", name));
+                }
+                if keyspace.as_ref() == Some(&name) {
+                    *keyspace = None;
+                }
+                Ok(ResultMessage::SchemaChange(SchemaChange {
+                    change_type: SchemaChangeType::Dropped,
+                    target: SchemaChangeTarget::Keyspace(cql_string(&name)?),
+                }))
+            }
+            Statement::CreateTable { table, if_not_exists, columns, partition_key, clustering_key } => {
+                let ks = self.keyspace_name(keyspace.as_ref(), &table)?;
+                let tables = self.keyspaces
+                    .get_mut(&ks)
+                    .ok_or_else(|| ErrorKind::Invalid(format!("Keyspace {} does not exist", ks)))?;
+                if tables.contains_key(&table.name) {
+                    if if_not_exists {
+                        return Ok(ResultMessage::Void);
+                    }
+                    return Err(ErrorKind::AlreadyExists(ks, table.name).into());
+                }
+                for (i, &(ref name, _)) in columns.iter().enumerate() {
+                    if columns[..i].iter().any(|&(ref c, _)| c == name) {
+                        return invalid(format!("Multiple definition of identifier {}", name));
+                    }
+                }
+                let mut new = Table {
+                    columns: columns,
+                    partition_key: Vec::new(),
+                    clustering_key: Vec::new(),
+                    partitions: BTreeMap::new(),
+                };
+                new.partition_key = partition_key.iter().map(|c| new.column(c)).collect::<Result<_>>()?;
+                new.clustering_key = clustering_key.iter().map(|c| new.column(c)).collect::<Result<_>>()?;
+                tables.insert(table.name.clone(), new);
+                Ok(ResultMessage::SchemaChange(SchemaChange {
+                    change_type: SchemaChangeType::Created,
+                    target: SchemaChangeTarget::Table {
+                        keyspace: cql_string(&ks)?,
+                        name: cql_string(&table.name)?,
+                    },
+                }))
+            }
+            Statement::DropTable { table, if_exists } => {
+                let ks = self.keyspace_name(keyspace.as_ref(), &table)?;
+                let dropped = self.keyspaces.get_mut(&ks).and_then(|tables| tables.remove(&table.name));
+                if dropped.is_none() {
+                    if if_exists {
+                        return Ok(ResultMessage::Void);
+                    }
+                    return invalid(format!("Cannot drop non existing table '{}' in keyspace '{}'",
+                                           table.name,
+                                           ks));
+                }
+                Ok(ResultMessage::SchemaChange(SchemaChange {
+                    change_type: SchemaChangeType::Dropped,
+                    target: SchemaChangeTarget::Table {
+                        keyspace: cql_string(&ks)?,
+                        name: cql_string(&table.name)?,
+                    },
+                }))
+            }
+            Statement::Use(name) => {
+                if !self.keyspaces.contains_key(&name) {
+                    return invalid(format!("Keyspace '{}' does not exist", name));
+                }
+                *keyspace = Some(name.clone());
+                Ok(ResultMessage::SetKeyspace(cql_string(&name)?))
+            }
+            Statement::Insert { table, columns, values } => {
+                let t = self.table_mut(keyspace.as_ref(), &table)?;
+                let mut row = vec![None; t.columns.len()];
+                let mut given = vec![false; t.columns.len()];
+                for (name, term) in columns.iter().zip(values.iter()) {
+                    let c = t.column(name)?;
+                    if given[c] {
+                        return invalid(format!("Multiple definitions found for column {}", name));
+                    }
+                    given[c] = true;
+                    row[c] = binds.value(term, &t.columns[c].1, name)?;
+                }
+                let key = |columns: &[usize], what: &str| -> Result<Key> {
+                    columns.iter()
+                        .map(|c| {
+                            row[*c].clone().ok_or_else(|| {
+                                ErrorKind::Invalid(format!("Some {} parts are missing: {}", what, t.columns[*c].0))
+                                    .into()
+                            })
+                        })
+                        .collect::<Result<_>>()
+                        .map(Key)
+                };
+                let (pk, ck) = (key(&t.partition_key, "partition key")?, key(&t.clustering_key, "clustering key")?);
+                let stored = t.partitions
+                    .entry(pk)
+                    .or_insert_with(BTreeMap::new)
+                    .entry(ck)
+                    .or_insert_with(|| vec![None; row.len()]);
+                for (c, v) in row.into_iter().enumerate() {
+                    if given[c] {
+                        stored[c] = v;
+                    }
+                }
+                Ok(ResultMessage::Void)
+            }
+            Statement::Update { table, assignments, relations } => {
+                let t = self.table_mut(keyspace.as_ref(), &table)?;
+                let restrictions = restrictions(t, &relations, &binds)?;
+                let pk = full_key(t, &t.partition_key, &restrictions, "partition key")?;
+                let ck = full_key(t, &t.clustering_key, &restrictions, "clustering key")?;
+                let mut updates = Vec::with_capacity(assignments.len());
+                for &(ref name, ref term) in &assignments {
+                    let c = t.column(name)?;
+                    if t.is_key(c) {
+                        return invalid(format!("PRIMARY KEY part {} found in SET part", name));
+                    }
+                    updates.push((c, binds.value(term, &t.columns[c].1, name)?));
+                }
+                let width = t.columns.len();
+                let row = t.partitions
+                    .entry(pk.clone())
+                    .or_insert_with(BTreeMap::new)
+                    .entry(ck.clone())
+                    .or_insert_with(|| vec![None; width]);
+                for (c, v) in pk.0.into_iter().zip(t.partition_key.iter()).chain(ck.0.into_iter().zip(t.clustering_key.iter())) {
+                    row[*v] = Some(c);
+                }
+                for (c, v) in updates {
+                    row[c] = v;
+                }
+                Ok(ResultMessage::Void)
+            }
+            Statement::Delete { table, columns, relations } => {
+                let t = self.table_mut(keyspace.as_ref(), &table)?;
+                let restrictions = restrictions(t, &relations, &binds)?;
+                let columns = columns.iter().map(|c| t.column(c)).collect::<Result<Vec<_>>>()?;
+                if let Some(c) = columns.iter().find(|c| t.is_key(**c)) {
+                    return invalid(format!("Invalid identifier {} for deletion (should not be a PRIMARY KEY part)",
+                                           t.columns[*c].0));
+                }
+                let (keys, restricts_clustering) = {
+                    let selected = select(t, &restrictions)?;
+                    match selected.partitions {
+                        Some(ref keys) => (keys.clone(), selected.restricts_clustering()),
+                        None => return invalid("Some partition key parts are missing".into()),
+                    }
+                };
+                let clustering: Vec<&Restriction> = restrictions.iter()
+                    .filter(|r| t.clustering_key.contains(&r.column))
+                    .collect();
+                let selected = Selected {
+                    partitions: None,
+                    clustering: clustering,
+                };
+                for key in keys {
+                    if columns.is_empty() && !restricts_clustering {
+                        t.partitions.remove(&key);
+                        continue;
+                    }
+                    let mut empty = false;
+                    let clustering_key = &t.clustering_key;
+                    if let Some(rows) = t.partitions.get_mut(&key) {
+                        let matching: Vec<Key> = rows.keys()
+                            .filter(|ck| selected.matches(clustering_key, ck))
+                            .cloned()
+                            .collect();
+                        for ck in matching {
+                            if columns.is_empty() {
+                                rows.remove(&ck);
+                            } else if let Some(row) = rows.get_mut(&ck) {
+                                for c in &columns {
+                                    row[*c] = None;
+                                }
+                            }
+                        }
+                        empty = rows.is_empty();
+                    }
+                    if empty {
+                        t.partitions.remove(&key);
+                    }
+                }
+                Ok(ResultMessage::Void)
+            }
+            Statement::Select { table, selection, relations, descending, limit } => {
+                let ks = self.keyspace_name(keyspace.as_ref(), &table)?;
+                let t = self.table(&ks, &table)?;
+                let restrictions = restrictions(t, &relations, &binds)?;
+                let selected = select(t, &restrictions)?;
+                if descending && selected.partitions.is_none() {
+                    return invalid("ORDER BY is only supported when the partition key is restricted by an EQ or \
+                                    an IN."
+                        .into());
+                }
+                let limit = match limit {
+                    Some(ref term) => {
+                        match binds.non_null(term, &ColumnType::Int, "[limit]")? {
+                            CqlValue::Int(l) if l > 0 => Some(l as usize),
+                            _ => return invalid("LIMIT must be strictly positive".into()),
+                        }
+                    }
+                    None => None,
+                };
+
+                let partitions: Vec<&BTreeMap<Key, Values>> = match selected.partitions {
+                    Some(ref keys) => keys.iter().filter_map(|key| t.partitions.get(key)).collect(),
+                    None => t.partitions.values().collect(),
+                };
+                let mut rows: Vec<&Values> = Vec::new();
+                for partition in partitions {
+                    let matching = partition.iter()
+                        .filter(|&(ck, _)| selected.matches(&t.clustering_key, ck))
+                        .map(|(_, r)| r);
+                    if descending {
+                        rows.extend(matching.rev());
+                    } else {
+                        rows.extend(matching);
+                    }
+                }
+                if let Some(limit) = limit {
+                    rows.truncate(limit);
+                }
+
+                let (columns, values): (Vec<(String, ColumnType)>, Vec<Values>) = match selection {
+                    Selection::Count => {
+                        (vec![("count".into(), ColumnType::Bigint)], vec![vec![Some(CqlValue::Bigint(rows.len() as i64))]])
+                    }
+                    selection => {
+                        let columns = match selection {
+                            Selection::Columns(ref names) => names.iter().map(|c| t.column(c)).collect::<Result<_>>()?,
+                            _ => t.all_columns(),
+                        };
+                        (columns.iter().map(|c| t.columns[*c].clone()).collect(),
+                         rows.iter().map(|r| columns.iter().map(|c| r[*c].clone()).collect()).collect())
+                    }
+                };
+                page(&ks, &table.name, columns, values, parameters)
+            }
+        }
+    }
+
+    /// Describes the bind markers of the statement and the rows it returns, as done for PREPARE.
+    pub fn prepare(&self, keyspace: Option<&String>, query: &str) -> Result<(PreparedMetadata, RowsMetadata)> {
+        let (statement, binds) = cql::parse(query)?;
+        let mut markers: Vec<Option<(String, ColumnType)>> = vec![None; binds];
+        let mut pk_indices = Vec::new();
+        let no_rows = RowsMetadata {
+            no_metadata: true,
+            columns_count: 0,
+            ..Default::default()
+        };
+        let (ks, table, result) = {
+            let mut mark = |t: &Table, term: &Term, column: &str| -> Result<()> {
+                if let Term::Bind(index, _) = *term {
+                    let c = t.column(column)?;
+                    markers[index] = Some(t.columns[c].clone());
+                    if let Some(position) = t.partition_key.iter().position(|pk| *pk == c) {
+                        pk_indices.push((position, index as u16));
+                    }
+                }
+                Ok(())
+            };
+            let mark_relations = |mark: &mut FnMut(&Table, &Term, &str) -> Result<()>,
+                                  t: &Table,
+                                  relations: &[Relation]|
+                                  -> Result<()> {
+                for r in relations {
+                    for term in &r.terms {
+                        mark(t, term, &r.column)?;
+                    }
+                }
+                Ok(())
+            };
+            match statement {
+                Statement::Insert { ref table, ref columns, ref values } => {
+                    let ks = self.keyspace_name(keyspace, table)?;
+                    let t = self.table(&ks, table)?;
+                    for (c, term) in columns.iter().zip(values.iter()) {
+                        mark(t, term, c)?;
+                    }
+                    (ks, table.name.clone(), no_rows)
+                }
+                Statement::Update { ref table, ref assignments, ref relations } => {
+                    let ks = self.keyspace_name(keyspace, table)?;
+                    let t = self.table(&ks, table)?;
+                    for &(ref c, ref term) in assignments {
+                        mark(t, term, c)?;
+                    }
+                    mark_relations(&mut mark, t, relations)?;
+                    (ks, table.name.clone(), no_rows)
+                }
+                Statement::Delete { ref table, ref relations, .. } => {
+                    let ks = self.keyspace_name(keyspace, table)?;
+                    let t = self.table(&ks, table)?;
+                    mark_relations(&mut mark, t, relations)?;
+                    (ks, table.name.clone(), no_rows)
+                }
+                Statement::Select { ref table, ref selection, ref relations, ref limit, .. } => {
+                    let ks = self.keyspace_name(keyspace, table)?;
+                    let t = self.table(&ks, table)?;
+                    mark_relations(&mut mark, t, relations)?;
+                    if let Some(Term::Bind(index, _)) = *limit {
+                        markers[index] = Some(("[limit]".into(), ColumnType::Int));
+                    }
+                    let columns: Vec<(String, ColumnType)> = match *selection {
+                        Selection::Count => vec![("count".into(), ColumnType::Bigint)],
+                        Selection::Columns(ref names) => {
+                            names.iter().map(|c| t.column(c).map(|c| t.columns[c].clone())).collect::<Result<_>>()?
+                        }
+                        Selection::All => t.all_columns().into_iter().map(|c| t.columns[c].clone()).collect(),
+                    };
+                    let result = metadata(&ks, &table.name, &columns)?;
+                    (ks, table.name.clone(), result)
+                }
+                _ => return Ok((PreparedMetadata {
+                                    global_tables_spec: None,
+                                    pk_indices: Vec::new(),
+                                    column_specs: Vec::new(),
+                                },
+                                no_rows)),
+            }
+        };
+        pk_indices.sort();
+        let markers = markers.into_iter()
+            .enumerate()
+            .map(|(i, m)| m.ok_or_else(|| ErrorKind::Invalid(format!("Bind marker {} is not supported", i)).into()))
+            .collect::<Result<Vec<_>>>()?;
+        let bind_metadata = metadata(&ks, &table, &markers)?;
+        Ok((PreparedMetadata {
+                global_tables_spec: bind_metadata.global_tables_spec,
+                pk_indices: pk_indices.into_iter().map(|(_, i)| i).collect(),
+                column_specs: bind_metadata.column_specs,
+            },
+            result))
+    }
+
+    fn keyspace_name(&self, keyspace: Option<&String>, table: &TableName) -> Result<String> {
+        match table.keyspace.as_ref().or(keyspace) {
+            Some(ks) => Ok(ks.clone()),
+            None => {
+                invalid("No keyspace has been specified. USE a keyspace, or explicitly specify keyspace.tablename"
+                    .into())
+            }
+        }
+    }
+
+    fn table(&self, ks: &str, table: &TableName) -> Result<&Table> {
+        self.keyspaces
+            .get(ks)
+            .ok_or_else(|| ErrorKind::Invalid(format!("Keyspace {} does not exist", ks)))?
+            .get(&table.name)
+            .ok_or_else(|| ErrorKind::Invalid(format!("unconfigured table {}", table.name)).into())
+    }
+
+    fn table_mut(&mut self, keyspace: Option<&String>, table: &TableName) -> Result<&mut Table> {
+        let ks = self.keyspace_name(keyspace, table)?;
+        self.keyspaces
+            .get_mut(&ks)
+            .ok_or_else(|| ErrorKind::Invalid(format!("Keyspace {} does not exist", ks)))?
+            .get_mut(&table.name)
+            .ok_or_else(|| ErrorKind::Invalid(format!("unconfigured table {}", table.name)).into())
+    }
+}
+
+fn metadata(ks: &str, table: &str, columns: &[(String, ColumnType)]) -> Result<RowsMetadata> {
+    Ok(RowsMetadata {
+        global_tables_spec: Some(TableSpec {
+            keyspace: cql_string(ks)?,
+            table: cql_string(table)?,
+        }),
+        paging_state: None,
+        no_metadata: false,
+        columns_count: columns.len() as i32,
+        column_specs: columns.iter()
+            .map(|&(ref name, ref t)| {
+                Ok(ColumnSpec {
+                    table_spec: None,
+                    name: cql_string(name)?,
+                    column_type: t.clone(),
+                })
+            })
+            .collect::<Result<_>>()?,
+    })
+}
+
+/// Returns the page of rows requested by the parameters. The paging state is the amount of rows
+/// returned by previous pages.
+fn page(ks: &str,
+        table: &str,
+        columns: Vec<(String, ColumnType)>,
+        values: Vec<Values>,
+        parameters: &QueryParameters)
+        -> Result<ResultMessage> {
+    let offset = match parameters.paging_state.as_ref().and_then(|s| s.as_bytes()) {
+        Some(state) if state.len() == 8 => BigEndian::read_u64(state) as usize,
+        Some(_) => return invalid("Invalid paging state".into()),
+        None => 0,
+    };
+    let end = match parameters.page_size {
+        Some(size) if size > 0 => values.len().min(offset.saturating_add(size as usize)),
+        _ => values.len(),
+    };
+    let mut metadata = metadata(ks, table, &columns)?;
+    if end < values.len() {
+        let mut state = vec![0; 8];
+        BigEndian::write_u64(&mut state, end as u64);
+        metadata.paging_state = Some(CqlBytes::try_from(state)?);
+    }
+    if parameters.skip_metadata {
+        metadata.no_metadata = true;
+        metadata.global_tables_spec = None;
+        metadata.column_specs = Vec::new();
+    }
+    let rows = values.into_iter()
+        .skip(offset)
+        .take(end.saturating_sub(offset))
+        .map(|values| {
+            Ok(Row {
+                cells: values.iter()
+                    .map(|v| match *v {
+                        Some(ref v) => Ok(CqlBytes::try_from(encoded(v))?),
+                        None => Ok(CqlBytes::null_value()),
+                    })
+                    .collect::<Result<_>>()?,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(ResultMessage::Rows(RowsMessage {
+        metadata: metadata,
+        rows: rows,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use codec::request::QueryParameters;
+
+    fn run(engine: &mut Engine, keyspace: &mut Option<String>, query: &str) -> Result<ResultMessage> {
+        engine.execute(keyspace, query, &QueryParameters::default())
+    }
+
+    fn rows(res: ResultMessage) -> Vec<Vec<Option<CqlValue>>> {
+        match res {
+            ResultMessage::Rows(rows) => rows.rows.iter().map(|r| rows.decode_row(r).unwrap()).collect(),
+            res => panic!("Expected rows, got {:?}", res),
+        }
+    }
+
+    fn events() -> (Engine, Option<String>) {
+        let mut engine = Engine::default();
+        let mut keyspace = None;
+        for q in &["CREATE KEYSPACE ks WITH replication = {'class': 'SimpleStrategy', 'replication_factor': 1}",
+                   "USE ks",
+                   "CREATE TABLE events (id int, at int, name text, PRIMARY KEY (id, at))",
+                   "INSERT INTO events (id, at, name) VALUES (1, 10, 'a')",
+                   "INSERT INTO events (id, at, name) VALUES (1, 20, 'b')",
+                   "INSERT INTO events (id, at, name) VALUES (1, 30, 'c')",
+                   "INSERT INTO events (id, at, name) VALUES (2, 10, 'd')"] {
+            run(&mut engine, &mut keyspace, q).unwrap();
+        }
+        (engine, keyspace)
+    }
+
+    fn names(res: ResultMessage) -> Vec<String> {
+        rows(res)
+            .into_iter()
+            .map(|r| match r.last() {
+                Some(&Some(CqlValue::Varchar(ref s))) => s.clone(),
+                v => panic!("Unexpected value {:?}", v),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn creates_schema() {
+        let (mut engine, mut ks) = events();
+        assert_eq!(ks, Some("ks".to_string()));
+        match run(&mut engine, &mut ks, "CREATE TABLE events (id int PRIMARY KEY)") {
+            Err(Error(ErrorKind::AlreadyExists(ref keyspace, ref table), _)) => {
+                assert_eq!((keyspace.as_str(), table.as_str()), ("ks", "events"))
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+        match run(&mut engine, &mut ks, "CREATE TABLE IF NOT EXISTS events (id int PRIMARY KEY)").unwrap() {
+            ResultMessage::Void => {}
+            res => panic!("Unexpected result {:?}", res),
+        }
+        let mut none = None;
+        assert!(run(&mut engine, &mut none, "SELECT * FROM events").is_err());
+        assert_eq!(rows(run(&mut engine, &mut none, "SELECT * FROM ks.events").unwrap()).len(), 4);
+        run(&mut engine, &mut ks, "DROP KEYSPACE ks").unwrap();
+        assert_eq!(ks, None);
+    }
+
+    #[test]
+    fn selects_by_key() {
+        let (mut engine, mut ks) = events();
+        assert_eq!(rows(run(&mut engine, &mut ks, "SELECT * FROM events WHERE id = 1 AND at = 20").unwrap()),
+                   vec![vec![Some(CqlValue::Int(1)), Some(CqlValue::Int(20)), Some(CqlValue::Varchar("b".into()))]]);
+        assert_eq!(names(run(&mut engine, &mut ks, "SELECT name FROM events WHERE id = 1 AND at > 10").unwrap()),
+                   vec!["b", "c"]);
+        assert_eq!(names(run(&mut engine,
+                             &mut ks,
+                             "SELECT name FROM events WHERE id = 1 ORDER BY at DESC LIMIT 2")
+                       .unwrap()),
+                   vec!["c", "b"]);
+        assert_eq!(names(run(&mut engine, &mut ks, "SELECT name FROM events WHERE id IN (2, 1)").unwrap()),
+                   vec!["a", "b", "c", "d"]);
+        assert!(rows(run(&mut engine, &mut ks, "SELECT * FROM events WHERE id = 1 AND at IN ()").unwrap()).is_empty());
+        assert!(rows(run(&mut engine, &mut ks, "SELECT * FROM events WHERE id IN ()").unwrap()).is_empty());
+        assert_eq!(rows(run(&mut engine, &mut ks, "SELECT COUNT(*) FROM events").unwrap()),
+                   vec![vec![Some(CqlValue::Bigint(4))]]);
+        for invalid in &["SELECT * FROM events WHERE name = 'a'",
+                         "SELECT * FROM events WHERE at = 10",
+                         "SELECT * FROM events LIMIT 0",
+                         "SELECT * FROM events ORDER BY at DESC",
+                         "SELECT nope FROM events"] {
+            assert!(run(&mut engine, &mut ks, invalid).is_err(), "{} should fail", invalid);
+        }
+    }
+
+    #[test]
+    fn updates_and_deletes() {
+        let (mut engine, mut ks) = events();
+        run(&mut engine, &mut ks, "UPDATE events SET name = 'x' WHERE id = 1 AND at = 10").unwrap();
+        run(&mut engine, &mut ks, "UPDATE events SET name = 'y' WHERE id = 3 AND at = 10").unwrap();
+        run(&mut engine, &mut ks, "DELETE FROM events WHERE id = 1 AND at >= 20").unwrap();
+        run(&mut engine, &mut ks, "DELETE name FROM events WHERE id = 2 AND at = 10").unwrap();
+        assert_eq!(rows(run(&mut engine, &mut ks, "SELECT * FROM events").unwrap()),
+                   vec![vec![Some(CqlValue::Int(1)), Some(CqlValue::Int(10)), Some(CqlValue::Varchar("x".into()))],
+                        vec![Some(CqlValue::Int(2)), Some(CqlValue::Int(10)), None],
+                        vec![Some(CqlValue::Int(3)), Some(CqlValue::Int(10)), Some(CqlValue::Varchar("y".into()))]]);
+        run(&mut engine, &mut ks, "DELETE FROM events WHERE id = 2").unwrap();
+        assert_eq!(rows(run(&mut engine, &mut ks, "SELECT * FROM events WHERE id = 2").unwrap()).len(), 0);
+        assert!(run(&mut engine, &mut ks, "UPDATE events SET at = 1 WHERE id = 1 AND at = 10").is_err());
+        assert!(run(&mut engine, &mut ks, "INSERT INTO events (id, name) VALUES (1, 'z')").is_err());
+    }
+
+    #[test]
+    fn binds_values() {
+        let (mut engine, mut ks) = events();
+        let (metadata, _) = engine.prepare(ks.as_ref(), "INSERT INTO events (id, at, name) VALUES (?, ?, ?)")
+            .unwrap();
+        assert_eq!(metadata.column_specs.len(), 3);
+        assert_eq!(metadata.pk_indices, vec![0]);
+        let values = [CqlValue::Int(5), CqlValue::Int(50), CqlValue::Varchar("e".into())]
+            .iter()
+            .map(|v| CqlBytes::try_from(encoded(v)).unwrap())
+            .collect();
+        let parameters = QueryParameters {
+            values: Some(QueryValues::Positional(values)),
+            ..Default::default()
+        };
+        engine.execute(&mut ks, "INSERT INTO events (id, at, name) VALUES (?, ?, ?)", &parameters).unwrap();
+        assert_eq!(names(run(&mut engine, &mut ks, "SELECT name FROM events WHERE id = 5").unwrap()),
+                   vec!["e"]);
+    }
+
+    #[test]
+    fn pages_results() {
+        let (mut engine, mut ks) = events();
+        let mut parameters = QueryParameters { page_size: Some(3), ..Default::default() };
+        let first = match engine.execute(&mut ks, "SELECT * FROM events", &parameters).unwrap() {
+            ResultMessage::Rows(rows) => rows,
+            res => panic!("Expected rows, got {:?}", res),
+        };
+        assert_eq!(first.rows.len(), 3);
+        let state = first.metadata.paging_state.expect("more pages");
+        parameters.paging_state = Some(CqlBytes::try_from(state.as_bytes().unwrap().to_vec()).unwrap());
+        let second = match engine.execute(&mut ks, "SELECT * FROM events", &parameters).unwrap() {
+            ResultMessage::Rows(rows) => rows,
+            res => panic!("Expected rows, got {:?}", res),
+        };
+        assert_eq!(second.rows.len(), 1);
+        assert!(second.metadata.paging_state.is_none());
+    }
+}
@@ -0,0 +1,575 @@
+//! An in-process server speaking the CQL protocol, to test clients without a Cassandra node.
+//!
+//! The server completes the handshake, optionally asking for a password, and answers queries
+//! according to the rules registered with it. All requests it receives are recorded, to be
+//! inspected by the test afterwards. Responses to traced requests carry a `tracing_id`, and rules
+//! may add warnings and a custom payload to their replies. Statements no rule matches can be
+//! executed by a small in-memory `engine` instead, which keeps the data written by earlier
+//! statements.
+use codec::header::{ProtocolVersion, FLAG_TRACING};
+use codec::request;
+use codec::response::{self, CqlErrorKind, ErrorMessage, ResultMessage, RowsMessage, RowsMetadata, Row, TableSpec,
+                      ColumnSpec, PreparedMessage, PreparedMetadata};
+use codec::primitives::{self, CqlFrom, CqlString, CqlStringList, CqlStringMultiMap, CqlBytes, CqlValue, ColumnType};
+use tokio::server::{CqlServerProto, Request, Response};
+use self::engine::Engine;
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_core::net::TcpListener;
+use tokio_proto::BindServer;
+use tokio_service::Service;
+use futures::{future, Future, Stream};
+use futures::sync::oneshot;
+use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+use std::{io, thread};
+use byteorder::{BigEndian, ByteOrder};
+
+pub mod cql;
+pub mod engine;
+
+/// The class name of the authenticator the server asks for if a login is required.
+pub const PASSWORD_AUTHENTICATOR: &str = "org.apache.cassandra.auth.PasswordAuthenticator";
+
+/// Selects the queries a rule applies to, by the text of a QUERY, PREPARE or BATCH statement.
+#[derive(Clone)]
+pub enum QueryPattern {
+    Exact(String),
+    Prefix(String),
+    /// Matches all queries for which the function returns true, which allows to use regular
+    /// expressions, for example.
+    Matching(Arc<Fn(&str) -> bool + Send + Sync>),
+}
+
+impl QueryPattern {
+    pub fn matching<F>(f: F) -> QueryPattern
+        where F: Fn(&str) -> bool + Send + Sync + 'static
+    {
+        QueryPattern::Matching(Arc::new(f))
+    }
+
+    pub fn matches(&self, query: &str) -> bool {
+        match *self {
+            QueryPattern::Exact(ref q) => q == query,
+            QueryPattern::Prefix(ref p) => query.starts_with(p.as_str()),
+            QueryPattern::Matching(ref f) => f(query),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for QueryPattern {
+    fn from(query: &'a str) -> Self {
+        QueryPattern::Exact(query.into())
+    }
+}
+
+impl From<String> for QueryPattern {
+    fn from(query: String) -> Self {
+        QueryPattern::Exact(query)
+    }
+}
+
+/// The response to send for a matching query.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Result(ResultMessage),
+    Error(ErrorMessage),
+}
+
+impl Reply {
+    pub fn void() -> Reply {
+        Reply::Result(ResultMessage::Void)
+    }
+
+    /// Rows of the table `mock.mock` with the given columns, where each row has one value per column.
+    pub fn rows(columns: &[(&str, ColumnType)], rows: Vec<Vec<Option<CqlValue>>>) -> primitives::Result<Reply> {
+        let rows = rows.into_iter()
+            .map(|values| {
+                if values.len() != columns.len() {
+                    return Err(format!("Expected {} values per row, got {}", columns.len(), values.len()).into());
+                }
+                Ok(Row {
+                    cells: values.iter()
+                        .map(|v| match *v {
+                            Some(ref v) => {
+                                let bytes = v.to_cql_bytes()?;
+                                CqlBytes::try_from(bytes.as_bytes().unwrap_or(&[]).to_vec())
+                            }
+                            None => Ok(CqlBytes::null_value()),
+                        })
+                        .collect::<primitives::Result<_>>()?,
+                })
+            })
+            .collect::<primitives::Result<_>>()?;
+        Ok(Reply::Result(ResultMessage::Rows(RowsMessage {
+            metadata: RowsMetadata {
+                global_tables_spec: Some(TableSpec {
+                    keyspace: CqlString::try_from("mock")?,
+                    table: CqlString::try_from("mock")?,
+                }),
+                paging_state: None,
+                no_metadata: false,
+                columns_count: columns.len() as i32,
+                column_specs: columns.iter()
+                    .map(|&(name, ref t)| {
+                        Ok(ColumnSpec {
+                            table_spec: None,
+                            name: CqlString::try_from(name)?,
+                            column_type: t.clone(),
+                        })
+                    })
+                    .collect::<primitives::Result<_>>()?,
+            },
+            rows: rows,
+        })))
+    }
+
+    pub fn error(kind: CqlErrorKind, text: &str) -> response::Result<Reply> {
+        ErrorMessage::new(kind, text).map(Reply::Error)
+    }
+}
+
+/// Answers the queries matching the pattern with the reply, optionally after a delay.
+#[derive(Clone)]
+pub struct Rule {
+    pub pattern: QueryPattern,
+    pub reply: Reply,
+    pub delay: Option<Duration>,
+    /// Warnings sent in the prelude of the reply (since v4).
+    pub warnings: Vec<String>,
+    /// The custom payload sent in the prelude of the reply (since v4).
+    pub custom_payload: Vec<(String, Vec<u8>)>,
+}
+
+impl Rule {
+    pub fn new<P: Into<QueryPattern>>(pattern: P, reply: Reply) -> Rule {
+        Rule {
+            pattern: pattern.into(),
+            reply: reply,
+            delay: None,
+            warnings: Vec::new(),
+            custom_payload: Vec::new(),
+        }
+    }
+
+    pub fn delayed(mut self, delay: Duration) -> Rule {
+        self.delay = Some(delay);
+        self
+    }
+
+    pub fn with_warning(mut self, warning: &str) -> Rule {
+        self.warnings.push(warning.into());
+        self
+    }
+
+    pub fn with_payload(mut self, key: &str, value: &[u8]) -> Rule {
+        self.custom_payload.push((key.into(), value.to_vec()));
+        self
+    }
+
+    /// The prelude of the reply, holding the warnings and custom payload if there are any.
+    fn prelude(&self) -> primitives::Result<response::ResponsePrelude> {
+        let mut prelude = response::ResponsePrelude::default();
+        if !self.warnings.is_empty() {
+            prelude.warnings = Some(CqlStringList::try_from_iter_easy(self.warnings.iter().map(String::as_str))?);
+        }
+        if !self.custom_payload.is_empty() {
+            let mut payload = HashMap::new();
+            for &(ref key, ref value) in &self.custom_payload {
+                payload.insert(CqlString::try_from(key.as_str())?, CqlBytes::try_from(value.clone())?);
+            }
+            prelude.custom_payload = Some(payload);
+        }
+        Ok(prelude)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockOptions {
+    /// If set, clients have to authenticate with this username and password.
+    pub login: Option<(String, String)>,
+    /// The highest protocol version accepted. Clients asking for a higher one receive a protocol error.
+    pub version: ProtocolVersion,
+    pub cql_version: String,
+    /// If set, statements no rule matches are executed by an in-memory `Engine`, whose keyspaces
+    /// and tables live as long as the server.
+    pub engine: bool,
+}
+
+impl Default for MockOptions {
+    fn default() -> Self {
+        MockOptions {
+            login: None,
+            version: ProtocolVersion::latest(),
+            cql_version: "3.2.1".into(),
+            engine: false,
+        }
+    }
+}
+
+struct Shared {
+    options: MockOptions,
+    rules: Vec<Rule>,
+    requests: Vec<Request>,
+    engine: Engine,
+    /// The queries of all prepared statements by their id.
+    prepared: HashMap<Vec<u8>, String>,
+}
+
+/// A server listening on a local port, which stops once it is dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    shared: Arc<Mutex<Shared>>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Starts the server on a free port of the loopback interface, serving connections on a
+    /// thread of its own.
+    pub fn start(options: MockOptions) -> io::Result<MockServer> {
+        let shared = Arc::new(Mutex::new(Shared {
+            options: options,
+            rules: Vec::new(),
+            requests: Vec::new(),
+            engine: Engine::default(),
+            prepared: HashMap::new(),
+        }));
+        let (addr_tx, addr_rx) = mpsc::channel();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let thread_shared = shared.clone();
+        let thread = thread::spawn(move || {
+            let res = serve(thread_shared, shutdown_rx, &addr_tx);
+            if let Err(e) = res {
+                addr_tx.send(Err(e)).ok();
+            }
+        });
+        let addr = addr_rx.recv()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Mock server thread terminated unexpectedly"))??;
+        Ok(MockServer {
+            addr: addr,
+            shared: shared,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Adds a rule, which is tried after all rules added before. Queries without a matching rule
+    /// are answered with an `Invalid` error, unless the engine is enabled.
+    pub fn add_rule(&self, rule: Rule) -> &Self {
+        self.shared.lock().expect("no poisoned lock").rules.push(rule);
+        self
+    }
+
+    /// Shorthand for adding a rule without delay.
+    pub fn on<P: Into<QueryPattern>>(&self, pattern: P, reply: Reply) -> &Self {
+        self.add_rule(Rule::new(pattern, reply))
+    }
+
+    /// Returns all requests received since the last call, in the order they were received.
+    pub fn take_requests(&self) -> Vec<Request> {
+        ::std::mem::replace(&mut self.shared.lock().expect("no poisoned lock").requests,
+                            Vec::new())
+    }
+
+    /// The texts of all QUERY and PREPARE requests received so far.
+    pub fn queries(&self) -> Vec<String> {
+        self.shared
+            .lock()
+            .expect("no poisoned lock")
+            .requests
+            .iter()
+            .filter_map(|r| match r.message {
+                request::Message::Query(ref m) => Some(String::from(m.query.as_ref())),
+                request::Message::Prepare(ref m) => Some(String::from(m.query.as_ref())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn serve(shared: Arc<Mutex<Shared>>,
+         shutdown: oneshot::Receiver<()>,
+         addr_tx: &mpsc::Sender<io::Result<SocketAddr>>)
+         -> io::Result<()> {
+    let mut core = Core::new()?;
+    let handle = core.handle();
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().expect("valid address"), &handle)?;
+    addr_tx.send(Ok(listener.local_addr()?)).ok();
+
+    let server = listener.incoming().for_each(|(socket, peer)| {
+        debug!("mock server accepted connection from {}", peer);
+        let proto = CqlServerProto { version: shared.lock().expect("no poisoned lock").options.version };
+        proto.bind_server(&handle,
+                          socket,
+                          Connection {
+                              shared: shared.clone(),
+                              handle: handle.clone(),
+                              authenticated: Cell::new(false),
+                              keyspace: RefCell::new(None),
+                          });
+        Ok(())
+    });
+    core.run(server.select(shutdown.then(|_| Ok(()))).map(|_| ()).map_err(|(e, _)| e))
+}
+
+/// Serves the requests of a single connection.
+struct Connection {
+    shared: Arc<Mutex<Shared>>,
+    handle: Handle,
+    authenticated: Cell<bool>,
+    /// The keyspace chosen by a USE statement executed by the engine.
+    keyspace: RefCell<Option<String>>,
+}
+
+impl Connection {
+    fn respond(&self, shared: &mut Shared, req: &Request) -> response::Result<(Response, Option<Duration>)> {
+        use codec::request::Message::*;
+        let options = &shared.options;
+        let needs_auth = options.login.is_some() && !self.authenticated.get();
+        Ok((Response::from(match req.message {
+                Options => supported(options)?,
+                Startup(_) => {
+                    if needs_auth {
+                        response::Message::Authenticate(response::AuthenticateMessage {
+                            authenticator: CqlString::try_from(PASSWORD_AUTHENTICATOR)?,
+                        })
+                    } else {
+                        response::Message::Ready
+                    }
+                }
+                AuthResponse(ref m) => {
+                    let expected = options.login.as_ref().map(|&(ref user, ref password)| {
+                        format!("\u{0}{}\u{0}{}", user, password).into_bytes()
+                    });
+                    if expected.is_some() && m.auth_data.as_bytes() == expected.as_ref().map(|e| &e[..]) {
+                        self.authenticated.set(true);
+                        response::Message::AuthSuccess(response::AuthSuccessMessage {
+                            payload: CqlBytes::null_value(),
+                        })
+                    } else {
+                        return error(CqlErrorKind::AuthenticationError,
+                                     "Username and/or password are incorrect");
+                    }
+                }
+                _ if needs_auth => return error(CqlErrorKind::Unauthorized, "The client is not authenticated"),
+                Register(_) => response::Message::Ready,
+                Query(ref m) => return self.answer(shared, m.query.as_ref(), &m.parameters),
+                Prepare(ref m) => self.prepared(shared, m.query.as_ref())?,
+                Execute(ref m) => {
+                    let id = m.id.as_bytes().unwrap_or(&[]);
+                    let query = match shared.prepared.get(id) {
+                        Some(query) => query.clone(),
+                        None => return unprepared(id),
+                    };
+                    return self.answer(shared, &query, &m.parameters);
+                }
+                Batch(ref m) => {
+                    for q in &m.queries {
+                        let query = match q.statement {
+                            request::BatchStatement::Query(ref query) => String::from(query.as_ref()),
+                            request::BatchStatement::Prepared(ref id) => {
+                                let id = id.as_bytes().unwrap_or(&[]);
+                                match shared.prepared.get(id) {
+                                    Some(query) => query.clone(),
+                                    None => return unprepared(id),
+                                }
+                            }
+                        };
+                        let parameters = request::QueryParameters {
+                            values: Some(request::QueryValues::Positional(q.values.clone())),
+                            ..Default::default()
+                        };
+                        let (res, _) = self.answer(shared, &query, &parameters)?;
+                        if let response::Message::Error(_) = res.message {
+                            return Ok((res, None));
+                        }
+                    }
+                    response::Message::Result(ResultMessage::Void)
+                }
+            }),
+            None))
+    }
+
+    /// Replies according to the first matching rule, or lets the engine execute the query if
+    /// there is none.
+    fn answer(&self,
+              shared: &mut Shared,
+              query: &str,
+              parameters: &request::QueryParameters)
+              -> response::Result<(Response, Option<Duration>)> {
+        if !shared.options.engine || find_rule(shared, query).is_some() {
+            return reply(shared, query);
+        }
+        Ok((Response::from(match shared.engine.execute(&mut self.keyspace.borrow_mut(), query, parameters) {
+                Ok(res) => response::Message::Result(res),
+                Err(e) => response::Message::Error(e.to_message()?),
+            }),
+            None))
+    }
+
+    /// Prepares the query, which is looked up again by its id once executed.
+    fn prepared(&self, shared: &mut Shared, query: &str) -> response::Result<response::Message> {
+        if !shared.options.engine || find_rule(shared, query).is_some() {
+            return prepared(shared, query);
+        }
+        Ok(match shared.engine.prepare(self.keyspace.borrow().as_ref(), query) {
+            Ok((metadata, result_metadata)) => {
+                response::Message::Result(ResultMessage::Prepared(PreparedMessage {
+                    id: CqlBytes::try_from(prepare(shared, query))?,
+                    metadata: metadata,
+                    result_metadata: result_metadata,
+                }))
+            }
+            Err(e) => response::Message::Error(e.to_message()?),
+        })
+    }
+}
+
+impl Service for Connection {
+    type Request = Request;
+    type Response = Response;
+    type Error = io::Error;
+    type Future = Box<Future<Item = Response, Error = io::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let mut shared = self.shared.lock().expect("no poisoned lock");
+        let res = self.respond(&mut shared, &req);
+        let tracing_id = if req.header.flags & FLAG_TRACING != 0 {
+            Some(tracing_id(shared.requests.len()))
+        } else {
+            None
+        };
+        shared.requests.push(req);
+        let (mut res, delay) = match res {
+            Ok(res) => res,
+            Err(e) => return Box::new(future::err(io::Error::new(io::ErrorKind::Other, e))),
+        };
+        res.prelude.tracing_id = tracing_id;
+        match delay {
+            Some(delay) => {
+                Box::new(future::result(Timeout::new(delay, &self.handle))
+                    .flatten()
+                    .map(move |_| res))
+            }
+            None => Box::new(future::ok(res)),
+        }
+    }
+}
+
+/// The id of the trace session of a traced request, which is a version 1 uuid made of the
+/// index of the request among all requests received by the server.
+pub fn tracing_id(request_index: usize) -> response::TracingId {
+    let mut id = [0; 16];
+    BigEndian::write_u64(&mut id[8..], request_index as u64);
+    id[6] = 0x10;
+    id
+}
+
+fn error(kind: CqlErrorKind, text: &str) -> response::Result<(Response, Option<Duration>)> {
+    Ok((Response::from(response::Message::Error(ErrorMessage::new(kind, text)?)), None))
+}
+
+fn supported(options: &MockOptions) -> response::Result<response::Message> {
+    let options = vec![(CqlString::try_from("CQL_VERSION")?,
+                        CqlStringList::try_from_iter_easy(vec![options.cql_version.as_str()])?),
+                       (CqlString::try_from("COMPRESSION")?,
+                        CqlStringList::try_from_iter_easy(vec!["lz4", "snappy"])?)];
+    Ok(response::Message::Supported(response::SupportedMessage(CqlStringMultiMap::try_from_iter(options)?)))
+}
+
+fn find_rule<'a>(shared: &'a Shared, query: &str) -> Option<&'a Rule> {
+    shared.rules.iter().find(|r| r.pattern.matches(query))
+}
+
+fn reply(shared: &Shared, query: &str) -> response::Result<(Response, Option<Duration>)> {
+    let rule = match find_rule(shared, query) {
+        Some(rule) => rule,
+        None => {
+            return Ok((Response::from(response::Message::Error(ErrorMessage::new(CqlErrorKind::Invalid,
+                                                        &format!("No rule of the mock server matches '{}'",
+                                                                 query))
+                           .unwrap_or_else(|_| {
+                               ErrorMessage::new(CqlErrorKind::Invalid, "No rule matches").expect("valid")
+                           }))),
+                       None))
+        }
+    };
+    Ok((Response {
+            prelude: rule.prelude()?,
+            message: match rule.reply {
+                Reply::Result(ref res) => response::Message::Result(res.clone()),
+                Reply::Error(ref e) => response::Message::Error(e.clone()),
+            },
+        },
+        rule.delay))
+}
+
+/// The id of a prepared statement, which is made of two hashes of the query to have the size
+/// of the MD5 digest used by Cassandra.
+pub fn prepared_id(query: &str) -> Vec<u8> {
+    let mut id = vec![0; 16];
+    for (n, chunk) in id.chunks_mut(8).enumerate() {
+        let mut hasher = DefaultHasher::new();
+        (n, query).hash(&mut hasher);
+        BigEndian::write_u64(chunk, hasher.finish());
+    }
+    id
+}
+
+/// Remembers the query, returning its id.
+fn prepare(shared: &mut Shared, query: &str) -> Vec<u8> {
+    let id = prepared_id(query);
+    shared.prepared.insert(id.clone(), query.into());
+    id
+}
+
+/// Answers the execution of a statement which was not prepared before.
+fn unprepared(id: &[u8]) -> response::Result<(Response, Option<Duration>)> {
+    error(CqlErrorKind::Unprepared { id: CqlBytes::try_from(id.to_vec())? },
+          "Prepared statement is unknown")
+}
+
+fn prepared(shared: &mut Shared, query: &str) -> response::Result<response::Message> {
+    let result_metadata = match find_rule(shared, query).map(|r| &r.reply) {
+        Some(&Reply::Result(ResultMessage::Rows(ref rows))) => {
+            RowsMetadata { paging_state: None, ..rows.metadata.clone() }
+        }
+        _ => {
+            RowsMetadata {
+                no_metadata: true,
+                columns_count: 0,
+                ..Default::default()
+            }
+        }
+    };
+    Ok(response::Message::Result(ResultMessage::Prepared(PreparedMessage {
+        id: CqlBytes::try_from(prepare(shared, query))?,
+        metadata: PreparedMetadata {
+            global_tables_spec: None,
+            pk_indices: Vec::new(),
+            column_specs: Vec::new(),
+        },
+        result_metadata: result_metadata,
+    })))
+}
@@ -1,8 +1,12 @@
 use codec::request;
+use codec::response;
 use codec::header::ProtocolVersion;
-use codec::authentication::Credentials;
+use codec::authentication::{AuthenticatorRegistry, Credentials};
+use codec::compression::Compression;
+use codec::stream_id::{InFlight, MAX_STREAMS};
 use tokio_service::Service;
-use futures::Future;
+use futures::{future, Future};
+use futures::sync::mpsc;
 use tokio_core::reactor::Handle;
 use tokio_proto::util::client_proxy::{Response as ClientProxyResponse, ClientProxy};
 use tokio_proto::streaming::Message;
@@ -15,20 +19,41 @@ use semver;
 use super::ssl;
 
 use super::error::*;
-use super::messages::{RequestMessage, ResponseMessage, ChunkedMessage, StreamingMessage};
+use super::utils::io_err;
+use super::messages::{Request, Response, RequestMessage, ResponseMessage, ChunkedMessage, StreamingMessage};
+use super::trace::{self, TraceSession};
 use super::codec::{CqlCodec, CqlCodecDebuggingOptions};
 use super::handshake::interpret_response_and_handle;
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct CqlProto {
+    /// The protocol version to try first when connecting, which is lowered as long as the server
+    /// rejects it.
     pub version: ProtocolVersion,
     pub debug: Option<CqlCodecDebuggingOptions>,
+    /// Receives the events pushed by the server. `Client::connect` sets it up to make them
+    /// available through `ClientHandle::events()`.
+    pub events: Option<mpsc::UnboundedSender<response::Event>>,
+    /// The stream id reservations of the `ClientHandle`, which are released by the codec once
+    /// responses arrive. `Client::connect` sets it up.
+    pub in_flight: Option<InFlight>,
+}
+
+impl Default for CqlProto {
+    fn default() -> Self {
+        CqlProto {
+            version: ProtocolVersion::latest(),
+            debug: None,
+            events: None,
+            in_flight: None,
+        }
+    }
 }
 
 impl<T: Io + 'static> ClientProto<T> for CqlProto {
-    type Request = request::Message;
+    type Request = Request;
     type RequestBody = request::Message;
-    type Response = StreamingMessage;
+    type Response = Response;
     type ResponseBody = ChunkedMessage;
     type Error = io::Error;
 
@@ -38,7 +63,14 @@ impl<T: Io + 'static> ClientProto<T> for CqlProto {
 
     fn bind_transport(&self, io: T) -> Self::BindTransport {
         debug!("binding transport!");
-        Ok(io.framed(CqlCodec::new(self.version, self.debug.clone().unwrap_or_default())))
+        let mut codec = CqlCodec::new(self.version, self.debug.clone().unwrap_or_default());
+        if let Some(ref events) = self.events {
+            codec = codec.with_events(events.clone());
+        }
+        if let Some(ref in_flight) = self.in_flight {
+            codec = codec.with_in_flight(in_flight.clone());
+        }
+        Ok(io.framed(codec))
     }
 }
 
@@ -48,23 +80,126 @@ pub struct ClientHandle {
                        Response = ResponseMessage,
                        Error = io::Error,
                        Future = ClientProxyResponse<ResponseMessage, io::Error>>>,
+    version: ProtocolVersion,
+    events: Option<EventStream>,
+    /// The amount of requests sent which did not yet receive a response, each of which occupies
+    /// a stream id. Requests keep their stream id even if their future is dropped, until the
+    /// response arrives.
+    in_flight: InFlight,
 }
 
+/// The events pushed by the server, for all types of events the connection registered for.
+pub type EventStream = mpsc::UnboundedReceiver<response::Event>;
+
 impl From<request::Message> for RequestMessage {
     fn from(msg: request::Message) -> Self {
-        Message::WithoutBody(msg)
+        Message::WithoutBody(msg.into())
     }
 }
 
-impl From<ResponseMessage> for StreamingMessage {
+impl From<Request> for RequestMessage {
+    fn from(req: Request) -> Self {
+        Message::WithoutBody(req)
+    }
+}
+
+impl From<ResponseMessage> for Response {
     fn from(msg: ResponseMessage) -> Self {
         match msg {
             Message::WithoutBody(res) => res,
-            Message::WithBody(_head, bodystream) => StreamingMessage::Partial(bodystream),
+            Message::WithBody(head, bodystream) => Response { message: StreamingMessage::Partial(bodystream), ..head },
         }
     }
 }
 
+impl From<ResponseMessage> for StreamingMessage {
+    fn from(msg: ResponseMessage) -> Self {
+        Response::from(msg).message
+    }
+}
+
+impl ClientHandle {
+    /// The protocol version negotiated with the server, which is used for all messages.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Returns the stream of events pushed by the server, which can only be taken once.
+    /// Use `register()` to receive events of particular types.
+    pub fn events(&mut self) -> Option<EventStream> {
+        self.events.take()
+    }
+
+    /// Registers this connection for the given types of events, which are then sent through
+    /// the stream returned by `events()`.
+    pub fn register(&self, events: Vec<request::EventType>) -> Box<Future<Item = (), Error = Error>> {
+        Box::new(self.call(request::Message::Register(request::RegisterMessage { events: events }))
+            .map_err(|e| e.into())
+            .and_then(|res| match res {
+                StreamingMessage::Ready => Ok(()),
+                StreamingMessage::Error(msg) => Err(msg.into()),
+                msg => Err(ErrorKind::UnexpectedResponse(format!("{:?}", msg)).into()),
+            }))
+    }
+
+    /// Sends the given request with the flags and custom payload it specifies, resolving to the
+    /// response along with the header and prelude of its frame. Use it instead of `call()` to
+    /// trace a request, or to receive warnings.
+    ///
+    /// Fails right away if all stream ids of the connection are used by requests in flight.
+    pub fn send(&self, req: Request) -> Box<Future<Item = Response, Error = io::Error>> {
+        if !self.in_flight.reserve() {
+            return Box::new(future::err(io_err(format!("Connection saturated: all {} stream ids are in use",
+                                                       MAX_STREAMS))));
+        }
+        let in_flight = self.in_flight.clone();
+        Box::new(self.inner.call(req.into()).map(From::from).map_err(move |e| {
+            // The codec only releases the stream ids of requests which were answered.
+            in_flight.release();
+            e
+        }))
+    }
+
+    /// The amount of requests which did not yet receive a response.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.count()
+    }
+
+    /// Fetches the trace session with the given id, as returned for traced requests, along with all
+    /// of its events. Resolves to `None` if the session was not yet written by the coordinator.
+    /// Note that events may be written a while after the response was sent, and that a session
+    /// is complete only once it has a duration.
+    pub fn trace_session(&self, id: response::TracingId) -> Box<Future<Item = Option<TraceSession>, Error = Error>> {
+        let session = self.rows(trace::session_query(id));
+        let events = self.rows(trace::events_query(id));
+        Box::new(session.join(events).and_then(move |(session, events)| {
+            trace::assemble(id, &session, &events).chain_err(|| "Failed to decode trace session")
+        }))
+    }
+
+    fn rows(&self, query: request::Message) -> Box<Future<Item = response::RowsMessage, Error = Error>> {
+        Box::new(self.call(query)
+            .map_err(|e| e.into())
+            .and_then(|res| match res {
+                StreamingMessage::Result(response::ResultMessage::Rows(rows)) => Ok(rows),
+                StreamingMessage::Error(msg) => Err(msg.into()),
+                msg => Err(ErrorKind::UnexpectedResponse(format!("{:?}", msg)).into()),
+            }))
+    }
+
+    /// Sends the given batch and resolves to its result, which usually is `Void`.
+    /// Errors sent by the server are returned as `ErrorKind::CqlError`.
+    pub fn batch(&self, batch: request::BatchMessage) -> Box<Future<Item = response::ResultMessage, Error = Error>> {
+        Box::new(self.call(request::Message::Batch(batch))
+            .map_err(|e| e.into())
+            .and_then(|res| match res {
+                StreamingMessage::Result(res) => Ok(res),
+                StreamingMessage::Error(msg) => Err(msg.into()),
+                msg => Err(ErrorKind::UnexpectedResponse(format!("{:?}", msg)).into()),
+            }))
+    }
+}
+
 impl Service for ClientHandle {
     type Request = request::Message;
     type Response = StreamingMessage;
@@ -72,7 +207,7 @@ impl Service for ClientHandle {
     type Future = Box<Future<Item = Self::Response, Error = Self::Error>>;
 
     fn call(&self, req: Self::Request) -> Self::Future {
-        Box::new(self.inner.call(req.into()).map(From::from))
+        Box::new(self.send(req.into()).map(|res| res.message))
     }
 }
 
@@ -106,27 +241,73 @@ fn ssl_client(protocol: CqlProto,
 #[derive(Clone, Default)]
 pub struct ConnectOptions {
     pub creds: Option<Credentials>,
+    /// Provides the authenticator matching the one the server asks for.
+    pub authenticators: AuthenticatorRegistry,
     pub tls: Option<ssl::Options>,
     pub desired_cql_version: Option<semver::Version>,
+    /// The compression to use for frame bodies, if the server supports it.
+    pub compression: Option<Compression>,
 }
 
 impl Client {
+    /// Connects using the protocol version of `self.protocol`. If the server rejects that version
+    /// with a protocol error, it reconnects with the next lower version, until one is accepted.
     pub fn connect(self,
                    addr: &SocketAddr,
                    handle: &Handle,
                    options: ConnectOptions)
                    -> Box<Future<Item = ClientHandle, Error = Error>> {
-        let ConnectOptions { creds, tls, desired_cql_version } = options;
+        let protocol = self.protocol.clone();
+        let version = protocol.version;
+        let (retry_addr, retry_handle, retry_options) = (*addr, handle.clone(), options.clone());
+        let ConnectOptions { creds, authenticators, tls, desired_cql_version, compression } = options;
+        let (events_tx, events_rx) = mpsc::unbounded();
+        let in_flight = InFlight::default();
+        let connection_protocol = CqlProto {
+            events: Some(events_tx),
+            in_flight: Some(in_flight.clone()),
+            ..self.protocol
+        };
         let ret = match tls {
-                Some(tls) => ssl_client(self.protocol, addr, handle, tls),
-                None => Box::new(TcpClient::new(self.protocol).connect(addr, handle)),
+                Some(tls) => ssl_client(connection_protocol, addr, handle, tls),
+                None => Box::new(TcpClient::new(connection_protocol).connect(addr, handle)),
             }
-            .map(|client_proxy| ClientHandle { inner: Box::new(client_proxy) })
+            .map(move |client_proxy| {
+                ClientHandle {
+                    inner: Box::new(client_proxy),
+                    version: version,
+                    events: Some(events_rx),
+                    in_flight: in_flight,
+                }
+            })
             .and_then(|client_handle| client_handle.call(request::Message::Options).map(|r| (r, client_handle)))
             .map_err(|e| e.into())
-            .and_then(|(res, ch)| interpret_response_and_handle(ch, res, creds, desired_cql_version))
-            .and_then(|ch| Ok(ch));
+            .and_then(move |(res, ch)| match lower_version_on_rejection(&res, version) {
+                Some(lower) => {
+                    debug!("Server rejected protocol version {:?}, retrying with {:?}",
+                           version,
+                           lower);
+                    let client = Client { protocol: CqlProto { version: lower, ..protocol } };
+                    client.connect(&retry_addr, &retry_handle, retry_options)
+                }
+                None => interpret_response_and_handle(ch,
+                                                      res,
+                                                      creds,
+                                                      authenticators,
+                                                      desired_cql_version,
+                                                      compression),
+            });
 
         Box::new(ret)
     }
 }
+
+/// Returns the version to reconnect with if the server rejected the given one.
+fn lower_version_on_rejection(res: &StreamingMessage, version: ProtocolVersion) -> Option<ProtocolVersion> {
+    match *res {
+        StreamingMessage::Error(ref msg) if msg.kind == response::CqlErrorKind::ProtocolError => {
+            version.lower()
+        }
+        _ => None,
+    }
+}
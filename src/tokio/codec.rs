@@ -1,4 +1,6 @@
-use codec::request::{self, cql_encode};
+use codec::request::{self, cql_encode_frame};
+use codec::compression::Compression;
+use codec::stream_id::{InFlight, StreamIds};
 use codec::header::{Header, ProtocolVersion, Direction};
 use std::path::PathBuf;
 use std::fs::{File, OpenOptions};
@@ -9,17 +11,27 @@ use std::io::Write;
 use codec::header::OpCode;
 use codec::response::{self, CqlDecode};
 use super::utils::io_err;
+use super::recording::{Recorder, ConnectionRecorder};
+use futures::sync::mpsc::UnboundedSender;
 
 
 // FIXME - don't use pub here, fix imports
 pub use super::messages::*;
 pub use super::error::*;
-#[derive(PartialEq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct CqlCodec {
     state: Machine,
-    flags: u8,
     version: ProtocolVersion,
     debug: CqlCodecDebuggingOptions,
+    events: Option<UnboundedSender<response::Event>>,
+    /// Set once a STARTUP message negotiated it, and used for all subsequent frames.
+    compression: Option<Compression>,
+    /// Maps the ids of requests in flight to the stream ids used on the wire.
+    streams: StreamIds,
+    /// The reservations of stream ids made by the sender of requests, which are released along
+    /// with the stream ids.
+    in_flight: Option<InFlight>,
+    recorder: Option<ConnectionRecorder>,
 }
 
 #[derive(PartialEq, Debug, Clone, Default)]
@@ -27,6 +39,8 @@ pub struct CqlCodecDebuggingOptions {
     pub dump_decoded_frames_into: Option<PathBuf>,
     pub dump_encoded_frames_into: Option<PathBuf>,
     pub frames_count: usize,
+    /// Records the frames of all connections in both directions, to be replayed later.
+    pub record_into: Option<Recorder>,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -39,9 +53,58 @@ impl CqlCodec {
     pub fn new(v: ProtocolVersion, debug: CqlCodecDebuggingOptions) -> Self {
         CqlCodec {
             state: Machine::NeedHeader,
-            flags: 0,
             version: v,
+            recorder: debug.record_into.as_ref().map(Recorder::connection),
             debug: debug,
+            events: None,
+            compression: None,
+            streams: StreamIds::default(),
+            in_flight: None,
+        }
+    }
+
+    /// Sends events pushed by the server to the given sender, instead of dropping them.
+    pub fn with_events(mut self, events: UnboundedSender<response::Event>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Releases a reservation of `in_flight` whenever a response was decoded. Senders must reserve
+    /// a stream id before sending each request, which guarantees that one is free when encoding it,
+    /// and release it themselves if the request fails.
+    pub fn with_in_flight(mut self, in_flight: InFlight) -> Self {
+        self.in_flight = Some(in_flight);
+        self
+    }
+
+    /// Sends the event to the listener, if any. Events which can't be decoded, like those of types
+    /// added by newer versions of Cassandra, are dropped to keep the connection usable.
+    fn dispatch_event(&mut self, version: ProtocolVersion, buf: EasyBuf) {
+        let event = match response::Event::decode(version, buf) {
+            Ok(event) => event,
+            Err(e) => {
+                debug!("Dropping event which can't be decoded: {}", e);
+                return;
+            }
+        };
+        debug!("decoded event: {:?}", event);
+        let undelivered = match self.events {
+            Some(ref events) => events.unbounded_send(event).err().map(|e| e.into_inner()),
+            None => Some(event),
+        };
+        if let Some(event) = undelivered {
+            debug!("Dropping event as nobody listens: {:?}", event);
+        }
+    }
+
+    fn decompress(&self, h: &Header, body: EasyBuf) -> io::Result<EasyBuf> {
+        if !h.is_compressed() {
+            return Ok(body);
+        }
+        match self.compression {
+            Some(c) => c.decompress(body.as_slice()).map(EasyBuf::from).map_err(io_err),
+            None => Err(io_err(format!("Frame for stream {} is compressed, but no compression was negotiated",
+                                       h.stream_id))),
         }
     }
 
@@ -51,6 +114,9 @@ impl CqlCodec {
             let mut f = open_at(self.debug_path(path, &h))?;
             f.write_all(buf)?;
         }
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(buf)?;
+        }
         Ok(())
     }
 
@@ -69,6 +135,11 @@ impl CqlCodec {
             f.write_all(&h.encode().expect("header encode to work")[..])?;
             f.write_all(&buf.as_slice()[..body_len])?;
         }
+        if let Some(ref recorder) = self.recorder {
+            let mut frame = h.encode().expect("header encode to work").to_vec();
+            frame.extend_from_slice(&buf.as_slice()[..body_len]);
+            recorder.record(&frame)?;
+        }
         Ok(())
     }
 }
@@ -86,8 +157,8 @@ fn open_at(path: PathBuf) -> io::Result<File> {
         })
 }
 
-pub type CodecInputFrame = Frame<StreamingMessage, ChunkedMessage, io::Error>;
-pub type CodecOutputFrame = Frame<request::Message, request::Message, io::Error>;
+pub type CodecInputFrame = Frame<Response, ChunkedMessage, io::Error>;
+pub type CodecOutputFrame = Frame<Request, request::Message, io::Error>;
 
 impl Codec for CqlCodec {
     type In = CodecInputFrame;
@@ -123,17 +194,42 @@ impl Codec for CqlCodec {
                 /* TODO: implement version mismatch test */
                 let code = h.op_code.clone();
                 let version = h.version.version;
-                assert_stream_id(h.stream_id);
+                let body = self.decompress(&h, buf.drain_to(body_len))?;
+                if code == OpCode::Event {
+                    self.dispatch_event(version, body);
+                    return self.decode(buf);
+                }
+                let id = self.streams
+                    .release(h.stream_id)
+                    .ok_or_else(|| io_err(format!("Received a response for stream {}, which has no request",
+                                                  h.stream_id)))?;
+                let (body, prelude) = response::ResponsePrelude::decode(&h, body)
+                    .map_err(io_err)?;
+                if let Some(ref warnings) = prelude.warnings {
+                    for warning in warnings.iter() {
+                        warn!("Server warning for stream {}: {}", h.stream_id, warning.as_ref());
+                    }
+                }
+                if let Some(ref payload) = prelude.custom_payload {
+                    debug!("Ignoring custom payload for stream {}: {:?}", h.stream_id, payload);
+                }
                 let msg = Frame::Message {
-                    id: h.stream_id as RequestId,
-                    /* TODO: verify amount of consumed bytes equals the ones actually parsed */
-                    message: decode_complete_message_by_opcode(version, code, buf.drain_to(body_len))
-                        .map_err(io_err)?
-                        .into(),
+                    id: id as RequestId,
+                    message: Response {
+                        /* TODO: verify amount of consumed bytes equals the ones actually parsed */
+                        message: response::Message::decode(version, code, body)
+                            .map_err(io_err)?
+                            .into(),
+                        header: h,
+                        prelude: prelude,
+                    },
                     body: false,
                     solo: false,
                 };
                 debug!("decoded msg: {:?}", msg);
+                if let Some(ref in_flight) = self.in_flight {
+                    in_flight.release();
+                }
                 Ok(Some(msg))
             }
         }
@@ -141,18 +237,43 @@ impl Codec for CqlCodec {
 
     fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
         match msg {
-            Frame::Message { id, message, .. } => {
+            Frame::Message { id, message: Request { message, flags, custom_payload }, .. } => {
                 debug!("encoded msg: {:?}", message);
-                assert!(buf.len() == 0, "expecting an empty vector here");
-
-                assert_stream_id(id as u16);
-                let res = cql_encode(self.version,
-                                     self.flags,
-                                     id as u16, /* FIXME safe cast */
-                                     message,
-                                     buf)
+
+                // Senders reserve a stream id before sending, so this only fails if they don't.
+                let stream_id = self.streams
+                    .allocate(id as u64)
+                    .ok_or_else(|| {
+                        io_err(format!("All {} stream ids are in use, as the request was sent without \
+                                        reserving one",
+                                       self.streams.capacity()))
+                    })?;
+                let negotiated = match message {
+                    request::Message::Startup(ref m) => {
+                        Some(m.compression.as_ref().and_then(|c| Compression::from_name(c.as_ref())))
+                    }
+                    _ => None,
+                };
+                // Requests sent concurrently are written in one go, so the buffer may already
+                // contain frames.
+                let mut frame = Vec::new();
+                let res = cql_encode_frame(self.version,
+                                           flags,
+                                           stream_id,
+                                           custom_payload.as_ref(),
+                                           self.compression,
+                                           message,
+                                           &mut frame)
                     .map_err(io_err);
-                self.do_encode_debug(buf)?;
+                if res.is_err() {
+                    self.streams.release(stream_id);
+                    return res;
+                }
+                if let Some(compression) = negotiated {
+                    self.compression = compression;
+                }
+                self.do_encode_debug(&frame)?;
+                buf.extend_from_slice(&frame);
                 res
             }
             Frame::Error { error, .. } => Err(error),
@@ -161,27 +282,33 @@ impl Codec for CqlCodec {
     }
 }
 
-fn assert_stream_id(id: u16) {
-    // TODO This should not be an assertion, but just a result to be returned.
-    // The actual goal is to gain control over the domain of our request IDs, which right
-    // now is not present when clients use the service call interface.
-    // This should only be possible if there are more than i16::max_value() requests in flight!
-    assert!(id as i16 > -1,
-            "stream-id {} was negative, which makes it a broadcast id with a special meaning",
-            id);
-}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use codec::response::{Event, StatusChange};
+    use futures::{Future, Stream};
+    use futures::sync::mpsc;
 
-fn decode_complete_message_by_opcode(version: ProtocolVersion,
-                                     code: OpCode,
-                                     buf: EasyBuf)
-                                     -> response::Result<response::Message> {
-    use codec::header::OpCode::*;
-    Ok(match code {
-        Supported => response::Message::Supported(response::SupportedMessage::decode(version, buf)?),
-        Ready => response::Message::Ready,
-        Authenticate => response::Message::Authenticate(response::AuthenticateMessage::decode(version, buf)?),
-        AuthSuccess => response::Message::AuthSuccess(response::AuthSuccessMessage::decode(version, buf)?),
-        Error => response::Message::Error(response::ErrorMessage::decode(version, buf)?),
-        _ => unimplemented!(),
-    })
+    /// An EVENT frame in v4 with the given body.
+    fn event_frame(body: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x84, 0, 0xff, 0xff, 0x0c, 0, 0, 0, body.len() as u8];
+        frame.extend(body);
+        frame
+    }
+
+    #[test]
+    fn drops_events_which_cannot_be_decoded() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut codec = CqlCodec::new(ProtocolVersion::Version4, Default::default()).with_events(tx);
+        let mut buf = Vec::new();
+        buf.extend(event_frame(b"\x00\x0fKEYSPACE_CHANGE\x00\x07CREATED"));
+        buf.extend(event_frame(b"\x00\x0dSTATUS_CHANGE\x00\x02UP\x04\x7f\x00\x00\x01\x00\x00\x23\x52"));
+        let mut buf = EasyBuf::from(buf);
+
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 0);
+        drop(codec);
+        assert_eq!(rx.collect().wait().unwrap(),
+                   vec![Event::StatusChange(StatusChange::Up, "127.0.0.1:9042".parse().unwrap())]);
+    }
 }
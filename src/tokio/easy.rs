@@ -20,7 +20,10 @@ pub enum Message {
     Supported(response::SupportedMessage),
     Error(response::ErrorMessage),
     AuthSuccess(response::AuthSuccessMessage),
+    AuthChallenge(response::AuthChallengeMessage),
     Authenticate(response::AuthenticateMessage),
+    Result(response::ResultMessage),
+    Event(response::Event),
     Ready,
 }
 
@@ -31,7 +34,10 @@ impl From<StreamingMessage> for Message {
             StreamingMessage::Supported(msg) => Message::Supported(msg),
             StreamingMessage::Error(msg) => Message::Error(msg),
             StreamingMessage::AuthSuccess(msg) => Message::AuthSuccess(msg),
+            StreamingMessage::AuthChallenge(msg) => Message::AuthChallenge(msg),
             StreamingMessage::Authenticate(msg) => Message::Authenticate(msg),
+            StreamingMessage::Result(msg) => Message::Result(msg),
+            StreamingMessage::Event(msg) => Message::Event(msg),
             StreamingMessage::Partial(_stream) => {
                 // TODO: exhaust stream and build a singular response in a blocking fashion
                 unimplemented!()
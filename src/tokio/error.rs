@@ -1,15 +1,26 @@
 use std::io;
+use codec::response::{CqlErrorKind, ErrorMessage};
 
 error_chain! {
     errors{
-        CqlError(code: i32, msg: String) {
+        CqlError(kind: CqlErrorKind, msg: String) {
             description("Cql error message from server")
-            display("CQL Server Error({}): {}", code, msg)
+            display("CQL Server Error({}): {}", kind.code(), msg)
         }
         HandshakeError(msg: String)
+        UnexpectedResponse(msg: String) {
+            description("The server sent a response that does not match the request")
+            display("Unexpected response: {}", msg)
+        }
     }
 
     foreign_links{
         IoErr(io::Error);
     }
 }
+
+impl From<ErrorMessage> for Error {
+    fn from(msg: ErrorMessage) -> Self {
+        ErrorKind::CqlError(msg.kind, msg.text.into()).into()
+    }
+}
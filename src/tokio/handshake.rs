@@ -1,6 +1,7 @@
 use codec::request;
+use codec::compression::Compression;
 use codec::response;
-use codec::authentication::{Authenticator, Credentials};
+use codec::authentication::{Authenticator, AuthenticatorRegistry, Credentials};
 use codec::primitives::{CqlString, CqlBytes, CqlFrom};
 use tokio_core::io::EasyBuf;
 use tokio_service::Service;
@@ -11,46 +12,124 @@ use super::error::*;
 use super::client::ClientHandle;
 use super::messages::StreamingMessage;
 
-// TODO: prevent infinite recursion on malformed input
+/// The maximum amount of responses to handle until the connection is ready, which in particular
+/// limits the amount of authentication challenges a server may send.
+const MAX_HANDSHAKE_ROUNDS: usize = 32;
+
 pub fn interpret_response_and_handle(handle: ClientHandle,
                                      res: StreamingMessage,
                                      creds: Option<Credentials>,
-                                     desired_cql_version: Option<semver::Version>)
+                                     authenticators: AuthenticatorRegistry,
+                                     desired_cql_version: Option<semver::Version>,
+                                     desired_compression: Option<Compression>)
                                      -> Box<Future<Item = ClientHandle, Error = Error>> {
-    let res: response::Message = res.into();
-    match res {
-        response::Message::Supported(msg) => {
-            let startup = startup_message_from_supported(msg, desired_cql_version.as_ref());
-            let f = future::done(startup).and_then(|s| handle.call(s).map_err(|e| e.into()).map(|r| (r, handle)));
-            Box::new(f.and_then(move |(res, ch)| interpret_response_and_handle(ch, res, creds, desired_cql_version))
-                .and_then(|ch| Ok(ch)))
-        }
-        response::Message::Authenticate(msg) => {
-            let auth_response = auth_response_from_authenticate(creds.clone(), msg);
-            let f = future::done(auth_response).and_then(|s| handle.call(s).map_err(|e| e.into()).map(|r| (r, handle)));
-            Box::new(f.and_then(move |(res, ch)| interpret_response_and_handle(ch, res, creds, desired_cql_version))
-                .and_then(|ch| Ok(ch)))
-        }
-        response::Message::Ready => Box::new(future::ok(handle)),
-        response::Message::AuthSuccess(msg) => {
-            debug!("Authentication Succeded: {:?}", msg);
-            Box::new(future::ok(handle))
+    Handshake {
+            creds: creds,
+            authenticators: authenticators,
+            desired_cql_version: desired_cql_version,
+            desired_compression: desired_compression,
+            authenticator: None,
+            rounds_left: MAX_HANDSHAKE_ROUNDS,
         }
-        response::Message::Error(msg) => Box::new(future::err(ErrorKind::CqlError(msg.code, msg.text.into()).into())),
-        msg => {
-            Box::new(future::err(ErrorKind::HandshakeError(format!("Did not expect to receive \
-                                                                    the following message {:?}",
-                                                                   msg))
-                .into()))
+        .handle(handle, res)
+}
+
+struct Handshake {
+    creds: Option<Credentials>,
+    authenticators: AuthenticatorRegistry,
+    desired_cql_version: Option<semver::Version>,
+    desired_compression: Option<Compression>,
+    /// Set once the server asked for authentication, to answer all of its challenges.
+    authenticator: Option<Box<Authenticator>>,
+    rounds_left: usize,
+}
+
+impl Handshake {
+    fn handle(mut self, handle: ClientHandle, res: StreamingMessage) -> Box<Future<Item = ClientHandle, Error = Error>> {
+        if self.rounds_left == 0 {
+            return Box::new(future::err(ErrorKind::HandshakeError(format!("Connection was not ready after {} \
+                                                                           responses",
+                                                                          MAX_HANDSHAKE_ROUNDS))
+                .into()));
         }
+        self.rounds_left -= 1;
+
+        let res: response::Message = res.into();
+        let req = match res {
+            response::Message::Supported(msg) => {
+                startup_message_from_supported(msg, self.desired_cql_version.as_ref(), self.desired_compression)
+            }
+            response::Message::Authenticate(msg) => self.auth_response_from_authenticate(msg),
+            response::Message::AuthChallenge(msg) => self.auth_response_from_challenge(msg),
+            response::Message::Ready => return Box::new(future::ok(handle)),
+            response::Message::AuthSuccess(msg) => {
+                debug!("Authentication Succeded: {:?}", msg);
+                if let Some(ref mut authenticator) = self.authenticator {
+                    if let Err(e) = authenticator.on_success(msg.payload.as_bytes())
+                        .chain_err(|| "Authenticator Err") {
+                        return Box::new(future::err(e));
+                    }
+                }
+                return Box::new(future::ok(handle));
+            }
+            response::Message::Error(msg) => return Box::new(future::err(msg.into())),
+            msg => {
+                return Box::new(future::err(ErrorKind::HandshakeError(format!("Did not expect to receive \
+                                                                               the following message {:?}",
+                                                                              msg))
+                    .into()))
+            }
+        };
+
+        let f = future::done(req).and_then(|s| handle.call(s).map_err(|e| e.into()).map(|r| (r, handle)));
+        Box::new(f.and_then(move |(res, ch)| self.handle(ch, res)))
+    }
+
+    fn auth_response_from_authenticate(&mut self, msg: response::AuthenticateMessage) -> Result<request::Message> {
+        let creds = self.creds
+            .clone()
+            .ok_or(ErrorKind::HandshakeError(format!("No credentials provided but server requires \
+                                                      authentication by {}",
+                                                     msg.authenticator.as_ref())))?;
+
+        let mut authenticator = self.authenticators
+            .from_name(msg.authenticator.as_ref(), creds)
+            .chain_err(|| "Authenticator Err")?;
+
+        let mut buf = Vec::new();
+        authenticator.initial_response(&mut buf).chain_err(|| "Authenticator Err")?;
+        self.authenticator = Some(authenticator);
+
+        auth_response(buf)
     }
 
+    fn auth_response_from_challenge(&mut self, msg: response::AuthChallengeMessage) -> Result<request::Message> {
+        let authenticator = self.authenticator
+            .as_mut()
+            .ok_or(ErrorKind::HandshakeError("Received an authentication challenge before the server asked \
+                                              for authentication"
+                .into()))?;
+
+        let mut buf = Vec::new();
+        authenticator.evaluate_challenge(msg.token.as_bytes(), &mut buf).chain_err(|| "Authenticator Err")?;
 
+        auth_response(buf)
+    }
 }
 
 fn startup_message_from_supported(msg: response::SupportedMessage,
-                                  dv: Option<&semver::Version>)
+                                  dv: Option<&semver::Version>,
+                                  dc: Option<Compression>)
                                   -> Result<request::Message> {
+    let compression = dc.and_then(|c| {
+        let supported = msg.compression().map_or(false, |l| l.iter().any(|s| s.as_ref() == c.as_str()));
+        if !supported {
+            warn!("The server does not support {} compression, frames will not be compressed",
+                  c.as_str());
+            return None;
+        }
+        Some(CqlString::<EasyBuf>::try_from(c.as_str()).expect("compression names to be valid strings"))
+    });
     let startup = {
         request::StartupMessage {
             cql_version:
@@ -58,7 +137,7 @@ fn startup_message_from_supported(msg: response::SupportedMessage,
                 .or_else(|| msg.latest_cql_version().cloned())
                 .ok_or(ErrorKind::HandshakeError("Expected CQL_VERSION to contain at least one version".into()))?
                 .clone(),
-            compression: None,
+            compression: compression,
         }
     };
 
@@ -66,19 +145,7 @@ fn startup_message_from_supported(msg: response::SupportedMessage,
     Ok(request::Message::Startup(startup))
 }
 
-fn auth_response_from_authenticate(creds: Option<Credentials>,
-                                   msg: response::AuthenticateMessage)
-                                   -> Result<request::Message> {
-    let creds =
-        creds.ok_or(ErrorKind::HandshakeError(format!("No credentials provided but server requires authentication \
-                                                      by {}",
-                                                     msg.authenticator.as_ref())))?;
-
-    let authenticator = Authenticator::from_name(msg.authenticator.as_ref(), creds).chain_err(|| "Authenticator Err")?;
-
-    let mut buf = Vec::new();
-    authenticator.encode_auth_response(&mut buf);
-
+fn auth_response(buf: Vec<u8>) -> Result<request::Message> {
     Ok(request::Message::AuthResponse(request::AuthResponseMessage {
         auth_data: CqlBytes::try_from(buf).chain_err(|| "Message Err")?,
     }))
@@ -1,8 +1,49 @@
 use codec::request;
 use codec::response;
+use codec::header::{Header, FLAG_TRACING};
 use tokio_proto::streaming::{Message, Body};
 use std::io;
 
+/// A request along with the flags and values of the frame it is sent in.
+#[derive(Debug)]
+pub struct Request {
+    pub message: request::Message,
+    /// The flags of the frame, like `header::FLAG_TRACING` to have the server trace the request
+    /// and return the id of the trace session with the response. The compression and custom
+    /// payload flags are set by the codec.
+    pub flags: u8,
+    /// Passed to custom query handlers on the server (since v4).
+    pub custom_payload: Option<request::CustomPayload>,
+}
+
+impl Request {
+    /// Asks the server to trace the request. Only QUERY, PREPARE, EXECUTE and BATCH requests
+    /// support tracing.
+    pub fn traced(mut self) -> Self {
+        self.flags |= FLAG_TRACING;
+        self
+    }
+}
+
+impl From<request::Message> for Request {
+    fn from(msg: request::Message) -> Self {
+        Request {
+            message: msg,
+            flags: 0,
+            custom_payload: None,
+        }
+    }
+}
+
+/// A response along with the header and the values preceding the message in its frame.
+#[derive(Debug)]
+pub struct Response {
+    pub header: Header,
+    /// Holds the tracing id, warnings and custom payload sent by the server, if any.
+    pub prelude: response::ResponsePrelude,
+    pub message: StreamingMessage,
+}
+
 /// A chunk of a result - similar to response::ResultMessage, but only a chunk of it
 /// TODO: this is just a dummy to show the intent - this is likely to change
 #[derive(Debug)]
@@ -23,7 +64,10 @@ pub enum StreamingMessage {
     Error(response::ErrorMessage),
     Partial(ResponseStream),
     Authenticate(response::AuthenticateMessage),
+    AuthChallenge(response::AuthChallengeMessage),
     AuthSuccess(response::AuthSuccessMessage),
+    Result(response::ResultMessage),
+    Event(response::Event),
     Ready,
 }
 
@@ -35,7 +79,10 @@ impl From<StreamingMessage> for response::Message {
             Supported(msg) => response::Message::Supported(msg),
             Error(msg) => response::Message::Error(msg),
             AuthSuccess(msg) => response::Message::AuthSuccess(msg),
+            AuthChallenge(msg) => response::Message::AuthChallenge(msg),
             Authenticate(msg) => response::Message::Authenticate(msg),
+            Result(msg) => response::Message::Result(msg),
+            Event(msg) => response::Message::Event(msg),
             Partial(_) => panic!("Partials are not suppported - this is just used during handshake"),
         }
     }
@@ -47,15 +94,17 @@ impl From<response::Message> for StreamingMessage {
             response::Message::Ready => StreamingMessage::Ready,
             response::Message::Supported(msg) => StreamingMessage::Supported(msg),
             response::Message::AuthSuccess(msg) => StreamingMessage::AuthSuccess(msg),
+            response::Message::AuthChallenge(msg) => StreamingMessage::AuthChallenge(msg),
             response::Message::Authenticate(msg) => StreamingMessage::Authenticate(msg),
             response::Message::Error(msg) => StreamingMessage::Error(msg),
-            response::Message::Result => unimplemented!(),
+            response::Message::Result(msg) => StreamingMessage::Result(msg),
+            response::Message::Event(msg) => StreamingMessage::Event(msg),
         }
     }
 }
 
 pub type ResponseStream = Body<ChunkedMessage, io::Error>;
-pub type ResponseMessage = Message<StreamingMessage, ResponseStream>;
+pub type ResponseMessage = Message<Response, ResponseStream>;
 
-pub type RequestMessage = Message<request::Message, RequestStream>;
+pub type RequestMessage = Message<Request, RequestStream>;
 pub type RequestStream = Body<request::Message, io::Error>;
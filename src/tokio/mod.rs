@@ -6,4 +6,7 @@ pub mod easy;
 mod utils;
 
 pub mod client;
+pub mod trace;
+pub mod server;
+pub mod recording;
 mod handshake;
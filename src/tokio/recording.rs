@@ -0,0 +1,547 @@
+//! Records the frames a client exchanges with servers, and replays such recordings against a
+//! client or a server to reproduce a session.
+//!
+//! A recording is a text file with one frame per line, in the order the frames were sent or
+//! received:
+//!
+//! ```text
+//! # tokio-cassandra recording 1
+//! 0.000213 0 > 0 040000000500000000
+//! 0.001620 0 < 0 84000000060000004c0002000b43514c5f56455253494f4e...
+//! ```
+//!
+//! The fields are the seconds since the recording started, the number of the connection, the
+//! direction (`>` for requests, `<` for responses and events), the stream id and the complete
+//! frame in hex, exactly as it was on the wire. Lines starting with `#` are comments.
+use codec::compression::MAX_BODY_LEN;
+use codec::header::{Direction, Header, OpCode};
+use codec::primitives::decode;
+use tokio_core::io::EasyBuf;
+use byteorder::{BigEndian, ByteOrder};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+use super::utils::io_err;
+
+/// The first line of every recording, which identifies the format.
+pub const RECORDING_HEADER: &str = "# tokio-cassandra recording 1";
+
+/// The stream id of events pushed by the server.
+const EVENT_STREAM_ID: u16 = 0xFFFF;
+
+/// How long replays wait for a connection or frame before giving up on it.
+pub const REPLAY_TIMEOUT_SECS: u64 = 10;
+
+/// A frame as it was sent or received on a connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedFrame {
+    /// The time since the recording started.
+    pub elapsed: Duration,
+    /// The number of the connection, counting from zero in the order they were opened.
+    pub connection: usize,
+    pub direction: Direction,
+    pub stream_id: u16,
+    /// The complete frame including its header, possibly compressed.
+    pub bytes: Vec<u8>,
+}
+
+impl RecordedFrame {
+    fn new(elapsed: Duration, connection: usize, bytes: Vec<u8>) -> io::Result<RecordedFrame> {
+        let h = Header::try_from(&bytes).map_err(io_err)?;
+        Ok(RecordedFrame {
+            elapsed: elapsed,
+            connection: connection,
+            direction: h.version.direction,
+            stream_id: h.stream_id,
+            bytes: bytes,
+        })
+    }
+
+    pub fn header(&self) -> io::Result<Header> {
+        Header::try_from(&self.bytes).map_err(io_err)
+    }
+
+    fn is_event(&self) -> bool {
+        self.direction == Direction::Response && self.stream_id == EVENT_STREAM_ID
+    }
+
+    fn with_stream_id(&self, stream_id: u16) -> Vec<u8> {
+        let mut bytes = self.bytes.clone();
+        BigEndian::write_u16(&mut bytes[2..4], stream_id);
+        bytes
+    }
+
+    /// Returns true if both frames are equal, ignoring their stream ids. The string maps of
+    /// STARTUP and SUPPORTED messages are compared by their entries, as their order is arbitrary.
+    fn same_as(&self, other: &RecordedFrame) -> bool {
+        if self.bytes.len() != other.bytes.len() || self.bytes[..2] != other.bytes[..2] ||
+           self.bytes[4] != other.bytes[4] {
+            return false;
+        }
+        if self.bytes[4..] == other.bytes[4..] {
+            return true;
+        }
+        let body = |f: &RecordedFrame| EasyBuf::from(f.bytes[Header::encoded_len()..].to_vec());
+        match OpCode::try_from(self.bytes[4]) {
+            Ok(OpCode::Startup) => {
+                match (decode::string_map(body(self)), decode::string_map(body(other))) {
+                    (Ok((_, a)), Ok((_, b))) => a == b,
+                    _ => false,
+                }
+            }
+            Ok(OpCode::Supported) => {
+                match (decode::string_multimap(body(self)), decode::string_multimap(body(other))) {
+                    (Ok((_, a)), Ok((_, b))) => a == b,
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for RecordedFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.header() {
+            Ok(h) => {
+                write!(f,
+                       "{:?} on stream {} ({} bytes)",
+                       h.op_code,
+                       self.stream_id,
+                       self.bytes.len())
+            }
+            Err(_) => write!(f, "invalid frame on stream {}", self.stream_id),
+        }
+    }
+}
+
+/// All frames of a recorded session.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl Recording {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Recording> {
+        let path = path.as_ref();
+        let f = File::open(path).map_err(|e| io_err(format!("Failed to open '{}': {}", path.display(), e)))?;
+        Recording::read_from(BufReader::new(f))
+    }
+
+    pub fn read_from<R: BufRead>(r: R) -> io::Result<Recording> {
+        let mut frames = Vec::new();
+        for (n, line) in r.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            frames.push(parse_line(line)
+                .map_err(|e| io_err(format!("Invalid recording at line {}: {}", n + 1, e)))?);
+        }
+        Ok(Recording { frames: frames })
+    }
+
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "{}", RECORDING_HEADER)?;
+        for frame in &self.frames {
+            write_line(&mut w, frame.elapsed, frame.connection, &frame.bytes)?;
+        }
+        Ok(())
+    }
+
+    /// The frames of each connection, ordered by the number of the connection.
+    pub fn connections(&self) -> Vec<Vec<&RecordedFrame>> {
+        let count = self.frames.iter().map(|f| f.connection + 1).max().unwrap_or(0);
+        (0..count)
+            .map(|c| self.frames.iter().filter(|f| f.connection == c).collect())
+            .collect()
+    }
+}
+
+fn parse_line(line: &str) -> Result<RecordedFrame, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 5 {
+        return Err(format!("Expected 5 fields, got {}", fields.len()));
+    }
+    let elapsed = {
+        let mut parts = fields[0].splitn(2, '.');
+        let secs = parts.next().and_then(|s| s.parse::<u64>().ok());
+        let micros = parts.next().and_then(|s| s.parse::<u32>().ok());
+        match (secs, micros) {
+            (Some(secs), Some(micros)) if micros < 1_000_000 => Duration::new(secs, micros * 1000),
+            _ => return Err(format!("Invalid time '{}'", fields[0])),
+        }
+    };
+    let connection = fields[1].parse().map_err(|_| format!("Invalid connection '{}'", fields[1]))?;
+    let bytes = from_hex(fields[4]).ok_or_else(|| "Invalid hex encoding of the frame".to_string())?;
+    let frame = RecordedFrame::new(elapsed, connection, bytes).map_err(|e| e.to_string())?;
+    let direction = match fields[2] {
+        ">" => Direction::Request,
+        "<" => Direction::Response,
+        d => return Err(format!("Invalid direction '{}'", d)),
+    };
+    if direction != frame.direction || fields[3] != frame.stream_id.to_string() {
+        return Err(format!("Direction and stream id do not match the frame {}", frame));
+    }
+    Ok(frame)
+}
+
+fn write_line<W: Write>(w: &mut W, elapsed: Duration, connection: usize, frame: &[u8]) -> io::Result<()> {
+    let h = Header::try_from(frame).map_err(io_err)?;
+    writeln!(w,
+             "{}.{:06} {} {} {} {}",
+             elapsed.as_secs(),
+             elapsed.subsec_micros(),
+             connection,
+             if h.version.direction == Direction::Request { ">" } else { "<" },
+             h.stream_id,
+             to_hex(frame))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() & 1 == 1 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+struct RecorderState {
+    out: Box<Write + Send>,
+    start: Instant,
+    connections: usize,
+}
+
+/// Writes all frames of the connections using it into a recording, which is set up with
+/// `CqlCodecDebuggingOptions::record_into`.
+#[derive(Clone)]
+pub struct Recorder {
+    state: Arc<Mutex<RecorderState>>,
+}
+
+impl Recorder {
+    /// Creates a recording at the given path, overwriting any existing file.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Recorder> {
+        let path = path.as_ref();
+        let f = File::create(path).map_err(|e| io_err(format!("Failed to create '{}': {}", path.display(), e)))?;
+        Recorder::new(f)
+    }
+
+    pub fn new<W: Write + Send + 'static>(mut out: W) -> io::Result<Recorder> {
+        writeln!(out, "{}", RECORDING_HEADER)?;
+        Ok(Recorder {
+            state: Arc::new(Mutex::new(RecorderState {
+                out: Box::new(out),
+                start: Instant::now(),
+                connections: 0,
+            })),
+        })
+    }
+
+    /// Returns the recorder for the next connection.
+    pub fn connection(&self) -> ConnectionRecorder {
+        let mut state = self.state.lock().expect("no poisoned lock");
+        state.connections += 1;
+        ConnectionRecorder {
+            recorder: self.clone(),
+            connection: state.connections - 1,
+        }
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Recorder")
+    }
+}
+
+impl PartialEq for Recorder {
+    fn eq(&self, other: &Recorder) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+/// Records the frames of a single connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionRecorder {
+    recorder: Recorder,
+    connection: usize,
+}
+
+impl ConnectionRecorder {
+    /// Records the complete frame, which starts with its header.
+    pub fn record(&self, frame: &[u8]) -> io::Result<()> {
+        let mut state = self.recorder.state.lock().expect("no poisoned lock");
+        let elapsed = state.start.elapsed();
+        write_line(&mut state.out, elapsed, self.connection, frame)?;
+        state.out.flush()
+    }
+}
+
+/// A deviation from the recording observed while replaying it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub connection: usize,
+    /// The recorded frame, or None if an unexpected frame was seen.
+    pub expected: Option<RecordedFrame>,
+    /// The frame seen instead, or None if it did not arrive in time.
+    pub actual: Option<RecordedFrame>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "connection {}: ", self.connection)?;
+        match (&self.expected, &self.actual) {
+            (&Some(ref e), &Some(ref a)) => {
+                let offset = e.bytes.iter().zip(a.bytes.iter()).skip(4).position(|(e, a)| e != a).map(|p| p + 4);
+                write!(f, "expected {}, got {}", e, a)?;
+                match offset {
+                    Some(offset) => write!(f, ", which differs at byte {}", offset),
+                    None => Ok(()),
+                }
+            }
+            (&Some(ref e), &None) => write!(f, "expected {}, got nothing", e),
+            (&None, &Some(ref a)) => write!(f, "unexpected {}", a),
+            (&None, &None) => write!(f, "no difference"),
+        }
+    }
+}
+
+/// A recorded request along with the responses and events that followed it.
+struct Exchange<'a> {
+    request: &'a RecordedFrame,
+    responses: Vec<&'a RecordedFrame>,
+}
+
+fn exchanges<'a>(frames: &[&'a RecordedFrame]) -> Vec<Exchange<'a>> {
+    let mut exchanges: Vec<Exchange<'a>> = Vec::new();
+    for frame in frames {
+        if frame.direction == Direction::Request {
+            exchanges.push(Exchange {
+                request: frame,
+                responses: Vec::new(),
+            });
+            continue;
+        }
+        let position = if frame.is_event() {
+            exchanges.len().checked_sub(1)
+        } else {
+            exchanges.iter()
+                .rposition(|e| e.request.stream_id == frame.stream_id)
+                .or_else(|| exchanges.len().checked_sub(1))
+        };
+        match position {
+            Some(p) => exchanges[p].responses.push(frame),
+            None => debug!("Ignoring {} as it precedes all requests", frame),
+        }
+    }
+    exchanges
+}
+
+/// Reads a frame, or returns None if the connection was closed or nothing arrived in time.
+fn read_frame<R: Read>(r: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut bytes = vec![0; Header::encoded_len()];
+    match r.read_exact(&mut bytes) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof || e.kind() == io::ErrorKind::WouldBlock ||
+                      e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = BigEndian::read_u32(&bytes[5..9]) as usize;
+    if len > MAX_BODY_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("Frame body of {} bytes exceeds the limit of {} bytes", len, MAX_BODY_LEN)));
+    }
+    bytes.resize(Header::encoded_len() + len, 0);
+    r.read_exact(&mut bytes[Header::encoded_len()..])?;
+    Ok(Some(bytes))
+}
+
+fn timeout() -> Duration {
+    Duration::from_secs(REPLAY_TIMEOUT_SECS)
+}
+
+/// Plays the server side of a recording to a client, one recorded connection after another.
+pub struct ReplayServer {
+    addr: SocketAddr,
+    thread: Option<thread::JoinHandle<io::Result<Vec<Difference>>>>,
+}
+
+impl ReplayServer {
+    /// Listens on a free port of the loopback interface. Each request the client sends is
+    /// answered with the responses recorded for it, which get the client's stream id. Recorded
+    /// events are sent right after the response that preceded them.
+    pub fn start(recording: Recording) -> io::Result<ReplayServer> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let thread = thread::spawn(move || {
+            let mut differences = Vec::new();
+            for (connection, frames) in recording.connections().into_iter().enumerate() {
+                let exchanges = exchanges(&frames);
+                let socket = match accept(&listener)? {
+                    Some(socket) => socket,
+                    None => {
+                        differences.extend(exchanges.first().map(|e| {
+                            Difference {
+                                connection: connection,
+                                expected: Some(e.request.clone()),
+                                actual: None,
+                            }
+                        }));
+                        break;
+                    }
+                };
+                serve(socket, connection, &exchanges, &mut differences)?;
+            }
+            Ok(differences)
+        });
+        Ok(ReplayServer {
+            addr: addr,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Waits until all recorded connections were replayed, and returns how the requests of the
+    /// client differed from the recorded ones.
+    pub fn finish(mut self) -> io::Result<Vec<Difference>> {
+        self.thread
+            .take()
+            .expect("thread to be present until finished")
+            .join()
+            .map_err(|_| io_err("The replay server panicked"))?
+    }
+}
+
+fn accept(listener: &TcpListener) -> io::Result<Option<TcpStream>> {
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + timeout();
+    loop {
+        match listener.accept() {
+            Ok((socket, _)) => {
+                socket.set_nonblocking(false)?;
+                socket.set_read_timeout(Some(timeout()))?;
+                return Ok(Some(socket));
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if Instant::now() > deadline {
+                    return Ok(None);
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn serve(mut socket: TcpStream,
+         connection: usize,
+         exchanges: &[Exchange],
+         differences: &mut Vec<Difference>)
+         -> io::Result<()> {
+    let start = Instant::now();
+    for exchange in exchanges {
+        let actual = match read_frame(&mut socket)? {
+            Some(bytes) => RecordedFrame::new(start.elapsed(), connection, bytes)?,
+            None => {
+                differences.push(Difference {
+                    connection: connection,
+                    expected: Some(exchange.request.clone()),
+                    actual: None,
+                });
+                return Ok(());
+            }
+        };
+        if !exchange.request.same_as(&actual) {
+            differences.push(Difference {
+                connection: connection,
+                expected: Some(exchange.request.clone()),
+                actual: Some(actual.clone()),
+            });
+        }
+        for response in &exchange.responses {
+            if response.is_event() {
+                socket.write_all(&response.bytes)?;
+            } else {
+                socket.write_all(&response.with_stream_id(actual.stream_id))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Plays the client side of a recording to the server at the given address, opening one
+/// connection per recorded connection, and returns how the responses differed from the recorded
+/// ones. Requests are sent one at a time, and events are ignored.
+pub fn replay_to(addr: &SocketAddr, recording: &Recording) -> io::Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+    for (connection, frames) in recording.connections().into_iter().enumerate() {
+        let mut socket = TcpStream::connect(addr)?;
+        socket.set_read_timeout(Some(timeout()))?;
+        let start = Instant::now();
+        'exchanges: for exchange in exchanges(&frames) {
+            socket.write_all(&exchange.request.bytes)?;
+            for expected in exchange.responses.iter().filter(|r| !r.is_event()) {
+                let actual = loop {
+                    match read_frame(&mut socket)? {
+                        Some(bytes) => {
+                            let frame = RecordedFrame::new(start.elapsed(), connection, bytes)?;
+                            if !frame.is_event() {
+                                break frame;
+                            }
+                        }
+                        None => {
+                            differences.push(Difference {
+                                connection: connection,
+                                expected: Some((*expected).clone()),
+                                actual: None,
+                            });
+                            break 'exchanges;
+                        }
+                    }
+                };
+                if !expected.same_as(&actual) {
+                    differences.push(Difference {
+                        connection: connection,
+                        expected: Some((*expected).clone()),
+                        actual: Some(actual),
+                    });
+                }
+            }
+        }
+    }
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_frames() {
+        let frame = [0x84, 0, 0, 1, 0x02, 0, 0, 0, 2, 0xab, 0xcd];
+        assert_eq!(read_frame(&mut &frame[..]).unwrap(), Some(frame.to_vec()));
+        assert_eq!(read_frame(&mut &frame[..4]).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let frame = [0x84, 0, 0, 1, 0x02, 0xff, 0xff, 0xff, 0xff];
+        assert_eq!(read_frame(&mut &frame[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
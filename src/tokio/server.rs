@@ -0,0 +1,402 @@
+//! The server side of the protocol, which decodes request frames and encodes responses.
+use codec::request;
+use codec::response;
+use codec::compression::Compression;
+use codec::header::{Header, ProtocolVersion, Version, Direction};
+use codec::primitives::{decode, CqlFrom, CqlString, CqlBytes};
+use tokio_proto::multiplex::{RequestId, ServerProto};
+use tokio_core::io::{EasyBuf, Codec, Io, Framed};
+use futures::{Async, AsyncSink, Poll, Sink, StartSend, Stream};
+use byteorder::{BigEndian, ByteOrder};
+use std::{io, mem};
+
+use super::utils::io_err;
+
+/// A request as received by the server, along with the header of its frame.
+#[derive(Debug)]
+pub struct Request {
+    pub header: Header,
+    /// The payload for custom query handlers, if the client sent one (since v4).
+    pub custom_payload: Option<request::CustomPayload>,
+    pub message: request::Message,
+}
+
+/// A response to be sent, preceded by the values of its prelude.
+#[derive(Debug)]
+pub struct Response {
+    pub prelude: response::ResponsePrelude,
+    pub message: response::Message,
+}
+
+impl From<response::Message> for Response {
+    fn from(msg: response::Message) -> Self {
+        Response {
+            prelude: Default::default(),
+            message: msg,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CqlServerCodec {
+    state: Machine,
+    /// The version spoken by the client, which is used for the responses too.
+    version: ProtocolVersion,
+    /// The highest version accepted. Requests of higher versions are answered with a protocol error.
+    max_version: ProtocolVersion,
+    /// Set once a STARTUP message negotiated it, and used for all subsequent frames.
+    compression: Option<Compression>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum Machine {
+    NeedHeader,
+    WithHeader { header: Header, body_len: usize },
+    /// Skips the body of a frame which is answered with a protocol error.
+    Rejecting { stream_id: u16, body_len: usize, reason: String },
+    /// Ignores all input after a frame of an unsupported version, whose header can't be trusted.
+    Rejected,
+}
+
+impl Default for CqlServerCodec {
+    fn default() -> Self {
+        CqlServerCodec::new(ProtocolVersion::latest())
+    }
+}
+
+impl CqlServerCodec {
+    /// Creates a codec accepting requests up to the given protocol version.
+    pub fn new(max_version: ProtocolVersion) -> Self {
+        CqlServerCodec {
+            state: Machine::NeedHeader,
+            version: max_version,
+            max_version: max_version,
+            compression: None,
+        }
+    }
+
+    fn decompress(&self, h: &Header, body: EasyBuf) -> io::Result<EasyBuf> {
+        if !h.is_compressed() {
+            return Ok(body);
+        }
+        match self.compression {
+            Some(c) => c.decompress(body.as_slice()).map(EasyBuf::from).map_err(io_err),
+            None => Err(io_err(format!("Frame for stream {} is compressed, but no compression was negotiated",
+                                       h.stream_id))),
+        }
+    }
+
+    /// Answers the frame with a protocol error instead of passing it on as request.
+    fn reject(&mut self, stream_id: u16, reason: &str) -> io::Result<Option<<Self as Codec>::In>> {
+        debug!("rejecting frame for stream {}: {}", stream_id, reason);
+        let error = response::ErrorMessage::new(response::CqlErrorKind::ProtocolError, reason).map_err(io_err)?;
+        Ok(Some((stream_id as RequestId, Err(response::Message::Error(error).into()))))
+    }
+
+    fn decode_header(&mut self, buf: &mut EasyBuf) -> io::Result<Option<<Self as Codec>::In>> {
+        use self::Machine::*;
+        let raw = buf.drain_to(Header::encoded_len());
+        let raw = raw.as_slice();
+        let stream_id = BigEndian::read_u16(&raw[2..4]);
+        let body_len = BigEndian::read_u32(&raw[5..9]) as usize;
+        let version = match Version::try_from(raw[0]) {
+            Ok(ref v) if v.direction != Direction::Request => {
+                return Err(io_err(format!("Expected a request frame, got version {:#x}", raw[0])));
+            }
+            Ok(ref v) if v.version > self.max_version => None,
+            Ok(v) => Some(v),
+            Err(_) => None,
+        };
+        let version = match version {
+            Some(v) => v.version,
+            None => {
+                // The error is sent in the highest supported version, which tells clients to
+                // retry with a lower one.
+                self.state = Rejected;
+                self.version = self.max_version;
+                return self.reject(stream_id,
+                                   &format!("Invalid or unsupported protocol version ({})", raw[0] & 0x7f));
+            }
+        };
+        self.version = version;
+        match Header::try_from(raw) {
+            Ok(h) => {
+                self.state = WithHeader {
+                    header: h,
+                    body_len: body_len,
+                };
+            }
+            Err(e) => {
+                self.state = Rejecting {
+                    stream_id: stream_id,
+                    body_len: body_len,
+                    reason: e.to_string(),
+                };
+            }
+        }
+        self.decode(buf)
+    }
+}
+
+fn custom_payload_from(payload: ::std::collections::HashMap<CqlString<EasyBuf>, CqlBytes<EasyBuf>>)
+                       -> request::CustomPayload {
+    payload.into_iter()
+        .map(|(k, v)| (unsafe { CqlString::unchecked_from(k.as_ref()) }, v.into()))
+        .collect()
+}
+
+impl Codec for CqlServerCodec {
+    /// Requests, or the responses to send right away for frames which are rejected.
+    type In = (RequestId, Result<Request, Response>);
+    type Out = (RequestId, Response);
+
+    fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Self::In>> {
+        use self::Machine::*;
+        match self.state {
+            NeedHeader => {
+                if buf.len() < Header::encoded_len() {
+                    return Ok(None);
+                }
+                self.decode_header(buf)
+            }
+            Rejected => {
+                let len = buf.len();
+                buf.drain_to(len);
+                Ok(None)
+            }
+            Rejecting { body_len, .. } => {
+                if body_len > buf.len() {
+                    return Ok(None);
+                }
+                buf.drain_to(body_len);
+                match mem::replace(&mut self.state, NeedHeader) {
+                    Rejecting { stream_id, reason, .. } => self.reject(stream_id, &reason),
+                    _ => unreachable!(),
+                }
+            }
+            WithHeader { body_len, .. } => {
+                if body_len > buf.len() {
+                    return Ok(None);
+                }
+                let h = match mem::replace(&mut self.state, NeedHeader) {
+                    WithHeader { header, .. } => header,
+                    _ => unreachable!(),
+                };
+                let version = h.version.version;
+                self.version = version;
+                let body = self.decompress(&h, buf.drain_to(body_len))?;
+                let (body, custom_payload) = if h.has_custom_payload() {
+                    let (body, payload) = decode::bytes_map(body).map_err(io_err)?;
+                    (body, Some(custom_payload_from(payload)))
+                } else {
+                    (body, None)
+                };
+                let message = match request::Message::decode(version, h.op_code.clone(), body) {
+                    Ok(message) => message,
+                    Err(e) => return self.reject(h.stream_id, &e.to_string()),
+                };
+                if let request::Message::Startup(ref m) = message {
+                    self.compression = match m.compression {
+                        Some(ref name) => {
+                            match Compression::from_name(name.as_ref()) {
+                                Some(c) => Some(c),
+                                None => {
+                                    return self.reject(h.stream_id,
+                                                       &format!("Unsupported compression '{}'", name.as_ref()))
+                                }
+                            }
+                        }
+                        None => None,
+                    };
+                }
+                debug!("decoded request: {:?}", message);
+                Ok(Some((h.stream_id as RequestId,
+                         Ok(Request {
+                             header: h,
+                             custom_payload: custom_payload,
+                             message: message,
+                         }))))
+            }
+        }
+    }
+
+    fn encode(&mut self, (id, res): Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
+        debug!("encoded response: {:?}", res.message);
+        // Responses completing together are written in one go, so the buffer may already
+        // contain frames.
+        let mut frame = Vec::new();
+        response::cql_encode_frame(self.version,
+                                   0,
+                                   id as u16,
+                                   &res.prelude,
+                                   self.compression,
+                                   &res.message,
+                                   &mut frame)
+            .map_err(io_err)?;
+        buf.extend_from_slice(&frame);
+        Ok(())
+    }
+}
+
+/// Passes requests on to the service, and answers frames rejected by the codec right away.
+pub struct CqlServerTransport<T> {
+    inner: Framed<T, CqlServerCodec>,
+    /// The response to a rejected frame which could not be queued yet, as the connection
+    /// is busy writing. No further frames are read before it was queued.
+    rejection: Option<(RequestId, Response)>,
+}
+
+impl<T: Io> Stream for CqlServerTransport<T> {
+    type Item = (RequestId, Request);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        loop {
+            if let Some(rejection) = self.rejection.take() {
+                if let AsyncSink::NotReady(rejection) = self.inner.start_send(rejection)? {
+                    // Flushing registers the task to be polled again once the connection is writable.
+                    self.rejection = Some(rejection);
+                    self.inner.poll_complete()?;
+                    return Ok(Async::NotReady);
+                }
+                self.inner.poll_complete()?;
+            }
+            match try_ready!(self.inner.poll()) {
+                Some((id, Ok(req))) => return Ok(Async::Ready(Some((id, req)))),
+                Some((id, Err(res))) => self.rejection = Some((id, res)),
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+impl<T: Io> Sink for CqlServerTransport<T> {
+    type SinkItem = (RequestId, Response);
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, io::Error> {
+        self.inner.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.inner.poll_complete()
+    }
+}
+
+/// Serves the CQL protocol, responding to each request as it completes. Events are not pushed
+/// to clients.
+#[derive(Debug, Clone)]
+pub struct CqlServerProto {
+    /// The highest protocol version accepted. Clients asking for a higher one receive a protocol
+    /// error, after which they may reconnect with a lower one.
+    pub version: ProtocolVersion,
+}
+
+impl Default for CqlServerProto {
+    fn default() -> Self {
+        CqlServerProto { version: ProtocolVersion::latest() }
+    }
+}
+
+impl<T: Io + 'static> ServerProto<T> for CqlServerProto {
+    type Request = Request;
+    type Response = Response;
+
+    type Transport = CqlServerTransport<T>;
+    type BindTransport = io::Result<Self::Transport>;
+
+    fn bind_transport(&self, io: T) -> Self::BindTransport {
+        debug!("binding server transport!");
+        Ok(CqlServerTransport {
+            inner: io.framed(CqlServerCodec::new(self.version)),
+            rejection: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use codec::header::OpCode;
+    use futures::executor::{self, Notify};
+    use std::cell::Cell;
+    use std::io::{Read, Write};
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    /// A connection which received the given bytes, and can only be written to while `writable`.
+    struct Connection {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+        writable: Rc<Cell<bool>>,
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.input.read(buf)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.writable.get() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Io for Connection {}
+
+    struct Ignore;
+
+    impl Notify for Ignore {
+        fn notify(&self, _id: usize) {}
+    }
+
+    #[test]
+    fn rejections_wait_for_the_connection_to_be_writable() {
+        const FRAMES: usize = 1000;
+        let mut input = Vec::new();
+        for _ in 0..FRAMES {
+            // Opcode 0x42 on stream 3
+            input.extend(&[0x04, 0, 0, 3, 0x42, 0, 0, 0, 0]);
+        }
+        // OPTIONS on stream 5
+        input.extend(&[0x04, 0, 0, 5, 0x05, 0, 0, 0, 0]);
+        let writable = Rc::new(Cell::new(false));
+        let connection = Connection {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+            writable: writable.clone(),
+        };
+        let transport = CqlServerProto::default().bind_transport(connection).unwrap();
+        let mut transport = executor::spawn(transport);
+        let notify = Arc::new(Ignore);
+
+        match transport.poll_stream_notify(&notify, 0).unwrap() {
+            Async::NotReady => {}
+            res => panic!("Expected to wait for the connection, got {:?}", res),
+        }
+        writable.set(true);
+        match transport.poll_stream_notify(&notify, 0).unwrap() {
+            Async::Ready(Some((5, ref req))) => assert_eq!(req.header.op_code, OpCode::Options),
+            res => panic!("Expected the OPTIONS request, got {:?}", res),
+        }
+
+        let mut output = &transport.get_ref().inner.get_ref().output[..];
+        for _ in 0..FRAMES {
+            let h = Header::try_from(output).unwrap();
+            assert_eq!((h.stream_id, h.op_code), (3, OpCode::Error));
+            output = &output[Header::encoded_len() + h.length as usize..];
+        }
+        assert!(output.is_empty());
+    }
+}
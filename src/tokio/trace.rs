@@ -0,0 +1,193 @@
+//! Retrieval of trace sessions, which the server records in the `system_traces` keyspace for
+//! traced requests.
+use codec::request::{self, QueryMessage, QueryParameters, QueryValues};
+use codec::response::{RowsMessage, TracingId};
+use codec::primitives::{self, CqlFrom, CqlLongString, ToCql};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// A session as recorded by the coordinator of a traced request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceSession {
+    pub id: TracingId,
+    pub client: Option<IpAddr>,
+    pub command: Option<String>,
+    pub coordinator: Option<IpAddr>,
+    /// The duration of the request in microseconds, which is unset while it is still in progress.
+    pub duration: Option<i32>,
+    pub parameters: HashMap<String, String>,
+    pub request: Option<String>,
+    /// The milliseconds since epoch at which the request was received.
+    pub started_at: Option<i64>,
+    /// All events recorded so far, in the order they happened.
+    pub events: Vec<TraceEvent>,
+}
+
+impl TraceSession {
+    /// Returns true once the coordinator finished the request, after which no more events follow.
+    pub fn is_complete(&self) -> bool {
+        self.duration.is_some()
+    }
+}
+
+/// A single step taken by one of the nodes involved in a traced request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    /// A time based uuid, which orders the events.
+    pub id: [u8; 16],
+    pub activity: Option<String>,
+    pub source: Option<IpAddr>,
+    /// The microseconds elapsed on the source node since it started working on the request.
+    pub source_elapsed: Option<i32>,
+    pub thread: Option<String>,
+}
+
+fn query_for_session(query: &str, id: TracingId) -> request::Message {
+    request::Message::Query(QueryMessage {
+        query: CqlLongString::try_from(query).expect("static query to be valid"),
+        parameters: QueryParameters {
+            values: Some(QueryValues::positional(&[&id as &ToCql]).expect("uuid to be encodable")),
+            ..Default::default()
+        },
+    })
+}
+
+pub fn session_query(id: TracingId) -> request::Message {
+    query_for_session("SELECT client, command, coordinator, duration, parameters, request, started_at \
+                       FROM system_traces.sessions WHERE session_id = ?",
+                      id)
+}
+
+pub fn events_query(id: TracingId) -> request::Message {
+    query_for_session("SELECT event_id, activity, source, source_elapsed, thread FROM system_traces.events \
+                       WHERE session_id = ?",
+                      id)
+}
+
+/// Builds the session from the results of `session_query` and `events_query`, if the session
+/// was recorded already.
+pub fn assemble(id: TracingId, session: &RowsMessage, events: &RowsMessage) -> primitives::Result<Option<TraceSession>> {
+    let row = match session.rows.first() {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    Ok(Some(TraceSession {
+        id: id,
+        client: session.get(row, "client")?,
+        command: session.get(row, "command")?,
+        coordinator: session.get(row, "coordinator")?,
+        duration: session.get(row, "duration")?,
+        parameters: session.get::<Option<_>>(row, "parameters")?.unwrap_or_default(),
+        request: session.get(row, "request")?,
+        started_at: session.get(row, "started_at")?,
+        events: events.rows
+            .iter()
+            .map(|row| {
+                Ok(TraceEvent {
+                    id: events.get(row, "event_id")?,
+                    activity: events.get(row, "activity")?,
+                    source: events.get(row, "source")?,
+                    source_elapsed: events.get(row, "source_elapsed")?,
+                    thread: events.get(row, "thread")?,
+                })
+            })
+            .collect::<primitives::Result<_>>()?,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use codec::primitives::{CqlValue, ColumnType};
+    use codec::response::ResultMessage;
+    use testing::Reply;
+    use std::str::FromStr;
+
+    const ID: TracingId = [1; 16];
+
+    fn rows(columns: &[(&str, ColumnType)], rows: Vec<Vec<Option<CqlValue>>>) -> RowsMessage {
+        match Reply::rows(columns, rows).unwrap() {
+            Reply::Result(ResultMessage::Rows(rows)) => rows,
+            _ => unreachable!(),
+        }
+    }
+
+    fn session(duration: Option<i32>) -> RowsMessage {
+        let text = || Box::new(ColumnType::Varchar);
+        rows(&[("client", ColumnType::Inet),
+               ("command", ColumnType::Varchar),
+               ("coordinator", ColumnType::Inet),
+               ("duration", ColumnType::Int),
+               ("parameters", ColumnType::Map(text(), text())),
+               ("request", ColumnType::Varchar),
+               ("started_at", ColumnType::Timestamp)],
+             vec![vec![Some(CqlValue::Inet(IpAddr::from_str("10.0.0.1").unwrap())),
+                       Some(CqlValue::Varchar("QUERY".into())),
+                       Some(CqlValue::Inet(IpAddr::from_str("10.0.0.2").unwrap())),
+                       duration.map(CqlValue::Int),
+                       Some(CqlValue::Map(vec![(CqlValue::Varchar("query".into()),
+                                                CqlValue::Varchar("SELECT 1".into()))])),
+                       Some(CqlValue::Varchar("Execute CQL3 query".into())),
+                       Some(CqlValue::Timestamp(1490000000000))]])
+    }
+
+    fn events(ids: &[u8]) -> RowsMessage {
+        rows(&[("event_id", ColumnType::Timeuuid),
+               ("activity", ColumnType::Varchar),
+               ("source", ColumnType::Inet),
+               ("source_elapsed", ColumnType::Int),
+               ("thread", ColumnType::Varchar)],
+             ids.iter()
+                 .map(|&id| {
+                     vec![Some(CqlValue::Timeuuid([id; 16])),
+                          Some(CqlValue::Varchar(format!("step {}", id))),
+                          Some(CqlValue::Inet(IpAddr::from_str("10.0.0.2").unwrap())),
+                          Some(CqlValue::Int(id as i32 * 10)),
+                          None]
+                 })
+                 .collect())
+    }
+
+    #[test]
+    fn assemble_complete_session() {
+        let s = assemble(ID, &session(Some(1500)), &events(&[1, 2, 3])).unwrap().unwrap();
+        assert_eq!(s.id, ID);
+        assert_eq!(s.client, Some(IpAddr::from_str("10.0.0.1").unwrap()));
+        assert_eq!(s.command, Some("QUERY".into()));
+        assert_eq!(s.duration, Some(1500));
+        assert!(s.is_complete());
+        assert_eq!(s.parameters.get("query").map(|q| q.as_str()), Some("SELECT 1"));
+        assert_eq!(s.started_at, Some(1490000000000));
+        assert_eq!(s.events.len(), 3);
+        assert_eq!(s.events[0].activity, Some("step 1".into()));
+        assert_eq!(s.events[0].source_elapsed, Some(10));
+        assert_eq!(s.events[0].thread, None);
+    }
+
+    #[test]
+    fn assemble_keeps_the_order_of_events() {
+        let s = assemble(ID, &session(Some(1500)), &events(&[3, 1, 2])).unwrap().unwrap();
+        assert_eq!(s.events.iter().map(|e| e.id[0]).collect::<Vec<_>>(), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn assemble_session_in_progress() {
+        let s = assemble(ID, &session(None), &events(&[])).unwrap().unwrap();
+        assert_eq!(s.duration, None);
+        assert!(!s.is_complete());
+        assert!(s.events.is_empty());
+    }
+
+    #[test]
+    fn assemble_unrecorded_session() {
+        let no_session = rows(&[("client", ColumnType::Inet)], Vec::new());
+        assert_eq!(assemble(ID, &no_session, &events(&[1])).unwrap(), None);
+    }
+
+    #[test]
+    fn assemble_fails_for_wrong_types() {
+        let session = rows(&[("duration", ColumnType::Varchar)],
+                           vec![vec![Some(CqlValue::Varchar("long".into()))]]);
+        assert!(assemble(ID, &session, &events(&[])).is_err());
+    }
+}
@@ -0,0 +1,494 @@
+extern crate tokio_cassandra;
+extern crate tokio_core;
+extern crate tokio_service;
+extern crate futures;
+
+use tokio_cassandra::codec::authentication::Credentials;
+use tokio_cassandra::codec::compression::Compression;
+use tokio_cassandra::codec::header::{Direction, Header, OpCode, ProtocolVersion, FLAG_CUSTOM_PAYLOAD, FLAG_TRACING,
+                                     FLAG_WARNING};
+use tokio_cassandra::codec::request::{self, QueryMessage, PrepareMessage, ExecuteMessage, BatchMessage, BatchType};
+use tokio_cassandra::codec::response::{CqlErrorKind, ResultMessage};
+use tokio_cassandra::codec::primitives::{CqlFrom, CqlLongString, CqlString, CqlStringList, CqlBytes, CqlValue,
+                                         ColumnType};
+use tokio_cassandra::tokio::client::{Client, ClientHandle, ConnectOptions, CqlProto};
+use tokio_cassandra::tokio::messages::{Request, StreamingMessage};
+use tokio_cassandra::tokio::error::{Error, ErrorKind};
+use tokio_cassandra::testing::{self, MockServer, MockOptions, Reply, Rule, QueryPattern};
+use tokio_core::reactor::{Core, Timeout};
+use tokio_service::Service;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn connect(core: &mut Core, server: &MockServer, options: ConnectOptions) -> Result<ClientHandle, Error> {
+    let client = Client { protocol: CqlProto::default() };
+    core.run(client.connect(&server.addr(), &core.handle(), options))
+}
+
+fn query(q: &str) -> request::Message {
+    request::Message::Query(QueryMessage {
+        query: CqlLongString::try_from(q).unwrap(),
+        ..Default::default()
+    })
+}
+
+fn rows_of(res: StreamingMessage) -> Vec<Vec<Option<CqlValue>>> {
+    match res {
+        StreamingMessage::Result(ResultMessage::Rows(rows)) => {
+            rows.rows.iter().map(|r| rows.decode_row(r).unwrap()).collect()
+        }
+        res => panic!("Expected rows, got {:?}", res),
+    }
+}
+
+fn users() -> Reply {
+    Reply::rows(&[("name", ColumnType::Varchar), ("age", ColumnType::Int)],
+                vec![vec![Some(CqlValue::Varchar("alice".into())), Some(CqlValue::Int(42))],
+                     vec![Some(CqlValue::Varchar("bob".into())), None]])
+        .unwrap()
+}
+
+#[test]
+fn answers_queries_by_rule() {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    server.on("SELECT * FROM users", users())
+        .on(QueryPattern::Prefix("INSERT".into()), Reply::void())
+        .on(QueryPattern::matching(|q| q.contains("broken")),
+            Reply::error(CqlErrorKind::SyntaxError, "line 1: no viable alternative").unwrap());
+
+    let mut core = Core::new().unwrap();
+    let client = connect(&mut core, &server, ConnectOptions::default()).unwrap();
+    assert_eq!(client.protocol_version(), ProtocolVersion::Version4);
+
+    let rows = rows_of(core.run(client.call(query("SELECT * FROM users"))).unwrap());
+    assert_eq!(rows,
+               vec![vec![Some(CqlValue::Varchar("alice".into())), Some(CqlValue::Int(42))],
+                    vec![Some(CqlValue::Varchar("bob".into())), None]]);
+
+    match core.run(client.call(query("INSERT INTO users (name) VALUES ('carol')"))).unwrap() {
+        StreamingMessage::Result(ResultMessage::Void) => {}
+        res => panic!("Unexpected response {:?}", res),
+    }
+    match core.run(client.call(query("a broken query"))).unwrap() {
+        StreamingMessage::Error(e) => assert_eq!(e.kind, CqlErrorKind::SyntaxError),
+        res => panic!("Unexpected response {:?}", res),
+    }
+    match core.run(client.call(query("SELECT 1"))).unwrap() {
+        StreamingMessage::Error(e) => assert_eq!(e.kind, CqlErrorKind::Invalid),
+        res => panic!("Unexpected response {:?}", res),
+    }
+
+    assert_eq!(server.queries(),
+               vec!["SELECT * FROM users",
+                    "INSERT INTO users (name) VALUES ('carol')",
+                    "a broken query",
+                    "SELECT 1"]);
+    let requests = server.take_requests();
+    match requests[0].message {
+        request::Message::Options => {}
+        ref msg => panic!("Expected OPTIONS first, got {:?}", msg),
+    }
+    assert!(server.take_requests().is_empty());
+}
+
+#[test]
+fn delays_replies() {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    server.add_rule(Rule::new("SELECT now()", Reply::void()).delayed(Duration::from_millis(200)));
+
+    let mut core = Core::new().unwrap();
+    let client = connect(&mut core, &server, ConnectOptions::default()).unwrap();
+    let start = Instant::now();
+    core.run(client.call(query("SELECT now()"))).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn dropped_requests_keep_their_stream_id_until_answered() {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    server.add_rule(Rule::new("SELECT now()", Reply::void()).delayed(Duration::from_millis(200)));
+
+    let mut core = Core::new().unwrap();
+    let client = connect(&mut core, &server, ConnectOptions::default()).unwrap();
+    assert_eq!(client.in_flight(), 0);
+    drop(client.call(query("SELECT now()")));
+    assert_eq!(client.in_flight(), 1);
+
+    // The response to the dropped request arrives while waiting for the second one.
+    core.run(client.call(query("SELECT now()"))).unwrap();
+    core.run(Timeout::new(Duration::from_millis(300), &core.handle()).unwrap()).unwrap();
+    assert_eq!(client.in_flight(), 0);
+    core.run(client.call(query("SELECT 1"))).unwrap();
+}
+
+/// Forwards one connection to the server. The returned stream is the forwarding end of the client's
+/// connection, which allows to send frames to the client as if they came from the server.
+fn proxy(server: &MockServer) -> (SocketAddr, mpsc::Receiver<TcpStream>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_addr = server.addr();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let client = listener.accept().unwrap().0;
+        let server = TcpStream::connect(server_addr).unwrap();
+        tx.send(client.try_clone().unwrap()).unwrap();
+        let (mut client_reader, mut server_writer) = (client.try_clone().unwrap(), server.try_clone().unwrap());
+        thread::spawn(move || io::copy(&mut client_reader, &mut server_writer));
+        let (mut server_reader, mut client_writer) = (server, client);
+        io::copy(&mut server_reader, &mut client_writer).ok();
+    });
+    (addr, rx)
+}
+
+#[test]
+fn requests_on_broken_connections_release_their_stream_id() {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    let (addr, connection) = proxy(&server);
+    let mut core = Core::new().unwrap();
+    let client = core.run(Client { protocol: CqlProto::default() }.connect(&addr,
+                                                                           &core.handle(),
+                                                                           ConnectOptions::default()))
+        .unwrap();
+    // A frame of an unknown version breaks the connection.
+    connection.recv().unwrap().write_all(&[0x7f, 0, 0, 0, 0x02, 0, 0, 0, 0]).unwrap();
+
+    for _ in 0..3 {
+        assert!(core.run(client.call(query("SELECT now()"))).is_err());
+        assert_eq!(client.in_flight(), 0);
+    }
+}
+
+#[test]
+fn traces_requests() {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    server.on("SELECT name, age FROM users", users())
+        .on(QueryPattern::Prefix("SELECT client, command".into()),
+            Reply::rows(&[("client", ColumnType::Inet),
+                          ("command", ColumnType::Varchar),
+                          ("coordinator", ColumnType::Inet),
+                          ("duration", ColumnType::Int),
+                          ("parameters", ColumnType::Map(Box::new(ColumnType::Varchar), Box::new(ColumnType::Varchar))),
+                          ("request", ColumnType::Varchar),
+                          ("started_at", ColumnType::Timestamp)],
+                        vec![vec![None, None, None, Some(CqlValue::Int(1500)), None, None, None]])
+                .unwrap())
+        .on(QueryPattern::Prefix("SELECT event_id".into()),
+            Reply::rows(&[("event_id", ColumnType::Timeuuid),
+                          ("activity", ColumnType::Varchar),
+                          ("source", ColumnType::Inet),
+                          ("source_elapsed", ColumnType::Int),
+                          ("thread", ColumnType::Varchar)],
+                        vec![vec![Some(CqlValue::Timeuuid([1; 16])),
+                                  Some(CqlValue::Varchar("Parsing".into())),
+                                  None,
+                                  None,
+                                  None]])
+                .unwrap());
+
+    let mut core = Core::new().unwrap();
+    let client = connect(&mut core, &server, ConnectOptions::default()).unwrap();
+    server.take_requests();
+
+    let res = core.run(client.send(Request::from(query("SELECT name, age FROM users")).traced())).unwrap();
+    let requests = server.take_requests();
+    assert_eq!(requests[0].header.flags & FLAG_TRACING, FLAG_TRACING);
+    assert_eq!(res.header.flags & FLAG_TRACING, FLAG_TRACING);
+    assert_eq!(res.prelude.tracing_id, Some(testing::tracing_id(0)));
+    assert_eq!(rows_of(res.message).len(), 2);
+
+    let untraced = core.run(client.send(query("SELECT name, age FROM users").into())).unwrap();
+    assert_eq!(untraced.header.flags & FLAG_TRACING, 0);
+    assert_eq!(untraced.prelude.tracing_id, None);
+
+    let session = core.run(client.trace_session(testing::tracing_id(0))).unwrap().expect("a recorded session");
+    assert_eq!(session.id, testing::tracing_id(0));
+    assert_eq!(session.duration, Some(1500));
+    assert_eq!(session.events.len(), 1);
+    assert_eq!(session.events[0].activity, Some("Parsing".into()));
+}
+
+#[test]
+fn passes_response_headers_and_preludes_to_the_caller() {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    server.add_rule(Rule::new("SELECT name, age FROM users", users())
+        .with_warning("Aggregation query used without partition key")
+        .with_payload("origin", b"mock"));
+
+    let mut core = Core::new().unwrap();
+    let client = connect(&mut core, &server, ConnectOptions::default()).unwrap();
+    server.take_requests();
+
+    let res = core.run(client.send(Request::from(query("SELECT name, age FROM users")).traced())).unwrap();
+    let requests = server.take_requests();
+    assert_eq!(res.header.version.version, ProtocolVersion::Version4);
+    assert_eq!(res.header.version.direction, Direction::Response);
+    assert_eq!(res.header.op_code, OpCode::Result);
+    assert_eq!(res.header.stream_id, requests[0].header.stream_id);
+    assert_eq!(res.header.flags & (FLAG_TRACING | FLAG_WARNING | FLAG_CUSTOM_PAYLOAD),
+               FLAG_TRACING | FLAG_WARNING | FLAG_CUSTOM_PAYLOAD);
+    assert_eq!(res.prelude.tracing_id, Some(testing::tracing_id(0)));
+    assert_eq!(res.prelude.warnings,
+               Some(CqlStringList::try_from_iter_easy(vec!["Aggregation query used without partition key"])
+                   .unwrap()));
+    let mut payload = HashMap::new();
+    payload.insert(CqlString::try_from("origin").unwrap(),
+                   CqlBytes::try_from(b"mock".to_vec()).unwrap());
+    assert_eq!(res.prelude.custom_payload, Some(payload));
+    assert_eq!(rows_of(res.message).len(), 2);
+
+    // The next response is decoded with flags of its own.
+    server.on("INSERT INTO users (name) VALUES ('carol')", Reply::void());
+    let res = core.run(client.send(query("INSERT INTO users (name) VALUES ('carol')").into())).unwrap();
+    assert_eq!(res.header.flags & (FLAG_TRACING | FLAG_WARNING | FLAG_CUSTOM_PAYLOAD), 0);
+    assert_eq!(res.prelude, Default::default());
+}
+
+#[test]
+fn prepares_and_executes() {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    server.on("SELECT * FROM users", users());
+
+    let mut core = Core::new().unwrap();
+    let client = connect(&mut core, &server, ConnectOptions::default()).unwrap();
+    let prepare = request::Message::Prepare(PrepareMessage {
+        query: CqlLongString::try_from("SELECT * FROM users").unwrap(),
+    });
+    let id = match core.run(client.call(prepare)).unwrap() {
+        StreamingMessage::Result(ResultMessage::Prepared(p)) => {
+            assert_eq!(p.result_metadata.columns_count, 2);
+            p.id
+        }
+        res => panic!("Unexpected response {:?}", res),
+    };
+    let execute = request::Message::Execute(ExecuteMessage {
+        id: CqlBytes::try_from(id.as_bytes().unwrap().to_vec()).unwrap(),
+        parameters: Default::default(),
+    });
+    assert_eq!(rows_of(core.run(client.call(execute)).unwrap()).len(), 2);
+    assert_eq!(id.as_bytes(), Some(&testing::prepared_id("SELECT * FROM users")[..]));
+
+    let unknown = || CqlBytes::try_from(vec![0xff; 16]).unwrap();
+    let is_unprepared = |res: StreamingMessage| match res {
+        StreamingMessage::Error(e) => {
+            assert_eq!(e.kind, CqlErrorKind::Unprepared { id: CqlBytes::try_from(vec![0xff; 16]).unwrap() })
+        }
+        res => panic!("Expected an UNPREPARED error, got {:?}", res),
+    };
+    let execute = request::Message::Execute(ExecuteMessage {
+        id: unknown(),
+        parameters: Default::default(),
+    });
+    is_unprepared(core.run(client.call(execute)).unwrap());
+    let mut batch = BatchMessage::new(BatchType::Logged);
+    batch.add_prepared(CqlBytes::try_from(id.as_bytes().unwrap().to_vec()).unwrap(), Vec::new())
+        .add_prepared(unknown(), Vec::new());
+    is_unprepared(core.run(client.call(request::Message::Batch(batch))).unwrap());
+}
+
+#[test]
+fn requires_login() {
+    let server = MockServer::start(MockOptions {
+            login: Some(("cassandra".into(), "secret".into())),
+            ..Default::default()
+        })
+        .unwrap();
+    let mut core = Core::new().unwrap();
+
+    assert!(connect(&mut core, &server, ConnectOptions::default()).is_err());
+
+    let login = |password: &str| {
+        ConnectOptions {
+            creds: Some(Credentials::Login {
+                username: "cassandra".into(),
+                password: password.into(),
+            }),
+            ..Default::default()
+        }
+    };
+    match connect(&mut core, &server, login("wrong")) {
+        Err(Error(ErrorKind::CqlError(CqlErrorKind::AuthenticationError, _), _)) => {}
+        res => panic!("Unexpected result {:?}", res.map(|_| ())),
+    }
+    assert!(connect(&mut core, &server, login("secret")).is_ok());
+}
+
+#[test]
+fn negotiates_version_and_compression() {
+    let server = MockServer::start(MockOptions { version: ProtocolVersion::Version3, ..Default::default() })
+        .unwrap();
+    server.on("SELECT * FROM users", users());
+
+    let mut core = Core::new().unwrap();
+    for compression in &[Compression::Lz4, Compression::Snappy] {
+        let client = connect(&mut core,
+                             &server,
+                             ConnectOptions { compression: Some(*compression), ..Default::default() })
+            .unwrap();
+        assert_eq!(client.protocol_version(), ProtocolVersion::Version3);
+        assert_eq!(rows_of(core.run(client.call(query("SELECT * FROM users"))).unwrap()).len(),
+                   2);
+    }
+    assert!(server.take_requests().iter().any(|r| r.header.is_compressed()));
+}
+
+#[test]
+fn v4_clients_downgrade_against_v3_servers() {
+    let server = MockServer::start(MockOptions { version: ProtocolVersion::Version3, ..Default::default() })
+        .unwrap();
+    server.on("SELECT * FROM users", users());
+
+    let mut core = Core::new().unwrap();
+    let client = connect(&mut core, &server, ConnectOptions::default()).unwrap();
+    assert_eq!(client.protocol_version(), ProtocolVersion::Version3);
+    assert_eq!(rows_of(core.run(client.call(query("SELECT * FROM users"))).unwrap()).len(),
+               2);
+    // The rejected v4 frame never reaches the service.
+    assert!(server.take_requests().iter().all(|r| r.header.version.version == ProtocolVersion::Version3));
+}
+
+/// Sends the raw frame and returns the header and body of the response.
+fn exchange(stream: &mut TcpStream, frame: &[u8]) -> (Header, Vec<u8>) {
+    stream.write_all(frame).unwrap();
+    let mut header = [0; 9];
+    stream.read_exact(&mut header).unwrap();
+    let header = Header::try_from(&header[..]).unwrap();
+    let mut body = vec![0; header.length as usize];
+    stream.read_exact(&mut body).unwrap();
+    (header, body)
+}
+
+fn is_protocol_error(body: &[u8]) -> bool {
+    body[..4] == [0, 0, 0, 0x0a]
+}
+
+#[test]
+fn rejects_unsupported_versions_with_a_protocol_error() {
+    let server = MockServer::start(MockOptions { version: ProtocolVersion::Version3, ..Default::default() })
+        .unwrap();
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    // OPTIONS in v4, on stream 7
+    let (header, body) = exchange(&mut stream, &[0x04, 0, 0, 7, 0x05, 0, 0, 0, 0]);
+    assert_eq!(header.version.encode(), 0x83);
+    assert_eq!(header.stream_id, 7);
+    assert_eq!(header.op_code, OpCode::Error);
+    assert!(is_protocol_error(&body));
+    assert!(server.take_requests().is_empty());
+}
+
+#[test]
+fn rejects_unknown_opcodes_with_a_protocol_error() {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    // Opcode 0x42 with a body of 2 bytes, on stream 3
+    let (header, body) = exchange(&mut stream, &[0x04, 0, 0, 3, 0x42, 0, 0, 0, 2, 0xab, 0xcd]);
+    assert_eq!(header.version.encode(), 0x84);
+    assert_eq!(header.stream_id, 3);
+    assert_eq!(header.op_code, OpCode::Error);
+    assert!(is_protocol_error(&body));
+
+    // A READY sent by the client is no request either.
+    let (header, body) = exchange(&mut stream, &[0x04, 0, 0, 4, 0x02, 0, 0, 0, 0]);
+    assert_eq!(header.op_code, OpCode::Error);
+    assert!(is_protocol_error(&body));
+
+    // The connection is still usable afterwards.
+    let (header, _) = exchange(&mut stream, &[0x04, 0, 0, 5, 0x05, 0, 0, 0, 0]);
+    assert_eq!(header.stream_id, 5);
+    assert_eq!(header.op_code, OpCode::Supported);
+    assert_eq!(server.take_requests().len(), 1);
+}
+
+/// A STARTUP frame in v4 on the given stream, with the given options.
+fn startup_frame(stream_id: u8, options: &[(&str, &str)]) -> Vec<u8> {
+    let mut body = vec![0, options.len() as u8];
+    for &(key, value) in options {
+        for s in &[key, value] {
+            body.extend(&[0, s.len() as u8]);
+            body.extend(s.as_bytes());
+        }
+    }
+    let mut frame = vec![0x04, 0, 0, stream_id, 0x01, 0, 0, 0, body.len() as u8];
+    frame.extend(body);
+    frame
+}
+
+#[test]
+fn rejects_unknown_compressions_with_a_protocol_error() {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let (header, body) = exchange(&mut stream,
+                                  &startup_frame(2, &[("CQL_VERSION", "3.0.0"), ("COMPRESSION", "zip")]));
+    assert_eq!(header.stream_id, 2);
+    assert_eq!(header.op_code, OpCode::Error);
+    assert!(is_protocol_error(&body));
+    assert!(server.take_requests().is_empty());
+
+    // The client may start up again without compression.
+    let (header, _) = exchange(&mut stream, &startup_frame(3, &[("CQL_VERSION", "3.0.0")]));
+    assert_eq!(header.stream_id, 3);
+    assert_eq!(header.op_code, OpCode::Ready);
+}
+
+#[test]
+fn executes_statements_in_memory() {
+    let server = MockServer::start(MockOptions { engine: true, ..Default::default() }).unwrap();
+    server.on("SELECT release_version FROM system.local",
+              Reply::rows(&[("release_version", ColumnType::Varchar)],
+                          vec![vec![Some(CqlValue::Varchar("3.0.9".into()))]])
+                  .unwrap());
+
+    let mut core = Core::new().unwrap();
+    let client = connect(&mut core, &server, ConnectOptions::default()).unwrap();
+    for q in &["CREATE KEYSPACE ks WITH replication = {'class': 'SimpleStrategy', 'replication_factor': 1}",
+               "USE ks",
+               "CREATE TABLE users (name text PRIMARY KEY, age int)",
+               "INSERT INTO users (name, age) VALUES ('alice', 42)",
+               "INSERT INTO users (name, age) VALUES ('bob', 23)",
+               "INSERT INTO users (name) VALUES ('carol')"] {
+        if let StreamingMessage::Error(e) = core.run(client.call(query(q))).unwrap() {
+            panic!("'{}' failed: {:?}", q, e);
+        }
+    }
+    assert_eq!(rows_of(core.run(client.call(query("SELECT release_version FROM system.local"))).unwrap()),
+               vec![vec![Some(CqlValue::Varchar("3.0.9".into()))]]);
+
+    let page = |state: Option<CqlBytes<Vec<u8>>>| {
+        request::Message::Query(QueryMessage {
+            query: CqlLongString::try_from("SELECT name, age FROM users").unwrap(),
+            parameters: request::QueryParameters {
+                page_size: Some(2),
+                paging_state: state,
+                ..Default::default()
+            },
+        })
+    };
+    let first = match core.run(client.call(page(None))).unwrap() {
+        StreamingMessage::Result(ResultMessage::Rows(rows)) => rows,
+        res => panic!("Expected rows, got {:?}", res),
+    };
+    assert_eq!(first.rows.iter().map(|r| first.decode_row(r).unwrap()).collect::<Vec<_>>(),
+               vec![vec![Some(CqlValue::Varchar("alice".into())), Some(CqlValue::Int(42))],
+                    vec![Some(CqlValue::Varchar("bob".into())), Some(CqlValue::Int(23))]]);
+    let state = first.metadata.paging_state.as_ref().and_then(|s| s.as_bytes()).expect("a paging state").to_vec();
+    assert_eq!(rows_of(core.run(client.call(page(Some(CqlBytes::try_from(state).unwrap())))).unwrap()),
+               vec![vec![Some(CqlValue::Varchar("carol".into())), None]]);
+
+    match core.run(client.call(query("SELECT * FROM missing"))).unwrap() {
+        StreamingMessage::Error(e) => assert_eq!(e.kind, CqlErrorKind::Invalid),
+        res => panic!("Unexpected response {:?}", res),
+    }
+    match core.run(client.call(query("SELEKT 1"))).unwrap() {
+        StreamingMessage::Error(e) => assert_eq!(e.kind, CqlErrorKind::SyntaxError),
+        res => panic!("Unexpected response {:?}", res),
+    }
+}
@@ -0,0 +1,130 @@
+extern crate tokio_cassandra;
+extern crate tokio_core;
+extern crate tokio_service;
+
+use tokio_cassandra::codec::request::{self, QueryMessage};
+use tokio_cassandra::codec::response::ResultMessage;
+use tokio_cassandra::codec::primitives::{CqlFrom, CqlLongString, CqlValue, ColumnType};
+use tokio_cassandra::tokio::client::{Client, ClientHandle, ConnectOptions, CqlProto};
+use tokio_cassandra::tokio::codec::CqlCodecDebuggingOptions;
+use tokio_cassandra::tokio::messages::StreamingMessage;
+use tokio_cassandra::tokio::recording::{self, Recorder, Recording, ReplayServer};
+use tokio_cassandra::testing::{MockServer, MockOptions, Reply};
+use tokio_core::reactor::Core;
+use tokio_service::Service;
+use std::io::{Cursor, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Collects what the recorder writes, to be read back as recording.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn connect(core: &mut Core, addr: &SocketAddr, recorder: Option<Recorder>) -> ClientHandle {
+    let client = Client {
+        protocol: CqlProto {
+            debug: recorder.map(|r| CqlCodecDebuggingOptions { record_into: Some(r), ..Default::default() }),
+            ..Default::default()
+        },
+    };
+    core.run(client.connect(addr, &core.handle(), ConnectOptions::default())).unwrap()
+}
+
+fn query(q: &str) -> request::Message {
+    request::Message::Query(QueryMessage {
+        query: CqlLongString::try_from(q).unwrap(),
+        ..Default::default()
+    })
+}
+
+fn names(core: &mut Core, client: &ClientHandle, q: &str) -> Vec<Vec<Option<CqlValue>>> {
+    match core.run(client.call(query(q))).unwrap() {
+        StreamingMessage::Result(ResultMessage::Rows(rows)) => {
+            rows.rows.iter().map(|r| rows.decode_row(r).unwrap()).collect()
+        }
+        res => panic!("Expected rows, got {:?}", res),
+    }
+}
+
+fn mock() -> MockServer {
+    let server = MockServer::start(MockOptions::default()).unwrap();
+    server.on("SELECT name FROM users",
+              Reply::rows(&[("name", ColumnType::Varchar)],
+                          vec![vec![Some(CqlValue::Varchar("alice".into()))]])
+                  .unwrap());
+    server
+}
+
+fn record_session(server: &MockServer) -> Recording {
+    let buffer = Buffer::default();
+    {
+        let mut core = Core::new().unwrap();
+        let client = connect(&mut core, &server.addr(), Some(Recorder::new(buffer.clone()).unwrap()));
+        assert_eq!(names(&mut core, &client, "SELECT name FROM users").len(), 1);
+    }
+    let bytes = buffer.0.lock().unwrap().clone();
+    Recording::read_from(Cursor::new(bytes)).unwrap()
+}
+
+#[test]
+fn records_both_directions() {
+    let server = mock();
+    let recording = record_session(&server);
+    let connections = recording.connections();
+    assert_eq!(connections.len(), 1);
+    assert!(connections[0].len() >= 4, "handshake and query in both directions");
+    assert!(recording.frames.windows(2).all(|w| w[0].elapsed <= w[1].elapsed));
+
+    let mut written = Vec::new();
+    recording.write_to(&mut written).unwrap();
+    assert_eq!(Recording::read_from(Cursor::new(written)).unwrap(), recording);
+
+    assert!(Recording::read_from(Cursor::new("0.1 0 > 0 0400".as_bytes())).is_err());
+}
+
+#[test]
+fn replays_to_a_server() {
+    let server = mock();
+    let recording = record_session(&server);
+    assert_eq!(recording::replay_to(&server.addr(), &recording).unwrap(), vec![]);
+
+    let other = MockServer::start(MockOptions::default()).unwrap();
+    other.on("SELECT name FROM users", Reply::void());
+    let differences = recording::replay_to(&other.addr(), &recording).unwrap();
+    assert_eq!(differences.len(), 1);
+    assert!(differences[0].to_string().contains("Result"));
+}
+
+#[test]
+fn replays_to_a_client() {
+    let recording = record_session(&mock());
+
+    let replay = ReplayServer::start(recording.clone()).unwrap();
+    {
+        let mut core = Core::new().unwrap();
+        let client = connect(&mut core, &replay.addr(), None);
+        assert_eq!(names(&mut core, &client, "SELECT name FROM users"),
+                   vec![vec![Some(CqlValue::Varchar("alice".into()))]]);
+    }
+    assert_eq!(replay.finish().unwrap(), vec![]);
+
+    let replay = ReplayServer::start(recording).unwrap();
+    {
+        let mut core = Core::new().unwrap();
+        let client = connect(&mut core, &replay.addr(), None);
+        names(&mut core, &client, "SELECT age FROM users");
+    }
+    let differences = replay.finish().unwrap();
+    assert_eq!(differences.len(), 1);
+    assert!(differences[0].expected.is_some() && differences[0].actual.is_some());
+}